@@ -0,0 +1,66 @@
+use axum::{
+    extract::Request,
+    http::{HeaderName, Response},
+    middleware::Next,
+    response::Response as AxumResponse,
+};
+use std::time::Duration;
+use tracing::{field::Empty, info, info_span, Span};
+
+/// リクエストIDを運ぶヘッダー
+static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// 処理中のリクエストのID。リクエストの外から呼ばれた場合は`None`
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// リクエストIDをタスクローカルに設定してからハンドラーを呼ぶミドルウェア
+///
+/// エラーレスポンスを作る箇所ではリクエストを受け取っていないことが多いため、
+/// タスクローカル経由で`current_request_id`から参照できるようにする。
+pub async fn scope_request_id(req: Request, next: Next) -> AxumResponse {
+    let request_id = request_id(&req).to_string();
+    REQUEST_ID.scope(request_id, next.run(req)).await
+}
+
+/// アクセスログのスパンを作成する。リクエストID・メソッド・パス・手牌を記録する
+///
+/// 分析エンドポイントでは処理の段階ごとの所要時間を後から`timing::record_stages`が記録する。
+pub fn make_span<B>(req: &axum::http::Request<B>) -> Span {
+    let hand = req.uri().query().and_then(|query| {
+        form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == "hand")
+            .map(|(_, value)| value.into_owned())
+    });
+    info_span!(
+        "request",
+        request_id = %request_id(req),
+        method = %req.method(),
+        path = %req.uri().path(),
+        hand = hand.as_deref().unwrap_or(""),
+        parse_ms = Empty,
+        encode_ms = Empty,
+        read_ms = Empty,
+    )
+}
+
+/// レスポンスのステータスと処理時間をログに出力する
+pub fn on_response<B>(res: &Response<B>, latency: Duration, _span: &Span) {
+    info!(
+        status = res.status().as_u16(),
+        latency_ms = latency.as_secs_f64() * 1000.0,
+        "Request completed"
+    );
+}
+
+fn request_id<B>(req: &axum::http::Request<B>) -> &str {
+    req.headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+}
@@ -0,0 +1,84 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json as JsonResponse, Response},
+};
+use serde::Serialize;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::access_log::current_request_id;
+use crate::analysis::{DatasetFile, ReadStats};
+use crate::cache::CacheStats;
+use crate::converter_registry::ConverterRegistryStats;
+use crate::ErrorResponse;
+
+/// 管理用エンドポイントの認証に使うトークン
+#[derive(Clone)]
+pub struct AdminToken(Arc<str>);
+
+impl AdminToken {
+    pub fn new(token: &str) -> Self {
+        Self(token.into())
+    }
+
+    /// `Authorization: Bearer <token>`が一致するか
+    fn verify(&self, authorization: Option<&HeaderValue>) -> bool {
+        authorization
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .is_some_and(|token| constant_time_eq(token.as_bytes(), self.0.as_bytes()))
+    }
+}
+
+/// 管理用統計情報
+#[derive(Serialize, Debug, ToSchema)]
+pub struct AdminStats {
+    pub converters: ConverterRegistryStats,
+    pub reads: Vec<ReadStats>,
+    pub datasets: Vec<DatasetFile>,
+    pub cache: CacheStats,
+}
+
+/// キャッシュ破棄の結果
+#[derive(Serialize, Debug, ToSchema)]
+pub struct FlushResult {
+    /// 解放した未参照のconverterの数
+    pub evicted_converters: usize,
+    /// 捨てたキャッシュの行数
+    pub evicted_cache_entries: usize,
+}
+
+/// データセットの再読み込みの結果
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ReloadResult {
+    /// 差し替えたデータセットのバージョン（ETagの前半）
+    pub version: String,
+    /// 読み込みと自己診断にかかった時間（ミリ秒）
+    pub elapsed_ms: u64,
+}
+
+/// トークンが一致しないリクエストを401で拒否するミドルウェア
+pub async fn require_token(State(token): State<AdminToken>, req: Request, next: Next) -> Response {
+    if token.verify(req.headers().get(header::AUTHORIZATION)) {
+        return next.run(req).await;
+    }
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"))],
+        JsonResponse(ErrorResponse {
+            error: "Unauthorized".to_string(),
+            code: "UNAUTHORIZED".to_string(),
+            message: "Unauthorized: a valid admin bearer token is required".to_string(),
+            details: Vec::new(),
+            request_id: current_request_id(),
+        }),
+    )
+        .into_response()
+}
+
+/// トークンの比較にかかる時間から一致した長さを推測されないよう、常に全体を比較する
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
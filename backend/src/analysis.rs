@@ -1,249 +1,2065 @@
-use crate::flat_file_vec_pool::{create_flat_file_vec_pool, FlatFileVecPool};
-use common::mahjong::{Dimension, Hand, HandConverter, Metrics, Tile, NUM_ROUNDS};
-use serde::Serialize;
+use crate::access_log::current_request_id;
+use crate::cache::{CacheStats, RowCache, TableKind};
+use crate::pagination::{Page, PageParams};
+use crate::params::{FieldError, InvalidParams};
+use crate::stats::HistogramSnapshot;
+use crate::table::{open_table, TableOptions, TableSource};
+use crate::timing::{measure, measure_async, Stage};
+use crate::{ApiError, ErrorResponse};
+use common::mahjong::{
+    dimension_dora_count, parse_hand_str, payment, random_hand, shanten, ukeire_count,
+    validate_hand_tiles, AgariFamily, Dimension, Hand, HandConverter, HandSpace, Metrics, Tile,
+    TileMultiset, TileSet, Translation, WinContext, NUM_ROUNDS, TURNS_SCALE,
+};
+use async_graphql::SimpleObject;
+use futures_util::future::try_join_all;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Json as JsonResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use std::{
-    path::{Path, PathBuf},
-    sync::Arc,
+    fmt,
+    ops::Range,
+    sync::{atomic::Ordering, Arc},
 };
 
-use anyhow::Result;
+/// 分析エンジンのエラー
+///
+/// 手牌や残り巡数の誤りは400、読み出しの失敗は500として返す。
+#[derive(Debug)]
+pub enum AnalyzerError {
+    /// 手牌が13枚でも14枚でもない
+    InvalidHandLength(usize),
+    /// 残り巡数が手牌の枚数に対して範囲外
+    InvalidDrawsLeft(usize),
+    /// 手牌を正規形にエンコードできない（同じ牌が5枚以上など）
+    EncodingFailed(String),
+    /// データファイルの読み出しに失敗した
+    IoError(anyhow::Error),
+    /// 必要なデータファイルが設定されていない（聴牌率など、任意のデータファイル）
+    Unavailable(&'static str),
+}
+
+type Result<T, E = AnalyzerError> = std::result::Result<T, E>;
+
+impl fmt::Display for AnalyzerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnalyzerError::InvalidHandLength(n) => write!(f, "Invalid hand length: {}", n),
+            AnalyzerError::InvalidDrawsLeft(n) => write!(f, "Invalid draws_left: {}", n),
+            AnalyzerError::EncodingFailed(e) => write!(f, "Failed to encode hand: {}", e),
+            AnalyzerError::IoError(e) => write!(f, "Failed to read data file: {}", e),
+            AnalyzerError::Unavailable(name) => write!(f, "{} tables are not configured", name),
+        }
+    }
+}
+
+impl std::error::Error for AnalyzerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AnalyzerError::IoError(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// データファイルの読み出しエラー
+impl From<anyhow::Error> for AnalyzerError {
+    fn from(e: anyhow::Error) -> Self {
+        AnalyzerError::IoError(e)
+    }
+}
+
+impl From<AnalyzerError> for ApiError {
+    fn from(e: AnalyzerError) -> Self {
+        let (status, error, code) = match &e {
+            AnalyzerError::InvalidHandLength(n) => {
+                return InvalidParams(vec![FieldError::new(
+                    "hand",
+                    format!("must have 13 or 14 tiles, got {}", n),
+                )])
+                .into()
+            }
+            AnalyzerError::InvalidDrawsLeft(n) => {
+                return InvalidParams(vec![FieldError::new(
+                    "draws_left",
+                    format!("out of range for this hand: {}", n),
+                )])
+                .into()
+            }
+            AnalyzerError::EncodingFailed(reason) => {
+                return InvalidParams(vec![FieldError::new("hand", reason.clone())]).into()
+            }
+            AnalyzerError::IoError(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to read data file",
+                "INTERNAL_SERVER_ERROR",
+            ),
+            AnalyzerError::Unavailable(_) => (
+                StatusCode::NOT_FOUND,
+                "Data file not configured",
+                "NOT_FOUND",
+            ),
+        };
+        (
+            status,
+            JsonResponse(ErrorResponse {
+                error: error.to_string(),
+                code: code.to_string(),
+                message: e.to_string(),
+                details: Vec::new(),
+                request_id: current_request_id(),
+            }),
+        )
+    }
+}
+
+impl IntoResponse for AnalyzerError {
+    fn into_response(self) -> Response {
+        ApiError::from(self).into_response()
+    }
+}
+
+/// converterのエンコードの失敗を`EncodingFailed`にする
+fn encoding_failed(e: anyhow::Error) -> AnalyzerError {
+    AnalyzerError::EncodingFailed(e.to_string())
+}
 
-#[derive(Debug, Serialize)]
+/// 手牌を正規形にエンコードできるかを確かめる。枚数や同じ牌の数が不正な手牌を、converterに渡す前に分かりやすいエラーにする
+fn check_encodable(hand: &[Tile]) -> Result<()> {
+    if hand.len() != 13 && hand.len() != 14 {
+        return Err(AnalyzerError::InvalidHandLength(hand.len()));
+    }
+    validate_hand_tiles(hand).map_err(|e| AnalyzerError::EncodingFailed(e.to_string()))
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct TsumoAnalysis {
     pub probabilities: Vec<TsumoProbability>,
+    /// `fixed_point=true`のときのみ。`raw`をこの値で割ると確率になる
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale: Option<u64>,
+    /// `include_canonical=true`のときのみ
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub canonical: Option<CanonicalHand>,
+    /// 手牌に赤5（0m/0p/0s）を含むときのみ。その枚数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aka_count: Option<usize>,
+    /// 手牌に暗槓を含むときのみtrue。暗槓を刻子とみなし、4枚目をツモれるものとして
+    /// 嶺上牌を考えずに近似した結果であることを表す
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kan_approximated: Option<bool>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema, SimpleObject)]
 pub struct TsumoProbability {
     pub draws_left: u32,
     pub probability: f64,
+    /// `fixed_point=true`のときのみ。データファイルに保存されている固定小数点の値
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[graphql(skip)]
+    pub raw: Option<u32>,
+    /// 一般形（4メンツ1雀頭）のみを和了とみなしたツモ率。`tsumo_family_dir`を設定したときのみ
+    ///
+    /// 和了形ごとにその和了形だけを目指す最善の打牌をしたときの確率なので、3つの和は`probability`と一致しない
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p_standard: Option<f64>,
+    /// 七対子のみを和了とみなしたツモ率
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p_chiitoi: Option<f64>,
+    /// 国士無双のみを和了とみなしたツモ率
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p_kokushi: Option<f64>,
+    /// 和了したときの、和了までのツモ回数の期待値。`tsumo_13_turns_path`などを設定し、ツモ率が0でないときのみ
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_draws: Option<f64>,
+}
+
+/// 13枚の手牌が何回目のツモで和了するかの分布
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct WinTurnAnalysis {
+    pub draws_left: u32,
+    /// 残り巡数のうちに和了する確率（`/analyze-tsumo`のツモ率）。`pmf`の和
+    pub total_probability: f64,
+    /// 1回目のツモから順に、そのツモでちょうど和了する確率
+    pub pmf: Vec<WinTurnProbability>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct WinTurnProbability {
+    /// 何回目のツモか（1〜`draws_left`）
+    pub draw: u32,
+    pub probability: f64,
+}
+
+/// 流局時の聴牌率の分析結果
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TenpaiAnalysis {
+    pub probabilities: Vec<TenpaiProbability>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TenpaiProbability {
+    pub draws_left: u32,
+    /// 残り巡数をすべてツモったあと（流局時）に聴牌している確率
+    pub probability: f64,
 }
 
 /// メンツ実現確率分析結果
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct MentsuAnalysis {
     pub probabilities: Vec<MentsuProbability>,
+    /// `fixed_point=true`のときのみ。`raw`をこの値で割ると確率になる
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale: Option<u64>,
+    /// `include_canonical=true`のときのみ
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub canonical: Option<CanonicalHand>,
+    /// 手牌に赤5（0m/0p/0s）を含むときのみ。その枚数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aka_count: Option<usize>,
+    /// 手牌に暗槓を含むときのみtrue。暗槓を刻子とみなし、4枚目をツモれるものとして
+    /// 嶺上牌を考えずに近似した結果であることを表す
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kan_approximated: Option<bool>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema, SimpleObject)]
 pub struct MentsuProbability {
     pub mentsu_type: String,
     pub probability: f64,
+    /// `fixed_point=true`のときのみ。データファイルに保存されている固定小数点の値
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[graphql(skip)]
+    pub raw: Option<u32>,
+}
+
+/// メンツ実現確率の並び順
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// メンツ実現確率の絞り込み・並べ替えの条件
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MentsuFilter {
+    /// この確率未満のメンツを除く
+    pub min_probability: Option<f64>,
+    /// 確率で並べ替える。省略時はメンツの種類順のまま
+    pub sort: Option<SortOrder>,
+    /// 並べ替えの後、先頭からこの件数だけ残す
+    pub top_k: Option<usize>,
+}
+
+/// ツモ率の固定小数点の分母
+pub const TSUMO_SCALE: u64 = 1 << 32;
+
+/// メンツ実現確率の固定小数点の分母
+pub const METRICS_SCALE: u64 = 1 << 30;
+
+impl TsumoAnalysis {
+    /// 各確率に固定小数点の値を付ける
+    ///
+    /// 確率は32bitの値を2のべき乗で割ったものなので、f64から誤差なく元の値に戻せる。
+    pub fn fill_fixed_point(&mut self) {
+        for p in &mut self.probabilities {
+            p.raw = Some((p.probability * TSUMO_SCALE as f64) as u32);
+        }
+        self.scale = Some(TSUMO_SCALE);
+    }
+
+    /// 正規形の手牌をUnicodeの麻雀牌で表す
+    pub fn render_unicode(&mut self) {
+        if let Some(canonical) = &mut self.canonical {
+            canonical.render_unicode();
+        }
+    }
+}
+
+impl MentsuAnalysis {
+    /// 各確率に固定小数点の値を付ける
+    pub fn fill_fixed_point(&mut self) {
+        for p in &mut self.probabilities {
+            p.raw = Some((p.probability * METRICS_SCALE as f64) as u32);
+        }
+        self.scale = Some(METRICS_SCALE);
+    }
+
+    /// メンツの種類と正規形の手牌をUnicodeの麻雀牌で表す
+    pub fn render_unicode(&mut self) {
+        for p in &mut self.probabilities {
+            p.mentsu_type = unicode_tiles(&p.mentsu_type);
+        }
+        if let Some(canonical) = &mut self.canonical {
+            canonical.render_unicode();
+        }
+    }
+
+    /// 条件に合うメンツだけを残す
+    pub fn apply_filter(&mut self, filter: &MentsuFilter) {
+        if let Some(min_probability) = filter.min_probability {
+            self.probabilities
+                .retain(|p| p.probability >= min_probability);
+        }
+        match filter.sort {
+            Some(SortOrder::Asc) => self
+                .probabilities
+                .sort_by(|a, b| a.probability.total_cmp(&b.probability)),
+            Some(SortOrder::Desc) => self
+                .probabilities
+                .sort_by(|a, b| b.probability.total_cmp(&a.probability)),
+            None => {}
+        }
+        if let Some(top_k) = filter.top_k {
+            self.probabilities.truncate(top_k);
+        }
+    }
+}
+
+/// 残り巡数ごとのメンツ実現確率
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct MentsuRoundAnalysis {
+    pub draws_left: u32,
+    #[serde(flatten)]
+    pub analysis: MentsuAnalysis,
+}
+
+/// 全巡数のメンツ実現確率
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct MentsuRoundsAnalysis {
+    pub rounds: Vec<MentsuRoundAnalysis>,
+    /// `include_canonical=true`のときのみ
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub canonical: Option<CanonicalHand>,
+    /// 手牌に赤5（0m/0p/0s）を含むときのみ。その枚数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aka_count: Option<usize>,
+    /// 手牌に暗槓を含むときのみtrue。暗槓を刻子とみなし、4枚目をツモれるものとして
+    /// 嶺上牌を考えずに近似した結果であることを表す
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kan_approximated: Option<bool>,
+}
+
+impl MentsuRoundsAnalysis {
+    /// メンツの種類と正規形の手牌をUnicodeの麻雀牌で表す
+    pub fn render_unicode(&mut self) {
+        for round in &mut self.rounds {
+            round.analysis.render_unicode();
+        }
+        if let Some(canonical) = &mut self.canonical {
+            canonical.render_unicode();
+        }
+    }
+}
+
+/// 13枚の手牌に1枚ツモったときの和了確率
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DrawTsumoAnalysis {
+    /// ツモる牌
+    pub tile: String,
+    /// 山に残っている枚数（4枚から手牌にある枚数を引いたもの）
+    pub remaining: u32,
+    /// ツモる前の13枚の手牌の残り巡数（1〜18）ごとの、この牌をツモったときの和了確率。
+    /// `include_canonical=true`のときはツモ後の14枚の手牌の正規形を含める
+    #[serde(flatten)]
+    pub analysis: TsumoAnalysis,
+}
+
+impl DrawTsumoAnalysis {
+    /// ツモる牌と正規形の手牌をUnicodeの麻雀牌で表す
+    pub fn render_unicode(&mut self) {
+        self.tile = unicode_tiles(&self.tile);
+        self.analysis.render_unicode();
+    }
+}
+
+/// `/analyze-tsumo-draws`のレスポンス
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TsumoDrawsAnalysis {
+    /// ツモりうる牌。萬子・筒子・索子・字牌の順に並べる
+    pub draws: Vec<DrawTsumoAnalysis>,
+}
+
+/// 14枚の手牌から1枚捨てたときのメンツ実現確率
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DiscardMentsuAnalysis {
+    /// 捨てる牌
+    pub discard: String,
+    /// 打牌後の13枚の手牌
+    pub hand: String,
+    /// 打牌後の残り巡数ごとのメンツ実現確率
+    pub rounds: Vec<MentsuRoundAnalysis>,
+    /// `include_canonical=true`のときのみ。打牌後の手牌の正規形
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub canonical: Option<CanonicalHand>,
+}
+
+impl DiscardMentsuAnalysis {
+    /// 牌・手牌・メンツの種類をUnicodeの麻雀牌で表す
+    pub fn render_unicode(&mut self) {
+        self.discard = unicode_tiles(&self.discard);
+        self.hand = unicode_tiles(&self.hand);
+        for round in &mut self.rounds {
+            round.analysis.render_unicode();
+        }
+        if let Some(canonical) = &mut self.canonical {
+            canonical.render_unicode();
+        }
+    }
+}
+
+/// `/analyze-mentsu-discards`のレスポンス
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct MentsuDiscardsAnalysis {
+    /// 打牌候補。同じ牌は1つにまとめ、手牌に現れた順に並べる
+    pub discards: Vec<DiscardMentsuAnalysis>,
+}
+
+/// `/analyze-mentsu`のレスポンス。`draws_left=all`のときは全巡数を返す
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum MentsuResponse {
+    Single(MentsuAnalysis),
+    AllRounds(MentsuRoundsAnalysis),
+}
+
+/// ツモ率とメンツ実現確率をまとめた分析結果
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CombinedAnalysis {
+    pub tsumo: TsumoAnalysis,
+    pub mentsu: Vec<MentsuRoundAnalysis>,
+    /// `include_canonical=true`のときのみ
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub canonical: Option<CanonicalHand>,
+    /// 手牌に赤5（0m/0p/0s）を含むときのみ。その枚数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aka_count: Option<usize>,
+    /// 手牌に暗槓を含むときのみtrue。暗槓を刻子とみなし、4枚目をツモれるものとして
+    /// 嶺上牌を考えずに近似した結果であることを表す
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kan_approximated: Option<bool>,
+}
+
+impl CombinedAnalysis {
+    /// ツモ率と各巡数のメンツ実現確率に固定小数点の値を付ける
+    pub fn fill_fixed_point(&mut self) {
+        self.tsumo.fill_fixed_point();
+        for round in &mut self.mentsu {
+            round.analysis.fill_fixed_point();
+        }
+    }
+
+    /// メンツの種類と正規形の手牌をUnicodeの麻雀牌で表す
+    pub fn render_unicode(&mut self) {
+        self.tsumo.render_unicode();
+        for round in &mut self.mentsu {
+            round.analysis.render_unicode();
+        }
+        if let Some(canonical) = &mut self.canonical {
+            canonical.render_unicode();
+        }
+    }
+}
+
+/// 手牌がエンコードされた正規形。データファイルはこの形で引かれる
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CanonicalHand {
+    /// 正規形の手牌。字牌は枚数の多い順に1z, 2z, ...と振り直したもの
+    pub hand: String,
+    /// 正規形の手牌インデックス
+    pub hand_id: u32,
+    /// 正規形の各スートが元の手牌のどのスートか。負の値は数字が反転していることを表し、ビット反転すると元のスートになる
+    pub translation: [i8; 3],
+}
+
+impl CanonicalHand {
+    /// 正規形の手牌をUnicodeの麻雀牌で表す
+    pub fn render_unicode(&mut self) {
+        self.hand = unicode_tiles(&self.hand);
+    }
+}
+
+/// 向聴数分析結果。和了形は-1、聴牌は0
+#[derive(Debug, Clone, Serialize, ToSchema, SimpleObject)]
+pub struct ShantenAnalysis {
+    pub standard: i8,
+    pub chiitoitsu: i8,
+    pub kokushi: i8,
+}
+
+/// ランダムに生成した手牌（`/random-hand`）
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RandomHand {
+    /// mpsz表記の手牌
+    pub hand: String,
+    /// 3種類のうち最小の向聴数
+    pub shanten: i8,
+    /// 生成に使ったシード。同じシードと条件で同じ手牌を再現できる
+    pub seed: u64,
+}
+
+impl RandomHand {
+    /// `seed`で決まる手牌を、同じ枚数のありうる手牌から一様に選ぶ。
+    /// `target`を指定したときは、その向聴数の手牌が出るまで引き直す
+    pub fn generate(seed: u64, num_tiles: usize, target: Option<i8>) -> anyhow::Result<Self> {
+        let hand = random_hand(seed, num_tiles, target)?;
+        Ok(Self {
+            hand: format_tiles(&hand.to_tiles()),
+            shanten: shanten(&hand.to_hand()).min(),
+            seed,
+        })
+    }
+}
+
+/// 手牌の評価値（`/score`）
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct HandScore {
+    /// 0〜1の総合評価。高いほど和了に近い
+    pub score: f64,
+    /// 3種類のうち最小の向聴数
+    pub shanten: i8,
+    /// 有効牌の枚数。14枚の手牌では最も有効牌の多い打牌の後の枚数
+    pub ukeire: u32,
+    /// 全巡数のツモ率の平均
+    pub mean_tsumo_probability: f64,
+    /// 総合評価の内訳
+    pub components: ScoreComponents,
+}
+
+/// 総合評価の内訳。それぞれ0〜1に正規化した値で、重みを掛けて足したものが総合評価になる
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ScoreComponents {
+    /// 向聴数が-1（和了形）で1、6以上で0
+    pub shanten: f64,
+    /// 有効牌の枚数を見えていない牌の枚数で割ったもの
+    pub ukeire: f64,
+    /// 全巡数のツモ率の平均
+    pub tsumo: f64,
+}
+
+/// 総合評価での向聴数・有効牌・ツモ率の重み
+const SCORE_WEIGHTS: (f64, f64, f64) = (0.2, 0.2, 0.6);
+
+/// 和了したときの打点の見積もり（`/analyze-value`）
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ValueAnalysis {
+    pub draws_left: u32,
+    /// 残り巡数のうちに和了する確率
+    pub win_probability: f64,
+    /// 和了したときの翻数の期待値。ドラを含む
+    pub expected_han: f64,
+    /// 和了したときの手牌のドラの枚数（ドラ表示牌が同じ牌を指すときは重ねて数える）の期待値。
+    /// ドラ表示牌を指定しなければ0
+    pub expected_dora: f64,
+    /// 和了したときの点数の期待値
+    pub expected_points: f64,
+    /// 和了率×和了したときの点数の期待値
+    pub expected_value: f64,
+    /// 和了したときの和了形の内訳
+    pub shapes: AgariShapes,
+}
+
+/// 和了したときに各和了形になる確率。合計は1
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AgariShapes {
+    /// 4面子1雀頭
+    pub standard: f64,
+    pub chiitoitsu: f64,
+    pub kokushi: f64,
+}
+
+/// 役ごとの和了確率の見積もり（`/analyze-yaku`）
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct YakuAnalysis {
+    pub draws_left: u32,
+    /// 残り巡数のうちに和了する確率
+    pub win_probability: f64,
+    pub yaku: Vec<YakuProbability>,
+}
+
+/// 残り巡数のうちにその役を含む形で和了する確率の範囲
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct YakuProbability {
+    /// 役の名前（例: tanyao, yakuhai_haku, chiitoitsu）
+    pub yaku: String,
+    pub lower_bound: f64,
+    pub upper_bound: f64,
+}
+
+/// ツモ率走査結果の1エントリ
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TsumoScanEntry {
+    pub hand_index: u32,
+    pub hand: String,
+    pub probability: f64,
+}
+
+/// データファイル1つ分の検査結果
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FileHealth {
+    pub name: String,
+    pub ok: bool,
+    /// 期待される要素数
+    pub expected_len: usize,
+    /// 実際の要素数。開けなかった場合は`null`
+    pub actual_len: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// 既知の手牌を使った検査の結果
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SampleHealth {
+    pub name: String,
+    pub hand: String,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// データファイルの詳細なヘルスチェック結果
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeepHealth {
+    pub ok: bool,
+    pub files: Vec<FileHealth>,
+    pub samples: Vec<SampleHealth>,
+}
+
+/// データファイルからの読み出しの統計
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReadStats {
+    pub name: String,
+    pub path: String,
+    /// 読み出し中の数
+    pub in_flight: usize,
+    /// 読み出しに失敗した回数
+    pub failures: u64,
+    /// 1回の読み出しにかかった時間
+    pub latency: HistogramSnapshot,
+}
+
+/// 読み込んでいるデータファイル
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DatasetFile {
+    pub name: String,
+    pub path: String,
+    /// ファイルサイズ（バイト）。取得できなかった場合は`null`
+    pub bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// 和了形の14枚手牌。ツモ率はすべての巡数で1になる
+const SAMPLE_AGARI_HAND: &str = "123m456p789s11122z";
+
+/// 聴牌の13枚手牌。ツモ率は残り巡数が多いほど高くなる
+const SAMPLE_TENPAI_HAND: &str = "123m456p789s1122z";
+
+/// DPの定義から厳密に求まるツモ率
+///
+/// DPは毎巡123枚（136-13枚）の山から1枚ツモるとみなすため、残り1巡で和了牌が`n`枚あれば
+/// ツモ率は`n/123`、固定小数点では`floor(n * 2^32 / 123)`になる。和了形は`u32::MAX`に飽和する。
+struct GoldenTsumo {
+    name: &'static str,
+    hand: &'static str,
+    draws_left: u32,
+    raw: u32,
+}
+
+const GOLDEN_TSUMO: [GoldenTsumo; 5] = [
+    // 1z・2zのシャンポン待ち（残り4枚）
+    GoldenTsumo {
+        name: "golden_tsumo_13_shanpon",
+        hand: SAMPLE_TENPAI_HAND,
+        draws_left: 1,
+        raw: 139673733,
+    },
+    // 1m・4mの両面待ち（1mは手牌に1枚あるので残り7枚）
+    GoldenTsumo {
+        name: "golden_tsumo_13_ryanmen",
+        hand: "12323m456p789s11z",
+        draws_left: 1,
+        raw: 244429033,
+    },
+    // 3zを捨てるとシャンポン待ち
+    GoldenTsumo {
+        name: "golden_tsumo_14_tenpai",
+        hand: "123m456p789s11223z",
+        draws_left: 1,
+        raw: 139673733,
+    },
+    GoldenTsumo {
+        name: "golden_tsumo_14_noten",
+        hand: "123m456p789s11223z",
+        draws_left: 0,
+        raw: 0,
+    },
+    GoldenTsumo {
+        name: "golden_tsumo_14_agari",
+        hand: SAMPLE_AGARI_HAND,
+        draws_left: 0,
+        raw: u32::MAX,
+    },
+];
+
+/// 保存されている生のメトリクス
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RawMetrics {
+    /// 正規形の手牌インデックス
+    pub hand_id: u32,
+    /// 正規形の各スートが元の手牌のどのスートか。負の値は数字が反転していることを表し、ビット反転すると元のスートになる
+    pub translation: [i8; 3],
+    /// 元の手牌の字牌（1z〜7z）ごとの枚数
+    pub jihai_counts: [usize; 7],
+    /// 各次元の正規形でのラベル（`values`と同じ順）
+    pub labels: Vec<String>,
+    pub rounds: Vec<RawMetricsRound>,
+}
+
+/// 残り巡数ごとの生のメトリクス。確率は値を2^30で割ったもの
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RawMetricsRound {
+    pub draws_left: u32,
+    pub values: Vec<u32>,
+}
+
+/// メトリクスファイルから読み出した手牌1つ分の行
+struct MetricsRows {
+    hand_id: u32,
+    trans: [i8; 3],
+    jihai_cnt: [usize; 7],
+    draws: Range<usize>,
+    metrics: Vec<Metrics>,
 }
 
 /// 共有可能な手牌分析エンジン
 #[derive(Clone)]
 pub struct SharedHandAnalyzer {
     converter: Arc<HandConverter>,
-    // ツモ率データファイル用プール（13枚用）
-    tsumo_13_pool: Arc<FlatFileVecPool<u32>>,
-    // ツモ率データファイル用プール（14枚用）
-    tsumo_14_pool: Arc<FlatFileVecPool<u32>>,
-    // メトリクスデータファイル用プール（13枚用）
-    metrics_13_pool: Arc<FlatFileVecPool<Metrics>>,
-    // メトリクスデータファイル用プール（14枚用）
-    metrics_14_pool: Arc<FlatFileVecPool<Metrics>>,
+    // ツモ率データファイル（13枚用）
+    tsumo_13: Arc<dyn TableSource<u32>>,
+    // ツモ率データファイル（14枚用）
+    tsumo_14: Arc<dyn TableSource<u32>>,
+    // メトリクスデータファイル（13枚用）
+    metrics_13: Arc<dyn TableSource<Metrics>>,
+    // メトリクスデータファイル（14枚用）
+    metrics_14: Arc<dyn TableSource<Metrics>>,
+    // 読み出した行のキャッシュ
+    cache: Arc<RowCache>,
+    // 和了形ごとのツモ率データファイル。`with_tsumo_families`で設定したときのみ
+    tsumo_families: Option<TsumoFamilies>,
+    // 聴牌率データファイル。`with_tenpai`で設定したときのみ
+    tenpai: Option<TablePair>,
+    // 和了したときの残り巡数の期待値のデータファイル。`with_turns`で設定したときのみ
+    turns: Option<TablePair>,
+    // 副露した手牌のツモ率データファイル。`with_open_tsumo`で設定したときのみ
+    open_tsumo: Vec<OpenTsumoTable>,
+}
+
+/// 副露した手牌の門前の牌の枚数。副露1〜3個で、ツモる前（3n+1枚）とツモった後（3n+2枚）
+pub const OPEN_HAND_TILES: [usize; 6] = [4, 5, 7, 8, 10, 11];
+
+/// 門前の牌が1種類の枚数の手牌のツモ率データファイルと、その手牌の番号の振り方
+#[derive(Clone)]
+struct OpenTsumoTable {
+    space: Arc<HandSpace>,
+    table: Arc<dyn TableSource<u32>>,
+}
+
+/// 和了形ごとのツモ率データファイル。それぞれ`AgariFamily::ALL`の順
+#[derive(Clone)]
+struct TsumoFamilies {
+    tsumo_13: Vec<Arc<dyn TableSource<u32>>>,
+    tsumo_14: Vec<Arc<dyn TableSource<u32>>>,
+}
+
+/// 13枚用・14枚用の1組の任意のデータファイル。行の並びはツモ率データファイルと同じ
+#[derive(Clone)]
+struct TablePair {
+    table_13: Arc<dyn TableSource<u32>>,
+    table_14: Arc<dyn TableSource<u32>>,
+}
+
+impl TablePair {
+    /// 読み出し方はツモ率データファイルと同じ
+    fn open(path_13: &str, path_14: &str, options: &TableOptions) -> Result<Self> {
+        let modes = options.io_modes;
+        Ok(TablePair {
+            table_13: open_table(path_13, modes.tsumo_13, options)?,
+            table_14: open_table(path_14, modes.tsumo_14, options)?,
+        })
+    }
+}
+
+/// 副露した手牌のツモ率データファイルのパス。`num_tiles`は門前の牌の枚数
+pub fn open_tsumo_path(dir: &str, num_tiles: usize) -> String {
+    format!("{}/tsumo_{}.dat", dir.trim_end_matches('/'), num_tiles)
+}
+
+/// 和了形ごとのツモ率データファイルのパス（`{dir}/tsumo_13_standard.dat`など）
+pub fn tsumo_family_path(dir: &str, num_tiles: usize, family: AgariFamily) -> String {
+    format!(
+        "{}/tsumo_{}_{}.dat",
+        dir.trim_end_matches('/'),
+        num_tiles,
+        family.as_str()
+    )
 }
 
 impl SharedHandAnalyzer {
     /// 新しい共有分析エンジンを作成
+    ///
+    /// converterはデータセット間で共有できるよう、呼び出し側で読み込んだものを受け取る。
+    /// データファイルは`options.io_modes`に従ってファイルハンドル・メモリマップ・メモリへの読み込みのいずれかで読み出す。
+    /// `s3://`や`gs://`で始まるパスはオブジェクトストレージから読み出す
     pub fn new(
-        conv_path: impl AsRef<Path>,
-        tsumo_13_path: impl Into<PathBuf>,
-        tsumo_14_path: impl Into<PathBuf>,
-        metrics_13_path: impl Into<PathBuf>,
-        metrics_14_path: impl Into<PathBuf>,
-        max_pool_size: usize,
+        converter: Arc<HandConverter>,
+        tsumo_13_path: &str,
+        tsumo_14_path: &str,
+        metrics_13_path: &str,
+        metrics_14_path: &str,
+        options: &TableOptions,
     ) -> Result<Self> {
-        // HandConverterを読み込み
-        let converter = HandConverter::load_from_file(conv_path)?;
-        let tsumo_13_pool = create_flat_file_vec_pool(tsumo_13_path, max_pool_size)?;
-        let tsumo_14_pool = create_flat_file_vec_pool(tsumo_14_path, max_pool_size)?;
-        let metrics_13_pool = create_flat_file_vec_pool(metrics_13_path, max_pool_size)?;
-        let metrics_14_pool = create_flat_file_vec_pool(metrics_14_path, max_pool_size)?;
+        let modes = options.io_modes;
+        Ok(Self::from_sources(
+            converter,
+            open_table(tsumo_13_path, modes.tsumo_13, options)?,
+            open_table(tsumo_14_path, modes.tsumo_14, options)?,
+            open_table(metrics_13_path, modes.metrics_13, options)?,
+            open_table(metrics_14_path, modes.metrics_14, options)?,
+        ))
+    }
 
-        Ok(SharedHandAnalyzer {
-            converter: Arc::new(converter),
-            // プールは後で追加する予定
-            tsumo_13_pool: Arc::new(tsumo_13_pool),
-            tsumo_14_pool: Arc::new(tsumo_14_pool),
-            metrics_13_pool: Arc::new(metrics_13_pool),
-            metrics_14_pool: Arc::new(metrics_14_pool),
-        })
+    /// 任意の置き場所から行を読み出す分析エンジンを作成
+    pub fn from_sources(
+        converter: Arc<HandConverter>,
+        tsumo_13: Arc<dyn TableSource<u32>>,
+        tsumo_14: Arc<dyn TableSource<u32>>,
+        metrics_13: Arc<dyn TableSource<Metrics>>,
+        metrics_14: Arc<dyn TableSource<Metrics>>,
+    ) -> Self {
+        SharedHandAnalyzer {
+            converter,
+            tsumo_13,
+            tsumo_14,
+            metrics_13,
+            metrics_14,
+            cache: Arc::new(RowCache::new(0)),
+            tsumo_families: None,
+            tenpai: None,
+            turns: None,
+            open_tsumo: Vec::new(),
+        }
+    }
+
+    /// 和了形ごとのツモ率データファイルを`dir`から読み込み、ツモ率に内訳を含めるようにする
+    ///
+    /// ファイル名は[`tsumo_family_path`]のとおりで、dpが出力する`tsumo_13_standard.dat`などをそのまま置けばよい。
+    /// 読み出し方は通常のツモ率データファイルと同じ
+    pub fn with_tsumo_families(mut self, dir: &str, options: &TableOptions) -> Result<Self> {
+        let modes = options.io_modes;
+        let open = |num_tiles, mode| {
+            AgariFamily::ALL
+                .iter()
+                .map(|&family| {
+                    open_table(&tsumo_family_path(dir, num_tiles, family), mode, options)
+                })
+                .collect::<anyhow::Result<Vec<_>>>()
+        };
+        self.tsumo_families = Some(TsumoFamilies {
+            tsumo_13: open(13, modes.tsumo_13)?,
+            tsumo_14: open(14, modes.tsumo_14)?,
+        });
+        Ok(self)
+    }
+
+    /// 聴牌率データファイルを読み込み、聴牌率を分析できるようにする
+    ///
+    /// dpが出力する`tenpai_13.dat`・`tenpai_14.dat`で、形式はツモ率データファイルと同じ。
+    /// 読み出し方はツモ率データファイルと同じ
+    pub fn with_tenpai(
+        mut self,
+        tenpai_13_path: &str,
+        tenpai_14_path: &str,
+        options: &TableOptions,
+    ) -> Result<Self> {
+        self.tenpai = Some(TablePair::open(tenpai_13_path, tenpai_14_path, options)?);
+        Ok(self)
+    }
+
+    /// 和了したときの残り巡数の期待値のデータファイルを読み込み、ツモ率に和了までのツモ回数の期待値を含めるようにする
+    ///
+    /// dpが出力する`tsumo_13_turns.dat`・`tsumo_14_turns.dat`で、値は`TURNS_SCALE`倍の固定小数点
+    pub fn with_turns(
+        mut self,
+        turns_13_path: &str,
+        turns_14_path: &str,
+        options: &TableOptions,
+    ) -> Result<Self> {
+        self.turns = Some(TablePair::open(turns_13_path, turns_14_path, options)?);
+        Ok(self)
+    }
+
+    /// 副露した手牌のツモ率データファイルを`dir`から読み込み、副露1〜3個の手牌のツモ率を分析できるようにする
+    ///
+    /// ファイル名は[`open_tsumo_path`]のとおりで、dpが出力する`tsumo_10.dat`などをそのまま置けばよい。
+    /// 手牌の番号の振り方はconverterから作る。読み出し方は13枚用・14枚用と同じ
+    pub fn with_open_tsumo(mut self, dir: &str, options: &TableOptions) -> Result<Self> {
+        let modes = options.io_modes;
+        self.open_tsumo = OPEN_HAND_TILES
+            .iter()
+            .map(|&num_tiles| {
+                let mode = if num_tiles % 3 == 1 {
+                    modes.tsumo_13
+                } else {
+                    modes.tsumo_14
+                };
+                Ok(OpenTsumoTable {
+                    space: Arc::new(self.converter.hand_space(num_tiles)),
+                    table: open_table(&open_tsumo_path(dir, num_tiles), mode, options)?,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(self)
+    }
+
+    /// 門前の牌が`num_tiles`枚の副露した手牌のツモ率データファイル
+    fn open_tsumo_table(&self, num_tiles: usize) -> Result<&OpenTsumoTable> {
+        self.open_tsumo
+            .iter()
+            .find(|open| open.space.num_tiles() == num_tiles)
+            .ok_or(AnalyzerError::Unavailable("open hand tsumo"))
+    }
+
+    /// 読み出した行を`capacity`行までキャッシュするようにする
+    pub fn with_cache_capacity(mut self, capacity: usize) -> Self {
+        self.cache = Arc::new(RowCache::new(capacity));
+        self
+    }
+
+    /// 各データファイルの先頭と末尾の行を読んでおき、データファイルの名前ごとの結果を返す
+    pub async fn warm_up(&self) -> Vec<(&'static str, Result<()>)> {
+        let (tsumo_13, tsumo_14, metrics_13, metrics_14) = tokio::join!(
+            self.tsumo_13.warm_up(),
+            self.tsumo_14.warm_up(),
+            self.metrics_13.warm_up(),
+            self.metrics_14.warm_up(),
+        );
+        vec![
+            ("tsumo_13", tsumo_13.map_err(Into::into)),
+            ("tsumo_14", tsumo_14.map_err(Into::into)),
+            ("metrics_13", metrics_13.map_err(Into::into)),
+            ("metrics_14", metrics_14.map_err(Into::into)),
+        ]
+    }
+
+    /// ファイルハンドルから読み出すデータファイルの統計。メモリマップやメモリに読み込んだものは含めない
+    pub fn read_stats(&self) -> Vec<ReadStats> {
+        [
+            read_stats("tsumo_13", &self.tsumo_13),
+            read_stats("tsumo_14", &self.tsumo_14),
+            read_stats("metrics_13", &self.metrics_13),
+            read_stats("metrics_14", &self.metrics_14),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    /// 読み込んでいるデータファイルのパスとサイズ
+    pub fn dataset_files(&self) -> Vec<DatasetFile> {
+        vec![
+            dataset_file("tsumo_13", &self.tsumo_13),
+            dataset_file("tsumo_14", &self.tsumo_14),
+            dataset_file("metrics_13", &self.metrics_13),
+            dataset_file("metrics_14", &self.metrics_14),
+        ]
+    }
+
+    /// 読み出した行のキャッシュの統計情報
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// 読み出した行のキャッシュをすべて捨て、捨てた行数を返す
+    pub fn clear_cache(&self) -> usize {
+        self.cache.clear()
     }
 
     /// 手牌を分析してツモ率を計算
     pub async fn analyze_tsumo(&self, hand: &[Tile]) -> Result<TsumoAnalysis> {
+        if OPEN_HAND_TILES.contains(&hand.len()) {
+            return self.analyze_open_tsumo(hand).await;
+        }
+        check_encodable(hand)?;
         let probs;
         let hand_id;
         if hand.len() == 13 {
-            hand_id = self.converter.encode_hand13_fast(&Hand::from_tiles(hand)) as usize;
+            hand_id = measure(Stage::Encode, || {
+                self.converter.try_encode_hand13(&Hand::from_tiles(hand))
+            })
+            .map_err(encoding_failed)?
+            .0 as usize;
             probs = self
-                .tsumo_13_pool
-                .get()
-                .await
-                .map_err(|e| anyhow::anyhow!("Failed to get pool: {}", e))?
-                .get_range(hand_id * NUM_ROUNDS, (hand_id + 1) * NUM_ROUNDS)?;
+                .cache
+                .tsumo(TableKind::Tsumo13, hand_id as u32, 0..NUM_ROUNDS, async {
+                    measure_async(Stage::Read, self.tsumo_13.get_row(hand_id, 0..NUM_ROUNDS)).await
+                })
+                .await?;
         } else if hand.len() == 14 {
-            hand_id = self.converter.encode_hand14_fast(&Hand::from_tiles(hand)) as usize;
+            hand_id = measure(Stage::Encode, || {
+                self.converter.try_encode_hand14(&Hand::from_tiles(hand))
+            })
+            .map_err(encoding_failed)?
+            .0 as usize;
             probs = self
-                .tsumo_14_pool
-                .get()
-                .await
-                .map_err(|e| anyhow::anyhow!("Failed to get pool: {}", e))?
-                .get_range(hand_id * NUM_ROUNDS, (hand_id + 1) * NUM_ROUNDS)?;
+                .cache
+                .tsumo(TableKind::Tsumo14, hand_id as u32, 0..NUM_ROUNDS, async {
+                    measure_async(Stage::Read, self.tsumo_14.get_row(hand_id, 0..NUM_ROUNDS)).await
+                })
+                .await?;
         } else {
-            return Err(anyhow::anyhow!("Invalid hand length: {}", hand.len()));
+            return Err(AnalyzerError::InvalidHandLength(hand.len()));
         }
 
-        let probabilities = if hand.len() == 13 {
-            probs
-                .into_iter()
-                .enumerate()
-                .map(|(round, p)| TsumoProbability {
-                    draws_left: (round as u32) + 1,
-                    probability: (p as f64) / 2f64.powi(32),
-                })
-                .collect()
-        } else {
-            probs
-                .into_iter()
-                .enumerate()
-                .map(|(round, p)| TsumoProbability {
-                    draws_left: round as u32,
+        // 和了形ごとの内訳。要求されることが少ないため行はキャッシュしない
+        let family_rows = match &self.tsumo_families {
+            Some(families) => {
+                let tables = if hand.len() == 13 {
+                    &families.tsumo_13
+                } else {
+                    &families.tsumo_14
+                };
+                let rows = try_join_all(tables.iter().map(|table| {
+                    measure_async(Stage::Read, table.get_row(hand_id, 0..NUM_ROUNDS))
+                }))
+                .await?;
+                Some(rows)
+            }
+            None => None,
+        };
+        let family = |i: usize, round: usize| {
+            family_rows
+                .as_ref()
+                .map(|rows| (rows[i][round] as f64) / 2f64.powi(32))
+        };
+
+        // 和了したときの残り巡数の期待値（和了しなければ0）
+        let turns = match &self.turns {
+            Some(tables) => {
+                let (table, kind) = if hand.len() == 13 {
+                    (&tables.table_13, TableKind::Turns13)
+                } else {
+                    (&tables.table_14, TableKind::Turns14)
+                };
+                let row = self
+                    .cache
+                    .tsumo(kind, hand_id as u32, 0..NUM_ROUNDS, async {
+                        measure_async(Stage::Read, table.get_row(hand_id, 0..NUM_ROUNDS)).await
+                    })
+                    .await?;
+                Some(row)
+            }
+            None => None,
+        };
+
+        // 13枚は残り1〜NUM_ROUNDS巡、14枚は残り0〜NUM_ROUNDS-1巡
+        let first_draws = if hand.len() == 13 { 1 } else { 0 };
+        let probabilities = probs
+            .into_iter()
+            .enumerate()
+            .map(|(round, p)| {
+                let draws_left = (round + first_draws) as u32;
+                let probability = (p as f64) / 2f64.powi(32);
+                // 残り巡数から和了したときの残り巡数の期待値を引くと、和了までのツモ回数の期待値になる
+                let expected_draws = turns.as_ref().filter(|_| p > 0).map(|row| {
+                    let left = (row[round] as f64) / (TURNS_SCALE as f64) / probability;
+                    (draws_left as f64 - left).max(0.0)
+                });
+                TsumoProbability {
+                    draws_left,
+                    probability,
+                    raw: None,
+                    p_standard: family(0, round),
+                    p_chiitoi: family(1, round),
+                    p_kokushi: family(2, round),
+                    expected_draws,
+                }
+            })
+            .collect();
+        Ok(TsumoAnalysis {
+            probabilities,
+            scale: None,
+            canonical: None,
+            aka_count: None,
+            kan_approximated: None,
+        })
+    }
+
+    /// 副露した手牌の門前の牌（4・5・7・8・10・11枚）のツモ率を計算
+    ///
+    /// 和了形は一般形のみで、副露した牌も山に残っているものとみなす。和了形ごとの内訳や
+    /// ツモ回数の期待値のデータファイルはないため含めない
+    async fn analyze_open_tsumo(&self, hand: &[Tile]) -> Result<TsumoAnalysis> {
+        TileMultiset::from_tiles(hand).map_err(|e| AnalyzerError::EncodingFailed(e.to_string()))?;
+        let open = self.open_tsumo_table(hand.len())?;
+        let hand_id = measure(Stage::Encode, || {
+            self.converter
+                .try_encode_in(&open.space, &Hand::from_tiles(hand))
+        })
+        .map_err(encoding_failed)?
+        .0 as usize;
+        let kind = TableKind::OpenTsumo(hand.len() as u8);
+        let probs = self
+            .cache
+            .tsumo(kind, hand_id as u32, 0..NUM_ROUNDS, async {
+                measure_async(Stage::Read, open.table.get_row(hand_id, 0..NUM_ROUNDS)).await
+            })
+            .await?;
+
+        // 3n+1枚は残り1〜NUM_ROUNDS巡、3n+2枚は残り0〜NUM_ROUNDS-1巡
+        let first_draws = if hand.len() % 3 == 1 { 1 } else { 0 };
+        let probabilities = probs
+            .into_iter()
+            .enumerate()
+            .map(|(round, p)| TsumoProbability {
+                draws_left: (round + first_draws) as u32,
+                probability: (p as f64) / 2f64.powi(32),
+                raw: None,
+                p_standard: None,
+                p_chiitoi: None,
+                p_kokushi: None,
+                expected_draws: None,
+            })
+            .collect();
+        Ok(TsumoAnalysis {
+            probabilities,
+            scale: None,
+            canonical: None,
+            aka_count: None,
+            kan_approximated: None,
+        })
+    }
+
+    /// 13枚の手牌が、これからk回目のツモでちょうど和了する確率（k = 1〜残り巡数）を計算
+    ///
+    /// `tsumo_13`の残りk巡のツモ率（k巡以内に和了する確率）の差分から求める。残り巡数ごとに
+    /// 最善の打牌は異なるため近似だが、和は残り巡数のツモ率に一致する。`draws_left`の省略時はNUM_ROUNDS
+    pub async fn analyze_win_turns(
+        &self,
+        hand: &[Tile],
+        draws_left: Option<usize>,
+    ) -> Result<WinTurnAnalysis> {
+        if hand.len() != 13 {
+            return Err(AnalyzerError::InvalidHandLength(hand.len()));
+        }
+        let draws_left = draws_left.unwrap_or(NUM_ROUNDS);
+        if !(1..=NUM_ROUNDS).contains(&draws_left) {
+            return Err(AnalyzerError::InvalidDrawsLeft(draws_left));
+        }
+        check_encodable(hand)?;
+        let hand_id = measure(Stage::Encode, || {
+            self.converter.try_encode_hand13(&Hand::from_tiles(hand))
+        })
+        .map_err(encoding_failed)?
+        .0 as usize;
+        // analyze_tsumoと同じ範囲で読み、キャッシュを共有する
+        let cumulative = self
+            .cache
+            .tsumo(TableKind::Tsumo13, hand_id as u32, 0..NUM_ROUNDS, async {
+                measure_async(Stage::Read, self.tsumo_13.get_row(hand_id, 0..NUM_ROUNDS)).await
+            })
+            .await?;
+
+        // ツモ率は巡数について単調増加だが、丸め誤差で減ったときは0とする
+        let mut prev = 0;
+        let pmf = cumulative[..draws_left]
+            .iter()
+            .enumerate()
+            .map(|(round, &cum)| {
+                let p = cum.saturating_sub(prev);
+                prev = prev.max(cum);
+                WinTurnProbability {
+                    draw: (round as u32) + 1,
                     probability: (p as f64) / 2f64.powi(32),
-                })
-                .collect()
+                }
+            })
+            .collect();
+        Ok(WinTurnAnalysis {
+            draws_left: draws_left as u32,
+            total_probability: (prev as f64) / 2f64.powi(32),
+            pmf,
+        })
+    }
+
+    /// 手牌を分析して、流局時（残り巡数をすべてツモったとき）に聴牌している確率を計算
+    ///
+    /// 和了は考えず、流局時の聴牌だけを目指して打牌したときの確率。聴牌率データファイルがなければエラー
+    pub async fn analyze_tenpai(&self, hand: &[Tile]) -> Result<TenpaiAnalysis> {
+        let Some(tables) = &self.tenpai else {
+            return Err(AnalyzerError::Unavailable("tenpai"));
+        };
+        check_encodable(hand)?;
+        // 13枚は残り1〜NUM_ROUNDS巡、14枚は残り0〜NUM_ROUNDS-1巡
+        let (first_draws, table, kind) = match hand.len() {
+            13 => (1, &tables.table_13, TableKind::Tenpai13),
+            14 => (0, &tables.table_14, TableKind::Tenpai14),
+            _ => return Err(AnalyzerError::InvalidHandLength(hand.len())),
         };
-        Ok(TsumoAnalysis { probabilities })
+        let (hand_id, _) = measure(Stage::Encode, || {
+            let hand = Hand::from_tiles(hand);
+            if first_draws == 1 {
+                self.converter.try_encode_hand13(&hand)
+            } else {
+                self.converter.try_encode_hand14(&hand)
+            }
+        })
+        .map_err(encoding_failed)?;
+        let probs = self
+            .cache
+            .tsumo(kind, hand_id, 0..NUM_ROUNDS, async {
+                measure_async(Stage::Read, table.get_row(hand_id as usize, 0..NUM_ROUNDS)).await
+            })
+            .await?;
+
+        let probabilities = probs
+            .into_iter()
+            .enumerate()
+            .map(|(round, p)| TenpaiProbability {
+                draws_left: (round + first_draws) as u32,
+                probability: (p as f64) / 2f64.powi(32),
+            })
+            .collect();
+        Ok(TenpaiAnalysis { probabilities })
     }
 
     /// 手牌を分析してメンツ実現確率を計算
     pub async fn analyze_mentsu(&self, hand: &[Tile], draws_left: usize) -> Result<MentsuAnalysis> {
-        let met;
-        let hand_id;
-        let trans;
-        let jihai_cnt;
-        if hand.len() == 13 {
-            if draws_left < 1 || draws_left > NUM_ROUNDS {
-                return Err(anyhow::anyhow!("Invalid draws_left: {}", draws_left));
-            }
-            let _hand;
-            let _hi;
-            (_hand, jihai_cnt) = Hand::from_tiles_with_jihai_cnt(hand);
-            (_hi, trans) = self.converter.encode_hand13(&_hand);
-            hand_id = _hi as usize;
-            met = self
-                .metrics_13_pool
-                .get()
-                .await
-                .map_err(|e| anyhow::anyhow!("Failed to get pool: {}", e))?
-                .get(hand_id * NUM_ROUNDS + draws_left - 1)?;
-        } else if hand.len() == 14 {
-            if draws_left >= NUM_ROUNDS {
-                return Err(anyhow::anyhow!("Invalid draws_left: {}", draws_left));
-            }
-            let _hand;
-            let _hi;
-            (_hand, jihai_cnt) = Hand::from_tiles_with_jihai_cnt(hand);
-            (_hi, trans) = self.converter.encode_hand14(&_hand);
-            hand_id = _hi as usize;
-            met = self
-                .metrics_14_pool
-                .get()
-                .await
-                .map_err(|e| anyhow::anyhow!("Failed to get pool: {}", e))?
-                .get(hand_id * NUM_ROUNDS + draws_left)?;
-        } else {
-            return Err(anyhow::anyhow!("Invalid hand length: {}", hand.len()));
+        let mut rounds = self.analyze_mentsu_rounds(hand, Some(draws_left)).await?;
+        Ok(rounds.remove(0).analysis)
+    }
+
+    /// 手牌を分析して、指定した残り巡数（省略時は全巡数）のメンツ実現確率を計算
+    pub async fn analyze_mentsu_rounds(
+        &self,
+        hand: &[Tile],
+        draws_left: Option<usize>,
+    ) -> Result<Vec<MentsuRoundAnalysis>> {
+        let rows = self.read_metrics(hand, draws_left).await?;
+        Ok(rows
+            .metrics
+            .into_iter()
+            .zip(rows.draws)
+            .map(|(met, draws_left)| MentsuRoundAnalysis {
+                draws_left: draws_left as u32,
+                analysis: MentsuAnalysis {
+                    probabilities: mentsu_probabilities(met, &rows.trans, &rows.jihai_cnt),
+                    scale: None,
+                    canonical: None,
+                    aka_count: None,
+                    kan_approximated: None,
+                },
+            })
+            .collect())
+    }
+
+    /// 13枚の手牌にツモりうる牌ごとに、その牌をツモったときの和了確率を計算する
+    ///
+    /// `include_canonical`ならツモ後の14枚の手牌の正規形も含める。
+    ///
+    /// ツモ後の14枚の手牌の行を`tsumo_14`から読み出し、ツモる前の残り巡数に付け直して返す
+    /// （13枚で残りd巡なら、ツモ後の14枚は残りd-1巡）。`remaining`で重み付けした平均が
+    /// 13枚の手牌のツモ率になる。重みは`Hand::for_each_draw_hand`と同じだが、字牌は種類ごとに分けて返す。
+    pub async fn analyze_tsumo_draws(
+        &self,
+        hand: &[Tile],
+        include_canonical: bool,
+    ) -> Result<Vec<DrawTsumoAnalysis>> {
+        if hand.len() != 13 {
+            return Err(AnalyzerError::InvalidHandLength(hand.len()));
         }
+        let held = TileMultiset::from_tiles(hand)
+            .map_err(|e| AnalyzerError::EncodingFailed(e.to_string()))?;
+        let suits = (0..3).flat_map(|suit| (0..9).map(move |num| Tile::Supai(suit, num)));
+        let draws: Vec<(Tile, u32)> = suits
+            .chain((0..7).map(Tile::Jihai))
+            .filter_map(|tile| {
+                let remaining = 4 - held.count(tile) as u32;
+                (remaining > 0).then_some((tile, remaining))
+            })
+            .collect();
+        try_join_all(draws.into_iter().map(|(tile, remaining)| async move {
+            let mut tiles = hand.to_vec();
+            tiles.push(tile);
+            let mut analysis = self.analyze_tsumo(&tiles).await?;
+            for p in &mut analysis.probabilities {
+                p.draws_left += 1;
+            }
+            if include_canonical {
+                analysis.canonical = Some(self.canonical_hand(&tiles)?);
+            }
+            Ok(DrawTsumoAnalysis {
+                tile: format_tiles(&[tile]),
+                remaining,
+                analysis,
+            })
+        }))
+        .await
+    }
 
-        const SUPAI_LOOKUP: [char; 3] = ['m', 'p', 's'];
+    /// 14枚の手牌から捨てる牌の候補ごとに、打牌後の13枚の手牌のメンツ実現確率を計算する
+    ///
+    /// `draws_left`は打牌後の残り巡数（1〜NUM_ROUNDS）で、省略時は全巡数。
+    /// `include_canonical`なら打牌後の手牌の正規形も含める。各候補の行は並行して読み出す。
+    pub async fn analyze_mentsu_discards(
+        &self,
+        hand: &[Tile],
+        draws_left: Option<usize>,
+        include_canonical: bool,
+    ) -> Result<Vec<DiscardMentsuAnalysis>> {
+        if hand.len() != 14 {
+            return Err(AnalyzerError::InvalidHandLength(hand.len()));
+        }
+        let mut discards: Vec<Tile> = Vec::new();
+        for &tile in hand {
+            if !discards.contains(&tile) {
+                discards.push(tile);
+            }
+        }
+        try_join_all(discards.into_iter().map(|discard| async move {
+            let mut tiles = hand.to_vec();
+            let index = tiles.iter().position(|&t| t == discard).unwrap();
+            tiles.remove(index);
+            let rounds = self.analyze_mentsu_rounds(&tiles, draws_left).await?;
+            let canonical = if include_canonical {
+                Some(self.canonical_hand(&tiles)?)
+            } else {
+                None
+            };
+            Ok(DiscardMentsuAnalysis {
+                discard: format_tiles(&[discard]),
+                hand: format_tiles(&tiles),
+                rounds,
+                canonical,
+            })
+        }))
+        .await
+    }
 
-        let mut probabilities = Vec::with_capacity(21 + 27 + 27 + 7 + 7 + 1);
-        for (i, p) in met.values.into_iter().enumerate() {
-            let dim = Dimension::from_id(i % Dimension::len());
-            let probability = (p as f64) / 2f64.powi(30);
-            match dim {
-                Dimension::Shuntsu(Tile::Supai(s, mut n)) => {
-                    let mut t = trans[s as usize];
-                    if t < 0 {
-                        t = !t;
-                        n = 6 - n;
-                    }
-                    probabilities.push(MentsuProbability {
-                        mentsu_type: format!(
-                            "{}{}{}{}",
-                            n + 1,
-                            n + 2,
-                            n + 3,
-                            SUPAI_LOOKUP[t as usize]
-                        ),
-                        probability,
-                    });
+    /// 保存されているメトリクスを正規形の次元ラベルと変換情報とともにそのまま返す
+    pub async fn raw_metrics(&self, hand: &[Tile], draws_left: Option<usize>) -> Result<RawMetrics> {
+        let rows = self.read_metrics(hand, draws_left).await?;
+        Ok(RawMetrics {
+            hand_id: rows.hand_id,
+            translation: rows.trans,
+            jihai_counts: rows.jihai_cnt,
+            labels: Dimension::all_dimensions()
+                .iter()
+                .map(|&dim| dimension_label(dim))
+                .collect(),
+            rounds: rows
+                .metrics
+                .into_iter()
+                .zip(rows.draws)
+                .map(|(met, draws_left)| RawMetricsRound {
+                    draws_left: draws_left as u32,
+                    values: met.values.to_vec(),
+                })
+                .collect(),
+        })
+    }
+
+    /// 手牌の行から、指定した残り巡数（省略時は全巡数）のメトリクスを1回で読み出す
+    async fn read_metrics(&self, hand: &[Tile], draws_left: Option<usize>) -> Result<MetricsRows> {
+        // 13枚は残り1〜NUM_ROUNDS巡、14枚は残り0〜NUM_ROUNDS-1巡
+        check_encodable(hand)?;
+        let (first_draws, table, kind) = match hand.len() {
+            13 => (1, &self.metrics_13, TableKind::Metrics13),
+            14 => (0, &self.metrics_14, TableKind::Metrics14),
+            _ => return Err(AnalyzerError::InvalidHandLength(hand.len())),
+        };
+        let draws = match draws_left {
+            Some(draws_left) => {
+                if !(first_draws..first_draws + NUM_ROUNDS).contains(&draws_left) {
+                    return Err(AnalyzerError::InvalidDrawsLeft(draws_left));
                 }
-                Dimension::Kotsu(Tile::Supai(s, mut n)) => {
-                    let mut t = trans[s as usize];
-                    if t < 0 {
-                        t = !t;
-                        n = 8 - n;
-                    }
-                    probabilities.push(MentsuProbability {
-                        mentsu_type: format!(
-                            "{}{}{}{}",
-                            n + 1,
-                            n + 1,
-                            n + 1,
-                            SUPAI_LOOKUP[t as usize]
-                        ),
-                        probability,
-                    });
+                draws_left..draws_left + 1
+            }
+            None => first_draws..first_draws + NUM_ROUNDS,
+        };
+
+        let (hand, jihai_cnt) = Hand::from_tiles_with_jihai_cnt(hand);
+        let (hand_id, trans) = measure(Stage::Encode, || {
+            if first_draws == 1 {
+                self.converter.try_encode_hand13(&hand)
+            } else {
+                self.converter.try_encode_hand14(&hand)
+            }
+        })
+        .map_err(encoding_failed)?;
+        let rounds = draws.start - first_draws..draws.end - first_draws;
+        let metrics = self
+            .cache
+            .metrics(kind, hand_id, rounds.clone(), async {
+                measure_async(Stage::Read, table.get_row(hand_id as usize, rounds)).await
+            })
+            .await?;
+
+        Ok(MetricsRows {
+            hand_id,
+            trans,
+            jihai_cnt,
+            draws,
+            metrics,
+        })
+    }
+
+    /// ツモ率とメンツ実現確率をまとめて計算する。2つのテーブルは並行して読み出す
+    pub async fn analyze(&self, hand: &[Tile], draws_left: Option<usize>) -> Result<CombinedAnalysis> {
+        let (tsumo, mentsu) = tokio::try_join!(
+            self.analyze_tsumo(hand),
+            self.analyze_mentsu_rounds(hand, draws_left)
+        )?;
+        Ok(CombinedAnalysis {
+            tsumo,
+            mentsu,
+            canonical: None,
+            aka_count: None,
+            kan_approximated: None,
+        })
+    }
+
+    /// 手牌がどの正規形にエンコードされるかを求める
+    pub fn canonical_hand(&self, hand: &[Tile]) -> Result<CanonicalHand> {
+        if OPEN_HAND_TILES.contains(&hand.len()) {
+            TileMultiset::from_tiles(hand)
+                .map_err(|e| AnalyzerError::EncodingFailed(e.to_string()))?;
+            let open = self.open_tsumo_table(hand.len())?;
+            let (hand_id, translation) = measure(Stage::Encode, || {
+                self.converter
+                    .try_encode_in(&open.space, &Hand::from_tiles(hand))
+            })
+            .map_err(encoding_failed)?;
+            return Ok(CanonicalHand {
+                hand: self
+                    .converter
+                    .decode_in(&open.space, hand_id)
+                    .to_mpsz_string(),
+                hand_id,
+                translation,
+            });
+        }
+        check_encodable(hand)?;
+        let hand = Hand::from_tiles(hand);
+        let (hand_id, translation, canonical) = measure(Stage::Encode, || match hand.num_tiles() {
+            13 => {
+                let (hand_id, trans) = self
+                    .converter
+                    .try_encode_hand13(&hand)
+                    .map_err(encoding_failed)?;
+                Ok((hand_id, trans, self.converter.decode_hand13(hand_id)))
+            }
+            14 => {
+                let (hand_id, trans) = self
+                    .converter
+                    .try_encode_hand14(&hand)
+                    .map_err(encoding_failed)?;
+                Ok((hand_id, trans, self.converter.decode_hand14(hand_id)))
+            }
+            n => Err(AnalyzerError::InvalidHandLength(n)),
+        })?;
+        Ok(CanonicalHand {
+            hand: canonical.to_mpsz_string(),
+            hand_id,
+            translation,
+        })
+    }
+
+    /// 手牌の向聴数を計算
+    pub fn analyze_shanten(&self, hand: &[Tile]) -> Result<ShantenAnalysis> {
+        if hand.len() != 13 && hand.len() != 14 {
+            return Err(AnalyzerError::InvalidHandLength(hand.len()));
+        }
+        let shanten = shanten(&Hand::from_tiles(hand));
+        Ok(ShantenAnalysis {
+            standard: shanten.standard,
+            chiitoitsu: shanten.chiitoitsu,
+            kokushi: shanten.kokushi,
+        })
+    }
+
+    /// 向聴数・有効牌の枚数・ツモ率を1つの評価値にまとめる
+    pub async fn score_hand(&self, hand: &[Tile]) -> Result<HandScore> {
+        // 手牌の検証はツモ率の読み出しで行う
+        let tsumo = self.analyze_tsumo(hand).await?;
+        let tiles = Hand::from_tiles(hand);
+        let shanten = shanten(&tiles).min();
+        let ukeire = ukeire_count(&tiles);
+        let mean_tsumo_probability = tsumo.probabilities.iter().map(|p| p.probability).sum::<f64>()
+            / tsumo.probabilities.len() as f64;
+
+        let unseen = (4 * 34 - hand.len()) as f64;
+        let components = ScoreComponents {
+            shanten: (1.0 - (shanten + 1) as f64 / 7.0).max(0.0),
+            ukeire: ukeire as f64 / unseen,
+            tsumo: mean_tsumo_probability,
+        };
+        let (w_shanten, w_ukeire, w_tsumo) = SCORE_WEIGHTS;
+        let score = w_shanten * components.shanten
+            + w_ukeire * components.ukeire
+            + w_tsumo * components.tsumo;
+        Ok(HandScore {
+            score,
+            shanten,
+            ukeire,
+            mean_tsumo_probability,
+            components,
+        })
+    }
+
+    /// 残り巡数での和了率と、メンツの種類ごとのメンツ実現確率
+    ///
+    /// メンツ実現確率は、そのメンツを含む形で和了したときのメンツの個数の期待値
+    /// （和了しなければ0）である。
+    async fn win_mentsu_probabilities(
+        &self,
+        hand: &[Tile],
+        draws_left: usize,
+    ) -> Result<(f64, Vec<(Dimension, f64)>)> {
+        let tsumo = self.analyze_tsumo(hand).await?;
+        let win_probability = tsumo
+            .probabilities
+            .iter()
+            .find(|p| p.draws_left as usize == draws_left)
+            .ok_or(AnalyzerError::InvalidDrawsLeft(draws_left))?
+            .probability;
+        let mentsu = self.analyze_mentsu(hand, draws_left).await?;
+        let probabilities = mentsu
+            .probabilities
+            .into_iter()
+            .filter_map(|p| Some((Dimension::from_label(&p.mentsu_type).ok()?, p.probability)))
+            .collect();
+        Ok((win_probability, probabilities))
+    }
+
+    /// 役ごとに、その役を含む形で和了する確率の下限と上限を見積もる
+    ///
+    /// メンツ実現確率はメンツの個数の期待値しか持たないので、複数のメンツが揃う必要がある
+    /// 役は期待値から確率を挟む。国士無双・役牌・門前清自摸和は下限と上限が一致する。
+    /// 七対子は、4面子1雀頭とも読める手牌では読み方の数で按分した値になる。
+    pub async fn analyze_yaku(
+        &self,
+        hand: &[Tile],
+        draws_left: usize,
+        seat: Tile,
+        round: Tile,
+    ) -> Result<YakuAnalysis> {
+        let (win, mentsu) = self.win_mentsu_probabilities(hand, draws_left).await?;
+        let expected = |pred: &dyn Fn(Dimension) -> bool| {
+            mentsu
+                .iter()
+                .filter(|(dim, _)| pred(*dim))
+                .map(|(_, p)| p)
+                .sum::<f64>()
+        };
+
+        let kokushi = expected(&|dim| dim == Dimension::Kokushi).min(win);
+        // 雀頭は4面子1雀頭で1つ、七対子で7つ、国士無双では数えない
+        let toitsu = expected(&|dim| matches!(dim, Dimension::Toitsu(_)));
+        let chiitoitsu = ((toitsu - win + kokushi) / 6.0).clamp(0.0, win - kokushi);
+        let standard = (win - chiitoitsu - kokushi).max(0.0);
+
+        // 刻子は4面子1雀頭で0〜4個。和了はすべて門前ツモなのですべて暗刻になる
+        let kotsu = expected(&|dim| matches!(dim, Dimension::Kotsu(_)));
+        let at_least = |n: f64| {
+            // E[刻子数] ≤ (n-1)×(和了率 - P) + 4×P から下限、マルコフの不等式から上限を得る
+            let lower = (kotsu - (n - 1.0) * standard) / (5.0 - n);
+            let upper = kotsu / n;
+            (lower.clamp(0.0, standard), upper.clamp(0.0, standard))
+        };
+
+        // 么九牌を含むメンツが1つでもあれば断么九にならない
+        let yaochuu = |dim: Dimension| match dim {
+            Dimension::Shuntsu(Tile::Supai(_, num)) => num == 0 || num == 6,
+            Dimension::Kotsu(tile) | Dimension::Toitsu(tile) => tile.is_yaochuu(),
+            _ => true,
+        };
+        let tanyao_lower = win - expected(&yaochuu);
+        let tanyao_upper = win
+            - mentsu
+                .iter()
+                .filter(|(dim, _)| yaochuu(*dim))
+                .map(|(_, p)| *p)
+                .fold(0.0, f64::max);
+
+        let exact = |yaku: &str, probability: f64| YakuProbability {
+            yaku: yaku.to_string(),
+            lower_bound: probability,
+            upper_bound: probability,
+        };
+        let bounded = |yaku: &str, (lower, upper): (f64, f64)| YakuProbability {
+            yaku: yaku.to_string(),
+            lower_bound: lower.max(0.0),
+            upper_bound: upper.max(lower.max(0.0)),
+        };
+        let yakuhai = |tile: Tile| expected(&|dim| dim == Dimension::Kotsu(tile));
+        let yaku = vec![
+            exact("menzen_tsumo", win),
+            bounded("tanyao", (tanyao_lower, tanyao_upper)),
+            exact("yakuhai_haku", yakuhai(Tile::haku())),
+            exact("yakuhai_hatsu", yakuhai(Tile::hatsu())),
+            exact("yakuhai_chun", yakuhai(Tile::chun())),
+            exact("yakuhai_seat", yakuhai(seat)),
+            exact("yakuhai_round", yakuhai(round)),
+            exact("chiitoitsu", chiitoitsu),
+            bounded("toitoi", at_least(4.0)),
+            bounded("sanankou", at_least(3.0)),
+            exact("kokushi", kokushi),
+        ];
+        Ok(YakuAnalysis {
+            draws_left: draws_left as u32,
+            win_probability: win,
+            yaku,
+        })
+    }
+
+    /// 和了率とメンツ実現確率から、和了したときの打点を見積もる
+    ///
+    /// 和了はすべて門前ツモなので門前清自摸和の1翻を必ず数える。ほかに数える役は
+    /// 役牌（刻子の実現確率から）・七対子・国士無双のみで、裏ドラ・立直は数えない。
+    /// そのため実際の打点より低めの見積もりになる。符は4面子1雀頭では刻子の符だけを数え、
+    /// 待ちと雀頭の符は数えない。ドラは`dora_indicators`（ドラ表示牌）から、各メンツの
+    /// 実現確率とそのメンツに含まれるドラの枚数の積の和として数える。
+    pub async fn analyze_value(
+        &self,
+        hand: &[Tile],
+        draws_left: usize,
+        seat: Tile,
+        round: Tile,
+        dora_indicators: &[Tile],
+    ) -> Result<ValueAnalysis> {
+        let (win_probability, mentsu) = self.win_mentsu_probabilities(hand, draws_left).await?;
+
+        // メンツ実現確率を和了率で割ると和了したときの期待値になる
+        let mut kokushi = 0.0;
+        let mut toitsu = 0.0;
+        let mut yakuhai_han = 0.0;
+        let mut kotsu_fu = 0.0;
+        let mut dora = 0.0;
+        let mut kokushi_dora = 0.0;
+        if win_probability > 0.0 {
+            for (dim, p) in mentsu {
+                let count = p / win_probability;
+                let dim_dora = dimension_dora_count(dim, dora_indicators) * count;
+                match dim {
+                    Dimension::Kokushi => kokushi_dora += dim_dora,
+                    _ => dora += dim_dora,
                 }
-                Dimension::Toitsu(Tile::Supai(s, mut n)) => {
-                    let mut t = trans[s as usize];
-                    if t < 0 {
-                        t = !t;
-                        n = 8 - n;
+                match dim {
+                    Dimension::Kokushi => kokushi += count,
+                    Dimension::Toitsu(_) => toitsu += count,
+                    Dimension::Kotsu(tile) => {
+                        let han = tile.is_dragon() as u32
+                            + (tile == seat) as u32
+                            + (tile == round) as u32;
+                        yakuhai_han += han as f64 * count;
+                        kotsu_fu += if tile.is_yaochuu() { 8.0 } else { 4.0 } * count;
                     }
-                    probabilities.push(MentsuProbability {
-                        mentsu_type: format!("{}{}{}", n + 1, n + 1, SUPAI_LOOKUP[t as usize]),
-                        probability,
-                    });
+                    Dimension::Shuntsu(_) => {}
                 }
-                Dimension::Kotsu(Tile::Jihai(n)) => {
-                    for (ji, &cnt) in jihai_cnt.iter().enumerate() {
-                        if cnt == n as usize {
-                            probabilities.push(MentsuProbability {
-                                mentsu_type: format!("{}{}{}z", ji + 1, ji + 1, ji + 1),
-                                probability,
-                            });
-                        }
+            }
+        }
+        // 雀頭は4面子1雀頭で1つ、七対子で7つ、国士無双では数えない
+        let kokushi = kokushi.clamp(0.0, 1.0);
+        let chiitoitsu = ((toitsu - 1.0 + kokushi) / 6.0).clamp(0.0, 1.0 - kokushi);
+        let standard = 1.0 - chiitoitsu - kokushi;
+
+        let ctx = WinContext {
+            // 和了牌は点数に影響しない
+            winning_tile: seat,
+            tsumo: true,
+            seat,
+            round,
+            honba: 0,
+        };
+        let points = |han: f64, fu: u32| {
+            // 翻数の期待値は整数にならないので、前後の翻数の点数を線形補間する
+            let lo = han.floor();
+            let frac = han - lo;
+            let at = |han: u32| payment(han, fu, &ctx).total() as f64;
+            (1.0 - frac) * at(lo as u32) + frac * at(lo as u32 + 1)
+        };
+        // 4面子1雀頭と七対子のメンツは区別できないので、ドラは和了形によらず同じ枚数とみなす
+        let dora_per_win = if standard + chiitoitsu > 0.0 {
+            dora / (standard + chiitoitsu)
+        } else {
+            0.0
+        };
+        let (standard_han, standard_fu) = if standard > 0.0 {
+            let fu = 22.0 + kotsu_fu / standard;
+            (
+                1.0 + yakuhai_han / standard + dora_per_win,
+                (fu / 10.0).ceil() as u32 * 10,
+            )
+        } else {
+            (1.0, 30)
+        };
+        // 七対子は門前清自摸和と合わせて3翻25符、国士無双は役満（ドラは数えない）
+        let chiitoitsu_han = 3.0 + dora_per_win;
+        let expected_han = standard * standard_han + chiitoitsu * chiitoitsu_han + kokushi * 13.0;
+        let expected_points = if win_probability > 0.0 {
+            standard * points(standard_han, standard_fu)
+                + chiitoitsu * points(chiitoitsu_han, 25)
+                + kokushi * payment(13, 0, &ctx).total() as f64
+        } else {
+            0.0
+        };
+
+        Ok(ValueAnalysis {
+            draws_left: draws_left as u32,
+            win_probability,
+            expected_han,
+            expected_dora: dora + kokushi_dora,
+            expected_points,
+            expected_value: win_probability * expected_points,
+            shapes: AgariShapes {
+                standard,
+                chiitoitsu,
+                kokushi,
+            },
+        })
+    }
+
+    /// converterとデータファイルの長さを検査し、既知の手牌で実際に読み出して結果を確かめる
+    ///
+    /// 起動時の自己診断と`/health/deep`で使う。
+    pub async fn check_health(&self) -> DeepHealth {
+        let tile_set = self.converter.tile_set();
+        let (num_hand13, num_hand14) = (tile_set.num_hand13(), tile_set.num_hand14());
+        let files = vec![
+            length_health("converter_13", self.converter.num_hand13(), num_hand13),
+            length_health("converter_14", self.converter.num_hand14(), num_hand14),
+            file_health("tsumo_13", &self.tsumo_13, num_hand13 * NUM_ROUNDS).await,
+            file_health("tsumo_14", &self.tsumo_14, num_hand14 * NUM_ROUNDS).await,
+            file_health("metrics_13", &self.metrics_13, num_hand13 * NUM_ROUNDS).await,
+            file_health("metrics_14", &self.metrics_14, num_hand14 * NUM_ROUNDS).await,
+        ];
+
+        // 長さが合わないconverterやファイルでは読み出しがpanicや範囲外エラーになるため、サンプル検査は行わない。
+        // サンプルの手牌と期待値は四人麻雀のものなので、三人麻雀や小さなconverterでは長さだけを検査する
+        let check_samples = tile_set == TileSet::Yonma;
+        let samples = if check_samples && files.iter().all(|f| f.ok) {
+            let mut samples = vec![
+                sample_health("tsumo_14_agari", SAMPLE_AGARI_HAND, |hand| async move {
+                    let analysis = self.analyze_tsumo(&hand).await?;
+                    match analysis
+                        .probabilities
+                        .iter()
+                        .find(|p| p.probability < 1.0 - 1e-9)
+                    {
+                        Some(p) => Err(anyhow::anyhow!(
+                            "expected probability 1 but got {} at draws_left={}",
+                            p.probability,
+                            p.draws_left
+                        )),
+                        None => Ok(()),
                     }
-                }
-                Dimension::Toitsu(Tile::Jihai(n)) => {
-                    for (ji, &cnt) in jihai_cnt.iter().enumerate() {
-                        if cnt == n as usize {
-                            probabilities.push(MentsuProbability {
-                                mentsu_type: format!("{}{}z", ji + 1, ji + 1),
-                                probability,
-                            });
+                })
+                .await,
+                sample_health("tsumo_13_tenpai", SAMPLE_TENPAI_HAND, |hand| async move {
+                    let analysis = self.analyze_tsumo(&hand).await?;
+                    for w in analysis.probabilities.windows(2) {
+                        if w[1].probability < w[0].probability {
+                            return Err(anyhow::anyhow!(
+                                "probability decreased from draws_left={} to draws_left={}",
+                                w[0].draws_left,
+                                w[1].draws_left
+                            ));
                         }
                     }
+                    if analysis.probabilities.iter().any(|p| p.probability <= 0.0) {
+                        return Err(anyhow::anyhow!("probability of tenpai hand is zero"));
+                    }
+                    Ok(())
+                })
+                .await,
+                sample_health("metrics_13_tenpai", SAMPLE_TENPAI_HAND, |hand| async move {
+                    self.analyze_mentsu_rounds(&hand, None).await?;
+                    Ok(())
+                })
+                .await,
+                sample_health("metrics_14_agari", SAMPLE_AGARI_HAND, |hand| async move {
+                    self.analyze_mentsu_rounds(&hand, None).await?;
+                    Ok(())
+                })
+                .await,
+            ];
+            for golden in &GOLDEN_TSUMO {
+                samples.push(
+                    sample_health(golden.name, golden.hand, |hand| async move {
+                        let mut analysis = self.analyze_tsumo(&hand).await?;
+                        analysis.fill_fixed_point();
+                        let raw = analysis
+                            .probabilities
+                            .iter()
+                            .find(|p| p.draws_left == golden.draws_left)
+                            .and_then(|p| p.raw);
+                        if raw != Some(golden.raw) {
+                            return Err(anyhow::anyhow!(
+                                "expected raw value {} at draws_left={} but got {:?}",
+                                golden.raw,
+                                golden.draws_left,
+                                raw
+                            ));
+                        }
+                        Ok(())
+                    })
+                    .await,
+                );
+            }
+            samples
+        } else {
+            Vec::new()
+        };
+
+        DeepHealth {
+            ok: files.iter().all(|f| f.ok)
+                && (!check_samples || !samples.is_empty())
+                && samples.iter().all(|s| s.ok),
+            files,
+            samples,
+        }
+    }
+
+    /// 手牌インデックスの昇順にツモ率を走査する
+    pub async fn scan_tsumo(
+        &self,
+        num_tiles: usize,
+        draws_left: usize,
+        page: PageParams,
+    ) -> Result<Page<TsumoScanEntry>> {
+        let (table, num_hands, round) = match num_tiles {
+            13 => {
+                if !(1..=NUM_ROUNDS).contains(&draws_left) {
+                    return Err(AnalyzerError::InvalidDrawsLeft(draws_left));
                 }
-                Dimension::Kokushi => {
-                    probabilities.push(MentsuProbability {
-                        mentsu_type: "Kokushi".to_string(),
-                        probability,
-                    });
+                (&self.tsumo_13, self.converter.num_hand13(), draws_left - 1)
+            }
+            14 => {
+                if draws_left >= NUM_ROUNDS {
+                    return Err(AnalyzerError::InvalidDrawsLeft(draws_left));
                 }
-                _ => {
-                    return Err(anyhow::anyhow!(
-                        "Internal error: Invalid dimension: {:?}",
-                        dim
-                    ))
+                (&self.tsumo_14, self.converter.num_hand14(), draws_left)
+            }
+            _ => return Err(AnalyzerError::InvalidHandLength(num_tiles)),
+        };
+
+        let start = page.start.min(num_hands);
+        let end = (start + page.limit).min(num_hands);
+        let rows = measure_async(
+            Stage::Read,
+            table.get_range(start * NUM_ROUNDS, end * NUM_ROUNDS),
+        )
+        .await?;
+
+        let items = measure(Stage::Encode, || {
+            rows.chunks(NUM_ROUNDS)
+                .enumerate()
+                .map(|(i, row)| {
+                    let hand_index = (start + i) as u32;
+                    let hand = if num_tiles == 13 {
+                        self.converter.decode_hand13(hand_index)
+                    } else {
+                        self.converter.decode_hand14(hand_index)
+                    };
+                    TsumoScanEntry {
+                        hand_index,
+                        hand: hand.to_mpsz_string(),
+                        probability: (row[round] as f64) / 2f64.powi(32),
+                    }
+                })
+                .collect()
+        });
+        Ok(Page::new(items, end, num_hands))
+    }
+}
+
+/// 要素数が期待どおりかを検査する
+fn length_health(name: &str, actual_len: usize, expected_len: usize) -> FileHealth {
+    let ok = actual_len == expected_len;
+    FileHealth {
+        name: name.to_string(),
+        ok,
+        expected_len,
+        actual_len: Some(actual_len),
+        error: (!ok).then(|| "length mismatch".to_string()),
+    }
+}
+
+/// データファイルの要素数を検査する
+async fn file_health<T: Send + 'static>(
+    name: &str,
+    table: &Arc<dyn TableSource<T>>,
+    expected_len: usize,
+) -> FileHealth {
+    match table.len().await {
+        Ok(len) => length_health(name, len, expected_len),
+        Err(e) => FileHealth {
+            name: name.to_string(),
+            ok: false,
+            expected_len,
+            actual_len: None,
+            error: Some(e.to_string()),
+        },
+    }
+}
+
+fn read_stats<T: Send + 'static>(name: &str, table: &Arc<dyn TableSource<T>>) -> Option<ReadStats> {
+    let metrics = table.read_metrics()?;
+    Some(ReadStats {
+        name: name.to_string(),
+        path: table.location(),
+        in_flight: metrics.in_flight.load(Ordering::Relaxed),
+        failures: metrics.failures.load(Ordering::Relaxed),
+        latency: metrics.latency.snapshot(),
+    })
+}
+
+fn dataset_file<T: Send + 'static>(name: &str, table: &Arc<dyn TableSource<T>>) -> DatasetFile {
+    let metadata = table.local_path().map(std::fs::metadata);
+    DatasetFile {
+        name: name.to_string(),
+        path: table.location(),
+        bytes: metadata
+            .as_ref()
+            .and_then(|m| m.as_ref().ok())
+            .map(|m| m.len()),
+        error: metadata.and_then(|m| m.err()).map(|e| e.to_string()),
+    }
+}
+
+/// 既知の手牌で検査を実行する
+async fn sample_health<F, Fut>(name: &str, hand: &str, check: F) -> SampleHealth
+where
+    F: FnOnce(Vec<Tile>) -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<()>>,
+{
+    let result = match parse_hand_str(hand) {
+        Ok(tiles) => check(tiles).await,
+        Err(e) => Err(e),
+    };
+    SampleHealth {
+        name: name.to_string(),
+        hand: hand.to_string(),
+        ok: result.is_ok(),
+        error: result.err().map(|e| e.to_string()),
+    }
+}
+
+/// 次元の正規形でのラベル
+///
+/// 数牌は正規形のスート0〜2をm/p/sで表す（例: `shuntsu:1m`は正規形スート0の123）。
+/// 字牌は手牌での枚数で区別し、`kotsu:z(2)`は2枚持っている字牌の刻子を表す。
+fn dimension_label(dim: Dimension) -> String {
+    const SUPAI_LOOKUP: [char; 3] = ['m', 'p', 's'];
+
+    let (kind, tile) = match dim {
+        Dimension::Shuntsu(tile) => ("shuntsu", tile),
+        Dimension::Kotsu(tile) => ("kotsu", tile),
+        Dimension::Toitsu(tile) => ("toitsu", tile),
+        Dimension::Kokushi => return "kokushi".to_string(),
+    };
+    match tile {
+        Tile::Supai(s, n) => format!("{}:{}{}", kind, n + 1, SUPAI_LOOKUP[s as usize]),
+        Tile::Jihai(n) => format!("{}:z({})", kind, n),
+    }
+}
+
+/// メトリクスを元の手牌の牌種に戻してメンツ実現確率の一覧にする
+fn mentsu_probabilities(
+    met: Metrics,
+    trans: &[i8; 3],
+    jihai_cnt: &[usize; 7],
+) -> Vec<MentsuProbability> {
+    let trans = Translation(*trans);
+
+    let mut probabilities = Vec::with_capacity(21 + 27 + 27 + 7 + 7 + 1);
+    for (i, p) in met.values.into_iter().enumerate() {
+        let dim = Dimension::from_id(i % Dimension::len());
+        let probability = (p as f64) / 2f64.powi(30);
+        let mut push = |mentsu_type: String| {
+            probabilities.push(MentsuProbability {
+                mentsu_type,
+                probability,
+                raw: None,
+            })
+        };
+        match dim {
+            // 正規形の数牌を元のスートに戻す。反転したスートは数字も反転する
+            Dimension::Shuntsu(Tile::Supai(..))
+            | Dimension::Kotsu(Tile::Supai(..))
+            | Dimension::Toitsu(Tile::Supai(..)) => push(trans.map_dimension(dim).label()),
+            // 正規形の字牌は枚数で区別するので、同じ枚数の字牌すべてに展開する
+            Dimension::Kotsu(Tile::Jihai(n)) | Dimension::Toitsu(Tile::Jihai(n)) => {
+                for (ji, &cnt) in jihai_cnt.iter().enumerate() {
+                    if cnt == n as usize {
+                        let tile = Tile::Jihai(ji as u8);
+                        let dim = match dim {
+                            Dimension::Kotsu(_) => Dimension::Kotsu(tile),
+                            _ => Dimension::Toitsu(tile),
+                        };
+                        push(dim.label());
+                    }
                 }
-            };
+            }
+            // 互換性のため国士無双は従来の表記のまま返す
+            Dimension::Kokushi => push("Kokushi".to_string()),
+            // `Dimension::from_id`は字牌の順子を返さない
+            _ => unreachable!("Invalid dimension: {:?}", dim),
+        };
+    }
+    probabilities
+}
+
+/// 牌の並びを萬子・筒子・索子・字牌の順に整列した文字列表記に変換する
+pub fn format_tiles(tiles: &[Tile]) -> String {
+    const SUIT_LOOKUP: [char; 4] = ['m', 'p', 's', 'z'];
+
+    let mut counts = [[0usize; 9]; 4];
+    for tile in tiles {
+        match *tile {
+            Tile::Supai(suit, num) => counts[suit as usize][num as usize] += 1,
+            Tile::Jihai(num) => counts[3][num as usize] += 1,
+        }
+    }
+
+    let mut s = String::new();
+    for (suit, counts) in counts.iter().enumerate() {
+        let mut any = false;
+        for (num, &cnt) in counts.iter().enumerate() {
+            for _ in 0..cnt {
+                s.push((b'1' + num as u8) as char);
+                any = true;
+            }
+        }
+        if any {
+            s.push(SUIT_LOOKUP[suit]);
         }
-        Ok(MentsuAnalysis { probabilities })
+    }
+    s
+}
+
+/// mpsz表記（例: 123m, 11z）の牌をUnicodeの麻雀牌（🀇🀈🀉, 🀀🀀）に置き換える
+///
+/// `Kokushi`のように牌の表記でないものはそのまま返す。
+pub fn unicode_tiles(s: &str) -> String {
+    match parse_hand_str(s) {
+        // parse_hand_strは後ろから読むため逆順になる
+        Ok(tiles) => tiles.iter().rev().map(Tile::to_unicode).collect(),
+        Err(_) => s.to_string(),
     }
 }
@@ -0,0 +1,100 @@
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::analysis::{CombinedAnalysis, SharedHandAnalyzer};
+use crate::params::{FieldError, HandDrawsQuery, InvalidParams};
+
+/// 1回のバッチで受け付ける手牌の最大数
+pub const MAX_BATCH_SIZE: usize = 1000;
+
+/// 1回のバッチで同時に分析する手牌の数
+const BATCH_CONCURRENCY: usize = 8;
+
+/// まとめて分析する手牌の一覧
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BatchRequest {
+    pub items: Vec<BatchItem>,
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct BatchItem {
+    /// 13枚または14枚の手牌（例: 123m456p789s1122z）
+    pub hand: Option<String>,
+    /// 残り巡数（省略時は全巡数）
+    pub draws_left: Option<usize>,
+}
+
+/// 手牌1つ分の結果。失敗した手牌があっても他の手牌の分析は続ける
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchEntry {
+    /// リクエストの`items`内での位置
+    pub index: usize,
+    pub hand: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub analysis: Option<CombinedAnalysis>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<FieldError>,
+}
+
+/// バッチ全体の結果（JSON/MessagePack用）
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchResponse {
+    pub results: Vec<BatchEntry>,
+}
+
+impl BatchRequest {
+    pub fn validate(&self) -> Result<(), InvalidParams> {
+        if self.items.is_empty() || self.items.len() > MAX_BATCH_SIZE {
+            return Err(InvalidParams(vec![FieldError::new(
+                "items",
+                format!("must contain 1..={} hands", MAX_BATCH_SIZE),
+            )]));
+        }
+        Ok(())
+    }
+}
+
+/// 手牌を`BATCH_CONCURRENCY`個ずつ並行して分析し、先頭から順に結果を流す
+///
+/// 各手牌のツモ率とメトリクスも並行して読み出すため、データファイルの読み出しは最大で
+/// `BATCH_CONCURRENCY * 2`個が同時に走る。
+pub fn analyze_stream(
+    analyzer: SharedHandAnalyzer,
+    items: Vec<BatchItem>,
+) -> impl Stream<Item = BatchEntry> + Send + 'static {
+    futures_util::stream::iter(items.into_iter().enumerate())
+        .map(move |(index, item)| {
+            let analyzer = analyzer.clone();
+            async move { analyze_item(&analyzer, index, item).await }
+        })
+        .buffered(BATCH_CONCURRENCY)
+}
+
+async fn analyze_item(analyzer: &SharedHandAnalyzer, index: usize, item: BatchItem) -> BatchEntry {
+    let mut entry = BatchEntry {
+        index,
+        hand: item.hand.clone(),
+        analysis: None,
+        error: None,
+        details: Vec::new(),
+    };
+    let query = HandDrawsQuery {
+        hand: item.hand,
+        draws_left: item.draws_left,
+        format: None,
+    };
+    match query.validate(false) {
+        Ok((hand, draws_left)) => match analyzer.analyze(&hand, draws_left).await {
+            Ok(analysis) => entry.analysis = Some(analysis),
+            Err(e) => entry.error = Some(format!("Failed to analyze hand: {}", e)),
+        },
+        Err(e) => {
+            entry.error = Some(format!("Invalid parameters: {}", e.message()));
+            entry.details = e.0;
+        }
+    }
+    entry
+}
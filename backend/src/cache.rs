@@ -0,0 +1,165 @@
+use anyhow::Result;
+use common::mahjong::Metrics;
+use lru::LruCache;
+use serde::Serialize;
+use std::{
+    future::Future,
+    num::NonZeroUsize,
+    ops::Range,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+use utoipa::ToSchema;
+
+/// どのデータファイルの行か
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TableKind {
+    Tsumo13,
+    Tsumo14,
+    Tenpai13,
+    Tenpai14,
+    Turns13,
+    Turns14,
+    /// 副露した手牌のツモ率。門前の牌の枚数ごと
+    OpenTsumo(u8),
+    Metrics13,
+    Metrics14,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct RowKey {
+    table: TableKind,
+    hand_id: u32,
+    rounds: Range<usize>,
+}
+
+/// データファイルから読み出した行のLRUキャッシュ
+///
+/// 解説記事の手牌や共有リンクなど、同じ手牌が繰り返し分析されるときにファイルを読まずに済ませる。
+/// 容量はツモ率・メトリクスそれぞれの行数で、0なら何もキャッシュしない。
+pub struct RowCache {
+    capacity: usize,
+    tsumo: Option<Mutex<LruCache<RowKey, Vec<u32>>>>,
+    metrics: Option<Mutex<LruCache<RowKey, Vec<Metrics>>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// キャッシュの統計情報
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CacheStats {
+    pub capacity: usize,
+    pub tsumo_entries: usize,
+    pub metrics_entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// 参照のうちキャッシュにあった割合。参照がなければ`null`
+    pub hit_rate: Option<f64>,
+}
+
+impl RowCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            tsumo: new_lru(capacity),
+            metrics: new_lru(capacity),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// ツモ率の行を返す。キャッシュになければ`load`で読み出して登録する
+    pub async fn tsumo<F>(
+        &self,
+        table: TableKind,
+        hand_id: u32,
+        rounds: Range<usize>,
+        load: F,
+    ) -> Result<Vec<u32>>
+    where
+        F: Future<Output = Result<Vec<u32>>>,
+    {
+        self.get_or_load(self.tsumo.as_ref(), table, hand_id, rounds, load)
+            .await
+    }
+
+    /// メトリクスの行を返す。キャッシュになければ`load`で読み出して登録する
+    pub async fn metrics<F>(
+        &self,
+        table: TableKind,
+        hand_id: u32,
+        rounds: Range<usize>,
+        load: F,
+    ) -> Result<Vec<Metrics>>
+    where
+        F: Future<Output = Result<Vec<Metrics>>>,
+    {
+        self.get_or_load(self.metrics.as_ref(), table, hand_id, rounds, load)
+            .await
+    }
+
+    async fn get_or_load<T: Clone, F>(
+        &self,
+        lru: Option<&Mutex<LruCache<RowKey, Vec<T>>>>,
+        table: TableKind,
+        hand_id: u32,
+        rounds: Range<usize>,
+        load: F,
+    ) -> Result<Vec<T>>
+    where
+        F: Future<Output = Result<Vec<T>>>,
+    {
+        let Some(lru) = lru else {
+            return load.await;
+        };
+        let key = RowKey {
+            table,
+            hand_id,
+            rounds,
+        };
+        // 読み出し中はロックを持たない。同じ行を同時に読んだ場合は後から登録した方が残る
+        if let Some(rows) = lru.lock().unwrap().get(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(rows.clone());
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let rows = load.await?;
+        lru.lock().unwrap().put(key, rows.clone());
+        Ok(rows)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        CacheStats {
+            capacity: self.capacity,
+            tsumo_entries: self.tsumo.as_ref().map_or(0, |c| c.lock().unwrap().len()),
+            metrics_entries: self.metrics.as_ref().map_or(0, |c| c.lock().unwrap().len()),
+            hits,
+            misses,
+            hit_rate: (hits + misses > 0).then(|| hits as f64 / (hits + misses) as f64),
+        }
+    }
+
+    /// すべての行を捨て、捨てた行数を返す
+    pub fn clear(&self) -> usize {
+        let mut cleared = 0;
+        if let Some(c) = &self.tsumo {
+            let mut c = c.lock().unwrap();
+            cleared += c.len();
+            c.clear();
+        }
+        if let Some(c) = &self.metrics {
+            let mut c = c.lock().unwrap();
+            cleared += c.len();
+            c.clear();
+        }
+        cleared
+    }
+}
+
+fn new_lru<T>(capacity: usize) -> Option<Mutex<LruCache<RowKey, Vec<T>>>> {
+    NonZeroUsize::new(capacity).map(|c| Mutex::new(LruCache::new(c)))
+}
@@ -0,0 +1,317 @@
+use anyhow::Result;
+use figment::{
+    providers::{Env, Format, Serialized, Toml, Yaml},
+    Figment,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, net::SocketAddr, path::Path};
+
+use crate::cors::CorsConfig;
+use crate::dataset::DEFAULT_DATASET;
+use crate::table::{IoMode, IoModes, TableOptions};
+
+/// 環境変数のプレフィックス
+const ENV_PREFIX: &str = "MAHJONG_";
+
+/// サーバー設定
+///
+/// 既定値 < 設定ファイル < `MAHJONG_*`環境変数 < コマンドライン引数 の順に上書きする。
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// HandConverterファイルのパス
+    pub conv_path: String,
+    /// 13枚用ツモ率データファイルのパス
+    pub tsumo_13_path: String,
+    /// 14枚用ツモ率データファイルのパス
+    pub tsumo_14_path: String,
+    /// 13枚用メトリクスデータファイルのパス
+    pub metrics_13_path: String,
+    /// 14枚用メトリクスデータファイルのパス
+    pub metrics_14_path: String,
+    /// 和了形ごとのツモ率データファイル（`tsumo_13_standard.dat`など）を置いたディレクトリ。
+    /// 省略時はツモ率の和了形ごとの内訳を返さない
+    pub tsumo_family_dir: Option<String>,
+    /// 副露した手牌のツモ率データファイル（`tsumo_10.dat`など）を置いたディレクトリ。
+    /// 省略時は副露した手牌（門前の牌が4・5・7・8・10・11枚）のツモ率を返さない
+    pub open_tsumo_dir: Option<String>,
+    /// 13枚用聴牌率データファイルのパス。省略時は`/analyze-tenpai`を提供しない
+    pub tenpai_13_path: Option<String>,
+    /// 14枚用聴牌率データファイルのパス。`tenpai_13_path`と同時に指定する
+    pub tenpai_14_path: Option<String>,
+    /// 13枚用の和了したときの残り巡数の期待値のデータファイルのパス。省略時はツモ率に和了までのツモ回数の期待値を含めない
+    pub tsumo_13_turns_path: Option<String>,
+    /// 14枚用の和了したときの残り巡数の期待値のデータファイルのパス。`tsumo_13_turns_path`と同時に指定する
+    pub tsumo_14_turns_path: Option<String>,
+    /// 既定のデータセット以外に配信するデータセット（三人麻雀・ルール違いなど）。名前で選ぶ
+    #[serde(default)]
+    pub datasets: BTreeMap<String, DatasetConfig>,
+
+    /// 待ち受けるアドレス
+    #[serde(default = "default_listen_addr")]
+    pub listen_addr: SocketAddr,
+    /// 起動時・再読み込み時にデータファイルの先頭と末尾の行を読んでおく
+    #[serde(default = "default_warm_up")]
+    pub warm_up: bool,
+    /// リクエストのタイムアウト（秒）
+    #[serde(default = "default_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+    /// 同時に処理するリクエストの最大数
+    #[serde(default = "default_max_concurrent_requests")]
+    pub max_concurrent_requests: usize,
+    /// データファイル全体を起動時にメモリに読み込み、リクエストごとにファイルから読まない。`io_mode = "memory"`と同じ
+    #[serde(default)]
+    pub preload: bool,
+    /// データファイルの読み出し方（省略時はfile）
+    pub io_mode: Option<IoMode>,
+    /// データファイルごとの読み出し方（省略時は`io_mode`）
+    pub tsumo_13_io_mode: Option<IoMode>,
+    pub tsumo_14_io_mode: Option<IoMode>,
+    pub metrics_13_io_mode: Option<IoMode>,
+    pub metrics_14_io_mode: Option<IoMode>,
+    /// オブジェクトストレージ（`s3://`、`gs://`）から1回に読み出すバイト数
+    #[serde(default = "default_object_block_bytes")]
+    pub object_block_bytes: usize,
+    /// オブジェクトストレージから読み出したブロックをファイルごとにキャッシュする数
+    #[serde(default = "default_object_cache_blocks")]
+    pub object_cache_blocks: usize,
+    /// 読み出した行をキャッシュする数（ツモ率・メトリクスそれぞれ）。0ならキャッシュしない
+    #[serde(default = "default_cache_capacity")]
+    pub cache_capacity: usize,
+    /// バッチ分析のリクエストボディの最大サイズ（バイト）
+    #[serde(default = "default_max_body_bytes")]
+    pub max_body_bytes: usize,
+
+    #[serde(flatten)]
+    pub cors: CorsConfig,
+
+    /// TLS証明書（PEM）のパス
+    pub tls_cert: Option<String>,
+    /// TLS秘密鍵（PEM）のパス
+    pub tls_key: Option<String>,
+
+    /// シャドー検証用HandConverterファイルのパス（省略時は主系と同じ）
+    pub shadow_conv_path: Option<String>,
+    pub shadow_tsumo_13_path: Option<String>,
+    pub shadow_tsumo_14_path: Option<String>,
+    pub shadow_metrics_13_path: Option<String>,
+    pub shadow_metrics_14_path: Option<String>,
+    /// シャドー検証を行うリクエストの割合（0.0〜1.0）
+    #[serde(default = "default_shadow_fraction")]
+    pub shadow_fraction: f64,
+    /// シャドー検証で許容する確率の誤差
+    #[serde(default = "default_shadow_tolerance")]
+    pub shadow_tolerance: f64,
+
+    /// 管理用エンドポイントのBearerトークン。省略時は管理用エンドポイントを無効にする
+    pub admin_token: Option<Secret>,
+
+    /// 起動時に既知の手牌で自己診断を行い、失敗したら起動しない
+    #[serde(default = "default_self_test")]
+    pub self_test: bool,
+}
+
+/// 1組のデータセット（converterとデータファイル）の設定
+#[derive(Debug, Clone, Deserialize)]
+pub struct DatasetConfig {
+    pub conv_path: String,
+    pub tsumo_13_path: String,
+    pub tsumo_14_path: String,
+    pub metrics_13_path: String,
+    pub metrics_14_path: String,
+    pub tsumo_family_dir: Option<String>,
+    pub open_tsumo_dir: Option<String>,
+    pub tenpai_13_path: Option<String>,
+    pub tenpai_14_path: Option<String>,
+    pub tsumo_13_turns_path: Option<String>,
+    pub tsumo_14_turns_path: Option<String>,
+    /// 起動時・再読み込み時の自己診断（省略時はトップレベルの`self_test`）。
+    /// 既知の手牌の期待値は標準ルールのものなので、ルール違いのデータセットでは`false`にする
+    pub self_test: Option<bool>,
+}
+
+/// ログに出さない設定値。`Debug`では中身を伏せる
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"***\"")
+    }
+}
+
+fn default_listen_addr() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 3000))
+}
+
+fn default_warm_up() -> bool {
+    true
+}
+
+fn default_request_timeout_secs() -> u64 {
+    30
+}
+
+fn default_max_concurrent_requests() -> usize {
+    256
+}
+
+fn default_object_block_bytes() -> usize {
+    1 << 16
+}
+
+fn default_object_cache_blocks() -> usize {
+    4096
+}
+
+fn default_cache_capacity() -> usize {
+    4096
+}
+
+fn default_max_body_bytes() -> usize {
+    1 << 20
+}
+
+fn default_shadow_fraction() -> f64 {
+    0.01
+}
+
+fn default_shadow_tolerance() -> f64 {
+    1e-9
+}
+
+fn default_self_test() -> bool {
+    true
+}
+
+impl Config {
+    /// 設定ファイル・環境変数・コマンドライン引数を重ねて設定を読み込む
+    ///
+    /// 設定ファイルは拡張子が`.yaml`/`.yml`ならYAML、それ以外はTOMLとして読む。
+    /// `overrides`で`None`のフィールドは下位の設定を上書きしないよう、シリアライズ時に省略しておくこと。
+    pub fn load(config_path: Option<&Path>, overrides: impl Serialize) -> Result<Self> {
+        let mut figment = Figment::new();
+        if let Some(path) = config_path {
+            let is_yaml = matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("yaml" | "yml")
+            );
+            figment = if is_yaml {
+                figment.merge(Yaml::file_exact(path))
+            } else {
+                figment.merge(Toml::file_exact(path))
+            };
+        }
+        let config: Self = figment
+            .merge(Env::prefixed(ENV_PREFIX).ignore(&["config"]))
+            .merge(Serialized::defaults(overrides))
+            .extract()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// データファイルごとの読み出し方
+    pub fn io_modes(&self) -> IoModes {
+        let default = match self.io_mode {
+            Some(mode) => mode,
+            None if self.preload => IoMode::Memory,
+            None => IoMode::File,
+        };
+        IoModes {
+            tsumo_13: self.tsumo_13_io_mode.unwrap_or(default),
+            tsumo_14: self.tsumo_14_io_mode.unwrap_or(default),
+            metrics_13: self.metrics_13_io_mode.unwrap_or(default),
+            metrics_14: self.metrics_14_io_mode.unwrap_or(default),
+        }
+    }
+
+    /// 既定のデータセットの設定
+    pub fn default_dataset(&self) -> DatasetConfig {
+        DatasetConfig {
+            conv_path: self.conv_path.clone(),
+            tsumo_13_path: self.tsumo_13_path.clone(),
+            tsumo_14_path: self.tsumo_14_path.clone(),
+            metrics_13_path: self.metrics_13_path.clone(),
+            metrics_14_path: self.metrics_14_path.clone(),
+            tsumo_family_dir: self.tsumo_family_dir.clone(),
+            open_tsumo_dir: self.open_tsumo_dir.clone(),
+            tenpai_13_path: self.tenpai_13_path.clone(),
+            tenpai_14_path: self.tenpai_14_path.clone(),
+            tsumo_13_turns_path: self.tsumo_13_turns_path.clone(),
+            tsumo_14_turns_path: self.tsumo_14_turns_path.clone(),
+            self_test: None,
+        }
+    }
+
+    /// データファイルの開き方
+    pub fn table_options(&self) -> TableOptions {
+        TableOptions {
+            io_modes: self.io_modes(),
+            object_block_bytes: self.object_block_bytes,
+            object_cache_blocks: self.object_cache_blocks,
+        }
+    }
+
+    /// 複数の設定元にまたがる組み合わせの整合性を検証する
+    fn validate(&self) -> Result<()> {
+        if self.preload && self.io_mode.is_some_and(|mode| mode != IoMode::Memory) {
+            return Err(anyhow::anyhow!(
+                "preload cannot be combined with io_mode other than memory"
+            ));
+        }
+        for name in self.datasets.keys() {
+            let valid = !name.is_empty()
+                && name
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+            if !valid || name == DEFAULT_DATASET {
+                return Err(anyhow::anyhow!(
+                    "Invalid dataset name {:?}: must be [A-Za-z0-9_-]+ and not {:?}",
+                    name,
+                    DEFAULT_DATASET
+                ));
+            }
+        }
+        let datasets =
+            std::iter::once(self.default_dataset()).chain(self.datasets.values().cloned());
+        for dataset in datasets {
+            if dataset.tenpai_13_path.is_some() != dataset.tenpai_14_path.is_some() {
+                return Err(anyhow::anyhow!(
+                    "tenpai_13_path and tenpai_14_path must be specified together"
+                ));
+            }
+            if dataset.tsumo_13_turns_path.is_some() != dataset.tsumo_14_turns_path.is_some() {
+                return Err(anyhow::anyhow!(
+                    "tsumo_13_turns_path and tsumo_14_turns_path must be specified together"
+                ));
+            }
+        }
+        if self.object_block_bytes == 0 {
+            return Err(anyhow::anyhow!("object_block_bytes must be positive"));
+        }
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            return Err(anyhow::anyhow!(
+                "tls_cert and tls_key must be specified together"
+            ));
+        }
+        let shadow_paths = [
+            &self.shadow_tsumo_13_path,
+            &self.shadow_tsumo_14_path,
+            &self.shadow_metrics_13_path,
+            &self.shadow_metrics_14_path,
+        ];
+        let num_shadow_paths = shadow_paths.iter().filter(|p| p.is_some()).count();
+        if num_shadow_paths != 0 && num_shadow_paths != shadow_paths.len() {
+            return Err(anyhow::anyhow!(
+                "shadow_tsumo_13_path, shadow_tsumo_14_path, shadow_metrics_13_path and shadow_metrics_14_path must be specified together"
+            ));
+        }
+        Ok(())
+    }
+}
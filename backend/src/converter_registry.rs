@@ -0,0 +1,126 @@
+use anyhow::Result;
+use common::{
+    io::{file_content_hash, read_header_hash},
+    mahjong::{HandConverter, CONVERTER_MAGIC},
+};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use utoipa::ToSchema;
+
+/// 読み込み済みHandConverterの共有レジストリ
+///
+/// 同じ内容のconverterファイルは1つの`Arc<HandConverter>`を共有する。
+/// converterは数十MBの順位付けの表を持つため、データセットごとに読み込むとその分メモリを使う。
+#[derive(Clone, Default)]
+pub struct ConverterRegistry {
+    inner: Arc<Mutex<RegistryInner>>,
+}
+
+#[derive(Default)]
+struct RegistryInner {
+    converters: HashMap<u64, Arc<HandConverter>>,
+    // 重複として共有された読み込み要求の数
+    dedup_hits: usize,
+    // ファイルから読み込んだ回数
+    loads: usize,
+}
+
+/// レジストリの統計情報
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ConverterRegistryStats {
+    pub loaded: usize,
+    pub dedup_hits: usize,
+    /// 読み込み要求のうち既存のconverterを共有できた割合。要求がなければ`null`
+    pub hit_rate: Option<f64>,
+    pub converters: Vec<ConverterStats>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ConverterStats {
+    pub hash: String,
+    pub references: usize,
+}
+
+impl ConverterRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// converterを読み込む。同じハッシュのconverterが既にあればそれを返す
+    pub fn load(&self, path: impl AsRef<Path>) -> Result<Arc<HandConverter>> {
+        let hash = converter_hash(&path)?;
+        {
+            let mut inner = self.inner.lock().unwrap();
+            if let Some(converter) = inner.converters.get(&hash) {
+                let converter = converter.clone();
+                inner.dedup_hits += 1;
+                return Ok(converter);
+            }
+        }
+        // ファイルの読み込みはブロッキングI/Oのため、その間はロックを持たず統計情報などを返せるようにする。
+        // 同じconverterを同時に読み込んだ場合は先に登録した方を共有する
+        let loaded = Arc::new(HandConverter::open(path)?);
+        let mut inner = self.inner.lock().unwrap();
+        let mut inserted = false;
+        let converter = inner
+            .converters
+            .entry(hash)
+            .or_insert_with(|| {
+                inserted = true;
+                loaded
+            })
+            .clone();
+        if inserted {
+            inner.loads += 1;
+        } else {
+            inner.dedup_hits += 1;
+        }
+        Ok(converter)
+    }
+
+    pub fn stats(&self) -> ConverterRegistryStats {
+        let inner = self.inner.lock().unwrap();
+        let mut converters: Vec<ConverterStats> = inner
+            .converters
+            .iter()
+            .map(|(hash, converter)| ConverterStats {
+                hash: format!("{:016x}", hash),
+                // レジストリ自身が保持している分を除く
+                references: Arc::strong_count(converter) - 1,
+            })
+            .collect();
+        converters.sort_by(|a, b| a.hash.cmp(&b.hash));
+        let requests = inner.dedup_hits + inner.loads;
+        ConverterRegistryStats {
+            loaded: inner.converters.len(),
+            dedup_hits: inner.dedup_hits,
+            hit_rate: (requests > 0).then(|| inner.dedup_hits as f64 / requests as f64),
+            converters,
+        }
+    }
+
+    /// どの分析エンジンからも参照されていないconverterを解放し、解放した数を返す
+    pub fn evict_unused(&self) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let before = inner.converters.len();
+        inner
+            .converters
+            .retain(|_, converter| Arc::strong_count(converter) > 1);
+        before - inner.converters.len()
+    }
+}
+
+/// converterファイルの内容のハッシュを返す
+///
+/// `generate_hand_converter`が書き出すファイルはヘッダーに内容のハッシュを持つため、それを使う。
+/// `--raw`のファイルやヘッダーのない以前のファイルは、ファイル全体をハッシュする。
+fn converter_hash(path: impl AsRef<Path>) -> Result<u64> {
+    match read_header_hash(&path, CONVERTER_MAGIC)? {
+        Some(hash) => Ok(hash),
+        None => file_content_hash(&path),
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::Result;
+use axum::http::{HeaderName, HeaderValue, Method};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::time::Duration;
+use tower_http::cors::{AllowHeaders, AllowOrigin, Any, CorsLayer};
+
+/// CORS設定のコマンドライン引数。省略した項目は設定ファイル・環境変数の値を使う
+#[derive(clap::Args, Serialize, Debug, Clone)]
+pub struct CorsArgs {
+    /// 許可するオリジン（カンマ区切り、省略時または`*`ですべて許可）
+    #[arg(long, value_delimiter = ',')]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cors_allow_origins: Option<Vec<String>>,
+
+    /// 許可するHTTPメソッド（カンマ区切り、既定はGET,POST）
+    #[arg(long, value_delimiter = ',')]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cors_allow_methods: Option<Vec<String>>,
+
+    /// 許可するリクエストヘッダー（カンマ区切り、省略時または`*`ですべて許可）
+    #[arg(long, value_delimiter = ',')]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cors_allow_headers: Option<Vec<String>>,
+
+    /// プリフライトレスポンスのキャッシュ時間（秒）
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cors_max_age_secs: Option<u64>,
+}
+
+/// CORS設定。省略時はローカル開発向けにすべてのオリジン・ヘッダーを許可する
+///
+/// リストは配列のほか、環境変数向けにカンマ区切りの文字列も受け付ける。
+#[derive(Deserialize, Debug, Clone)]
+pub struct CorsConfig {
+    #[serde(default, deserialize_with = "comma_separated")]
+    pub cors_allow_origins: Vec<String>,
+    #[serde(
+        default = "default_cors_allow_methods",
+        deserialize_with = "comma_separated"
+    )]
+    pub cors_allow_methods: Vec<String>,
+    #[serde(default, deserialize_with = "comma_separated")]
+    pub cors_allow_headers: Vec<String>,
+    pub cors_max_age_secs: Option<u64>,
+}
+
+fn default_cors_allow_methods() -> Vec<String> {
+    vec!["GET".to_string(), "POST".to_string()]
+}
+
+fn comma_separated<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrList {
+        String(String),
+        List(Vec<String>),
+    }
+
+    Ok(match StringOrList::deserialize(deserializer)? {
+        StringOrList::String(s) => s
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect(),
+        StringOrList::List(list) => list,
+    })
+}
+
+/// リストが空か`*`を含めばすべて許可する
+fn allows_any(list: &[String]) -> bool {
+    list.is_empty() || list.iter().any(|v| v.trim() == "*")
+}
+
+impl CorsConfig {
+    /// 設定からCORSレイヤーを作成
+    pub fn layer(&self) -> Result<CorsLayer> {
+        let methods = self
+            .cors_allow_methods
+            .iter()
+            .map(|m| Method::from_bytes(m.trim().as_bytes()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| anyhow::anyhow!("Invalid CORS method: {}", e))?;
+
+        // `*`は個別の値としてリストに渡すとtower-httpがpanicするため、すべて許可として扱う
+        let origins = if allows_any(&self.cors_allow_origins) {
+            AllowOrigin::from(Any)
+        } else {
+            let origins = self
+                .cors_allow_origins
+                .iter()
+                .map(|o| HeaderValue::from_str(o.trim()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| anyhow::anyhow!("Invalid CORS origin: {}", e))?;
+            AllowOrigin::list(origins)
+        };
+
+        let headers = if allows_any(&self.cors_allow_headers) {
+            AllowHeaders::from(Any)
+        } else {
+            let headers = self
+                .cors_allow_headers
+                .iter()
+                .map(|h| HeaderName::from_bytes(h.trim().as_bytes()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| anyhow::anyhow!("Invalid CORS header: {}", e))?;
+            AllowHeaders::list(headers)
+        };
+
+        let mut cors = CorsLayer::new()
+            .allow_methods(methods)
+            .allow_origin(origins)
+            .allow_headers(headers);
+        if let Some(max_age) = self.cors_max_age_secs {
+            cors = cors.max_age(Duration::from_secs(max_age));
+        }
+        Ok(cors)
+    }
+}
@@ -0,0 +1,308 @@
+use anyhow::Result;
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{request::Parts, HeaderMap, HeaderName, Uri},
+};
+use common::mahjong::AgariFamily;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+use tracing::{error, info, warn};
+
+use crate::analysis::{open_tsumo_path, tsumo_family_path, SharedHandAnalyzer, OPEN_HAND_TILES};
+use crate::config::{Config, DatasetConfig};
+use crate::converter_registry::ConverterRegistry;
+use crate::etag::DatasetVersion;
+use crate::params::{DatasetQuery, FieldError, InvalidParams};
+
+/// トップレベルの設定で指定したデータセットの名前
+pub const DEFAULT_DATASET: &str = "default";
+
+/// データセットを名前で選ぶヘッダー。`dataset`クエリパラメータがあればそちらを優先する
+pub static X_DATASET: HeaderName = HeaderName::from_static("x-dataset");
+
+/// 起動時に読み込んだすべてのデータセット
+///
+/// converterは`ConverterRegistry`で共有するため、同じconverterを使うルール違いのデータセットを
+/// 1つのプロセスで配信してもconverterの分のメモリは増えない。
+#[derive(Clone)]
+pub struct Datasets(Arc<BTreeMap<String, LiveDataset>>);
+
+impl Datasets {
+    /// 既定のデータセットと`datasets`で指定したデータセットを読み込む
+    pub async fn open(config: Arc<Config>, converters: ConverterRegistry) -> Result<Self> {
+        let mut datasets = BTreeMap::new();
+        let named = std::iter::once((DEFAULT_DATASET.to_string(), config.default_dataset()))
+            .chain(config.datasets.clone());
+        for (name, settings) in named {
+            let dataset = LiveDataset::open(&name, settings, config.clone(), converters.clone())
+                .await
+                .map_err(|e| anyhow::anyhow!("dataset {}: {}", name, e))?;
+            info!(
+                "Dataset loaded: name={}, version={}",
+                name,
+                dataset.version()
+            );
+            datasets.insert(name, dataset);
+        }
+        Ok(Self(Arc::new(datasets)))
+    }
+
+    /// 既定のデータセット
+    pub fn default_dataset(&self) -> &LiveDataset {
+        &self.0[DEFAULT_DATASET]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &LiveDataset> {
+        self.0.values()
+    }
+
+    /// `dataset`クエリパラメータまたは`X-Dataset`ヘッダーで選ばれたデータセット。省略時は既定のデータセット
+    pub fn select(&self, uri: &Uri, headers: &HeaderMap) -> Result<&LiveDataset, InvalidParams> {
+        let from_query =
+            serde_urlencoded::from_str::<DatasetQuery>(uri.query().unwrap_or_default())
+                .ok()
+                .and_then(|query| query.dataset);
+        let name = match from_query {
+            Some(name) => name,
+            None => match headers.get(&X_DATASET) {
+                Some(value) => value.to_str().unwrap_or_default().to_string(),
+                None => return Ok(self.default_dataset()),
+            },
+        };
+        self.0.get(&name).ok_or_else(|| {
+            let names: Vec<&str> = self.0.keys().map(String::as_str).collect();
+            InvalidParams(vec![FieldError::new(
+                "dataset",
+                format!("unknown dataset (available: {})", names.join(", ")),
+            )])
+        })
+    }
+}
+
+/// リクエストで選ばれたデータセット
+pub struct SelectedDataset(pub LiveDataset);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for SelectedDataset
+where
+    Datasets: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = InvalidParams;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let datasets = Datasets::from_ref(state);
+        let dataset = datasets.select(&parts.uri, &parts.headers)?;
+        Ok(SelectedDataset(dataset.clone()))
+    }
+}
+
+/// 配信中の分析エンジンとデータセットのバージョン
+///
+/// データファイルやconverterを作り直したときに、再起動せずに丸ごと差し替えられるようにする。
+/// ハンドラーはリクエストごとに`analyzer()`で現在の分析エンジンを取り出して使う。
+#[derive(Clone)]
+pub struct LiveDataset {
+    name: Arc<str>,
+    current: Arc<RwLock<Dataset>>,
+    // 再読み込みは同時に1つだけ行う
+    reloading: Arc<tokio::sync::Mutex<()>>,
+    settings: Arc<DatasetConfig>,
+    config: Arc<Config>,
+    converters: ConverterRegistry,
+}
+
+#[derive(Clone)]
+struct Dataset {
+    analyzer: SharedHandAnalyzer,
+    version: DatasetVersion,
+}
+
+impl LiveDataset {
+    /// `settings`のデータセットを読み込む。自己診断に失敗したときはエラーを返す
+    pub async fn open(
+        name: &str,
+        settings: DatasetConfig,
+        config: Arc<Config>,
+        converters: ConverterRegistry,
+    ) -> Result<Self> {
+        let settings = Arc::new(settings);
+        let dataset = load(settings.clone(), config.clone(), converters.clone()).await?;
+        Ok(Self {
+            name: name.into(),
+            current: Arc::new(RwLock::new(dataset)),
+            reloading: Arc::default(),
+            settings,
+            config,
+            converters,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 既定のデータセットか
+    pub fn is_default(&self) -> bool {
+        &*self.name == DEFAULT_DATASET
+    }
+
+    /// 現在の分析エンジン
+    pub fn analyzer(&self) -> SharedHandAnalyzer {
+        self.current.read().unwrap().analyzer.clone()
+    }
+
+    /// 現在のデータセットのバージョン
+    pub fn version(&self) -> DatasetVersion {
+        self.current.read().unwrap().version
+    }
+
+    /// 同じパスからデータセットを読み込み直して差し替え、新しいバージョンを返す
+    ///
+    /// 読み込みや自己診断に失敗したときは差し替えず、それまでのデータセットで配信を続ける。
+    /// 差し替え前の分析エンジンを使っているリクエストはそのまま処理を終え、
+    /// 最後の参照がなくなった時点でファイルハンドルやメモリマップが解放される。
+    pub async fn reload(&self) -> Result<DatasetVersion> {
+        let _reloading = self.reloading.lock().await;
+        info!("Reloading dataset: name={}", self.name);
+        let dataset = load(
+            self.settings.clone(),
+            self.config.clone(),
+            self.converters.clone(),
+        )
+        .await?;
+        let version = dataset.version;
+        let previous = std::mem::replace(&mut *self.current.write().unwrap(), dataset);
+        drop(previous);
+        // 処理中のリクエストが参照しているconverterは残り、それ以外は解放される
+        let evicted = self.converters.evict_unused();
+        info!(
+            "Dataset reloaded: name={}, version={}, evicted_converters={}",
+            self.name, version, evicted
+        );
+        Ok(version)
+    }
+}
+
+async fn load(
+    settings: Arc<DatasetConfig>,
+    config: Arc<Config>,
+    converters: ConverterRegistry,
+) -> Result<Dataset> {
+    // converterやデータファイルの読み込みはブロッキングするため、Tokioのワーカースレッドでは行わない
+    let self_test = settings.self_test.unwrap_or(config.self_test);
+    let warm_up = config.warm_up;
+    let dataset = tokio::task::spawn_blocking(move || -> Result<Dataset> {
+        // 同じ内容のconverterは共有される
+        let converter = converters
+            .load(&settings.conv_path)
+            .map_err(|e| anyhow::anyhow!("Failed to load hand converter: {}", e))?;
+        let analyzer = SharedHandAnalyzer::new(
+            converter,
+            &settings.tsumo_13_path,
+            &settings.tsumo_14_path,
+            &settings.metrics_13_path,
+            &settings.metrics_14_path,
+            &config.table_options(),
+        )
+        .map_err(|e| anyhow::anyhow!("Failed to initialize hand analyzer: {}", e))?
+        .with_cache_capacity(config.cache_capacity);
+        let mut files = vec![
+            settings.conv_path.clone(),
+            settings.tsumo_13_path.clone(),
+            settings.tsumo_14_path.clone(),
+            settings.metrics_13_path.clone(),
+            settings.metrics_14_path.clone(),
+        ];
+        let analyzer = match &settings.tsumo_family_dir {
+            Some(dir) => {
+                for num_tiles in [13, 14] {
+                    for family in AgariFamily::ALL {
+                        files.push(tsumo_family_path(dir, num_tiles, family));
+                    }
+                }
+                analyzer
+                    .with_tsumo_families(dir, &config.table_options())
+                    .map_err(|e| anyhow::anyhow!("Failed to open tsumo family tables: {}", e))?
+            }
+            None => analyzer,
+        };
+        let analyzer = match &settings.open_tsumo_dir {
+            Some(dir) => {
+                for num_tiles in OPEN_HAND_TILES {
+                    files.push(open_tsumo_path(dir, num_tiles));
+                }
+                analyzer
+                    .with_open_tsumo(dir, &config.table_options())
+                    .map_err(|e| anyhow::anyhow!("Failed to open open hand tsumo tables: {}", e))?
+            }
+            None => analyzer,
+        };
+        let analyzer = match (&settings.tenpai_13_path, &settings.tenpai_14_path) {
+            (Some(tenpai_13_path), Some(tenpai_14_path)) => {
+                files.push(tenpai_13_path.clone());
+                files.push(tenpai_14_path.clone());
+                analyzer
+                    .with_tenpai(tenpai_13_path, tenpai_14_path, &config.table_options())
+                    .map_err(|e| anyhow::anyhow!("Failed to open tenpai tables: {}", e))?
+            }
+            _ => analyzer,
+        };
+        let analyzer = match (&settings.tsumo_13_turns_path, &settings.tsumo_14_turns_path) {
+            (Some(turns_13_path), Some(turns_14_path)) => {
+                files.push(turns_13_path.clone());
+                files.push(turns_14_path.clone());
+                analyzer
+                    .with_turns(turns_13_path, turns_14_path, &config.table_options())
+                    .map_err(|e| anyhow::anyhow!("Failed to open turns tables: {}", e))?
+            }
+            _ => analyzer,
+        };
+        // ETag用にデータセットのバージョンを計算
+        let version = DatasetVersion::from_files(&files)
+            .map_err(|e| anyhow::anyhow!("Failed to read data file metadata: {}", e))?;
+        Ok(Dataset { analyzer, version })
+    })
+    .await??;
+    info!("Hand analyzer initialized successfully");
+
+    // 失敗しても配信はできるため、警告だけ出して続ける。データの誤りは自己診断で検出する
+    if warm_up {
+        let started = std::time::Instant::now();
+        for (name, result) in dataset.analyzer.warm_up().await {
+            match result {
+                Ok(()) => info!("Warmed up {}", name),
+                Err(e) => warn!("Failed to warm up {}: {}", name, e),
+            }
+        }
+        info!("Warm-up finished in {:?}", started.elapsed());
+    }
+
+    // 既知の手牌でデータファイルを検査し、壊れたデータや組み合わせの誤りで配信しないようにする
+    if self_test {
+        let health = dataset.analyzer.check_health().await;
+        for file in health.files.iter().filter(|f| !f.ok) {
+            error!(
+                "Self-test failed: file={}, expected_len={}, actual_len={:?}, error={:?}",
+                file.name, file.expected_len, file.actual_len, file.error
+            );
+        }
+        for sample in health.samples.iter().filter(|s| !s.ok) {
+            error!(
+                "Self-test failed: sample={}, hand={}, error={:?}",
+                sample.name, sample.hand, sample.error
+            );
+        }
+        if !health.ok {
+            return Err(anyhow::anyhow!(
+                "Self-test failed (use --self-test false to skip)"
+            ));
+        }
+        info!("Self-test passed: {} samples", health.samples.len());
+    }
+
+    Ok(dataset)
+}
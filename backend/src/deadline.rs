@@ -0,0 +1,88 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json as JsonResponse, Response},
+};
+use std::time::Duration;
+use tracing::info;
+
+use crate::access_log::current_request_id;
+use crate::params::{FieldError, InvalidParams};
+use crate::ErrorResponse;
+
+/// サーバー側のタイムアウトで打ち切ったリクエストに`Retry-After`で示す再試行までの秒数
+const TIMEOUT_RETRY_AFTER_SECS: u64 = 5;
+
+/// クライアントが結果を待つ残り時間（ミリ秒）を表すヘッダー
+static X_DEADLINE_MS: HeaderName = HeaderName::from_static("x-deadline-ms");
+
+/// `X-Deadline-Ms`の時間を過ぎたリクエストの処理を打ち切るミドルウェア
+///
+/// 打ち切るとハンドラーのfutureが破棄され、まだ始まっていないデータファイルの読み出しは行われない。
+/// 同時実行数の空き待ちも時間に含める。
+pub async fn enforce_deadline(req: Request, next: Next) -> Response {
+    let deadline_ms = match req.headers().get(&X_DEADLINE_MS) {
+        None => return next.run(req).await,
+        Some(value) => match value.to_str().ok().and_then(|v| v.parse::<u64>().ok()) {
+            Some(deadline_ms) => deadline_ms,
+            None => {
+                return InvalidParams(vec![FieldError::new(
+                    "X-Deadline-Ms",
+                    "must be a non-negative integer (milliseconds)",
+                )])
+                .into_response()
+            }
+        },
+    };
+
+    match tokio::time::timeout(Duration::from_millis(deadline_ms), next.run(req)).await {
+        Ok(response) => response,
+        Err(_) => {
+            info!(
+                "Request aborted after deadline: deadline_ms={}",
+                deadline_ms
+            );
+            (
+                StatusCode::GATEWAY_TIMEOUT,
+                JsonResponse(ErrorResponse {
+                    error: "Deadline exceeded".to_string(),
+                    code: "DEADLINE_EXCEEDED".to_string(),
+                    message: format!("Deadline exceeded: {}ms", deadline_ms),
+                    details: Vec::new(),
+                    request_id: current_request_id(),
+                }),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// `request_timeout_secs`を過ぎたリクエストの処理を打ち切るミドルウェア
+///
+/// 遅いのはクライアントの送信ではなくサーバーの処理なので、408ではなく503と`Retry-After`を返す。
+/// 408を返すとブラウザが自動で再送し、混雑しているときに負荷が倍になる。同時実行数の空き待ちも時間に含める。
+pub async fn enforce_timeout(State(timeout): State<Duration>, req: Request, next: Next) -> Response {
+    match tokio::time::timeout(timeout, next.run(req)).await {
+        Ok(response) => response,
+        Err(_) => {
+            info!("Request timed out: timeout_secs={}", timeout.as_secs());
+            let mut response = (
+                StatusCode::SERVICE_UNAVAILABLE,
+                JsonResponse(ErrorResponse {
+                    error: "Request timed out".to_string(),
+                    code: "TIMEOUT".to_string(),
+                    message: format!("Request timed out after {}s", timeout.as_secs()),
+                    details: Vec::new(),
+                    request_id: current_request_id(),
+                }),
+            )
+                .into_response();
+            response.headers_mut().insert(
+                header::RETRY_AFTER,
+                HeaderValue::from(TIMEOUT_RETRY_AFTER_SECS),
+            );
+            response
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::Result;
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt, fs,
+    hash::{Hash, Hasher},
+    path::Path,
+    time::UNIX_EPOCH,
+};
+
+use crate::dataset::{Datasets, X_DATASET};
+use crate::object_table::is_object_url;
+
+/// 読み込んだconverterとデータファイルの組を識別するバージョン
+///
+/// ファイルの中身を読むと数GBのI/Oになるため、パス・サイズ・更新日時から計算する。
+/// データファイルを差し替えるとバージョンが変わり、以前のETagは一致しなくなる。
+/// オブジェクトストレージ上のデータファイルはURLだけで区別するため、作り直したときは別のキーに置く。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DatasetVersion(u64);
+
+impl DatasetVersion {
+    pub fn from_files<P: AsRef<Path>>(paths: &[P]) -> Result<Self> {
+        let mut hasher = DefaultHasher::new();
+        for path in paths {
+            let path = path.as_ref();
+            if path.to_str().is_some_and(is_object_url) {
+                path.hash(&mut hasher);
+                continue;
+            }
+            let metadata = fs::metadata(path)?;
+            path.hash(&mut hasher);
+            metadata.len().hash(&mut hasher);
+            metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .hash(&mut hasher);
+        }
+        Ok(Self(hasher.finish()))
+    }
+
+    /// リクエストに対するETagを計算する
+    ///
+    /// 分析結果は手牌・残り巡数（クエリ文字列）とデータセットだけで決まる。
+    /// レスポンス形式も`Accept`で変わるため合わせてハッシュする。
+    fn etag(&self, uri: &Uri, headers: &HeaderMap) -> HeaderValue {
+        let mut hasher = DefaultHasher::new();
+        uri.path().hash(&mut hasher);
+        uri.query().unwrap_or_default().hash(&mut hasher);
+        headers
+            .get(header::ACCEPT)
+            .map(|v| v.as_bytes())
+            .unwrap_or_default()
+            .hash(&mut hasher);
+        HeaderValue::from_str(&format!("\"{}-{:016x}\"", self, hasher.finish()))
+            .expect("ETag is always a valid header value")
+    }
+}
+
+impl fmt::Display for DatasetVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// ETagを付与し、`If-None-Match`が一致すれば分析せずに304を返すミドルウェア
+///
+/// ETagには選ばれたデータセットのバージョンを使う。データセット名が不正な場合はハンドラーが400を返す。
+pub async fn conditional_get(
+    State(datasets): State<Datasets>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let etag = match datasets.select(req.uri(), req.headers()) {
+        Ok(dataset) => dataset.version().etag(req.uri(), req.headers()),
+        Err(_) => return next.run(req).await,
+    };
+    if if_none_match(req.headers(), &etag) {
+        return (
+            StatusCode::NOT_MODIFIED,
+            [
+                (header::ETAG, etag),
+                (header::VARY, HeaderValue::from_static("accept, x-dataset")),
+            ],
+        )
+            .into_response();
+    }
+
+    let mut response = next.run(req).await;
+    if response.status() == StatusCode::OK {
+        let headers = response.headers_mut();
+        headers.insert(header::ETAG, etag);
+        headers.append(header::VARY, HeaderValue::from_static(X_DATASET.as_str()));
+    }
+    response
+}
+
+/// `If-None-Match`のいずれかのETagが一致するか（弱い比較）
+fn if_none_match(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let etag = etag.to_str().unwrap_or_default();
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
@@ -0,0 +1,269 @@
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, ErrorExtensions, InputObject, Object, Schema,
+    SimpleObject,
+};
+use common::mahjong::Tile;
+
+use crate::analysis::{
+    format_tiles, MentsuFilter, MentsuProbability, ShantenAnalysis, SharedHandAnalyzer, SortOrder,
+    TsumoProbability,
+};
+use crate::dataset::LiveDataset;
+use crate::params::{HandQuery, InvalidParams};
+
+/// `/graphql`で公開するスキーマ
+pub type AnalysisSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/// 配信中のデータセットをコンテキストに持つスキーマを作成
+///
+/// リクエストのデータに別のデータセットを入れると、そのリクエストではそちらを使う。
+pub fn build_schema(dataset: LiveDataset) -> AnalysisSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(dataset)
+        .finish()
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// 13枚または14枚の手牌（例: 123m456p789s1122z）を分析する
+    async fn hand(&self, hand: String) -> async_graphql::Result<HandNode> {
+        let tiles = HandQuery {
+            hand: Some(hand),
+            format: None,
+        }
+        .validate()
+        .map_err(invalid_params)?;
+        Ok(HandNode { tiles })
+    }
+}
+
+/// 手牌の分析結果。各フィールドは問い合わされたときにだけ計算する
+pub struct HandNode {
+    tiles: Vec<Tile>,
+}
+
+/// 残り巡数ごとのメンツ実現確率
+#[derive(SimpleObject)]
+pub struct MentsuRound {
+    draws_left: u32,
+    probabilities: Vec<MentsuProbability>,
+}
+
+/// 打牌候補
+pub struct DiscardNode {
+    discard: Tile,
+    probability: f64,
+    score: f64,
+    hand: HandNode,
+}
+
+/// 打牌候補の評価でのメンツの重み
+#[derive(InputObject)]
+pub struct MentsuWeight {
+    /// メンツの種類（例: 111z, 123m, Kokushi）。
+    /// `shuntsu`・`kotsu`・`toitsu`ならその種類のメンツすべてに同じ重みを付ける
+    mentsu: String,
+    weight: f64,
+}
+
+impl MentsuWeight {
+    fn matches(&self, mentsu_type: &str) -> bool {
+        match self.mentsu.as_str() {
+            "shuntsu" | "kotsu" | "toitsu" => mentsu_kind(mentsu_type) == Some(&self.mentsu[..]),
+            mentsu => mentsu == mentsu_type,
+        }
+    }
+}
+
+/// メンツの種類（例: 123m, 111z, 11z）の分類
+fn mentsu_kind(mentsu_type: &str) -> Option<&'static str> {
+    match mentsu_type.as_bytes() {
+        [a, b, _, _] if a == b => Some("kotsu"),
+        [_, _, _, _] => Some("shuntsu"),
+        [_, _, _] => Some("toitsu"),
+        _ => None,
+    }
+}
+
+#[Object]
+impl HandNode {
+    /// 萬子・筒子・索子・字牌の順に整列した手牌
+    async fn hand(&self) -> String {
+        format_tiles(&self.tiles)
+    }
+
+    async fn num_tiles(&self) -> usize {
+        self.tiles.len()
+    }
+
+    async fn shanten(&self, ctx: &Context<'_>) -> async_graphql::Result<ShantenAnalysis> {
+        Ok(analyzer(ctx).analyze_shanten(&self.tiles)?)
+    }
+
+    /// 残り巡数ごとのツモ率。`draws_left`を指定するとその巡数のみ返す
+    async fn tsumo(
+        &self,
+        ctx: &Context<'_>,
+        draws_left: Option<u32>,
+    ) -> async_graphql::Result<Vec<TsumoProbability>> {
+        let analysis = analyzer(ctx).analyze_tsumo(&self.tiles).await?;
+        Ok(analysis
+            .probabilities
+            .into_iter()
+            .filter(|p| draws_left.is_none_or(|d| p.draws_left == d))
+            .collect())
+    }
+
+    /// メンツ実現確率
+    ///
+    /// * `draws_left` - 省略時は全巡数
+    /// * `mentsu_types` - 指定したメンツ（例: 123m, 11z, Kokushi）のみ返す
+    /// * `top` - 各巡数で確率の高い順に上位のみ返す
+    async fn mentsu(
+        &self,
+        ctx: &Context<'_>,
+        draws_left: Option<usize>,
+        mentsu_types: Option<Vec<String>>,
+        top: Option<usize>,
+    ) -> async_graphql::Result<Vec<MentsuRound>> {
+        let rounds = analyzer(ctx)
+            .analyze_mentsu_rounds(&self.tiles, draws_left)
+            .await?;
+        Ok(rounds
+            .into_iter()
+            .map(|round| {
+                let mut analysis = round.analysis;
+                if let Some(types) = &mentsu_types {
+                    analysis
+                        .probabilities
+                        .retain(|p| types.contains(&p.mentsu_type));
+                }
+                analysis.apply_filter(&MentsuFilter {
+                    min_probability: None,
+                    sort: top.map(|_| SortOrder::Desc),
+                    top_k: top,
+                });
+                MentsuRound {
+                    draws_left: round.draws_left,
+                    probabilities: analysis.probabilities,
+                }
+            })
+            .collect())
+    }
+
+    /// 14枚の手牌の打牌候補を、打牌後の残り`draws_left`巡（1〜18）での評価値が高い順に返す
+    ///
+    /// 評価値は`tsumo_weight`（省略時は1）×ツモ率に、`weights`で指定したメンツの
+    /// 重み×メンツ実現確率を足したもの。`weights`を省略するとツモ率の順になる。
+    async fn best_discards(
+        &self,
+        ctx: &Context<'_>,
+        draws_left: u32,
+        top: Option<usize>,
+        tsumo_weight: Option<f64>,
+        weights: Option<Vec<MentsuWeight>>,
+    ) -> async_graphql::Result<Vec<DiscardNode>> {
+        if self.tiles.len() != 14 {
+            return Err(async_graphql::Error::new(
+                "bestDiscards requires a 14-tile hand",
+            ));
+        }
+        let tsumo_weight = tsumo_weight.unwrap_or(1.0);
+        let weights = weights.unwrap_or_default();
+        if !tsumo_weight.is_finite() || weights.iter().any(|w| !w.weight.is_finite()) {
+            return Err(async_graphql::Error::new("weights must be finite numbers"));
+        }
+        let analyzer = analyzer(ctx);
+
+        let mut discards: Vec<DiscardNode> = Vec::new();
+        for (i, &discard) in self.tiles.iter().enumerate() {
+            if discards.iter().any(|d| d.discard == discard) {
+                continue;
+            }
+            let mut tiles = self.tiles.clone();
+            tiles.remove(i);
+            let analysis = analyzer.analyze_tsumo(&tiles).await?;
+            let probability = analysis
+                .probabilities
+                .iter()
+                .find(|p| p.draws_left == draws_left)
+                .ok_or_else(|| async_graphql::Error::new("drawsLeft must be 1..=18"))?
+                .probability;
+            let mut score = tsumo_weight * probability;
+            if !weights.is_empty() {
+                let mentsu = analyzer.analyze_mentsu(&tiles, draws_left as usize).await?;
+                for w in &weights {
+                    let matched: Vec<f64> = mentsu
+                        .probabilities
+                        .iter()
+                        .filter(|p| w.matches(&p.mentsu_type))
+                        .map(|p| p.probability)
+                        .collect();
+                    if matched.is_empty() {
+                        return Err(async_graphql::Error::new(format!(
+                            "Unknown mentsu type: {}",
+                            w.mentsu
+                        )));
+                    }
+                    score += w.weight * matched.iter().sum::<f64>();
+                }
+            }
+            discards.push(DiscardNode {
+                discard,
+                probability,
+                score,
+                hand: HandNode { tiles },
+            });
+        }
+
+        discards.sort_by(|a, b| b.score.total_cmp(&a.score));
+        if let Some(top) = top {
+            discards.truncate(top);
+        }
+        Ok(discards)
+    }
+}
+
+#[Object]
+impl DiscardNode {
+    /// 捨てる牌
+    async fn discard(&self) -> String {
+        format_tiles(&[self.discard])
+    }
+
+    /// 打牌後のツモ率
+    async fn probability(&self) -> f64 {
+        self.probability
+    }
+
+    /// 打牌後のツモ率とメンツの重みから計算した評価値
+    async fn score(&self) -> f64 {
+        self.score
+    }
+
+    /// 打牌後の手牌
+    async fn hand(&self) -> &HandNode {
+        &self.hand
+    }
+}
+
+fn analyzer(ctx: &Context<'_>) -> SharedHandAnalyzer {
+    ctx.data_unchecked::<LiveDataset>().analyzer()
+}
+
+/// パラメータの検証エラーをフィールドエラー付きのGraphQLエラーに変換する
+fn invalid_params(e: InvalidParams) -> async_graphql::Error {
+    async_graphql::Error::new(format!("Invalid parameters: {}", e.message())).extend_with(
+        |_, ext| {
+            ext.set("code", "BAD_REQUEST");
+            ext.set(
+                "details",
+                e.0.iter()
+                    .map(|e| format!("{}: {}", e.field, e.reason))
+                    .collect::<Vec<_>>(),
+            );
+        },
+    )
+}
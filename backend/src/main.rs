@@ -1,225 +1,1126 @@
 use axum::{
-    extract::{State, Query},
-    http::{Method, StatusCode},
-    response::Json as JsonResponse,
-    routing::get,
+    extract::{DefaultBodyLimit, FromRef, State, WebSocketUpgrade},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{Html, IntoResponse, Json as JsonResponse, Response},
+    routing::{get, post},
     Router,
 };
+use axum_server::tls_rustls::RustlsConfig;
 use clap::Parser;
-use common::mahjong::parse_hand_str;
+use futures_util::StreamExt;
 use serde::Serialize;
-use tower_http::cors::{Any, CorsLayer};
-use tracing::{info, Level};
-use tracing_subscriber;
+use utoipa::{OpenApi, ToSchema};
+use utoipa_swagger_ui::SwaggerUi;
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
+use tracing::{error, info, Level};
+use tracing_subscriber::EnvFilter;
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
 
+mod access_log;
+mod admin;
 mod analysis;
-mod flat_file_vec_pool;
+mod batch;
+mod cache;
+mod config;
+mod converter_registry;
+mod cors;
+mod dataset;
+mod deadline;
+mod etag;
+mod graphql;
+mod negotiate;
+mod object_table;
+mod openapi;
+mod pagination;
+mod params;
+mod shadow;
+mod stats;
+mod table;
+mod timing;
+mod ws;
 
+use access_log::current_request_id;
+use admin::{AdminStats, AdminToken, FlushResult, ReloadResult};
 use analysis::SharedHandAnalyzer;
+use config::Config;
+use converter_registry::ConverterRegistry;
+use cors::CorsArgs;
+use dataset::{Datasets, LiveDataset, SelectedDataset};
+use stats::PrometheusWriter;
+use table::IoMode;
+use timing::LatencyBreakdown;
+
+use crate::analysis::{
+    CombinedAnalysis, DeepHealth, HandScore, MentsuDiscardsAnalysis, MentsuResponse,
+    MentsuRoundsAnalysis, RandomHand, RawMetrics, ShantenAnalysis, TenpaiAnalysis, TsumoAnalysis,
+    TsumoDrawsAnalysis, TsumoScanEntry, ValueAnalysis, WinTurnAnalysis, YakuAnalysis,
+};
+use crate::batch::{BatchRequest, BatchResponse};
+use crate::negotiate::{accepts_ndjson, NdjsonStream, Negotiated, ResponseFormat};
+use crate::pagination::Page;
+use crate::params::{
+    aka_count, kan_approximated, DatasetQuery, DiscardsQuery, FieldError, HandDrawsQuery,
+    HandFormat, HandQuery, InvalidParams, MentsuQuery, OutputQuery, RandomHandQuery, ScanQuery,
+    TsumoDrawsQuery, TypedQuery, ValueQuery,
+};
+use crate::shadow::ShadowVerifier;
 
-use crate::analysis::{MentsuAnalysis, TsumoAnalysis};
+/// ログの出力形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum LogFormat {
+    /// 人が読むための形式
+    Text,
+    /// 1行に1つのJSONオブジェクト（Loki・CloudWatchなどで取り込む）
+    Json,
+}
 
 /// コマンドライン引数
-#[derive(Parser, Debug)]
+///
+/// 指定した項目は設定ファイルと`MAHJONG_*`環境変数の値を上書きする。
+#[derive(Parser, Serialize, Debug)]
 #[command(author, version, about = "麻雀手牌分析サーバー", long_about = None)]
 struct Args {
+    /// 設定ファイル（TOMLまたはYAML）のパス
+    #[arg(long, env = "MAHJONG_CONFIG")]
+    #[serde(skip)]
+    config: Option<PathBuf>,
+
+    /// ログの出力形式（既定はtext）。レベルは`RUST_LOG`で指定する（既定はinfo）
+    #[arg(long, value_enum, env = "MAHJONG_LOG_FORMAT", default_value_t = LogFormat::Text)]
+    #[serde(skip)]
+    log_format: LogFormat,
+
     /// HandConverterファイルのパス
     #[arg(long)]
-    conv_path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conv_path: Option<String>,
 
     /// 13枚用ツモ率データファイルのパス
     #[arg(long)]
-    tsumo_13_path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tsumo_13_path: Option<String>,
 
     /// 14枚用ツモ率データファイルのパス
     #[arg(long)]
-    tsumo_14_path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tsumo_14_path: Option<String>,
 
     /// 13枚用メトリクスデータファイルのパス
     #[arg(long)]
-    metrics_13_path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metrics_13_path: Option<String>,
 
     /// 14枚用メトリクスデータファイルのパス
     #[arg(long)]
-    metrics_14_path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metrics_14_path: Option<String>,
+
+    /// 和了形ごとのツモ率データファイルを置いたディレクトリ
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tsumo_family_dir: Option<String>,
+
+    /// 副露した手牌のツモ率データファイルを置いたディレクトリ
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    open_tsumo_dir: Option<String>,
+
+    /// 13枚用聴牌率データファイルのパス
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tenpai_13_path: Option<String>,
+
+    /// 14枚用聴牌率データファイルのパス
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tenpai_14_path: Option<String>,
+
+    /// 13枚用の和了したときの残り巡数の期待値のデータファイルのパス
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tsumo_13_turns_path: Option<String>,
+
+    /// 14枚用の和了したときの残り巡数の期待値のデータファイルのパス
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tsumo_14_turns_path: Option<String>,
+
+    /// 待ち受けるアドレス（既定は127.0.0.1:3000）
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    listen_addr: Option<SocketAddr>,
+
+    /// 起動時にデータファイルの先頭と末尾の行を読んでおくか（既定はtrue）
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warm_up: Option<bool>,
+
+    /// リクエストのタイムアウト（秒、既定は30）
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_timeout_secs: Option<u64>,
+
+    /// 同時に処理するリクエストの最大数（既定は256）
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_concurrent_requests: Option<usize>,
+
+    /// データファイル全体を起動時にメモリに読み込む
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    preload: bool,
+
+    /// データファイルの読み出し方（既定はfile）
+    #[arg(long, value_enum)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    io_mode: Option<IoMode>,
+
+    /// 13枚用ツモ率データファイルの読み出し方（省略時はio_mode）
+    #[arg(long, value_enum)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tsumo_13_io_mode: Option<IoMode>,
+
+    /// 14枚用ツモ率データファイルの読み出し方（省略時はio_mode）
+    #[arg(long, value_enum)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tsumo_14_io_mode: Option<IoMode>,
+
+    /// 13枚用メトリクスデータファイルの読み出し方（省略時はio_mode）
+    #[arg(long, value_enum)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metrics_13_io_mode: Option<IoMode>,
+
+    /// 14枚用メトリクスデータファイルの読み出し方（省略時はio_mode）
+    #[arg(long, value_enum)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metrics_14_io_mode: Option<IoMode>,
+
+    /// オブジェクトストレージから1回に読み出すバイト数（既定は65536）
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    object_block_bytes: Option<usize>,
+
+    /// オブジェクトストレージから読み出したブロックをファイルごとにキャッシュする数（既定は4096）
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    object_cache_blocks: Option<usize>,
+
+    /// 読み出した行をキャッシュする数（既定は4096、0でキャッシュしない）
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_capacity: Option<usize>,
+
+    /// バッチ分析のリクエストボディの最大サイズ（バイト、既定は1MiB）
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_body_bytes: Option<usize>,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    cors: CorsArgs,
+
+    /// TLS証明書（PEM）のパス。TLS秘密鍵と合わせて指定するとHTTPSで待ち受ける
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tls_cert: Option<String>,
+
+    /// TLS秘密鍵（PEM）のパス
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tls_key: Option<String>,
+
+    /// シャドー検証用HandConverterファイルのパス（省略時は主系と同じ）
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shadow_conv_path: Option<String>,
+
+    /// シャドー検証用13枚ツモ率データファイルのパス
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shadow_tsumo_13_path: Option<String>,
+
+    /// シャドー検証用14枚ツモ率データファイルのパス
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shadow_tsumo_14_path: Option<String>,
+
+    /// シャドー検証用13枚メトリクスデータファイルのパス
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shadow_metrics_13_path: Option<String>,
+
+    /// シャドー検証用14枚メトリクスデータファイルのパス
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shadow_metrics_14_path: Option<String>,
+
+    /// シャドー検証を行うリクエストの割合（0.0〜1.0、既定は0.01）
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shadow_fraction: Option<f64>,
+
+    /// シャドー検証で許容する確率の誤差（既定は1e-9）
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shadow_tolerance: Option<f64>,
 
-    /// ファイルプールの最大サイズ
-    #[arg(long, default_value = "128")]
-    max_pool_size: usize,
+    /// 管理用エンドポイント（/admin/*）のBearerトークン。省略時は管理用エンドポイントを無効にする
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    admin_token: Option<String>,
+
+    /// 起動時の自己診断を行うか（既定はtrue）
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    self_test: Option<bool>,
 }
 
 // アプリケーションの状態
 #[derive(Clone)]
 struct AppState {
-    analyzer: SharedHandAnalyzer,
+    datasets: Datasets,
+    schema: graphql::AnalysisSchema,
+    converters: ConverterRegistry,
+    shadow: Option<Arc<ShadowVerifier>>,
+    latency: LatencyBreakdown,
+}
+
+impl AppState {
+    // シャドー検証は既定のデータセットに対してのみ行う
+    fn shadow_for(&self, dataset: &LiveDataset) -> Option<&Arc<ShadowVerifier>> {
+        self.shadow.as_ref().filter(|_| dataset.is_default())
+    }
+}
+
+impl FromRef<AppState> for Datasets {
+    fn from_ref(state: &AppState) -> Self {
+        state.datasets.clone()
+    }
 }
 
 // エラーレスポンス
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, ToSchema)]
 struct ErrorResponse {
     error: String,
     code: String,
     message: String,
+    // パラメータ検証エラーの詳細
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    details: Vec<FieldError>,
+    // 問い合わせ時にログと突き合わせるためのリクエストID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+// ハンドラーのエラー型
+type ApiError = (StatusCode, JsonResponse<ErrorResponse>);
+
+// 分析エンジンの失敗を500エラーに変換する
+fn internal_error(error: &str, e: anyhow::Error) -> ApiError {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        JsonResponse(ErrorResponse {
+            error: error.to_string(),
+            code: "INTERNAL_SERVER_ERROR".to_string(),
+            message: format!("{}: {}", error, e),
+            details: Vec::new(),
+            request_id: current_request_id(),
+        }),
+    )
 }
 
 // 手牌分析のハンドラー
+#[utoipa::path(
+    get,
+    path = "/analyze-tsumo",
+    params(HandQuery, OutputQuery, DatasetQuery),
+    responses(
+        (status = 200, description = "残り巡数ごとのツモ率（open_tsumo_dirを設定したときは副露1〜3個の手牌も門前の牌のみで分析する）", body = TsumoAnalysis, content_type = ["application/json", "application/msgpack"]),
+        (status = 400, description = "パラメータが不正", body = ErrorResponse),
+        (status = 500, description = "内部エラー", body = ErrorResponse),
+    )
+)]
 async fn analyze_tsumo(
     State(state): State<AppState>,
-    Query(params): Query<std::collections::HashMap<String, String>>,
-) -> Result<JsonResponse<TsumoAnalysis>, (StatusCode, JsonResponse<ErrorResponse>)> {
-    // クエリパラメータから手牌を取得
-    let hand_string = match params.get("hand") {
-        Some(hand) => hand,
-        None => {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                JsonResponse(ErrorResponse {
-                    error: "Missing 'hand' parameter".to_string(),
-                    code: "BAD_REQUEST".to_string(),
-                    message: "Missing 'hand' parameter".to_string(),
-                }),
-            ));
+    SelectedDataset(dataset): SelectedDataset,
+    format: ResponseFormat,
+    TypedQuery(params): TypedQuery<HandQuery>,
+    TypedQuery(output): TypedQuery<OutputQuery>,
+) -> Result<Negotiated<TsumoAnalysis>, ApiError> {
+    info!("Received tsumo analysis request: hand={:?}", params.hand);
+
+    // 副露した手牌は門前の牌のみで分析する
+    let hand = params.validate_open()?;
+
+    // 共有分析エンジンを使用して手牌を分析
+    let mut analysis = dataset.analyzer().analyze_tsumo(&hand).await?;
+
+    info!("Tsumo analysis completed: hand={:?}", params.hand);
+
+    // 副露した手牌は比較先が分析できないため照合しない
+    if let Some(shadow) = state.shadow_for(&dataset).filter(|_| hand.len() >= 13) {
+        shadow.verify_tsumo(&hand, &analysis);
+    }
+
+    if output.fixed_point() {
+        analysis.fill_fixed_point();
+    }
+    if output.include_canonical() {
+        let canonical = dataset.analyzer().canonical_hand(&hand)?;
+        analysis.canonical = Some(canonical);
+    }
+    analysis.aka_count = aka_count(params.hand.as_deref(), params.format);
+    analysis.kan_approximated = kan_approximated(params.hand.as_deref(), params.format);
+    if HandFormat::unicode_output(params.format) {
+        analysis.render_unicode();
+    }
+
+    Ok(format.respond(analysis))
+}
+
+// 何回目のツモで和了するかの分布のハンドラー
+#[utoipa::path(
+    get,
+    path = "/analyze-win-turns",
+    params(TsumoDrawsQuery, DatasetQuery),
+    responses(
+        (status = 200, description = "13枚の手牌がk回目のツモでちょうど和了する確率（draws_left省略時は18巡）", body = WinTurnAnalysis, content_type = ["application/json", "application/msgpack"]),
+        (status = 400, description = "パラメータが不正", body = ErrorResponse),
+        (status = 500, description = "内部エラー", body = ErrorResponse),
+    )
+)]
+async fn analyze_win_turns(
+    SelectedDataset(dataset): SelectedDataset,
+    format: ResponseFormat,
+    TypedQuery(params): TypedQuery<TsumoDrawsQuery>,
+) -> Result<Negotiated<WinTurnAnalysis>, ApiError> {
+    info!(
+        "Received win turn analysis request: hand={:?}, draws_left={:?}",
+        params.hand, params.draws_left
+    );
+
+    let (hand, draws_left) = params.validate()?;
+
+    let analysis = dataset
+        .analyzer()
+        .analyze_win_turns(&hand, draws_left)
+        .await?;
+
+    info!(
+        "Win turn analysis completed: hand={:?}, total_probability={}",
+        params.hand, analysis.total_probability
+    );
+
+    Ok(format.respond(analysis))
+}
+
+// 流局時の聴牌率のハンドラー
+#[utoipa::path(
+    get,
+    path = "/analyze-tenpai",
+    params(HandQuery, DatasetQuery),
+    responses(
+        (status = 200, description = "残り巡数ごとの流局時の聴牌率", body = TenpaiAnalysis, content_type = ["application/json", "application/msgpack"]),
+        (status = 400, description = "パラメータが不正", body = ErrorResponse),
+        (status = 404, description = "聴牌率データファイルが設定されていない", body = ErrorResponse),
+        (status = 500, description = "内部エラー", body = ErrorResponse),
+    )
+)]
+async fn analyze_tenpai(
+    SelectedDataset(dataset): SelectedDataset,
+    format: ResponseFormat,
+    TypedQuery(params): TypedQuery<HandQuery>,
+) -> Result<Negotiated<TenpaiAnalysis>, ApiError> {
+    info!("Received tenpai analysis request: hand={:?}", params.hand);
+
+    let hand = params.validate()?;
+
+    let analysis = dataset.analyzer().analyze_tenpai(&hand).await?;
+
+    info!("Tenpai analysis completed: hand={:?}", params.hand);
+
+    Ok(format.respond(analysis))
+}
+
+// ツモる牌ごとの和了確率のハンドラー
+#[utoipa::path(
+    get,
+    path = "/analyze-tsumo-draws",
+    params(TsumoDrawsQuery, OutputQuery, DatasetQuery),
+    responses(
+        (status = 200, description = "13枚の手牌にツモりうる牌ごとの和了確率", body = TsumoDrawsAnalysis, content_type = ["application/json", "application/msgpack"]),
+        (status = 400, description = "パラメータが不正", body = ErrorResponse),
+        (status = 500, description = "内部エラー", body = ErrorResponse),
+    )
+)]
+async fn analyze_tsumo_draws(
+    SelectedDataset(dataset): SelectedDataset,
+    format: ResponseFormat,
+    TypedQuery(params): TypedQuery<TsumoDrawsQuery>,
+    TypedQuery(output): TypedQuery<OutputQuery>,
+) -> Result<Negotiated<TsumoDrawsAnalysis>, ApiError> {
+    info!(
+        "Received tsumo draws analysis request: hand={:?}, draws_left={:?}",
+        params.hand, params.draws_left
+    );
+
+    let (hand, draws_left) = params.validate()?;
+    let mut draws = dataset
+        .analyzer()
+        .analyze_tsumo_draws(&hand, output.include_canonical())
+        .await?;
+
+    info!(
+        "Tsumo draws analysis completed: hand={:?}, draws={}",
+        params.hand,
+        draws.len()
+    );
+
+    for draw in &mut draws {
+        // draws_leftを指定したときはその巡数のみ返す
+        if let Some(draws_left) = draws_left {
+            draw.analysis
+                .probabilities
+                .retain(|p| p.draws_left as usize == draws_left);
         }
-    };
-    
-    info!("Received tsumo analysis request: hand={}", hand_string);
-    
-    // 手牌文字列をパース
-    let hand = match parse_hand_str(hand_string) {
-        Ok(hand) => hand,
-        Err(e) => {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                JsonResponse(ErrorResponse {
-                    error: "Invalid hand format".to_string(),
-                    code: "BAD_REQUEST".to_string(),
-                    message: format!("Invalid hand format: {}", e),
-                }),
-            ));
+        if output.fixed_point() {
+            draw.analysis.fill_fixed_point();
         }
-    };
-    
-    // 共有分析エンジンを使用して手牌を分析
-    let analysis = match state.analyzer.analyze_tsumo(&hand).await {
-        Ok(analysis) => analysis,
-        Err(e) => {
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                JsonResponse(ErrorResponse {
-                    error: "Failed to analyze tsumo".to_string(),
-                    code: "INTERNAL_SERVER_ERROR".to_string(),
-                    message: format!("Failed to analyze tsumo: {}", e),
-                }),
-            ));
+        if HandFormat::unicode_output(params.format) {
+            draw.render_unicode();
         }
-    };
-    
-    info!("Tsumo analysis completed: hand={}", hand_string);
+    }
 
-    Ok(JsonResponse(analysis))
+    Ok(format.respond(TsumoDrawsAnalysis { draws }))
 }
 
+#[utoipa::path(
+    get,
+    path = "/analyze-mentsu",
+    params(MentsuQuery, OutputQuery, DatasetQuery),
+    responses(
+        (status = 200, description = "メンツ実現確率（draws_left=allのときは全巡数）", body = MentsuResponse, content_type = ["application/json", "application/msgpack"]),
+        (status = 400, description = "パラメータが不正", body = ErrorResponse),
+        (status = 500, description = "内部エラー", body = ErrorResponse),
+    )
+)]
 async fn analyze_mentsu(
     State(state): State<AppState>,
-    Query(params): Query<std::collections::HashMap<String, String>>,
-) -> Result<JsonResponse<MentsuAnalysis>, (StatusCode, JsonResponse<ErrorResponse>)> {
-    // クエリパラメータから手牌を取得
-    let hand_string = match params.get("hand") {
-        Some(hand) => hand,
-        None => {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                JsonResponse(ErrorResponse {
-                    error: "Missing 'hand' parameter".to_string(),
-                    code: "BAD_REQUEST".to_string(),
-                    message: "Missing 'hand' parameter".to_string(),
-                }),
-            ));
-        }
-    };
+    SelectedDataset(dataset): SelectedDataset,
+    format: ResponseFormat,
+    TypedQuery(params): TypedQuery<MentsuQuery>,
+    TypedQuery(output): TypedQuery<OutputQuery>,
+) -> Result<Negotiated<MentsuResponse>, ApiError> {
+    info!(
+        "Received mentsu analysis request: hand={:?}, draws_left={:?}",
+        params.hand, params.draws_left
+    );
 
-    // クエリパラメータから残り巡数を取得
-    let draws_left_str = match params.get("draws_left") {
-        Some(draws_left) => draws_left,
-        None => {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                JsonResponse(ErrorResponse {
-                    error: "Missing 'draws_left' parameter".to_string(),
-                    code: "BAD_REQUEST".to_string(),
-                    message: "Missing 'draws_left' parameter".to_string(),
-                }),
-            ));
-        }
+    let (hand, draws_left, filter) = params.validate()?;
+    let canonical = if output.include_canonical() {
+        let canonical = dataset.analyzer().canonical_hand(&hand)?;
+        Some(canonical)
+    } else {
+        None
     };
 
-    info!("Received mentsu analysis request: hand={}, draws_left={}", hand_string, draws_left_str);
+    let Some(draws_left) = draws_left else {
+        // 全巡数の結果は手牌の行をまとめて1回で読み出す
+        let mut rounds = dataset
+            .analyzer()
+            .analyze_mentsu_rounds(&hand, None)
+            .await?;
 
-    let draws_left = match draws_left_str.parse::<usize>() {
-        Ok(draws_left) => draws_left,
-        Err(e) => {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                JsonResponse(ErrorResponse {
-                    error: "Invalid draws_left format".to_string(),
-                    code: "BAD_REQUEST".to_string(),
-                    message: format!("Invalid draws_left format: {}", e),
-                }),
-            ));
+        info!(
+            "Mentsu analysis completed: hand={:?}, draws_left=all",
+            params.hand
+        );
+
+        for round in &mut rounds {
+            round.analysis.apply_filter(&filter);
+            if output.fixed_point() {
+                round.analysis.fill_fixed_point();
+            }
         }
-    };
-    
-    // 手牌文字列をパース
-    let hand = match parse_hand_str(hand_string) {
-        Ok(hand) => hand,
-        Err(e) => {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                JsonResponse(ErrorResponse {
-                    error: "Invalid hand format".to_string(),
-                    code: "BAD_REQUEST".to_string(),
-                    message: format!("Invalid hand format: {}", e),
-                }),
-            ));
+        let mut analysis = MentsuRoundsAnalysis {
+            rounds,
+            canonical,
+            aka_count: aka_count(params.hand.as_deref(), params.format),
+            kan_approximated: kan_approximated(params.hand.as_deref(), params.format),
+        };
+        if HandFormat::unicode_output(params.format) {
+            analysis.render_unicode();
         }
+        return Ok(format.respond(MentsuResponse::AllRounds(analysis)));
     };
 
     // 共有分析エンジンを使用して手牌を分析
-    let analysis = match state.analyzer.analyze_mentsu(&hand, draws_left).await {
-        Ok(analysis) => analysis,
-        Err(e) => {
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                JsonResponse(ErrorResponse {
-                    error: "Failed to analyze mentsu".to_string(),
-                    code: "INTERNAL_SERVER_ERROR".to_string(),
-                    message: format!("Failed to analyze mentsu: {}", e),
-                }),
-            ));
+    let mut analysis = dataset.analyzer().analyze_mentsu(&hand, draws_left).await?;
+
+    info!(
+        "Mentsu analysis completed: hand={:?}, draws_left={}",
+        params.hand, draws_left
+    );
+
+    if let Some(shadow) = state.shadow_for(&dataset) {
+        shadow.verify_mentsu(&hand, draws_left, &analysis);
+    }
+
+    // シャドー検証は絞り込み前の結果で行う
+    analysis.apply_filter(&filter);
+    if output.fixed_point() {
+        analysis.fill_fixed_point();
+    }
+    analysis.canonical = canonical;
+    analysis.aka_count = aka_count(params.hand.as_deref(), params.format);
+    analysis.kan_approximated = kan_approximated(params.hand.as_deref(), params.format);
+    if HandFormat::unicode_output(params.format) {
+        analysis.render_unicode();
+    }
+
+    Ok(format.respond(MentsuResponse::Single(analysis)))
+}
+
+// 打牌候補ごとのメンツ実現確率のハンドラー
+#[utoipa::path(
+    get,
+    path = "/analyze-mentsu-discards",
+    params(DiscardsQuery, OutputQuery, DatasetQuery),
+    responses(
+        (status = 200, description = "14枚の手牌から1枚捨てた13枚の手牌ごとのメンツ実現確率", body = MentsuDiscardsAnalysis, content_type = ["application/json", "application/msgpack"]),
+        (status = 400, description = "パラメータが不正", body = ErrorResponse),
+        (status = 500, description = "内部エラー", body = ErrorResponse),
+    )
+)]
+async fn analyze_mentsu_discards(
+    SelectedDataset(dataset): SelectedDataset,
+    format: ResponseFormat,
+    TypedQuery(params): TypedQuery<DiscardsQuery>,
+    TypedQuery(output): TypedQuery<OutputQuery>,
+) -> Result<Negotiated<MentsuDiscardsAnalysis>, ApiError> {
+    info!(
+        "Received mentsu discards analysis request: hand={:?}, draws_left={:?}",
+        params.hand, params.draws_left
+    );
+
+    let (hand, draws_left, filter) = params.validate()?;
+    let mut discards = dataset
+        .analyzer()
+        .analyze_mentsu_discards(&hand, draws_left, output.include_canonical())
+        .await?;
+
+    info!(
+        "Mentsu discards analysis completed: hand={:?}, discards={}",
+        params.hand,
+        discards.len()
+    );
+
+    for discard in &mut discards {
+        for round in &mut discard.rounds {
+            round.analysis.apply_filter(&filter);
+            if output.fixed_point() {
+                round.analysis.fill_fixed_point();
+            }
         }
-    };
-    
-    info!("Mentsu analysis completed: hand={}, draws_left={}", hand_string, draws_left_str);
+        if HandFormat::unicode_output(params.format) {
+            discard.render_unicode();
+        }
+    }
+
+    Ok(format.respond(MentsuDiscardsAnalysis { discards }))
+}
+
+// ツモ率とメンツ実現確率をまとめて返すハンドラー
+#[utoipa::path(
+    get,
+    path = "/analyze",
+    params(HandDrawsQuery, OutputQuery, DatasetQuery),
+    responses(
+        (status = 200, description = "ツモ率とメンツ実現確率（draws_left省略時は全巡数）", body = CombinedAnalysis, content_type = ["application/json", "application/msgpack"]),
+        (status = 400, description = "パラメータが不正", body = ErrorResponse),
+        (status = 500, description = "内部エラー", body = ErrorResponse),
+    )
+)]
+async fn analyze(
+    SelectedDataset(dataset): SelectedDataset,
+    format: ResponseFormat,
+    TypedQuery(params): TypedQuery<HandDrawsQuery>,
+    TypedQuery(output): TypedQuery<OutputQuery>,
+) -> Result<Negotiated<CombinedAnalysis>, ApiError> {
+    info!(
+        "Received combined analysis request: hand={:?}, draws_left={:?}",
+        params.hand, params.draws_left
+    );
+
+    let (hand, draws_left) = params.validate(false)?;
+
+    // 共有分析エンジンを使用して手牌を分析
+    let mut analysis = dataset.analyzer().analyze(&hand, draws_left).await?;
+
+    info!(
+        "Combined analysis completed: hand={:?}, draws_left={:?}",
+        params.hand, draws_left
+    );
+
+    if output.fixed_point() {
+        analysis.fill_fixed_point();
+    }
+    if output.include_canonical() {
+        let canonical = dataset.analyzer().canonical_hand(&hand)?;
+        analysis.canonical = Some(canonical);
+    }
+    analysis.aka_count = aka_count(params.hand.as_deref(), params.format);
+    analysis.kan_approximated = kan_approximated(params.hand.as_deref(), params.format);
+    if HandFormat::unicode_output(params.format) {
+        analysis.render_unicode();
+    }
+
+    Ok(format.respond(analysis))
+}
+
+// 保存されている生のメトリクスを返すハンドラー
+#[utoipa::path(
+    get,
+    path = "/metrics-raw",
+    params(HandDrawsQuery, DatasetQuery),
+    responses(
+        (status = 200, description = "正規形の手牌に対する変換前のメトリクス（draws_left省略時は全巡数）", body = RawMetrics, content_type = ["application/json", "application/msgpack"]),
+        (status = 400, description = "パラメータが不正", body = ErrorResponse),
+        (status = 500, description = "内部エラー", body = ErrorResponse),
+    )
+)]
+async fn metrics_raw(
+    SelectedDataset(dataset): SelectedDataset,
+    format: ResponseFormat,
+    TypedQuery(params): TypedQuery<HandDrawsQuery>,
+) -> Result<Negotiated<RawMetrics>, ApiError> {
+    info!(
+        "Received raw metrics request: hand={:?}, draws_left={:?}",
+        params.hand, params.draws_left
+    );
+
+    let (hand, draws_left) = params.validate(false)?;
+
+    let metrics = dataset.analyzer().raw_metrics(&hand, draws_left).await?;
+
+    info!(
+        "Raw metrics read: hand={:?}, hand_id={}, rounds={}",
+        params.hand,
+        metrics.hand_id,
+        metrics.rounds.len()
+    );
+
+    Ok(format.respond(metrics))
+}
+
+// 向聴数のハンドラー
+#[utoipa::path(
+    get,
+    path = "/analyze-shanten",
+    params(HandQuery, DatasetQuery),
+    responses(
+        (status = 200, description = "向聴数", body = ShantenAnalysis, content_type = ["application/json", "application/msgpack"]),
+        (status = 400, description = "パラメータが不正", body = ErrorResponse),
+    )
+)]
+async fn analyze_shanten(
+    SelectedDataset(dataset): SelectedDataset,
+    format: ResponseFormat,
+    TypedQuery(params): TypedQuery<HandQuery>,
+) -> Result<Negotiated<ShantenAnalysis>, ApiError> {
+    info!("Received shanten analysis request: hand={:?}", params.hand);
+
+    let hand = params.validate()?;
+
+    let analysis = dataset.analyzer().analyze_shanten(&hand)?;
+
+    Ok(format.respond(analysis))
+}
+
+// ランダムな手牌のハンドラー
+#[utoipa::path(
+    get,
+    path = "/random-hand",
+    params(RandomHandQuery),
+    responses(
+        (status = 200, description = "ありうる手牌から一様に選んだ手牌", body = RandomHand, content_type = ["application/json", "application/msgpack"]),
+        (status = 400, description = "パラメータが不正、または指定した向聴数の手牌が見つからない", body = ErrorResponse),
+    )
+)]
+async fn random_hand(
+    format: ResponseFormat,
+    TypedQuery(params): TypedQuery<RandomHandQuery>,
+) -> Result<Response, ApiError> {
+    info!(
+        "Received random hand request: tiles={:?}, shanten={:?}, seed={:?}",
+        params.tiles, params.shanten, params.seed
+    );
+
+    let (num_tiles, target) = params.validate()?;
+    let seeded = params.seed.is_some();
+    let seed = params.seed.unwrap_or_else(rand::random);
+
+    // 向聴数を指定すると何万回も引き直すことがあるため、ワーカースレッドを塞がないようにする
+    let hand = tokio::task::spawn_blocking(move || RandomHand::generate(seed, num_tiles, target))
+        .await
+        .map_err(|e| internal_error("Failed to generate hand", e.into()))?
+        .map_err(|e| InvalidParams(vec![FieldError::new("shanten", e.to_string())]))?;
+
+    info!("Random hand generated: hand={}, seed={}", hand.hand, seed);
+
+    // seedを省いた結果はブラウザやプロキシにキャッシュさせない
+    let mut response = format.respond(hand).into_response();
+    if !seeded {
+        response
+            .headers_mut()
+            .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    }
+    Ok(response)
+}
+
+// 手牌の評価値のハンドラー
+#[utoipa::path(
+    get,
+    path = "/score",
+    params(HandQuery, DatasetQuery),
+    responses(
+        (status = 200, description = "向聴数・有効牌・ツモ率をまとめた評価値とその内訳", body = HandScore, content_type = ["application/json", "application/msgpack"]),
+        (status = 400, description = "パラメータが不正", body = ErrorResponse),
+        (status = 500, description = "内部エラー", body = ErrorResponse),
+    )
+)]
+async fn score_hand(
+    SelectedDataset(dataset): SelectedDataset,
+    format: ResponseFormat,
+    TypedQuery(params): TypedQuery<HandQuery>,
+) -> Result<Negotiated<HandScore>, ApiError> {
+    info!("Received score request: hand={:?}", params.hand);
+
+    let hand = params.validate()?;
+
+    let score = dataset.analyzer().score_hand(&hand).await?;
+
+    info!("Score completed: hand={:?}, score={}", hand, score.score);
+
+    Ok(format.respond(score))
+}
+
+// 和了したときの打点の見積もりのハンドラー
+#[utoipa::path(
+    get,
+    path = "/analyze-value",
+    params(ValueQuery, DatasetQuery),
+    responses(
+        (status = 200, description = "和了率と、和了したときの翻数・点数の期待値", body = ValueAnalysis, content_type = ["application/json", "application/msgpack"]),
+        (status = 400, description = "パラメータが不正", body = ErrorResponse),
+        (status = 500, description = "内部エラー", body = ErrorResponse),
+    )
+)]
+async fn analyze_value(
+    SelectedDataset(dataset): SelectedDataset,
+    format: ResponseFormat,
+    TypedQuery(params): TypedQuery<ValueQuery>,
+) -> Result<Negotiated<ValueAnalysis>, ApiError> {
+    info!(
+        "Received value analysis request: hand={:?}, draws_left={:?}, seat={:?}, round={:?}, dora={:?}",
+        params.hand, params.draws_left, params.seat, params.round, params.dora
+    );
+
+    let (hand, draws_left, seat, round) = params.validate()?;
+    let dora_indicators = params.validate_dora(&hand)?;
+
+    let analysis = dataset
+        .analyzer()
+        .analyze_value(&hand, draws_left, seat, round, &dora_indicators)
+        .await?;
+
+    info!(
+        "Value analysis completed: hand={:?}, expected_points={}",
+        hand, analysis.expected_points
+    );
+
+    Ok(format.respond(analysis))
+}
+
+// 役ごとの和了確率の見積もりのハンドラー
+#[utoipa::path(
+    get,
+    path = "/analyze-yaku",
+    params(ValueQuery, DatasetQuery),
+    responses(
+        (status = 200, description = "役ごとに、その役を含む形で和了する確率の下限と上限", body = YakuAnalysis, content_type = ["application/json", "application/msgpack"]),
+        (status = 400, description = "パラメータが不正", body = ErrorResponse),
+        (status = 500, description = "内部エラー", body = ErrorResponse),
+    )
+)]
+async fn analyze_yaku(
+    SelectedDataset(dataset): SelectedDataset,
+    format: ResponseFormat,
+    TypedQuery(params): TypedQuery<ValueQuery>,
+) -> Result<Negotiated<YakuAnalysis>, ApiError> {
+    info!(
+        "Received yaku analysis request: hand={:?}, draws_left={:?}, seat={:?}, round={:?}",
+        params.hand, params.draws_left, params.seat, params.round
+    );
+
+    let (hand, draws_left, seat, round) = params.validate()?;
+
+    let analysis = dataset
+        .analyzer()
+        .analyze_yaku(&hand, draws_left, seat, round)
+        .await?;
+
+    info!("Yaku analysis completed: hand={:?}", hand);
+
+    Ok(format.respond(analysis))
+}
+
+// 複数の手牌をまとめて分析するハンドラー
+//
+// `Accept: application/x-ndjson`のときは手牌ごとの結果を1行ずつ流す
+#[utoipa::path(
+    post,
+    path = "/analyze-batch",
+    params(DatasetQuery),
+    request_body = BatchRequest,
+    responses(
+        (status = 200, description = "手牌ごとの分析結果（NDJSONでは1行1手牌）", content(
+            ("application/json" = BatchResponse),
+            ("application/msgpack" = BatchResponse),
+            ("application/x-ndjson" = BatchEntry),
+        )),
+        (status = 400, description = "パラメータが不正", body = ErrorResponse),
+    )
+)]
+async fn analyze_batch(
+    SelectedDataset(dataset): SelectedDataset,
+    format: ResponseFormat,
+    headers: HeaderMap,
+    JsonResponse(request): JsonResponse<BatchRequest>,
+) -> Result<Response, ApiError> {
+    info!("Received batch analysis request: {} hands", request.items.len());
+
+    request.validate()?;
+
+    let results = batch::analyze_stream(dataset.analyzer(), request.items);
+    if accepts_ndjson(&headers) {
+        return Ok(NdjsonStream(results).into_response());
+    }
+    let results = results.collect().await;
+    Ok(format.respond(BatchResponse { results }).into_response())
+}
+
+// ツモ率走査のハンドラー（手牌インデックス順、カーソルページング）
+#[utoipa::path(
+    get,
+    path = "/scan-tsumo",
+    params(ScanQuery, DatasetQuery),
+    responses(
+        (status = 200, description = "手牌インデックス順のツモ率", body = TsumoScanPage, content_type = ["application/json", "application/msgpack"]),
+        (status = 400, description = "パラメータが不正", body = ErrorResponse),
+        (status = 500, description = "内部エラー", body = ErrorResponse),
+    )
+)]
+async fn scan_tsumo(
+    SelectedDataset(dataset): SelectedDataset,
+    format: ResponseFormat,
+    TypedQuery(params): TypedQuery<ScanQuery>,
+) -> Result<Negotiated<Page<TsumoScanEntry>>, ApiError> {
+    let (num_tiles, draws_left, page) = params.validate()?;
+
+    info!(
+        "Received tsumo scan request: tiles={}, draws_left={}, start={}, limit={}",
+        num_tiles, draws_left, page.start, page.limit
+    );
+
+    let result = dataset
+        .analyzer()
+        .scan_tsumo(num_tiles, draws_left, page)
+        .await?;
+
+    Ok(format.respond(result))
+}
+
+// 対話的な分析セッション用のWebSocketエンドポイント
+//
+// 手牌の設定・ツモ・打牌のイベントを受け取るたびに分析結果を返す
+async fn ws_session(SelectedDataset(dataset): SelectedDataset, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| ws::run_session(socket, dataset.analyzer()))
+}
+
+// GraphQLのクエリを実行するハンドラー
+async fn graphql_query(
+    State(state): State<AppState>,
+    SelectedDataset(dataset): SelectedDataset,
+    JsonResponse(request): JsonResponse<async_graphql::Request>,
+) -> JsonResponse<async_graphql::Response> {
+    // リクエストごとのデータはスキーマのデータより優先される
+    JsonResponse(state.schema.execute(request.data(dataset)).await)
+}
 
-    Ok(JsonResponse(analysis))
+// GraphQLのクエリエディター（GraphiQL）
+async fn graphiql() -> Html<String> {
+    Html(
+        async_graphql::http::GraphiQLSource::build()
+            .endpoint("/graphql")
+            .finish(),
+    )
+}
 
+// レスポンス・リクエストのJSON Schema（フロントエンドの型生成用）
+async fn json_schema() -> JsonResponse<serde_json::Value> {
+    JsonResponse(openapi::json_schema())
 }
 
+// 管理用統計情報エンドポイント
+#[utoipa::path(
+    get,
+    path = "/admin/stats",
+    params(DatasetQuery),
+    responses(
+        (status = 200, description = "converter・データファイルの読み出し・データファイルの状態", body = AdminStats),
+        (status = 401, description = "管理用トークンが不正", body = ErrorResponse),
+    ),
+    security(("admin_token" = []))
+)]
+async fn admin_stats(
+    State(state): State<AppState>,
+    SelectedDataset(dataset): SelectedDataset,
+) -> JsonResponse<AdminStats> {
+    let analyzer = dataset.analyzer();
+    JsonResponse(AdminStats {
+        converters: state.converters.stats(),
+        reads: analyzer.read_stats(),
+        datasets: analyzer.dataset_files(),
+        cache: analyzer.cache_stats(),
+    })
+}
 
+// 未使用のconverterとキャッシュした行を解放するエンドポイント
+#[utoipa::path(
+    post,
+    path = "/admin/cache/flush",
+    params(DatasetQuery),
+    responses(
+        (status = 200, description = "解放したconverter・キャッシュした行の数", body = FlushResult),
+        (status = 401, description = "管理用トークンが不正", body = ErrorResponse),
+    ),
+    security(("admin_token" = []))
+)]
+async fn admin_flush_cache(
+    State(state): State<AppState>,
+    SelectedDataset(dataset): SelectedDataset,
+) -> JsonResponse<FlushResult> {
+    let analyzer = dataset.analyzer();
+    let result = FlushResult {
+        evicted_converters: state.converters.evict_unused(),
+        evicted_cache_entries: analyzer.clear_cache(),
+    };
+    info!(
+        "Admin cache flush: evicted_converters={}, evicted_cache_entries={}",
+        result.evicted_converters, result.evicted_cache_entries
+    );
+    JsonResponse(result)
+}
+
+// データセットを読み込み直して差し替えるエンドポイント
+#[utoipa::path(
+    post,
+    path = "/admin/reload",
+    params(DatasetQuery),
+    responses(
+        (status = 200, description = "差し替えたデータセットのバージョン", body = ReloadResult),
+        (status = 401, description = "管理用トークンが不正", body = ErrorResponse),
+        (status = 500, description = "読み込みまたは自己診断に失敗（差し替えない）", body = ErrorResponse),
+    ),
+    security(("admin_token" = []))
+)]
+async fn admin_reload(
+    SelectedDataset(dataset): SelectedDataset,
+) -> Result<JsonResponse<ReloadResult>, ApiError> {
+    info!("Admin reload requested: dataset={}", dataset.name());
+    let started = std::time::Instant::now();
+    let version = dataset
+        .reload()
+        .await
+        .map_err(|e| internal_error("Failed to reload dataset", e))?;
+    Ok(JsonResponse(ReloadResult {
+        version: version.to_string(),
+        elapsed_ms: started.elapsed().as_millis() as u64,
+    }))
+}
+
+// Prometheus形式のメトリクスエンドポイント（データファイルの読み出しと処理の段階ごとの所要時間）
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "Prometheusのテキスト形式のメトリクス", body = String, content_type = "text/plain"),
+    )
+)]
+async fn prometheus_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let reads: Vec<_> = state
+        .datasets
+        .iter()
+        .flat_map(|dataset| {
+            let reads = dataset.analyzer().read_stats();
+            reads.into_iter().map(move |read| (dataset.name(), read))
+        })
+        .collect();
+    let mut w = PrometheusWriter::default();
+    stats::write_read_metrics(&mut w, &reads);
+    state.latency.write_metrics(&mut w);
+    (
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; version=0.0.4"),
+        )],
+        w.finish(),
+    )
+}
 
 // ヘルスチェックエンドポイント
+#[utoipa::path(
+    get,
+    path = "/health",
+    responses((status = 200, description = "サーバーが稼働中", body = String))
+)]
 async fn health_check() -> &'static str {
     "OK"
 }
 
+// データファイルを検査する詳細なヘルスチェックエンドポイント
+#[utoipa::path(
+    get,
+    path = "/health/deep",
+    params(DatasetQuery),
+    responses(
+        (status = 200, description = "すべてのデータファイルが正常", body = DeepHealth),
+        (status = 503, description = "異常のあるデータファイルがある", body = DeepHealth),
+    )
+)]
+async fn deep_health_check(
+    SelectedDataset(dataset): SelectedDataset,
+) -> (StatusCode, JsonResponse<DeepHealth>) {
+    let health = dataset.analyzer().check_health().await;
+    let status = if health.ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, JsonResponse(health))
+}
+
 fn main() {
     // コマンドライン引数を解析
     let args = Args::parse();
 
     // ログの初期化
-    tracing_subscriber::fmt()
-        .with_max_level(Level::INFO)
-        .init();
+    init_logging(args.log_format);
 
     // 論理コア数を取得
     let worker_threads = std::thread::available_parallelism()
@@ -228,7 +1129,16 @@ fn main() {
 
     info!("Starting tsumo probability backend server...");
     info!("Using multi-threaded runtime with {} worker threads", worker_threads);
-    info!("Configuration: {:?}", args);
+
+    // 設定ファイル・環境変数・コマンドライン引数を重ねて設定を確定
+    let config = match Config::load(args.config.as_deref(), &args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    info!("Configuration: {:?}", config);
 
     // Tokioランタイムを手動で構築
     let rt = tokio::runtime::Builder::new_multi_thread()
@@ -238,48 +1148,258 @@ fn main() {
         .expect("Failed to build Tokio runtime");
 
     // 非同期メイン処理を実行
-    rt.block_on(async_main(args));
-}
-
-async fn async_main(args: Args) {
-    // 共有分析エンジンを初期化
-    let analyzer = match SharedHandAnalyzer::new(
-        &args.conv_path,
-        &args.tsumo_13_path,
-        &args.tsumo_14_path,
-        &args.metrics_13_path,
-        &args.metrics_14_path,
-        args.max_pool_size,
-    ) {
-        Ok(analyzer) => {
-            info!("Hand analyzer initialized successfully");
-            analyzer
-        }
+    rt.block_on(async_main(config));
+}
+
+// RUST_LOGのレベル（省略時はinfo）で、指定した形式のログを出力する
+fn init_logging(format: LogFormat) {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(Level::INFO.as_str()));
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        LogFormat::Text => subscriber.init(),
+        // リクエストID・パスなどはリクエストのスパン（`spans`）のフィールドとして出力する
+        LogFormat::Json => subscriber.json().init(),
+    }
+}
+
+async fn async_main(config: Config) {
+    // converterとデータファイルを読み込み、既知の手牌で自己診断する
+    let config = Arc::new(config);
+    let converters = ConverterRegistry::new();
+    let datasets = match Datasets::open(config.clone(), converters.clone()).await {
+        Ok(datasets) => datasets,
         Err(e) => {
-            eprintln!("Failed to initialize hand analyzer: {}", e);
+            eprintln!("Failed to load dataset: {}; refusing to start", e);
             std::process::exit(1);
         }
     };
 
+    // シャドー検証用の副分析エンジンを初期化
+    let shadow = match (
+        &config.shadow_tsumo_13_path,
+        &config.shadow_tsumo_14_path,
+        &config.shadow_metrics_13_path,
+        &config.shadow_metrics_14_path,
+    ) {
+        (Some(tsumo_13_path), Some(tsumo_14_path), Some(metrics_13_path), Some(metrics_14_path)) => {
+            let shadow_conv_path = config.shadow_conv_path.as_ref().unwrap_or(&config.conv_path);
+            let secondary = converters.load(shadow_conv_path).and_then(|converter| {
+                SharedHandAnalyzer::new(
+                    converter,
+                    tsumo_13_path,
+                    tsumo_14_path,
+                    metrics_13_path,
+                    metrics_14_path,
+                    &config.table_options(),
+                )
+                .map(|analyzer| analyzer.with_cache_capacity(config.cache_capacity))
+                .map_err(Into::into)
+            });
+            match secondary {
+                Ok(secondary) => {
+                    info!(
+                        "Shadow verification enabled: fraction={}, tolerance={}",
+                        config.shadow_fraction, config.shadow_tolerance
+                    );
+                    Some(Arc::new(ShadowVerifier::new(
+                        secondary,
+                        config.shadow_fraction,
+                        config.shadow_tolerance,
+                    )))
+                }
+                Err(e) => {
+                    eprintln!("Failed to initialize shadow analyzer: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        _ => None,
+    };
+
+    // SIGHUPでデータセットを読み込み直す
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup(datasets.clone()));
+
     // アプリケーション状態を作成
-    let state = AppState { analyzer };
+    let state = AppState {
+        schema: graphql::build_schema(datasets.default_dataset().clone()),
+        datasets: datasets.clone(),
+        converters,
+        shadow,
+        latency: LatencyBreakdown::default(),
+    };
 
     // CORS設定
-    let cors = CorsLayer::new()
-        .allow_methods([Method::POST, Method::GET])
-        .allow_origin(Any);
+    let cors = match config.cors.layer() {
+        Ok(cors) => cors,
+        Err(e) => {
+            eprintln!("Invalid CORS configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     // ルーターの設定（状態を共有）
-    let app = Router::new()
-        .route("/health", get(health_check))
+    // 結果が手牌・残り巡数・データセットだけで決まるエンドポイント（ETagで条件付きGETに対応）
+    let analysis_routes = Router::new()
+        .route("/analyze", get(analyze))
         .route("/analyze-tsumo", get(analyze_tsumo))
+        .route("/analyze-tsumo-draws", get(analyze_tsumo_draws))
+        .route("/analyze-tenpai", get(analyze_tenpai))
+        .route("/analyze-win-turns", get(analyze_win_turns))
         .route("/analyze-mentsu", get(analyze_mentsu))
+        .route("/analyze-mentsu-discards", get(analyze_mentsu_discards))
+        .route("/analyze-shanten", get(analyze_shanten))
+        .route("/score", get(score_hand))
+        .route("/analyze-value", get(analyze_value))
+        .route("/analyze-yaku", get(analyze_yaku))
+        .route("/metrics-raw", get(metrics_raw))
+        .route("/scan-tsumo", get(scan_tsumo))
+        // 手牌の解析・エンコード・読み出しにかかった時間をエンドポイントごとに集計する
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.latency.clone(),
+            timing::record_stages,
+        ))
+        .route_layer(axum::middleware::from_fn_with_state(
+            datasets,
+            etag::conditional_get,
+        ));
+
+    // 管理用エンドポイントはトークンを設定したときのみ有効にする
+    let admin_routes = match &config.admin_token {
+        Some(token) => Router::new()
+            .route("/admin/stats", get(admin_stats))
+            .route("/admin/cache/flush", post(admin_flush_cache))
+            .route("/admin/reload", post(admin_reload))
+            .route_layer(axum::middleware::from_fn_with_state(
+                AdminToken::new(token.expose()),
+                admin::require_token,
+            )),
+        None => {
+            info!("Admin endpoints are disabled because admin_token is not set");
+            Router::new()
+        }
+    };
+
+    let app = Router::new()
+        .route("/health", get(health_check))
+        .route("/health/deep", get(deep_health_check))
+        .route("/metrics", get(prometheus_metrics))
+        // seedを省くと毎回違う手牌を返すため、ETagを付けるエンドポイントには含めない
+        .route("/random-hand", get(random_hand))
+        .merge(analysis_routes)
+        .route(
+            "/analyze-batch",
+            post(analyze_batch).layer(DefaultBodyLimit::max(config.max_body_bytes)),
+        )
+        .route("/ws", get(ws_session))
+        .route("/graphql", get(graphiql).post(graphql_query))
+        .merge(admin_routes)
+        .route("/schema.json", get(json_schema))
+        .merge(SwaggerUi::new("/swagger-ui").url("/openapi.json", openapi::ApiDoc::openapi()))
+        // 上限を超えたリクエストは空きが出るまで待たせる
+        .layer(GlobalConcurrencyLimitLayer::new(config.max_concurrent_requests))
+        // 待ち時間も含めてタイムアウトしたリクエストには503とRetry-Afterを返す
+        .layer(axum::middleware::from_fn_with_state(
+            std::time::Duration::from_secs(config.request_timeout_secs),
+            deadline::enforce_timeout,
+        ))
+        // クライアントが諦めた後にデータファイルを読み続けないようにする
+        .layer(axum::middleware::from_fn(deadline::enforce_deadline))
         .layer(cors)
+        .layer(axum::middleware::from_fn(access_log::scope_request_id))
+        // メソッド・パス・手牌・ステータス・処理時間をリクエストIDとともに記録する
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(access_log::make_span)
+                .on_response(access_log::on_response),
+        )
+        // クライアントが指定したX-Request-Idはそのまま使い、なければ生成してレスポンスにも付ける
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
         .with_state(state);
 
     // サーバーの起動
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000").await.unwrap();
-    info!("Server listening on http://127.0.0.1:3000");
-    
-    axum::serve(listener, app).await.unwrap();
-}
\ No newline at end of file
+    let addr = config.listen_addr;
+    match (&config.tls_cert, &config.tls_key) {
+        (Some(tls_cert), Some(tls_key)) => {
+            let config = match RustlsConfig::from_pem_file(tls_cert, tls_key).await {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("Failed to load TLS certificate: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            info!("Server listening on https://{}", addr);
+
+            // シグナル受信後は新規接続を止め、処理中のリクエストが終わるまで待つ
+            let handle = axum_server::Handle::new();
+            let shutdown_handle = handle.clone();
+            tokio::spawn(async move {
+                shutdown_signal().await;
+                shutdown_handle.graceful_shutdown(None);
+            });
+            axum_server::bind_rustls(addr, config)
+                .handle(handle)
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        }
+        _ => {
+            let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+            info!("Server listening on http://{}", addr);
+
+            // シグナル受信後は新規接続を止め、処理中のリクエストが終わるまで待つ
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown_signal())
+                .await
+                .unwrap();
+        }
+    }
+
+    info!("Server stopped");
+}
+
+// SIGHUPを受け取るたびにすべてのデータセットを読み込み直す
+#[cfg(unix)]
+async fn reload_on_sighup(datasets: Datasets) {
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        .expect("Failed to install SIGHUP handler");
+    while hangup.recv().await.is_some() {
+        info!("Received SIGHUP, reloading datasets");
+        for dataset in datasets.iter() {
+            if let Err(e) = dataset.reload().await {
+                error!(
+                    "Failed to reload dataset {}; keeping the current one: {}",
+                    dataset.name(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+// SIGINT/SIGTERMを待つ
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Received SIGINT, shutting down gracefully"),
+        _ = terminate => info!("Received SIGTERM, shutting down gracefully"),
+    }
+}
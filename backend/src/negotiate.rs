@@ -0,0 +1,134 @@
+use axum::{
+    async_trait,
+    body::Body,
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Json as JsonResponse, Response},
+};
+use futures_util::{Stream, StreamExt};
+use serde::Serialize;
+use std::convert::Infallible;
+use tracing::error;
+
+/// MessagePackのメディアタイプ
+pub const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
+
+/// NDJSON（1行1JSON）のメディアタイプ
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// `Accept`ヘッダーの値がいずれかのメディアタイプを明示的に受け付けているか
+fn accepts(accept: &str, media_types: &[&str]) -> bool {
+    accept.split(',').any(|media_range| {
+        let mut params = media_range.split(';').map(str::trim);
+        let media_type = params.next().unwrap_or_default();
+        // q=0は「受け付けない」の意味
+        let rejected =
+            params.any(|p| p.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) == Some(0.0));
+        media_types.contains(&media_type) && !rejected
+    })
+}
+
+/// `Accept`ヘッダーでNDJSONが要求されているか
+pub fn accepts_ndjson(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accepts(accept, &[NDJSON_CONTENT_TYPE]))
+}
+
+/// `Accept`ヘッダーから決めたレスポンスの形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseFormat {
+    Json,
+    MessagePack,
+}
+
+impl ResponseFormat {
+    /// `Accept`ヘッダーの値から形式を選ぶ。MessagePackが明示されていなければJSONにする
+    fn from_accept(accept: &str) -> Self {
+        if accepts(accept, &[MSGPACK_CONTENT_TYPE, "application/x-msgpack"]) {
+            ResponseFormat::MessagePack
+        } else {
+            ResponseFormat::Json
+        }
+    }
+
+    /// 値をこの形式でレスポンスにする
+    pub fn respond<T>(self, value: T) -> Negotiated<T> {
+        Negotiated(self, value)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ResponseFormat
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .map(ResponseFormat::from_accept)
+            .unwrap_or(ResponseFormat::Json))
+    }
+}
+
+/// `Accept`ヘッダーに応じてJSONまたはMessagePackで返すレスポンス
+pub struct Negotiated<T>(pub ResponseFormat, pub T);
+
+impl<T: Serialize> IntoResponse for Negotiated<T> {
+    fn into_response(self) -> Response {
+        let mut response = match self.0 {
+            ResponseFormat::Json => JsonResponse(self.1).into_response(),
+            // フィールド名を残し、JSONと同じ構造で読めるようにする
+            ResponseFormat::MessagePack => match rmp_serde::to_vec_named(&self.1) {
+                Ok(body) => (
+                    [(
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static(MSGPACK_CONTENT_TYPE),
+                    )],
+                    body,
+                )
+                    .into_response(),
+                Err(e) => {
+                    error!("Failed to encode MessagePack response: {}", e);
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            },
+        };
+        // キャッシュが形式ごとに別のレスポンスを保持するようにする
+        response
+            .headers_mut()
+            .append(header::VARY, HeaderValue::from_static("accept"));
+        response
+    }
+}
+
+/// 要素ができるたびに1行ずつ送り出すNDJSONのレスポンス
+pub struct NdjsonStream<S>(pub S);
+
+impl<S, T> IntoResponse for NdjsonStream<S>
+where
+    S: Stream<Item = T> + Send + 'static,
+    T: Serialize,
+{
+    fn into_response(self) -> Response {
+        let lines = self.0.map(|item| {
+            serde_json::to_vec(&item).map(|mut line| {
+                line.push(b'\n');
+                line
+            })
+        });
+        (
+            [(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(NDJSON_CONTENT_TYPE),
+            )],
+            Body::from_stream(lines),
+        )
+            .into_response()
+    }
+}
@@ -0,0 +1,148 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use common::flat_file_vec::{data_offset, FixedRepr, HEADER_SIZE};
+use futures_util::future::try_join_all;
+use lru::LruCache;
+use object_store::{
+    aws::AmazonS3Builder, gcp::GoogleCloudStorageBuilder, path::Path as ObjectPath, ObjectStore,
+};
+use std::{
+    marker::PhantomData,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::OnceCell;
+
+use crate::table::TableSource;
+
+/// オブジェクトストレージ上のデータファイルを指すURLか（`s3://bucket/key`または`gs://bucket/key`）
+pub fn is_object_url(location: &str) -> bool {
+    location.starts_with("s3://") || location.starts_with("gs://")
+}
+
+/// オブジェクトストレージ上のデータファイルから範囲指定のGETで行を読み出すテーブル
+///
+/// 読み出しは`block_bytes`ごとのブロック単位で行い、読んだブロックは`cache_blocks`個までメモリに残す。
+/// 認証情報はS3なら`AWS_*`、GCSなら`GOOGLE_*`の環境変数から読み込む。
+pub struct ObjectStoreTable<T> {
+    location: String,
+    store: Arc<dyn ObjectStore>,
+    path: ObjectPath,
+    block_bytes: usize,
+    // 最初の要素の位置とオブジェクトのサイズ（バイト）。最初に必要になったときにHEADとヘッダーの読み出しで取得する
+    layout: OnceCell<(usize, usize)>,
+    blocks: Mutex<LruCache<usize, Bytes>>,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T: FixedRepr> ObjectStoreTable<T> {
+    pub fn open(location: &str, block_bytes: usize, cache_blocks: usize) -> Result<Self> {
+        let (scheme, rest) = location
+            .split_once("://")
+            .ok_or_else(|| anyhow::anyhow!("Invalid object URL: {}", location))?;
+        let (bucket, key) = rest
+            .split_once('/')
+            .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("Object URL must be {}://bucket/key", scheme))?;
+        let store: Arc<dyn ObjectStore> = match scheme {
+            "s3" => Arc::new(
+                AmazonS3Builder::from_env()
+                    .with_bucket_name(bucket)
+                    .build()?,
+            ),
+            "gs" => Arc::new(
+                GoogleCloudStorageBuilder::from_env()
+                    .with_bucket_name(bucket)
+                    .build()?,
+            ),
+            _ => return Err(anyhow::anyhow!("Unsupported object URL scheme: {}", scheme)),
+        };
+        let block_bytes = NonZeroUsize::new(block_bytes)
+            .ok_or_else(|| anyhow::anyhow!("object_block_bytes must be positive"))?;
+        let cache_blocks = NonZeroUsize::new(cache_blocks).unwrap_or(NonZeroUsize::MIN);
+
+        Ok(Self {
+            location: location.to_string(),
+            store,
+            path: ObjectPath::from(key),
+            block_bytes: block_bytes.get(),
+            layout: OnceCell::new(),
+            blocks: Mutex::new(LruCache::new(cache_blocks)),
+            _phantom: PhantomData,
+        })
+    }
+
+    async fn layout(&self) -> Result<(usize, usize)> {
+        self.layout
+            .get_or_try_init(|| async {
+                let size = self.store.head(&self.path).await?.size;
+                let head = self
+                    .store
+                    .get_range(&self.path, 0..HEADER_SIZE.min(size))
+                    .await?;
+                let offset = data_offset::<T>(&head, size as u64)
+                    .map_err(|e| anyhow::anyhow!("{}: {}", self.location, e))?;
+                Ok((offset as usize, size))
+            })
+            .await
+            .copied()
+    }
+
+    /// `index`番目のブロックを返す。キャッシュになければ読み出して登録する
+    async fn block(&self, index: usize, size: usize) -> Result<Bytes> {
+        if let Some(block) = self.blocks.lock().unwrap().get(&index) {
+            return Ok(block.clone());
+        }
+        let start = index * self.block_bytes;
+        let end = (start + self.block_bytes).min(size);
+        let block = self.store.get_range(&self.path, start..end).await?;
+        if block.len() != end - start {
+            return Err(anyhow::anyhow!(
+                "Short read from {}: expected {} bytes, got {}",
+                self.location,
+                end - start,
+                block.len()
+            ));
+        }
+        self.blocks.lock().unwrap().put(index, block.clone());
+        Ok(block)
+    }
+}
+
+#[async_trait]
+impl<T: FixedRepr + Send + Sync + 'static> TableSource<T> for ObjectStoreTable<T> {
+    fn location(&self) -> String {
+        self.location.clone()
+    }
+
+    async fn len(&self) -> Result<usize> {
+        let (offset, size) = self.layout().await?;
+        Ok((size - offset) / T::BYTE_SIZE)
+    }
+
+    async fn get_range(&self, start: usize, end: usize) -> Result<Vec<T>> {
+        let (offset, size) = self.layout().await?;
+        if start > end || end > (size - offset) / T::BYTE_SIZE {
+            return Err(anyhow::Error::msg("Invalid range"));
+        }
+        if start == end {
+            return Ok(Vec::new());
+        }
+
+        // 範囲にかかるブロックを並行して読み出し、必要な部分をつなげる
+        let (lo, hi) = (offset + start * T::BYTE_SIZE, offset + end * T::BYTE_SIZE);
+        let indices = lo / self.block_bytes..=(hi - 1) / self.block_bytes;
+        let blocks = try_join_all(indices.clone().map(|i| self.block(i, size))).await?;
+        let mut bytes = Vec::with_capacity(hi - lo);
+        for (index, block) in indices.zip(&blocks) {
+            let block_start = index * self.block_bytes;
+            let from = lo.max(block_start) - block_start;
+            let to = hi.min(block_start + block.len()) - block_start;
+            bytes.extend_from_slice(&block[from..to]);
+        }
+
+        let mut bytes = &bytes[..];
+        (start..end).map(|_| T::deserialize(&mut bytes)).collect()
+    }
+}
@@ -0,0 +1,161 @@
+use serde_json::{json, Value};
+use utoipa::{
+    openapi::security::{Http, HttpAuthScheme, SecurityScheme},
+    Modify, OpenApi,
+};
+
+use crate::admin::{AdminStats, FlushResult, ReloadResult};
+use crate::analysis::{
+    AgariShapes, CanonicalHand, CombinedAnalysis, DatasetFile, DeepHealth, DiscardMentsuAnalysis,
+    DrawTsumoAnalysis, FileHealth, HandScore, MentsuAnalysis, MentsuDiscardsAnalysis,
+    MentsuProbability, MentsuResponse, MentsuRoundAnalysis, MentsuRoundsAnalysis, RandomHand,
+    RawMetrics, RawMetricsRound, ReadStats, SampleHealth, ScoreComponents, ShantenAnalysis,
+    SortOrder, TenpaiAnalysis, TenpaiProbability, TsumoAnalysis, TsumoDrawsAnalysis,
+    TsumoProbability, TsumoScanEntry, ValueAnalysis, WinTurnAnalysis, WinTurnProbability,
+    YakuAnalysis, YakuProbability,
+};
+use crate::batch::{BatchEntry, BatchItem, BatchRequest, BatchResponse};
+use crate::cache::CacheStats;
+use crate::converter_registry::{ConverterRegistryStats, ConverterStats};
+use crate::pagination::TsumoScanPage;
+use crate::params::{FieldError, HandFormat};
+use crate::stats::{HistogramBucket, HistogramSnapshot};
+use crate::ErrorResponse;
+
+/// `/openapi.json`で配信するAPI仕様
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "麻雀手牌分析サーバー"),
+    paths(
+        crate::health_check,
+        crate::deep_health_check,
+        crate::prometheus_metrics,
+        crate::analyze,
+        crate::analyze_tsumo,
+        crate::analyze_tsumo_draws,
+        crate::analyze_tenpai,
+        crate::analyze_win_turns,
+        crate::analyze_mentsu,
+        crate::analyze_mentsu_discards,
+        crate::analyze_shanten,
+        crate::score_hand,
+        crate::random_hand,
+        crate::analyze_value,
+        crate::analyze_yaku,
+        crate::metrics_raw,
+        crate::analyze_batch,
+        crate::scan_tsumo,
+        crate::admin_stats,
+        crate::admin_flush_cache,
+        crate::admin_reload,
+    ),
+    components(schemas(
+        TsumoAnalysis,
+        TsumoProbability,
+        DrawTsumoAnalysis,
+        TsumoDrawsAnalysis,
+        TenpaiAnalysis,
+        TenpaiProbability,
+        WinTurnAnalysis,
+        WinTurnProbability,
+        MentsuAnalysis,
+        MentsuProbability,
+        MentsuRoundAnalysis,
+        MentsuRoundsAnalysis,
+        MentsuResponse,
+        DiscardMentsuAnalysis,
+        MentsuDiscardsAnalysis,
+        SortOrder,
+        HandFormat,
+        CombinedAnalysis,
+        CanonicalHand,
+        ShantenAnalysis,
+        RandomHand,
+        HandScore,
+        ScoreComponents,
+        ValueAnalysis,
+        AgariShapes,
+        YakuAnalysis,
+        YakuProbability,
+        RawMetrics,
+        RawMetricsRound,
+        BatchRequest,
+        BatchItem,
+        BatchEntry,
+        BatchResponse,
+        TsumoScanEntry,
+        TsumoScanPage,
+        DeepHealth,
+        FileHealth,
+        SampleHealth,
+        AdminStats,
+        FlushResult,
+        ReloadResult,
+        ReadStats,
+        HistogramSnapshot,
+        HistogramBucket,
+        DatasetFile,
+        CacheStats,
+        ConverterRegistryStats,
+        ConverterStats,
+        ErrorResponse,
+        FieldError,
+    )),
+    modifiers(&AdminTokenScheme)
+)]
+pub struct ApiDoc;
+
+/// 管理用エンドポイントのBearerトークン認証を登録する
+struct AdminTokenScheme;
+
+impl Modify for AdminTokenScheme {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "admin_token",
+                SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+            );
+        }
+    }
+}
+
+/// `/schema.json`で配信するレスポンス・リクエストのJSON Schema
+///
+/// OpenAPIのcomponentsから作るため、型を追加するときは`ApiDoc`に登録すればよい。
+/// 参照先を`$defs`に付け替え、`nullable`をJSON Schemaの`null`型に置き換える。
+pub fn json_schema() -> Value {
+    let schemas = ApiDoc::openapi()
+        .components
+        .map(|components| components.schemas)
+        .unwrap_or_default();
+    let mut defs = serde_json::to_value(schemas).expect("schemas are always serializable");
+    to_json_schema(&mut defs);
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": ApiDoc::openapi().info.title,
+        "$defs": defs,
+    })
+}
+
+fn to_json_schema(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            if let Some(Value::String(reference)) = object.get_mut("$ref") {
+                *reference = reference.replace("#/components/schemas/", "#/$defs/");
+            }
+            if object.remove("nullable") == Some(Value::Bool(true)) {
+                match object.get_mut("type") {
+                    Some(ty @ Value::String(_)) => *ty = json!([ty.take(), "null"]),
+                    // 型を持たない参照などは`null`との和にする
+                    _ => {
+                        let schema = Value::Object(std::mem::take(object));
+                        object.insert("anyOf".to_string(), json!([schema, { "type": "null" }]));
+                    }
+                }
+            }
+            object.values_mut().for_each(to_json_schema);
+        }
+        Value::Array(array) => array.iter_mut().for_each(to_json_schema),
+        _ => {}
+    }
+}
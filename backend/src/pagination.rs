@@ -0,0 +1,72 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::analysis::TsumoScanEntry;
+use crate::params::FieldError;
+
+/// 1ページあたりのデフォルト件数
+pub const DEFAULT_PAGE_LIMIT: usize = 100;
+
+/// 1ページあたりの最大件数
+pub const MAX_PAGE_LIMIT: usize = 1000;
+
+/// カーソルページングのパラメータ
+///
+/// カーソルは次に返すべき手牌インデックスを表す。
+/// 並び順は常に手牌インデックスの昇順なので、同じカーソルからは常に同じ結果が返る。
+#[derive(Debug, Clone, Copy)]
+pub struct PageParams {
+    pub start: usize,
+    pub limit: usize,
+}
+
+impl PageParams {
+    /// `cursor`と`limit`からページングパラメータを作成
+    pub fn new(cursor: Option<&str>, limit: Option<usize>) -> Result<Self, Vec<FieldError>> {
+        let mut errors = Vec::new();
+        let start = match cursor {
+            Some(cursor) => decode_cursor(cursor)
+                .map_err(|e| errors.push(FieldError::new("cursor", e)))
+                .unwrap_or(0),
+            None => 0,
+        };
+        let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+        if limit == 0 || limit > MAX_PAGE_LIMIT {
+            errors.push(FieldError::new(
+                "limit",
+                format!("must be 1..={}", MAX_PAGE_LIMIT),
+            ));
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(Self { start, limit })
+    }
+}
+
+/// カーソル付きのページ
+#[derive(Debug, Serialize, ToSchema)]
+#[aliases(TsumoScanPage = Page<TsumoScanEntry>)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// 次のページを取得するためのカーソル。最後のページでは`null`
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// `[start, end)`の要素からページを作成する。`end < total`なら次のカーソルを付与する
+    pub fn new(items: Vec<T>, end: usize, total: usize) -> Self {
+        Self {
+            items,
+            next_cursor: (end < total).then(|| encode_cursor(end)),
+        }
+    }
+}
+
+fn encode_cursor(index: usize) -> String {
+    format!("{:x}", index)
+}
+
+fn decode_cursor(cursor: &str) -> Result<usize, String> {
+    usize::from_str_radix(cursor, 16).map_err(|_| format!("invalid cursor: {}", cursor))
+}
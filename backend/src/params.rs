@@ -0,0 +1,650 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Json as JsonResponse, Response},
+};
+use common::mahjong::{
+    approximate_ankan, parse_dora_indicators, parse_hand_str_with_melds, tenhou_to_mpsz,
+    validate_hand_tiles, validate_open_hand, Meld, Tile, TileMultiset, NUM_ROUNDS,
+};
+use std::borrow::Cow;
+
+use serde::{
+    de::{self, DeserializeOwned},
+    Deserialize, Deserializer, Serialize,
+};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::access_log::current_request_id;
+use crate::analysis::{MentsuFilter, SortOrder};
+use crate::pagination::PageParams;
+use crate::timing::{measure, Stage};
+use crate::{ApiError, ErrorResponse};
+
+/// フィールド単位の検証エラー
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub reason: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+/// パラメータの検証に失敗したときのレスポンス（400）
+#[derive(Debug)]
+pub struct InvalidParams(pub Vec<FieldError>);
+
+impl InvalidParams {
+    /// すべてのフィールドエラーを1行にまとめたメッセージ
+    pub fn message(&self) -> String {
+        self.0
+            .iter()
+            .map(|e| format!("{}: {}", e.field, e.reason))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl From<InvalidParams> for ApiError {
+    fn from(e: InvalidParams) -> Self {
+        (
+            StatusCode::BAD_REQUEST,
+            JsonResponse(ErrorResponse {
+                error: "Invalid parameters".to_string(),
+                code: "BAD_REQUEST".to_string(),
+                message: format!("Invalid parameters: {}", e.message()),
+                details: e.0,
+                request_id: current_request_id(),
+            }),
+        )
+    }
+}
+
+impl IntoResponse for InvalidParams {
+    fn into_response(self) -> Response {
+        ApiError::from(self).into_response()
+    }
+}
+
+/// クエリ文字列を型付きの構造体として取り出すExtractor
+///
+/// `axum::extract::Query`と異なり、型の不一致をフィールド単位のエラーとして返す。
+pub struct TypedQuery<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for TypedQuery<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = InvalidParams;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let query = parts.uri.query().unwrap_or_default();
+        let deserializer =
+            serde_urlencoded::Deserializer::new(form_urlencoded::parse(query.as_bytes()));
+        serde_path_to_error::deserialize(deserializer)
+            .map(TypedQuery)
+            .map_err(|e| {
+                InvalidParams(vec![FieldError::new(
+                    e.path().to_string(),
+                    e.inner().to_string(),
+                )])
+            })
+    }
+}
+
+/// 手牌の書き方
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HandFormat {
+    /// 123m456p789s1122zのような表記
+    #[default]
+    Mpsz,
+    /// 天鳳の牌譜の牌番号（0〜135）。`牌番号 / 4`が牌の種類で、各5の`牌番号 % 4 == 0`が赤5
+    Tenhou,
+    /// 入力はmpszのまま、レスポンスのメンツの種類や手牌をUnicodeの麻雀牌（🀇🀈🀉…）で表す
+    Unicode,
+}
+
+impl HandFormat {
+    /// レスポンスの牌をUnicodeの麻雀牌で表すか
+    pub fn unicode_output(format: Option<HandFormat>) -> bool {
+        format == Some(HandFormat::Unicode)
+    }
+}
+
+/// 手牌のみを受け取るエンドポイントのパラメータ
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HandQuery {
+    /// 13枚または14枚の手牌（例: 123m456p789s1122z）
+    pub hand: Option<String>,
+    /// 手牌の書き方。`tenhou`なら天鳳の牌番号（0〜135）をカンマ区切りで指定する。`unicode`ならレスポンスの牌をUnicodeの麻雀牌で表す。省略時は`mpsz`
+    pub format: Option<HandFormat>,
+}
+
+impl HandQuery {
+    pub fn validate(&self) -> Result<Vec<Tile>, InvalidParams> {
+        validate_hand(self.hand.as_deref(), self.format, false).map_err(|e| InvalidParams(vec![e]))
+    }
+
+    /// 副露した手牌（副露1〜3個）も許して検証する。暗槓のみでなければ門前の牌のみを返す
+    pub fn validate_open(&self) -> Result<Vec<Tile>, InvalidParams> {
+        validate_hand(self.hand.as_deref(), self.format, true).map_err(|e| InvalidParams(vec![e]))
+    }
+}
+
+/// 手牌と残り巡数を受け取るエンドポイントのパラメータ
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HandDrawsQuery {
+    /// 13枚または14枚の手牌（例: 123m456p789s1122z）
+    pub hand: Option<String>,
+    /// 残り巡数（13枚は1〜18、14枚は0〜17）
+    pub draws_left: Option<usize>,
+    /// 手牌の書き方。`tenhou`なら天鳳の牌番号（0〜135）をカンマ区切りで指定する。`unicode`ならレスポンスの牌をUnicodeの麻雀牌で表す。省略時は`mpsz`
+    pub format: Option<HandFormat>,
+}
+
+impl HandDrawsQuery {
+    /// 手牌と残り巡数を検証する。`draws_left`が必須でなければ省略を許す
+    pub fn validate(
+        &self,
+        require_draws_left: bool,
+    ) -> Result<(Vec<Tile>, Option<usize>), InvalidParams> {
+        let mut errors = Vec::new();
+        let hand = validate_hand_draws(
+            self.hand.as_deref(),
+            self.format,
+            self.draws_left,
+            require_draws_left,
+            &mut errors,
+        );
+        match hand {
+            Some(hand) if errors.is_empty() => Ok((hand, self.draws_left)),
+            _ => Err(InvalidParams(errors)),
+        }
+    }
+}
+
+/// 打点と役の見積もり（`/analyze-value`・`/analyze-yaku`）のパラメータ
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ValueQuery {
+    /// 13枚または14枚の手牌（例: 123m456p789s1122z）
+    pub hand: Option<String>,
+    /// 残り巡数（13枚は1〜18、14枚は0〜17）
+    pub draws_left: Option<usize>,
+    /// 自風（1z〜4z）。省略時は東（1z）で、東なら親として計算する
+    pub seat: Option<String>,
+    /// 場風（1z〜4z）。省略時は東（1z）
+    pub round: Option<String>,
+    /// ドラ表示牌をmpsz表記で5枚まで（例: 3m7z）。`/analyze-value`のみで使い、省略時はドラなし
+    pub dora: Option<String>,
+    /// 手牌の書き方。`tenhou`なら天鳳の牌番号（0〜135）をカンマ区切りで指定する。`unicode`ならレスポンスの牌をUnicodeの麻雀牌で表す。省略時は`mpsz`
+    pub format: Option<HandFormat>,
+}
+
+impl ValueQuery {
+    /// 手牌・残り巡数・自風・場風を検証する
+    pub fn validate(&self) -> Result<(Vec<Tile>, usize, Tile, Tile), InvalidParams> {
+        let mut errors = Vec::new();
+        let hand = validate_hand_draws(
+            self.hand.as_deref(),
+            self.format,
+            self.draws_left,
+            true,
+            &mut errors,
+        );
+        let seat = validate_wind("seat", self.seat.as_deref(), &mut errors);
+        let round = validate_wind("round", self.round.as_deref(), &mut errors);
+        match (hand, self.draws_left) {
+            (Some(hand), Some(draws_left)) if errors.is_empty() => {
+                Ok((hand, draws_left, seat, round))
+            }
+            _ => Err(InvalidParams(errors)),
+        }
+    }
+
+    /// ドラ表示牌を検証する。検証済みの手牌と合わせて同じ牌が5枚以上にならないこと
+    pub fn validate_dora(&self, hand: &[Tile]) -> Result<Vec<Tile>, InvalidParams> {
+        let Some(dora) = self.dora.as_deref() else {
+            return Ok(Vec::new());
+        };
+        let indicators = parse_dora_indicators(dora).and_then(|indicators| {
+            let mut tiles = TileMultiset::from_tiles(hand)?;
+            for &tile in &indicators {
+                tiles.insert(tile)?;
+            }
+            Ok(indicators)
+        });
+        indicators.map_err(|e| InvalidParams(vec![FieldError::new("dora", e.to_string())]))
+    }
+}
+
+/// 風牌の指定を検証する。省略時は東
+fn validate_wind(field: &'static str, wind: Option<&str>, errors: &mut Vec<FieldError>) -> Tile {
+    match wind.map(str::parse::<Tile>) {
+        None => Tile::east(),
+        Some(Ok(tile)) if tile.is_wind() => tile,
+        _ => {
+            errors.push(FieldError::new(
+                field,
+                "must be a wind tile (1z, 2z, 3z or 4z)",
+            ));
+            Tile::east()
+        }
+    }
+}
+
+/// データセットを選ぶパラメータ。他のパラメータと併せて指定する
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DatasetQuery {
+    /// 分析に使うデータセットの名前（省略時は既定のデータセット。`X-Dataset`ヘッダーでも指定できる）
+    pub dataset: Option<String>,
+}
+
+/// 分析結果の出力方法のパラメータ。他のパラメータと併せて指定する
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OutputQuery {
+    /// trueのとき、手牌がエンコードされた正規形・手牌インデックス・スートの変換を含める
+    pub include_canonical: Option<bool>,
+    /// trueのとき、各確率にデータファイルの固定小数点の値（`raw`）と分母（`scale`）を含める
+    pub fixed_point: Option<bool>,
+}
+
+impl OutputQuery {
+    pub fn include_canonical(&self) -> bool {
+        self.include_canonical.unwrap_or(false)
+    }
+
+    pub fn fixed_point(&self) -> bool {
+        self.fixed_point.unwrap_or(false)
+    }
+}
+
+/// 残り巡数の指定。`all`は全巡数を表す
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrawsLeftParam {
+    All,
+    Round(usize),
+}
+
+impl<'de> Deserialize<'de> for DrawsLeftParam {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        if s == "all" {
+            return Ok(DrawsLeftParam::All);
+        }
+        s.parse()
+            .map(DrawsLeftParam::Round)
+            .map_err(|_| de::Error::custom("expected a number or `all`"))
+    }
+}
+
+/// メンツ実現確率のパラメータ
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MentsuQuery {
+    /// 13枚または14枚の手牌（例: 123m456p789s1122z）
+    pub hand: Option<String>,
+    /// 残り巡数（13枚は1〜18、14枚は0〜17）。`all`で全巡数
+    #[param(value_type = Option<String>)]
+    pub draws_left: Option<DrawsLeftParam>,
+    /// この確率未満のメンツを除く
+    pub min_probability: Option<f64>,
+    /// 確率で並べ替える（ascまたはdesc）。省略時はメンツの種類順
+    pub sort: Option<SortOrder>,
+    /// 先頭からこの件数だけ返す（並べ替えの後に適用）
+    pub top_k: Option<usize>,
+    /// 手牌の書き方。`tenhou`なら天鳳の牌番号（0〜135）をカンマ区切りで指定する。`unicode`ならレスポンスの牌をUnicodeの麻雀牌で表す。省略時は`mpsz`
+    pub format: Option<HandFormat>,
+}
+
+impl MentsuQuery {
+    /// 手牌・残り巡数・絞り込み条件を検証する。残り巡数が`None`なら全巡数
+    pub fn validate(&self) -> Result<(Vec<Tile>, Option<usize>, MentsuFilter), InvalidParams> {
+        let mut errors = Vec::new();
+        let (draws_left, require_draws_left) = match self.draws_left {
+            Some(DrawsLeftParam::Round(draws_left)) => (Some(draws_left), true),
+            Some(DrawsLeftParam::All) => (None, false),
+            None => (None, true),
+        };
+        let hand = validate_hand_draws(
+            self.hand.as_deref(),
+            self.format,
+            draws_left,
+            require_draws_left,
+            &mut errors,
+        );
+        validate_filter(self.min_probability, self.top_k, &mut errors);
+        match hand {
+            Some(hand) if errors.is_empty() => Ok((
+                hand,
+                draws_left,
+                MentsuFilter {
+                    min_probability: self.min_probability,
+                    sort: self.sort,
+                    top_k: self.top_k,
+                },
+            )),
+            _ => Err(InvalidParams(errors)),
+        }
+    }
+}
+
+/// 13枚の手牌と残り巡数を受け取るエンドポイント（`/analyze-tsumo-draws`・`/analyze-win-turns`）のパラメータ
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TsumoDrawsQuery {
+    /// 13枚の手牌（例: 123m456p789s1122z）
+    pub hand: Option<String>,
+    /// ツモる前の残り巡数（1〜18）。省略時は全巡数
+    pub draws_left: Option<usize>,
+    /// 手牌の書き方。`tenhou`なら天鳳の牌番号（0〜135）をカンマ区切りで指定する。`unicode`ならレスポンスの牌をUnicodeの麻雀牌で表す。省略時は`mpsz`
+    pub format: Option<HandFormat>,
+}
+
+impl TsumoDrawsQuery {
+    pub fn validate(&self) -> Result<(Vec<Tile>, Option<usize>), InvalidParams> {
+        let mut errors = Vec::new();
+        let hand = validate_hand_draws(
+            self.hand.as_deref(),
+            self.format,
+            self.draws_left,
+            false,
+            &mut errors,
+        );
+        if let Some(hand) = &hand {
+            if hand.len() != 13 {
+                errors.push(FieldError::new(
+                    "hand",
+                    format!("must have 13 tiles, got {}", hand.len()),
+                ));
+            }
+        }
+        match hand {
+            Some(hand) if errors.is_empty() => Ok((hand, self.draws_left)),
+            _ => Err(InvalidParams(errors)),
+        }
+    }
+}
+
+/// 打牌候補ごとのメンツ実現確率のパラメータ
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DiscardsQuery {
+    /// 14枚の手牌（例: 123m456p789s11223z）
+    pub hand: Option<String>,
+    /// 打牌後の残り巡数（1〜18）。`all`または省略で全巡数
+    #[param(value_type = Option<String>)]
+    pub draws_left: Option<DrawsLeftParam>,
+    /// この確率未満のメンツを除く
+    pub min_probability: Option<f64>,
+    /// 確率で並べ替える（ascまたはdesc）。省略時はメンツの種類順
+    pub sort: Option<SortOrder>,
+    /// 先頭からこの件数だけ返す（並べ替えの後に適用）
+    pub top_k: Option<usize>,
+    /// 手牌の書き方。`tenhou`なら天鳳の牌番号（0〜135）をカンマ区切りで指定する。`unicode`ならレスポンスの牌をUnicodeの麻雀牌で表す。省略時は`mpsz`
+    pub format: Option<HandFormat>,
+}
+
+impl DiscardsQuery {
+    /// 手牌・打牌後の残り巡数・絞り込み条件を検証する。残り巡数が`None`なら全巡数
+    pub fn validate(&self) -> Result<(Vec<Tile>, Option<usize>, MentsuFilter), InvalidParams> {
+        let mut errors = Vec::new();
+        let hand = match validate_hand(self.hand.as_deref(), self.format, false) {
+            Ok(hand) if hand.len() != 14 => {
+                errors.push(FieldError::new(
+                    "hand",
+                    format!("must have 14 tiles, got {}", hand.len()),
+                ));
+                None
+            }
+            Ok(hand) => Some(hand),
+            Err(e) => {
+                errors.push(e);
+                None
+            }
+        };
+        let draws_left = match self.draws_left {
+            Some(DrawsLeftParam::Round(draws_left)) => {
+                if !(1..=NUM_ROUNDS).contains(&draws_left) {
+                    errors.push(FieldError::new(
+                        "draws_left",
+                        format!("must be 1..={} after discarding", NUM_ROUNDS),
+                    ));
+                }
+                Some(draws_left)
+            }
+            Some(DrawsLeftParam::All) | None => None,
+        };
+        validate_filter(self.min_probability, self.top_k, &mut errors);
+        match hand {
+            Some(hand) if errors.is_empty() => Ok((
+                hand,
+                draws_left,
+                MentsuFilter {
+                    min_probability: self.min_probability,
+                    sort: self.sort,
+                    top_k: self.top_k,
+                },
+            )),
+            _ => Err(InvalidParams(errors)),
+        }
+    }
+}
+
+/// ツモ率走査のパラメータ
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ScanQuery {
+    /// 手牌の枚数（13または14）
+    pub tiles: Option<usize>,
+    /// 残り巡数（13枚は1〜18、14枚は0〜17）
+    pub draws_left: Option<usize>,
+    /// 前のページの`next_cursor`
+    pub cursor: Option<String>,
+    /// 1ページあたりの件数
+    pub limit: Option<usize>,
+}
+
+impl ScanQuery {
+    pub fn validate(&self) -> Result<(usize, usize, PageParams), InvalidParams> {
+        let mut errors = Vec::new();
+        let tiles = match self.tiles {
+            Some(tiles @ (13 | 14)) => Some(tiles),
+            Some(_) => {
+                errors.push(FieldError::new("tiles", "must be 13 or 14"));
+                None
+            }
+            None => {
+                errors.push(FieldError::new("tiles", "is required"));
+                None
+            }
+        };
+        match (self.draws_left, tiles) {
+            (None, _) => errors.push(FieldError::new("draws_left", "is required")),
+            (Some(draws_left), Some(tiles)) => {
+                if let Err(e) = validate_draws_left(tiles, draws_left) {
+                    errors.push(e);
+                }
+            }
+            _ => {}
+        }
+        let page = PageParams::new(self.cursor.as_deref(), self.limit)
+            .map_err(|e| errors.extend(e))
+            .ok();
+        match (tiles, self.draws_left, page) {
+            (Some(tiles), Some(draws_left), Some(page)) if errors.is_empty() => {
+                Ok((tiles, draws_left, page))
+            }
+            _ => Err(InvalidParams(errors)),
+        }
+    }
+}
+
+/// ランダムな手牌（`/random-hand`）のパラメータ
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RandomHandQuery {
+    /// 手牌の枚数（13または14）。省略時は13
+    pub tiles: Option<usize>,
+    /// 最小の向聴数（13枚は0〜6、14枚は-1〜6で、-1は和了形）。省略時は指定なし
+    pub shanten: Option<i8>,
+    /// 乱数のシード。同じシードと条件なら同じ手牌を返す。省略時はランダム
+    pub seed: Option<u64>,
+}
+
+impl RandomHandQuery {
+    pub fn validate(&self) -> Result<(usize, Option<i8>), InvalidParams> {
+        let mut errors = Vec::new();
+        let tiles = self.tiles.unwrap_or(13);
+        if tiles != 13 && tiles != 14 {
+            errors.push(FieldError::new("tiles", "must be 13 or 14"));
+        }
+        if let Some(shanten) = self.shanten {
+            let lowest = if tiles == 14 { -1 } else { 0 };
+            if !(lowest..=6).contains(&shanten) {
+                errors.push(FieldError::new(
+                    "shanten",
+                    format!("must be between {} and 6, got {}", lowest, shanten),
+                ));
+            }
+        }
+        if errors.is_empty() {
+            Ok((tiles, self.shanten))
+        } else {
+            Err(InvalidParams(errors))
+        }
+    }
+}
+
+/// 手牌を検証する。`allow_open`なら暗槓以外を含む副露した手牌も許し、門前の牌のみを返す
+fn validate_hand(
+    hand: Option<&str>,
+    format: Option<HandFormat>,
+    allow_open: bool,
+) -> Result<Vec<Tile>, FieldError> {
+    let hand = hand.ok_or_else(|| FieldError::new("hand", "is required"))?;
+    measure(Stage::Parse, || {
+        let hand = mpsz_hand(hand, format)?;
+        let (mut tiles, melds) =
+            parse_hand_str_with_melds(&hand).map_err(|e| FieldError::new("hand", e.to_string()))?;
+        if !melds.is_empty() {
+            // 副露した手牌も枚数までは検証し、誤りがあればそれを返す
+            validate_open_hand(&tiles, &melds)
+                .map_err(|e| FieldError::new("hand", e.to_string()))?;
+            // 暗槓のみなら刻子とみなして門前の手牌として分析する
+            match approximate_ankan(&tiles, &melds) {
+                Some(approximated) => tiles = approximated,
+                // 副露した手牌は門前の牌のみで分析する。副露4個（裸単騎）のデータファイルはない
+                None if allow_open && melds.len() <= 3 => return Ok(tiles),
+                None => {
+                    return Err(FieldError::new(
+                        "hand",
+                        "open hands (melds) are not supported by the data files",
+                    ))
+                }
+            }
+        }
+        // 不正な手牌はconverterでpanicするため、エンコード前に弾く
+        validate_hand_tiles(&tiles).map_err(|e| FieldError::new("hand", e.to_string()))?;
+        Ok(tiles)
+    })
+}
+
+/// 手牌を`format`の書き方からmpsz表記に直す
+fn mpsz_hand(hand: &str, format: Option<HandFormat>) -> Result<Cow<'_, str>, FieldError> {
+    match format.unwrap_or_default() {
+        HandFormat::Mpsz | HandFormat::Unicode => Ok(Cow::Borrowed(hand)),
+        HandFormat::Tenhou => tenhou_to_mpsz(hand)
+            .map(Cow::Owned)
+            .map_err(|e| FieldError::new("hand", e.to_string())),
+    }
+}
+
+/// 検証済みの手牌に含まれる赤5（0m/0p/0s）の枚数。含まなければ`None`
+///
+/// データファイルは赤5を区別しないため、分析には使わずレスポンスに含めるだけにする。
+pub fn aka_count(hand: Option<&str>, format: Option<HandFormat>) -> Option<usize> {
+    let hand = mpsz_hand(hand?, format).ok()?;
+    // 検証済みの手牌では、0は副露の中も含めて赤5のみ
+    let aka_count = hand.chars().filter(|&c| c == '0').count();
+    (aka_count > 0).then_some(aka_count)
+}
+
+/// 検証済みの手牌の副露がすべて暗槓のときのみ`Some(true)`
+///
+/// 暗槓のみなら刻子とみなして分析するため、その結果が近似であることをレスポンスで示す。
+/// ほかの副露を含む手牌は門前の牌のみで分析するため、近似ではない。
+pub fn kan_approximated(hand: Option<&str>, format: Option<HandFormat>) -> Option<bool> {
+    let hand = mpsz_hand(hand?, format).ok()?;
+    let (_, melds) = parse_hand_str_with_melds(&hand).ok()?;
+    (!melds.is_empty() && melds.iter().all(|meld| matches!(meld, Meld::Ankan(_)))).then_some(true)
+}
+
+/// メンツの絞り込み条件を検証し、エラーを`errors`に追加する
+fn validate_filter(
+    min_probability: Option<f64>,
+    top_k: Option<usize>,
+    errors: &mut Vec<FieldError>,
+) {
+    if let Some(p) = min_probability {
+        if !(0.0..=1.0).contains(&p) {
+            errors.push(FieldError::new("min_probability", "must be 0.0..=1.0"));
+        }
+    }
+    if top_k == Some(0) {
+        errors.push(FieldError::new("top_k", "must be at least 1"));
+    }
+}
+
+/// 手牌と残り巡数を検証し、エラーを`errors`に追加する。手牌が正しければそれを返す
+fn validate_hand_draws(
+    hand: Option<&str>,
+    format: Option<HandFormat>,
+    draws_left: Option<usize>,
+    require_draws_left: bool,
+    errors: &mut Vec<FieldError>,
+) -> Option<Vec<Tile>> {
+    let hand = validate_hand(hand, format, false)
+        .map_err(|e| errors.push(e))
+        .ok();
+    match (draws_left, &hand) {
+        (None, _) if require_draws_left => {
+            errors.push(FieldError::new("draws_left", "is required"))
+        }
+        (Some(draws_left), Some(hand)) => {
+            if let Err(e) = validate_draws_left(hand.len(), draws_left) {
+                errors.push(e);
+            }
+        }
+        _ => {}
+    }
+    hand
+}
+
+/// 残り巡数の範囲を検証する。13枚は残り1〜NUM_ROUNDS巡、14枚は残り0〜NUM_ROUNDS-1巡
+fn validate_draws_left(num_tiles: usize, draws_left: usize) -> Result<(), FieldError> {
+    let first = if num_tiles == 13 { 1 } else { 0 };
+    let last = first + NUM_ROUNDS - 1;
+    if !(first..=last).contains(&draws_left) {
+        return Err(FieldError::new(
+            "draws_left",
+            format!("must be {}..={}", first, last),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,145 @@
+use common::mahjong::Tile;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use tracing::{debug, warn};
+
+use crate::analysis::{MentsuAnalysis, SharedHandAnalyzer, TsumoAnalysis};
+
+/// シャドーリード（二重読み出し）による検証
+///
+/// 一定割合のリクエストを副データセット（または別エンジン）でも実行し、
+/// 結果の差が許容誤差を超えた場合にログを出力する。ストレージ層の入れ替え時の検証用。
+pub struct ShadowVerifier {
+    secondary: SharedHandAnalyzer,
+    fraction: f64,
+    tolerance: f64,
+    counter: AtomicU64,
+}
+
+impl ShadowVerifier {
+    pub fn new(secondary: SharedHandAnalyzer, fraction: f64, tolerance: f64) -> Self {
+        Self {
+            secondary,
+            fraction: fraction.clamp(0.0, 1.0),
+            tolerance,
+            counter: AtomicU64::new(0),
+        }
+    }
+
+    /// このリクエストをシャドー実行するかどうか
+    ///
+    /// 乱数ではなくリクエスト数から決定的に選ぶため、`fraction`の割合が正確に守られる。
+    fn should_sample(&self) -> bool {
+        let n = self.counter.fetch_add(1, Ordering::Relaxed) as f64;
+        ((n + 1.0) * self.fraction).floor() > (n * self.fraction).floor()
+    }
+
+    /// ツモ率分析をシャドー実行し、主系の結果と比較する
+    pub fn verify_tsumo(self: &Arc<Self>, hand: &[Tile], primary: &TsumoAnalysis) {
+        if !self.should_sample() {
+            return;
+        }
+        let this = self.clone();
+        let hand = hand.to_vec();
+        let primary = primary.clone();
+        tokio::spawn(async move {
+            let secondary = match this.secondary.analyze_tsumo(&hand).await {
+                Ok(secondary) => secondary,
+                Err(e) => {
+                    warn!("Shadow tsumo analysis failed: hand={:?}, error={}", hand, e);
+                    return;
+                }
+            };
+            let primary_values = primary
+                .probabilities
+                .iter()
+                .map(|p| (p.draws_left.to_string(), p.probability));
+            let secondary_values = secondary
+                .probabilities
+                .iter()
+                .map(|p| (p.draws_left.to_string(), p.probability));
+            this.report("tsumo", &hand, None, primary_values, secondary_values);
+        });
+    }
+
+    /// メンツ実現確率分析をシャドー実行し、主系の結果と比較する
+    pub fn verify_mentsu(
+        self: &Arc<Self>,
+        hand: &[Tile],
+        draws_left: usize,
+        primary: &MentsuAnalysis,
+    ) {
+        if !self.should_sample() {
+            return;
+        }
+        let this = self.clone();
+        let hand = hand.to_vec();
+        let primary = primary.clone();
+        tokio::spawn(async move {
+            let secondary = match this.secondary.analyze_mentsu(&hand, draws_left).await {
+                Ok(secondary) => secondary,
+                Err(e) => {
+                    warn!(
+                        "Shadow mentsu analysis failed: hand={:?}, draws_left={}, error={}",
+                        hand, draws_left, e
+                    );
+                    return;
+                }
+            };
+            let primary_values = primary
+                .probabilities
+                .iter()
+                .map(|p| (p.mentsu_type.clone(), p.probability));
+            let secondary_values = secondary
+                .probabilities
+                .iter()
+                .map(|p| (p.mentsu_type.clone(), p.probability));
+            this.report(
+                "mentsu",
+                &hand,
+                Some(draws_left),
+                primary_values,
+                secondary_values,
+            );
+        });
+    }
+
+    fn report(
+        &self,
+        kind: &str,
+        hand: &[Tile],
+        draws_left: Option<usize>,
+        primary: impl ExactSizeIterator<Item = (String, f64)>,
+        secondary: impl ExactSizeIterator<Item = (String, f64)>,
+    ) {
+        if primary.len() != secondary.len() {
+            warn!(
+                "Shadow {} mismatch: hand={:?}, draws_left={:?}, primary_len={}, secondary_len={}",
+                kind,
+                hand,
+                draws_left,
+                primary.len(),
+                secondary.len()
+            );
+            return;
+        }
+        let mut mismatches = 0;
+        for ((pk, pv), (sk, sv)) in primary.zip(secondary) {
+            if pk != sk || (pv - sv).abs() > self.tolerance {
+                warn!(
+                    "Shadow {} mismatch: hand={:?}, draws_left={:?}, primary=({}, {}), secondary=({}, {}), tolerance={}",
+                    kind, hand, draws_left, pk, pv, sk, sv, self.tolerance
+                );
+                mismatches += 1;
+            }
+        }
+        if mismatches == 0 {
+            debug!(
+                "Shadow {} matched: hand={:?}, draws_left={:?}",
+                kind, hand, draws_left
+            );
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use serde::Serialize;
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use utoipa::ToSchema;
+
+use crate::analysis::ReadStats;
+
+/// 所要時間のヒストグラムの上限（秒）
+pub const LATENCY_BUCKETS: &[f64] = &[0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+/// 所要時間のヒストグラム
+///
+/// 記録はロックを取らずに行い、読み出し時に累積の度数に直す。
+pub struct Histogram {
+    bounds: &'static [f64],
+    // 各区間の度数。最後の要素は上限を超えたもの
+    counts: Vec<AtomicU64>,
+    sum_nanos: AtomicU64,
+}
+
+/// ヒストグラムのある時点の値
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct HistogramSnapshot {
+    /// 上限ごとの累積の度数（上限を超えたものは`count`にのみ含む）
+    pub buckets: Vec<HistogramBucket>,
+    pub count: u64,
+    /// 合計（秒）
+    pub sum_secs: f64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct HistogramBucket {
+    /// 上限（秒）
+    pub le: f64,
+    pub count: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let index = self.bounds.partition_point(|&le| le < secs);
+        self.counts[index].fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let mut cumulative = 0;
+        let buckets = self
+            .bounds
+            .iter()
+            .zip(&self.counts)
+            .map(|(&le, count)| {
+                cumulative += count.load(Ordering::Relaxed);
+                HistogramBucket {
+                    le,
+                    count: cumulative,
+                }
+            })
+            .collect();
+        HistogramSnapshot {
+            buckets,
+            count: cumulative + self.counts[self.bounds.len()].load(Ordering::Relaxed),
+            sum_secs: self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9,
+        }
+    }
+}
+
+/// Prometheusのテキスト形式で書き出す
+///
+/// 同じ名前の系列はまとめて書く必要があるため、メトリクスごとに`family`を呼んでから系列を追加する。
+#[derive(Default)]
+pub struct PrometheusWriter {
+    out: String,
+}
+
+impl PrometheusWriter {
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let _ = writeln!(self.out, "{}{} {}", name, format_labels(labels), value);
+    }
+
+    pub fn histogram(&mut self, name: &str, labels: &[(&str, &str)], snapshot: &HistogramSnapshot) {
+        for bucket in &snapshot.buckets {
+            let le = bucket.le.to_string();
+            let mut labels = labels.to_vec();
+            labels.push(("le", &le));
+            self.sample(&format!("{}_bucket", name), &labels, bucket.count as f64);
+        }
+        let mut inf = labels.to_vec();
+        inf.push(("le", "+Inf"));
+        self.sample(&format!("{}_bucket", name), &inf, snapshot.count as f64);
+        self.sample(&format!("{}_sum", name), labels, snapshot.sum_secs);
+        self.sample(&format!("{}_count", name), labels, snapshot.count as f64);
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+}
+
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(key, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", key, value)
+        })
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+/// データセットごとのデータファイルの読み出しの統計をPrometheusのテキスト形式で書き出す
+pub fn write_read_metrics(w: &mut PrometheusWriter, reads: &[(&str, ReadStats)]) {
+    let name = "mahjong_table_reads_in_flight";
+    w.family(name, "gauge", "Number of data file reads in progress");
+    for (dataset, read) in reads {
+        let labels = [("dataset", *dataset), ("table", read.name.as_str())];
+        w.sample(name, &labels, read.in_flight as f64);
+    }
+
+    let name = "mahjong_table_read_failures_total";
+    w.family(name, "counter", "Number of failed data file reads");
+    for (dataset, read) in reads {
+        let labels = [("dataset", *dataset), ("table", read.name.as_str())];
+        w.sample(name, &labels, read.failures as f64);
+    }
+
+    let name = "mahjong_table_read_seconds";
+    w.family(name, "histogram", "Time spent reading from a data file");
+    for (dataset, read) in reads {
+        let labels = [("dataset", *dataset), ("table", read.name.as_str())];
+        w.histogram(name, &labels, &read.latency);
+    }
+}
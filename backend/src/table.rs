@@ -0,0 +1,274 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use common::flat_file_vec::{FixedRepr, FlatFileMmap, FlatFileVec};
+use common::mahjong::NUM_ROUNDS;
+use serde::{Deserialize, Serialize};
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use crate::object_table::{is_object_url, ObjectStoreTable};
+use crate::stats::{Histogram, LATENCY_BUCKETS};
+
+/// データファイルの読み出し方
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum IoMode {
+    /// 1つのファイルハンドルを共有し、リクエストごとに位置を指定して読む（`pread`）
+    #[default]
+    #[serde(alias = "pool")]
+    #[value(alias = "pool")]
+    File,
+    /// ファイルをメモリマップし、ページキャッシュからコピーする
+    Mmap,
+    /// 起動時にファイル全体をメモリに読み込む
+    Memory,
+}
+
+/// データファイルごとの読み出し方
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IoModes {
+    pub tsumo_13: IoMode,
+    pub tsumo_14: IoMode,
+    pub metrics_13: IoMode,
+    pub metrics_14: IoMode,
+}
+
+/// データファイルの開き方
+#[derive(Debug, Clone, Copy)]
+pub struct TableOptions {
+    pub io_modes: IoModes,
+    /// オブジェクトストレージから1回に読み出すバイト数
+    pub object_block_bytes: usize,
+    /// オブジェクトストレージから読み出したブロックをファイルごとにキャッシュする数
+    pub object_cache_blocks: usize,
+}
+
+/// 分析エンジンが読み出す行の置き場所
+///
+/// データファイルは手牌ごとに`NUM_ROUNDS`個の要素が並んだ行の列として扱う。
+/// ファイルハンドルからの読み出し・メモリマップ・メモリへの読み込みのほか、
+/// この型を実装すれば別のストレージから行を読み出せる。
+#[async_trait]
+pub trait TableSource<T: Send + 'static>: Send + Sync {
+    /// ログや統計情報に出す場所（ファイルパスなど）
+    fn location(&self) -> String;
+
+    /// ローカルファイルから読み出す場合はそのパス
+    fn local_path(&self) -> Option<&Path> {
+        None
+    }
+
+    /// 要素数
+    async fn len(&self) -> Result<usize>;
+
+    /// [start, end)の要素を読み出す
+    async fn get_range(&self, start: usize, end: usize) -> Result<Vec<T>>;
+
+    /// `hand_id`の行のうち、`rounds`の範囲の要素を読み出す
+    async fn get_row(&self, hand_id: usize, rounds: Range<usize>) -> Result<Vec<T>> {
+        if rounds.start > rounds.end || rounds.end > NUM_ROUNDS {
+            return Err(anyhow::anyhow!("Invalid rounds: {:?}", rounds));
+        }
+        let base = hand_id * NUM_ROUNDS;
+        self.get_range(base + rounds.start, base + rounds.end).await
+    }
+
+    /// 先頭と末尾の行を読み、ページキャッシュやブロックのキャッシュを温める
+    async fn warm_up(&self) -> Result<()> {
+        let rows = self.len().await? / NUM_ROUNDS;
+        if rows > 0 {
+            self.get_row(0, 0..NUM_ROUNDS).await?;
+            self.get_row(rows - 1, 0..NUM_ROUNDS).await?;
+        }
+        Ok(())
+    }
+
+    /// ファイルからの読み出しの累積の統計。ファイルハンドルから読まない場合は`None`
+    fn read_metrics(&self) -> Option<&ReadMetrics> {
+        None
+    }
+}
+
+/// 指定した読み出し方でデータファイルを開く
+///
+/// `s3://`や`gs://`で始まる場所はオブジェクトストレージから読み出し、`mode`は使わない。
+pub fn open_table<T: FixedRepr + Send + Sync + 'static>(
+    location: &str,
+    mode: IoMode,
+    options: &TableOptions,
+) -> Result<Arc<dyn TableSource<T>>> {
+    if is_object_url(location) {
+        return Ok(Arc::new(ObjectStoreTable::open(
+            location,
+            options.object_block_bytes,
+            options.object_cache_blocks,
+        )?));
+    }
+    Ok(match mode {
+        IoMode::File => Arc::new(FileTable::open(location)?),
+        IoMode::Mmap => Arc::new(MmapTable::open(location)?),
+        IoMode::Memory => Arc::new(MemoryTable::open(location)?),
+    })
+}
+
+/// 1つのファイルハンドルを共有し、リクエストごとに位置を指定して読むテーブル
+///
+/// [`FlatFileVec`]は位置を指定して読む（`pread`）ためファイルのオフセットを変えず、
+/// ハンドルを貸し借りせずに複数のリクエストから同時に読める。
+/// 読み出しはブロッキングするため、Tokioのワーカースレッドではなく`spawn_blocking`のスレッドで行う。
+pub struct FileTable<T: FixedRepr> {
+    path: PathBuf,
+    file: Arc<FlatFileVec<T>>,
+    metrics: Arc<ReadMetrics>,
+}
+
+/// ファイルからの読み出しの累積の統計
+pub struct ReadMetrics {
+    /// 1回の読み出しにかかった時間
+    pub latency: Histogram,
+    /// 読み出しに失敗した回数
+    pub failures: AtomicU64,
+    /// 読み出し中の数
+    pub in_flight: AtomicUsize,
+}
+
+impl<T: FixedRepr> FileTable<T> {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let file = FlatFileVec::open_readonly(&path)?;
+        Ok(Self {
+            path,
+            file: Arc::new(file),
+            metrics: Arc::new(ReadMetrics {
+                latency: Histogram::new(LATENCY_BUCKETS),
+                failures: AtomicU64::new(0),
+                in_flight: AtomicUsize::new(0),
+            }),
+        })
+    }
+}
+
+#[async_trait]
+impl<T: FixedRepr + Send + Sync + 'static> TableSource<T> for FileTable<T> {
+    fn location(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn local_path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+
+    async fn len(&self) -> Result<usize> {
+        Ok(self.file.len())
+    }
+
+    /// 読み出し中に呼び出し側のfutureが破棄されても、読み出しは最後まで行われる
+    async fn get_range(&self, start: usize, end: usize) -> Result<Vec<T>> {
+        if start > end || end > self.file.len() {
+            return Err(anyhow::Error::msg("Invalid range"));
+        }
+        let file = Arc::clone(&self.file);
+        let metrics = Arc::clone(&self.metrics);
+        tokio::task::spawn_blocking(move || {
+            metrics.in_flight.fetch_add(1, Ordering::Relaxed);
+            let started = Instant::now();
+            let result = file.get_range(start, end);
+            metrics.latency.observe(started.elapsed());
+            metrics.in_flight.fetch_sub(1, Ordering::Relaxed);
+            if result.is_err() {
+                metrics.failures.fetch_add(1, Ordering::Relaxed);
+            }
+            result.context("Failed to read data file")
+        })
+        .await?
+    }
+
+    fn read_metrics(&self) -> Option<&ReadMetrics> {
+        Some(&self.metrics)
+    }
+}
+
+/// ファイルをメモリマップし、ページキャッシュからコピーするテーブル
+pub struct MmapTable<T: FixedRepr> {
+    path: PathBuf,
+    mmap: Arc<FlatFileMmap<T>>,
+}
+
+impl<T: FixedRepr> MmapTable<T> {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        // データファイルは読み取り専用で、配信中に書き換えたり切り詰めたりしない前提とする
+        let mmap = FlatFileVec::open_mmap(&path)?;
+        Ok(Self {
+            path,
+            mmap: Arc::new(mmap),
+        })
+    }
+}
+
+#[async_trait]
+impl<T: FixedRepr + Send + Sync + 'static> TableSource<T> for MmapTable<T> {
+    fn location(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn local_path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+
+    async fn len(&self) -> Result<usize> {
+        Ok(self.mmap.len())
+    }
+
+    async fn get_range(&self, start: usize, end: usize) -> Result<Vec<T>> {
+        if start > end || end > self.mmap.len() {
+            return Err(anyhow::Error::msg("Invalid range"));
+        }
+        // ページキャッシュにないページはここでディスクから読まれるため、ブロッキングしてよいスレッドで読む
+        let mmap = Arc::clone(&self.mmap);
+        tokio::task::spawn_blocking(move || mmap.get_range(start, end)).await?
+    }
+}
+
+/// 起動時にファイル全体をメモリに読み込んだテーブル
+pub struct MemoryTable<T> {
+    path: PathBuf,
+    data: Vec<T>,
+}
+
+impl<T: FixedRepr> MemoryTable<T> {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let data = FlatFileVec::load_all(&path)?;
+        Ok(Self { path, data })
+    }
+}
+
+#[async_trait]
+impl<T: FixedRepr + Send + Sync + 'static> TableSource<T> for MemoryTable<T> {
+    fn location(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn local_path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+
+    async fn len(&self) -> Result<usize> {
+        Ok(self.data.len())
+    }
+
+    async fn get_range(&self, start: usize, end: usize) -> Result<Vec<T>> {
+        self.data
+            .get(start..end)
+            .map(<[T]>::to_vec)
+            .ok_or_else(|| anyhow::Error::msg("Invalid range"))
+    }
+}
@@ -0,0 +1,160 @@
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
+use tracing::{debug_span, Instrument, Span};
+
+use crate::stats::{Histogram, PrometheusWriter, LATENCY_BUCKETS};
+
+/// リクエストの処理の段階
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    /// 手牌の文字列の解析と検証
+    Parse,
+    /// converterでの手牌のエンコード・デコード（二分探索）
+    Encode,
+    /// データファイルからの行の読み出し（キャッシュにヒットした分は含めない）
+    Read,
+}
+
+impl Stage {
+    const ALL: [Stage; 3] = [Stage::Parse, Stage::Encode, Stage::Read];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Stage::Parse => "parse",
+            Stage::Encode => "encode",
+            Stage::Read => "read",
+        }
+    }
+}
+
+tokio::task_local! {
+    static TIMINGS: Arc<StageTimings>;
+}
+
+/// 1リクエストの段階ごとの所要時間の合計
+///
+/// ツモ率とメトリクスは並行して読み出すため、合計がリクエスト全体の処理時間を超えることがある。
+#[derive(Default)]
+struct StageTimings {
+    nanos: [AtomicU64; 3],
+    // 一度でも通った段階のビット
+    seen: AtomicU64,
+}
+
+impl StageTimings {
+    fn add(&self, stage: Stage, elapsed: Duration) {
+        self.nanos[stage as usize].fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+        self.seen.fetch_or(1 << stage as usize, Ordering::Relaxed);
+    }
+
+    /// 通った段階とその所要時間
+    fn stages(&self) -> impl Iterator<Item = (Stage, Duration)> + '_ {
+        let seen = self.seen.load(Ordering::Relaxed);
+        Stage::ALL
+            .into_iter()
+            .filter(move |&stage| seen & (1 << stage as usize) != 0)
+            .map(|stage| {
+                let nanos = self.nanos[stage as usize].load(Ordering::Relaxed);
+                (stage, Duration::from_nanos(nanos))
+            })
+    }
+}
+
+fn stage_span(stage: Stage) -> Span {
+    debug_span!("stage", stage = stage.as_str())
+}
+
+fn record(stage: Stage, elapsed: Duration) {
+    // リクエストの外（自己診断など）では記録しない
+    let _ = TIMINGS.try_with(|timings| timings.add(stage, elapsed));
+}
+
+/// `f`を`stage`のスパンの中で実行し、所要時間を処理中のリクエストに加える
+pub fn measure<R>(stage: Stage, f: impl FnOnce() -> R) -> R {
+    let started = Instant::now();
+    let result = stage_span(stage).in_scope(f);
+    record(stage, started.elapsed());
+    result
+}
+
+/// `future`を`stage`のスパンの中で実行し、所要時間を処理中のリクエストに加える
+pub async fn measure_async<F: Future>(stage: Stage, future: F) -> F::Output {
+    let started = Instant::now();
+    let result = future.instrument(stage_span(stage)).await;
+    record(stage, started.elapsed());
+    result
+}
+
+/// エンドポイントごと・段階ごとの所要時間のヒストグラム
+///
+/// 遅いリクエストがディスクの読み出しとconverterのどちらで時間を使っているかを見分けるために使う。
+#[derive(Clone, Default)]
+pub struct LatencyBreakdown(Arc<RwLock<BTreeMap<StageKey, Arc<Histogram>>>>);
+
+// エンドポイントのパスと段階
+type StageKey = (String, Stage);
+
+impl LatencyBreakdown {
+    fn observe(&self, endpoint: &str, stage: Stage, elapsed: Duration) {
+        let key = (endpoint.to_string(), stage);
+        let histogram = self.0.read().unwrap().get(&key).cloned();
+        let histogram = match histogram {
+            Some(histogram) => histogram,
+            None => self
+                .0
+                .write()
+                .unwrap()
+                .entry(key)
+                .or_insert_with(|| Arc::new(Histogram::new(LATENCY_BUCKETS)))
+                .clone(),
+        };
+        histogram.observe(elapsed);
+    }
+
+    /// Prometheusのテキスト形式で書き出す
+    pub fn write_metrics(&self, w: &mut PrometheusWriter) {
+        let name = "mahjong_request_stage_seconds";
+        w.family(name, "histogram", "Time spent in each stage of a request");
+        for ((endpoint, stage), histogram) in self.0.read().unwrap().iter() {
+            let labels = [("endpoint", endpoint.as_str()), ("stage", stage.as_str())];
+            w.histogram(name, &labels, &histogram.snapshot());
+        }
+    }
+}
+
+/// 段階ごとの所要時間を集計するミドルウェア
+///
+/// ルートに一致したリクエストのみを対象にするため、`route_layer`で使う。
+/// 所要時間はアクセスログのスパンにも`parse_ms`などとして記録する。
+pub async fn record_stages(
+    State(breakdown): State<LatencyBreakdown>,
+    matched_path: MatchedPath,
+    req: Request,
+    next: Next,
+) -> Response {
+    let timings = Arc::new(StageTimings::default());
+    let response = TIMINGS.scope(timings.clone(), next.run(req)).await;
+    let span = Span::current();
+    for (stage, elapsed) in timings.stages() {
+        breakdown.observe(matched_path.as_str(), stage, elapsed);
+        let field = match stage {
+            Stage::Parse => "parse_ms",
+            Stage::Encode => "encode_ms",
+            Stage::Read => "read_ms",
+        };
+        span.record(field, elapsed.as_secs_f64() * 1000.0);
+    }
+    response
+}
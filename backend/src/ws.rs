@@ -0,0 +1,173 @@
+use axum::extract::ws::{Message, WebSocket};
+use common::mahjong::{parse_hand_str, validate_hand_tiles, Tile};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::analysis::{format_tiles, CombinedAnalysis, ShantenAnalysis, SharedHandAnalyzer};
+use crate::params::{FieldError, HandDrawsQuery, InvalidParams};
+
+/// クライアントから送られるイベント
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    /// 手牌を設定する。`draws_left`を省略すると全巡数を分析する
+    SetHand {
+        hand: Option<String>,
+        draws_left: Option<usize>,
+    },
+    /// 13枚の手牌に1枚ツモる。残り巡数は1減る
+    Draw { tile: String },
+    /// 14枚の手牌から1枚捨てる
+    Discard { tile: String },
+}
+
+/// サーバーから送るメッセージ
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    Analysis {
+        hand: String,
+        draws_left: Option<usize>,
+        shanten: ShantenAnalysis,
+        analysis: CombinedAnalysis,
+    },
+    Error {
+        message: String,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        details: Vec<FieldError>,
+    },
+}
+
+impl From<InvalidParams> for ServerMessage {
+    fn from(e: InvalidParams) -> Self {
+        ServerMessage::Error {
+            message: format!("Invalid event: {}", e.message()),
+            details: e.0,
+        }
+    }
+}
+
+/// 1接続分の手牌の状態
+#[derive(Debug, Default)]
+struct Session {
+    tiles: Vec<Tile>,
+    draws_left: Option<usize>,
+}
+
+impl Session {
+    /// イベントを手牌に反映する。不正なイベントでは状態を変えない
+    fn apply(&mut self, event: ClientMessage) -> Result<(), InvalidParams> {
+        match event {
+            ClientMessage::SetHand { hand, draws_left } => {
+                let (tiles, draws_left) = HandDrawsQuery {
+                    hand,
+                    draws_left,
+                    format: None,
+                }
+                .validate(false)?;
+                self.tiles = tiles;
+                self.draws_left = draws_left;
+            }
+            ClientMessage::Draw { tile } => {
+                if self.tiles.len() != 13 {
+                    return Err(invalid_tile(format!(
+                        "cannot draw with {} tiles in hand",
+                        self.tiles.len()
+                    )));
+                }
+                let mut tiles = self.tiles.clone();
+                tiles.push(parse_tile(&tile)?);
+                validate_hand_tiles(&tiles).map_err(|e| invalid_tile(e.to_string()))?;
+                self.tiles = tiles;
+                // 13枚で残りd巡なら、ツモ後の14枚は残りd-1巡
+                self.draws_left = self.draws_left.map(|d| d - 1);
+            }
+            ClientMessage::Discard { tile } => {
+                if self.tiles.len() != 14 {
+                    return Err(invalid_tile(format!(
+                        "cannot discard with {} tiles in hand",
+                        self.tiles.len()
+                    )));
+                }
+                if self.draws_left == Some(0) {
+                    return Err(invalid_tile(
+                        "no draws left; send set_hand to start a new hand",
+                    ));
+                }
+                let tile = parse_tile(&tile)?;
+                let index = self
+                    .tiles
+                    .iter()
+                    .position(|&t| t == tile)
+                    .ok_or_else(|| invalid_tile("tile is not in hand"))?;
+                self.tiles.remove(index);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// WebSocketの1接続を処理する。イベントを受け取るたびに手牌を更新して分析結果を返す
+pub async fn run_session(mut socket: WebSocket, analyzer: SharedHandAnalyzer) {
+    info!("WebSocket session started");
+    let mut session = Session::default();
+
+    while let Some(message) = socket.recv().await {
+        let text = match message {
+            Ok(Message::Text(text)) => text,
+            Ok(Message::Close(_)) | Err(_) => break,
+            // Ping/Pongはaxumが処理する
+            Ok(_) => continue,
+        };
+        debug!("Received WebSocket event: {}", text);
+
+        let reply = match serde_json::from_str::<ClientMessage>(&text) {
+            Ok(event) => match session.apply(event) {
+                Ok(()) => analyze(&analyzer, &session).await,
+                Err(e) => e.into(),
+            },
+            Err(e) => ServerMessage::Error {
+                message: format!("Invalid event: {}", e),
+                details: Vec::new(),
+            },
+        };
+
+        let reply = serde_json::to_string(&reply).expect("ServerMessage is always serializable");
+        if socket.send(Message::Text(reply)).await.is_err() {
+            break;
+        }
+    }
+    info!("WebSocket session closed");
+}
+
+async fn analyze(analyzer: &SharedHandAnalyzer, session: &Session) -> ServerMessage {
+    let result = async {
+        let shanten = analyzer.analyze_shanten(&session.tiles)?;
+        let analysis = analyzer.analyze(&session.tiles, session.draws_left).await?;
+        anyhow::Ok((shanten, analysis))
+    }
+    .await;
+    match result {
+        Ok((shanten, analysis)) => ServerMessage::Analysis {
+            hand: format_tiles(&session.tiles),
+            draws_left: session.draws_left,
+            shanten,
+            analysis,
+        },
+        Err(e) => ServerMessage::Error {
+            message: format!("Failed to analyze hand: {}", e),
+            details: Vec::new(),
+        },
+    }
+}
+
+fn parse_tile(tile: &str) -> Result<Tile, InvalidParams> {
+    match parse_hand_str(tile).map_err(|e| invalid_tile(e.to_string()))?[..] {
+        [tile] => Ok(tile),
+        _ => Err(invalid_tile("must be a single tile (e.g. 5m)")),
+    }
+}
+
+fn invalid_tile(reason: impl Into<String>) -> InvalidParams {
+    InvalidParams(vec![FieldError::new("tile", reason)])
+}
@@ -0,0 +1,82 @@
+use std::env;
+
+use anyhow::{anyhow, ensure, Result};
+use common::mahjong::HandConverter;
+
+const DEFAULT_SAMPLES: usize = 100000;
+
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().collect();
+    let (samples, paths) = match args.get(1).map(String::as_str) {
+        Some("--samples") if args.len() > 2 => (args[2].parse().ok(), &args[3..]),
+        _ => (Some(DEFAULT_SAMPLES), &args[1..]),
+    };
+    let samples = match samples {
+        Some(samples) if (1..=2).contains(&paths.len()) => samples,
+        _ => {
+            eprintln!("Usage: {} [--samples N] <converter_path> [<other_path>]", args[0]);
+            eprintln!("Example: {} converter.dat converter.raw", args[0]);
+            eprintln!("  Without <other_path>, compare with a converter generated now");
+            eprintln!("  --samples: number of hands of each size to encode and decode (default {})", DEFAULT_SAMPLES);
+            std::process::exit(1);
+        }
+    };
+
+    let conv = HandConverter::open(&paths[0])?;
+    let other = match paths.get(1) {
+        Some(path) => HandConverter::open(path)?,
+        None => HandConverter::with_tile_set(conv.tile_set()),
+    };
+    conv.check_same_tables(&other)?;
+    println!("Tables match ({:?})", conv.tile_set());
+    for num_tiles in [13, 14] {
+        check_round_trips(&conv, &other, num_tiles, samples)?;
+        println!("{} round trips of {}-tile hands match", samples, num_tiles);
+    }
+    Ok(())
+}
+
+// Decode evenly spaced hands with `conv` and check that `other` encodes them to the
+// same indices and decodes the indices to the same hands
+fn check_round_trips(
+    conv: &HandConverter,
+    other: &HandConverter,
+    num_tiles: usize,
+    samples: usize,
+) -> Result<()> {
+    let space = conv.hand_space(num_tiles);
+    let other_space = other.hand_space(num_tiles);
+    ensure!(
+        space.len() == other_space.len(),
+        "{}-tile hands: {} != {}",
+        num_tiles,
+        space.len(),
+        other_space.len()
+    );
+    let last = space.len() - 1;
+    for k in 0..samples {
+        let hand_id = if samples == 1 { 0 } else { (k * last / (samples - 1)) as u32 };
+        let hand = conv.decode_in(&space, hand_id);
+        let (encoded, _) = other
+            .try_encode_in(&other_space, &hand)
+            .map_err(|e| anyhow!("{}-tile hand {} ({}): {}", num_tiles, hand_id, hand, e))?;
+        ensure!(
+            encoded == hand_id,
+            "{}-tile hand {} ({}) is encoded to {}",
+            num_tiles,
+            hand_id,
+            hand,
+            encoded
+        );
+        let decoded = other.decode_in(&other_space, hand_id);
+        ensure!(
+            decoded.supai == hand.supai && decoded.jihai == hand.jihai,
+            "{}-tile hand {} is decoded to {}, not {}",
+            num_tiles,
+            hand_id,
+            decoded,
+            hand
+        );
+    }
+    Ok(())
+}
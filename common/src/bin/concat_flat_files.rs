@@ -0,0 +1,61 @@
+use std::{env, fs::File, io::Read};
+
+use anyhow::{bail, Result};
+use common::{
+    flat_file_vec::{FixedRepr, FlatFileVec, HEADER_MAGIC},
+    mahjong::Metrics,
+};
+
+const TYPES: [&str; 5] = ["u16", "u32", "u64", "u128", "metrics"];
+
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().collect();
+    let (type_name, paths) = match args.get(1).map(String::as_str) {
+        Some("--type") if args.len() > 2 => (Some(args[2].clone()), &args[3..]),
+        _ => (None, &args[1..]),
+    };
+    if paths.len() < 2 || type_name.as_ref().is_some_and(|t| !TYPES.contains(&t.as_str())) {
+        eprintln!("Usage: {} [--type TYPE] <output_path> <input_path>...", args[0]);
+        eprintln!("Example: {} metrics_temp/00_00.dat metrics_temp/00_00_*.dat", args[0]);
+        eprintln!("  --type: element type, one of {} (default: read from the header of the first input)", TYPES.join(", "));
+        std::process::exit(1);
+    }
+
+    let (output, inputs) = (&paths[0], &paths[1..]);
+    let type_name = match type_name {
+        Some(type_name) => type_name,
+        None => header_type(&inputs[0])?,
+    };
+    let len = match type_name.as_str() {
+        "u16" => FlatFileVec::<u16>::concat(inputs, output)?,
+        "u32" => FlatFileVec::<u32>::concat(inputs, output)?,
+        "u64" => FlatFileVec::<u64>::concat(inputs, output)?,
+        "u128" => FlatFileVec::<u128>::concat(inputs, output)?,
+        "metrics" => FlatFileVec::<Metrics>::concat(inputs, output)?,
+        _ => bail!("Unsupported element type {}", type_name),
+    };
+    println!("Concatenated {} files ({} elements) to: {}", inputs.len(), len, output);
+    Ok(())
+}
+
+// Element type written in the header of a file, which older files don't have
+fn header_type(path: &str) -> Result<String> {
+    let mut head = Vec::new();
+    File::open(path)?.take(24).read_to_end(&mut head)?;
+    if head.len() < 24 || !head.starts_with(HEADER_MAGIC) {
+        bail!("{} has no header, pass --type", path);
+    }
+    let tag = &head[16..24];
+    for (type_name, type_tag) in TYPES.iter().zip([
+        u16::TYPE_TAG,
+        u32::TYPE_TAG,
+        u64::TYPE_TAG,
+        u128::TYPE_TAG,
+        Metrics::TYPE_TAG,
+    ]) {
+        if tag == type_tag {
+            return Ok(type_name.to_string());
+        }
+    }
+    bail!("{} holds an unknown element type", path)
+}
@@ -1,16 +1,37 @@
 use std::env;
 
+use common::mahjong::{HandConverter, TileSet};
+
 fn main() {
     let args: Vec<String> = env::args().collect();
     
-    if args.len() != 2 {
-        eprintln!("Usage: {} <output_path>", args[0]);
+    let flags = &args[1..args.len().saturating_sub(1)];
+    let has_flag = |flag: &str| flags.iter().any(|f| f == flag);
+    if args.len() < 2
+        || flags.iter().any(|f| !["--raw", "--sanma", "--mini"].contains(&f.as_str()))
+        || (has_flag("--sanma") && has_flag("--mini"))
+    {
+        eprintln!("Usage: {} [--raw] [--sanma | --mini] <output_path>", args[0]);
         eprintln!("Example: {} converter.dat", args[0]);
+        eprintln!("  --raw: write all tables in the memory-mapped format");
+        eprintln!("  --sanma: number the hands of three-player mahjong (no 2m-8m)");
+        eprintln!("  --mini: number the hands of manzu and two kinds of jihai, for development");
         std::process::exit(1);
     }
     
-    let output_path = &args[1];
-    let conv = common::mahjong::HandConverter::new();
-    conv.save_as_file(output_path).unwrap();
+    let output_path = args.last().unwrap();
+    let tile_set = if has_flag("--sanma") {
+        TileSet::Sanma
+    } else if has_flag("--mini") {
+        TileSet::Mini
+    } else {
+        TileSet::Yonma
+    };
+    let conv = HandConverter::with_tile_set(tile_set);
+    if has_flag("--raw") {
+        conv.save_as_raw_file(output_path).unwrap();
+    } else {
+        conv.save_as_file(output_path).unwrap();
+    }
     println!("HandConverter saved to: {}", output_path);
 }
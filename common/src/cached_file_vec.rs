@@ -0,0 +1,141 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use anyhow::{ensure, Result};
+use lru::LruCache;
+
+use crate::flat_file_vec::{FixedRepr, FlatFileVec};
+
+/// A [`FlatFileVec`] with an LRU cache of pages of `page_len` elements in memory, for readers
+/// that read the same regions repeatedly.
+///
+/// Reads go through the cache page by page, so a range spanning several pages reads the
+/// missing ones from the file and keeps them. Appending through the inner vector after
+/// wrapping it is not supported, as cached pages would not see the new elements.
+pub struct CachedFlatFileVec<T: FixedRepr> {
+    inner: FlatFileVec<T>,
+    page_len: usize,
+    capacity: usize,
+    pages: Mutex<LruCache<usize, Arc<Vec<T>>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Statistics of a [`CachedFlatFileVec`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageCacheStats {
+    /// Maximum number of cached pages
+    pub capacity: usize,
+    /// Number of cached pages
+    pub pages: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// Fraction of page reads served from the cache, `None` before any read
+    pub hit_rate: Option<f64>,
+}
+
+impl<T: FixedRepr> CachedFlatFileVec<T> {
+    /// Wrap `inner`, caching up to `capacity` pages of `page_len` elements
+    pub fn new(inner: FlatFileVec<T>, page_len: usize, capacity: usize) -> Result<Self> {
+        ensure!(page_len > 0, "Page length must be positive");
+        let capacity = NonZeroUsize::new(capacity)
+            .ok_or_else(|| anyhow::anyhow!("Cache capacity must be positive"))?;
+        Ok(Self {
+            inner,
+            page_len,
+            capacity: capacity.get(),
+            pages: Mutex::new(LruCache::new(capacity)),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    /// Get the number of elements in the vector
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Check if the vector is empty
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Get a single element at the specified index
+    pub fn get(&self, index: usize) -> Result<T> {
+        if index >= self.len() {
+            return Err(anyhow::Error::msg("Index out of bounds"));
+        }
+        let page = self.page(index / self.page_len)?;
+        Ok(page[index % self.page_len].clone())
+    }
+
+    /// Get a range of elements [start, end)
+    pub fn get_range(&self, start: usize, end: usize) -> Result<Vec<T>> {
+        let mut result = Vec::new();
+        self.get_range_into(start, end, &mut result)?;
+        Ok(result)
+    }
+
+    /// Like `get_range`, replacing the contents of `buf` so that its allocation can be reused
+    pub fn get_range_into(&self, start: usize, end: usize, buf: &mut Vec<T>) -> Result<()> {
+        if start > end || end > self.len() {
+            return Err(anyhow::Error::msg("Invalid range"));
+        }
+        buf.clear();
+        buf.reserve(end - start);
+        let mut index = start;
+        while index < end {
+            let page_id = index / self.page_len;
+            let page = self.page(page_id)?;
+            let page_start = page_id * self.page_len;
+            let page_end = end.min(page_start + page.len());
+            buf.extend_from_slice(&page[index - page_start..page_end - page_start]);
+            index = page_end;
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> PageCacheStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        PageCacheStats {
+            capacity: self.capacity,
+            pages: self.pages.lock().unwrap().len(),
+            hits,
+            misses,
+            hit_rate: (hits + misses > 0).then(|| hits as f64 / (hits + misses) as f64),
+        }
+    }
+
+    /// Drop all cached pages and return how many there were
+    pub fn clear(&self) -> usize {
+        let mut pages = self.pages.lock().unwrap();
+        let cleared = pages.len();
+        pages.clear();
+        cleared
+    }
+
+    /// Drop the cache and return the inner vector
+    pub fn into_inner(self) -> FlatFileVec<T> {
+        self.inner
+    }
+
+    fn page(&self, page_id: usize) -> Result<Arc<Vec<T>>> {
+        // The lock is not held while reading. If two threads read the same page, the later one is kept
+        if let Some(page) = self.pages.lock().unwrap().get(&page_id) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(Arc::clone(page));
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let start = page_id * self.page_len;
+        let end = self.len().min(start + self.page_len);
+        let page = Arc::new(self.inner.get_range(start, end)?);
+        self.pages.lock().unwrap().put(page_id, Arc::clone(&page));
+        Ok(page)
+    }
+}
@@ -0,0 +1,265 @@
+use std::{
+    fs::{create_dir_all, File, OpenOptions},
+    io::{BufWriter, Seek, SeekFrom, Write},
+    marker::PhantomData,
+    path::Path,
+};
+
+use anyhow::{bail, ensure, Result};
+
+use crate::{
+    flat_file_vec::{
+        header, parse_header, read_exact_at, FixedRepr, HEADER_LEN_OFFSET, HEADER_SIZE,
+    },
+    io::content_hash,
+};
+
+/// First bytes of a file written by [`CheckedFileVec`]
+pub const CHECKED_MAGIC: &[u8; 8] = b"FLATFCHK";
+/// Default number of elements in a block
+pub const DEFAULT_BLOCK_LEN: usize = 4096;
+
+// The header of FlatFileVec with CHECKED_MAGIC, followed by the block length (u32) and 4 zero bytes
+const CHECKED_HEADER_SIZE: usize = HEADER_SIZE + 8;
+const CHECKSUM_SIZE: usize = 8;
+
+/// A flat file vector whose elements are written in blocks of a fixed number of elements,
+/// each followed by its [`content_hash`], so that corruption on disk is noticed instead of
+/// being read as data.
+///
+/// `get` and `get_range` check the blocks they read, and `verify` checks the whole file.
+/// Appending to a file whose last block is partial rewrites that block and its checksum.
+/// Use [`FlatFileVec`](crate::flat_file_vec::FlatFileVec) for files read in place or by the backend.
+#[derive(Debug)]
+pub struct CheckedFileVec<T: FixedRepr> {
+    file: File,
+    len: usize,
+    block_len: usize,
+    _phantom: PhantomData<T>,
+}
+
+impl<T: FixedRepr> CheckedFileVec<T> {
+    /// Create a new checked file vector with blocks of [`DEFAULT_BLOCK_LEN`] elements
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::create_with_block_len(path, DEFAULT_BLOCK_LEN)
+    }
+
+    /// Create a new checked file vector with blocks of `block_len` elements
+    pub fn create_with_block_len<P: AsRef<Path>>(path: P, block_len: usize) -> Result<Self> {
+        ensure!(
+            (1..=u32::MAX as usize).contains(&block_len),
+            "Invalid block length {}",
+            block_len
+        );
+        if let Some(parent) = path.as_ref().parent() {
+            create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.write_all(&header::<T>(CHECKED_MAGIC, 0))?;
+        file.write_all(&(block_len as u32).to_le_bytes())?;
+        file.write_all(&[0; 4])?;
+        Ok(Self {
+            file,
+            len: 0,
+            block_len,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Open an existing checked file vector
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Self::from_file(file)
+    }
+
+    /// Open an existing checked file vector in read-only mode
+    pub fn open_readonly<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_file(File::open(path)?)
+    }
+
+    /// Create a checked file vector from an existing File object
+    pub fn from_file(file: File) -> Result<Self> {
+        let file_size = file.metadata()?.len();
+        ensure!(
+            file_size >= CHECKED_HEADER_SIZE as u64,
+            "File is too short for a checked file vector"
+        );
+        let mut head = [0u8; CHECKED_HEADER_SIZE];
+        read_exact_at(&file, &mut head, 0)?;
+        ensure!(
+            head.starts_with(CHECKED_MAGIC),
+            "File has no checksums, open it with FlatFileVec"
+        );
+        let len = parse_header::<T>(&head)? as usize;
+        let block_len =
+            u32::from_le_bytes(head[HEADER_SIZE..HEADER_SIZE + 4].try_into().unwrap()) as usize;
+        ensure!(block_len > 0, "Invalid block length 0");
+
+        let ffv = Self {
+            file,
+            len,
+            block_len,
+            _phantom: PhantomData,
+        };
+        ensure!(
+            file_size == ffv.block_pos(ffv.num_blocks()),
+            "Header says {} elements, but the file holds {} bytes",
+            len,
+            file_size
+        );
+        Ok(ffv)
+    }
+
+    /// Load all elements from a file path, checking every block
+    pub fn load_all<P: AsRef<Path>>(path: P) -> Result<Vec<T>> {
+        let ffv = Self::open_readonly(path)?;
+        ffv.get_range(0, ffv.len())
+    }
+
+    /// Get the number of elements in the vector
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if the vector is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get the number of elements in a block
+    pub fn block_len(&self) -> usize {
+        self.block_len
+    }
+
+    /// Get a single element at the specified index
+    pub fn get(&self, index: usize) -> Result<T> {
+        if index >= self.len {
+            return Err(anyhow::Error::msg("Index out of bounds"));
+        }
+        let block = self.read_block(index / self.block_len)?;
+        T::deserialize(&mut &block[(index % self.block_len) * T::BYTE_SIZE..])
+    }
+
+    /// Get a range of elements [start, end)
+    pub fn get_range(&self, start: usize, end: usize) -> Result<Vec<T>> {
+        if start > end || end > self.len {
+            return Err(anyhow::Error::msg("Invalid range"));
+        }
+        let mut result = Vec::with_capacity(end - start);
+        let mut index = start;
+        while index < end {
+            let block_id = index / self.block_len;
+            let block = self.read_block(block_id)?;
+            let block_end = end.min((block_id + 1) * self.block_len);
+            let mut bytes = &block[(index % self.block_len) * T::BYTE_SIZE..];
+            for _ in index..block_end {
+                result.push(T::deserialize(&mut bytes)?);
+            }
+            index = block_end;
+        }
+        Ok(result)
+    }
+
+    /// Check the checksums of all blocks
+    pub fn verify(&self) -> Result<()> {
+        for block_id in 0..self.num_blocks() {
+            self.read_block(block_id)?;
+        }
+        Ok(())
+    }
+
+    /// Append a single element to the end of the vector
+    pub fn push(&mut self, item: &T) -> Result<()> {
+        self.extend(std::iter::once(item.clone()))
+    }
+
+    /// Append multiple elements to the end of the vector
+    pub fn extend<I>(&mut self, items: I) -> Result<()>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let block_bytes = self.block_len * T::BYTE_SIZE;
+        // Start from the last block if it is partial, since its checksum changes
+        let (block_id, partial) = (self.len / self.block_len, self.len % self.block_len);
+        let mut buf = if partial == 0 {
+            Vec::with_capacity(block_bytes)
+        } else {
+            let mut block = self.read_block(block_id)?;
+            block.reserve(block_bytes - block.len());
+            block
+        };
+        let mut len = self.len;
+
+        self.file.seek(SeekFrom::Start(self.block_pos(block_id)))?;
+        let mut writer = BufWriter::new(&mut self.file);
+        for item in items {
+            item.serialize(&mut buf)?;
+            len += 1;
+            if buf.len() == block_bytes {
+                write_block(&mut writer, &buf)?;
+                buf.clear();
+            }
+        }
+        if !buf.is_empty() {
+            write_block(&mut writer, &buf)?;
+        }
+        writer.flush()?;
+        drop(writer);
+
+        self.len = len;
+        self.file.seek(SeekFrom::Start(HEADER_LEN_OFFSET))?;
+        self.file.write_all(&(self.len as u64).to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Sync all data to disk
+    pub fn sync_all(&mut self) -> Result<()> {
+        self.file.sync_all()?;
+        Ok(())
+    }
+
+    fn num_blocks(&self) -> usize {
+        self.len.div_ceil(self.block_len)
+    }
+
+    fn block_pos(&self, block_id: usize) -> u64 {
+        let block_size = self.block_len * T::BYTE_SIZE + CHECKSUM_SIZE;
+        let full = (self.len / self.block_len).min(block_id);
+        let mut pos = (CHECKED_HEADER_SIZE + full * block_size) as u64;
+        if block_id > full {
+            // After the partial last block
+            pos += ((self.len % self.block_len) * T::BYTE_SIZE + CHECKSUM_SIZE) as u64;
+        }
+        pos
+    }
+
+    // Read the elements of a block, checking its checksum
+    fn read_block(&self, block_id: usize) -> Result<Vec<u8>> {
+        let start = block_id * self.block_len;
+        let data_size = (self.len - start).min(self.block_len) * T::BYTE_SIZE;
+        let mut buf = vec![0; data_size + CHECKSUM_SIZE];
+        read_exact_at(&self.file, &mut buf, self.block_pos(block_id))?;
+        let checksum = u64::from_le_bytes(buf[data_size..].try_into().unwrap());
+        buf.truncate(data_size);
+        if content_hash(&buf) != checksum {
+            bail!(
+                "Block {} (elements {}..{}) is corrupted: checksum mismatch",
+                block_id,
+                start,
+                start + data_size / T::BYTE_SIZE
+            );
+        }
+        Ok(buf)
+    }
+}
+
+fn write_block<W: Write>(writer: &mut W, data: &[u8]) -> Result<()> {
+    writer.write_all(data)?;
+    writer.write_all(&content_hash(data).to_le_bytes())?;
+    Ok(())
+}
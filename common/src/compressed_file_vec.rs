@@ -0,0 +1,268 @@
+use std::{
+    fs::{create_dir_all, File},
+    io::{BufWriter, Seek, SeekFrom, Write},
+    marker::PhantomData,
+    path::Path,
+};
+
+use anyhow::{ensure, Result};
+
+use crate::flat_file_vec::{
+    header, parse_header, read_exact_at, FixedRepr, HEADER_LEN_OFFSET, HEADER_SIZE,
+};
+
+/// First bytes of a file written by [`CompressedFileVecWriter`]
+pub const COMPRESSED_MAGIC: &[u8; 8] = b"FLATFZST";
+/// Default number of elements in a block
+pub const DEFAULT_BLOCK_LEN: usize = 1 << 16;
+/// Default zstd compression level
+pub const DEFAULT_LEVEL: i32 = 3;
+
+// The header of FlatFileVec with COMPRESSED_MAGIC, followed by the block length (u32),
+// 4 zero bytes and the position of the block index (u64), which is 0 until the writer finishes.
+// The index at the end of the file holds the start of each block and the end of the last one (u64 each)
+const COMPRESSED_HEADER_SIZE: usize = HEADER_SIZE + 16;
+const INDEX_POS_OFFSET: u64 = HEADER_SIZE as u64 + 8;
+
+/// Writes a [`CompressedFileVec`], compressing each block of `block_len` elements with zstd.
+///
+/// Elements can only be appended, and the file can be read after `finish` writes the block index.
+pub struct CompressedFileVecWriter<T: FixedRepr> {
+    writer: BufWriter<File>,
+    len: usize,
+    block_len: usize,
+    level: i32,
+    buf: Vec<u8>,
+    offsets: Vec<u64>,
+    _phantom: PhantomData<T>,
+}
+
+impl<T: FixedRepr> CompressedFileVecWriter<T> {
+    /// Create a new file with blocks of [`DEFAULT_BLOCK_LEN`] elements and [`DEFAULT_LEVEL`]
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::create_with(path, DEFAULT_BLOCK_LEN, DEFAULT_LEVEL)
+    }
+
+    /// Create a new file with blocks of `block_len` elements, compressed at zstd `level`
+    pub fn create_with<P: AsRef<Path>>(path: P, block_len: usize, level: i32) -> Result<Self> {
+        ensure!(
+            (1..=u32::MAX as usize).contains(&block_len),
+            "Invalid block length {}",
+            block_len
+        );
+        if let Some(parent) = path.as_ref().parent() {
+            create_dir_all(parent)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(&header::<T>(COMPRESSED_MAGIC, 0))?;
+        writer.write_all(&(block_len as u32).to_le_bytes())?;
+        writer.write_all(&[0; 12])?;
+        Ok(Self {
+            writer,
+            len: 0,
+            block_len,
+            level,
+            buf: Vec::with_capacity(block_len * T::BYTE_SIZE),
+            offsets: vec![COMPRESSED_HEADER_SIZE as u64],
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Get the number of elements written so far
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if no elements have been written
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Append a single element
+    pub fn push(&mut self, item: &T) -> Result<()> {
+        item.serialize(&mut self.buf)?;
+        self.len += 1;
+        if self.buf.len() == self.block_len * T::BYTE_SIZE {
+            self.flush_block()?;
+        }
+        Ok(())
+    }
+
+    /// Append multiple elements
+    pub fn extend<I>(&mut self, items: I) -> Result<()>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        for item in items {
+            self.push(&item)?;
+        }
+        Ok(())
+    }
+
+    /// Write the last block and the block index, and update the header
+    pub fn finish(mut self) -> Result<()> {
+        if !self.buf.is_empty() {
+            self.flush_block()?;
+        }
+        let index_pos = *self.offsets.last().unwrap();
+        for offset in &self.offsets {
+            self.writer.write_all(&offset.to_le_bytes())?;
+        }
+        let mut file = self.writer.into_inner()?;
+        file.seek(SeekFrom::Start(HEADER_LEN_OFFSET))?;
+        file.write_all(&(self.len as u64).to_le_bytes())?;
+        file.seek(SeekFrom::Start(INDEX_POS_OFFSET))?;
+        file.write_all(&index_pos.to_le_bytes())?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    fn flush_block(&mut self) -> Result<()> {
+        let block = zstd::bulk::compress(&self.buf, self.level)?;
+        self.writer.write_all(&block)?;
+        self.offsets
+            .push(self.offsets.last().unwrap() + block.len() as u64);
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+/// A read-only flat file vector compressed in blocks with zstd, written by [`CompressedFileVecWriter`].
+///
+/// The block index is loaded on open, and `get` and `get_range` decompress only the blocks
+/// they need, so random access costs a block per call. Reading whole files with `load_all`
+/// or in large ranges amortizes it.
+#[derive(Debug)]
+pub struct CompressedFileVec<T: FixedRepr> {
+    file: File,
+    len: usize,
+    block_len: usize,
+    offsets: Vec<u64>,
+    _phantom: PhantomData<T>,
+}
+
+impl<T: FixedRepr> CompressedFileVec<T> {
+    /// Open a compressed file vector
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_file(File::open(path)?)
+    }
+
+    /// Create a compressed file vector from an existing File object
+    pub fn from_file(file: File) -> Result<Self> {
+        let file_size = file.metadata()?.len();
+        ensure!(
+            file_size >= COMPRESSED_HEADER_SIZE as u64,
+            "File is too short for a compressed file vector"
+        );
+        let mut head = [0u8; COMPRESSED_HEADER_SIZE];
+        read_exact_at(&file, &mut head, 0)?;
+        ensure!(
+            head.starts_with(COMPRESSED_MAGIC),
+            "File is not compressed, open it with FlatFileVec"
+        );
+        let len = parse_header::<T>(&head)? as usize;
+        let block_len =
+            u32::from_le_bytes(head[HEADER_SIZE..HEADER_SIZE + 4].try_into().unwrap()) as usize;
+        let index_pos = u64::from_le_bytes(head[HEADER_SIZE + 8..].try_into().unwrap());
+        ensure!(block_len > 0, "Invalid block length 0");
+        ensure!(index_pos > 0, "File was not finished");
+
+        let num_blocks = len.div_ceil(block_len);
+        ensure!(
+            file_size == index_pos + (num_blocks as u64 + 1) * 8,
+            "Block index does not match the file size"
+        );
+        let mut buf = vec![0u8; (num_blocks + 1) * 8];
+        read_exact_at(&file, &mut buf, index_pos)?;
+        let offsets: Vec<u64> = buf
+            .chunks_exact(8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        ensure!(
+            offsets[0] == COMPRESSED_HEADER_SIZE as u64
+                && offsets.windows(2).all(|w| w[0] <= w[1])
+                && offsets[num_blocks] == index_pos,
+            "Block index is corrupted"
+        );
+        Ok(Self {
+            file,
+            len,
+            block_len,
+            offsets,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Load all elements from a file path
+    pub fn load_all<P: AsRef<Path>>(path: P) -> Result<Vec<T>> {
+        let ffv = Self::open(path)?;
+        ffv.get_range(0, ffv.len())
+    }
+
+    /// Get the number of elements in the vector
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if the vector is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get the number of elements in a block
+    pub fn block_len(&self) -> usize {
+        self.block_len
+    }
+
+    /// Get the size of the compressed blocks in bytes
+    pub fn compressed_size(&self) -> u64 {
+        self.offsets[self.offsets.len() - 1] - self.offsets[0]
+    }
+
+    /// Get a single element at the specified index
+    pub fn get(&self, index: usize) -> Result<T> {
+        if index >= self.len {
+            return Err(anyhow::Error::msg("Index out of bounds"));
+        }
+        let block = self.read_block(index / self.block_len)?;
+        T::deserialize(&mut &block[(index % self.block_len) * T::BYTE_SIZE..])
+    }
+
+    /// Get a range of elements [start, end)
+    pub fn get_range(&self, start: usize, end: usize) -> Result<Vec<T>> {
+        if start > end || end > self.len {
+            return Err(anyhow::Error::msg("Invalid range"));
+        }
+        let mut result = Vec::with_capacity(end - start);
+        let mut index = start;
+        while index < end {
+            let block_id = index / self.block_len;
+            let block = self.read_block(block_id)?;
+            let block_end = end.min((block_id + 1) * self.block_len);
+            let mut bytes = &block[(index % self.block_len) * T::BYTE_SIZE..];
+            for _ in index..block_end {
+                result.push(T::deserialize(&mut bytes)?);
+            }
+            index = block_end;
+        }
+        Ok(result)
+    }
+
+    // Read and decompress a block, checking its size
+    fn read_block(&self, block_id: usize) -> Result<Vec<u8>> {
+        let (from, to) = (self.offsets[block_id], self.offsets[block_id + 1]);
+        let mut buf = vec![0u8; (to - from) as usize];
+        read_exact_at(&self.file, &mut buf, from)?;
+        let start = block_id * self.block_len;
+        let size = (self.len - start).min(self.block_len) * T::BYTE_SIZE;
+        let block = zstd::bulk::decompress(&buf, size)?;
+        ensure!(
+            block.len() == size,
+            "Block {} has {} bytes, expected {}",
+            block_id,
+            block.len(),
+            size
+        );
+        Ok(block)
+    }
+}
@@ -1,15 +1,24 @@
 use std::{
-    fs::{create_dir_all, File, OpenOptions},
-    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    cell::RefCell,
+    ffi::OsString,
+    fs::{create_dir_all, rename, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
     marker::PhantomData,
+    mem::{align_of, size_of},
     path::Path,
 };
 
-use anyhow::Result;
+use anyhow::{ensure, Context, Result};
+use memmap2::Mmap;
+use rayon::prelude::*;
+
+use crate::{checked_file_vec::CHECKED_MAGIC, compressed_file_vec::COMPRESSED_MAGIC};
 
 // A trait for types that can be serialized and deserialized from a fixed-size byte array.
 pub trait FixedRepr: Default + Clone {
     const BYTE_SIZE: usize;
+    /// Name of the type written in the header of a file, padded with zeros
+    const TYPE_TAG: [u8; 8];
     fn serialize<W: Write>(&self, writer: &mut W) -> Result<()>;
     fn deserialize<R: Read>(reader: &mut R) -> Result<Self>;
 }
@@ -17,6 +26,7 @@ pub trait FixedRepr: Default + Clone {
 // FixedRepr implementations for integer types
 impl FixedRepr for u16 {
     const BYTE_SIZE: usize = 2;
+    const TYPE_TAG: [u8; 8] = *b"u16\0\0\0\0\0";
 
     fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
         writer.write_all(&self.to_le_bytes())?;
@@ -32,6 +42,7 @@ impl FixedRepr for u16 {
 
 impl FixedRepr for u32 {
     const BYTE_SIZE: usize = 4;
+    const TYPE_TAG: [u8; 8] = *b"u32\0\0\0\0\0";
 
     fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
         writer.write_all(&self.to_le_bytes())?;
@@ -47,6 +58,7 @@ impl FixedRepr for u32 {
 
 impl FixedRepr for u64 {
     const BYTE_SIZE: usize = 8;
+    const TYPE_TAG: [u8; 8] = *b"u64\0\0\0\0\0";
 
     fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
         writer.write_all(&self.to_le_bytes())?;
@@ -62,6 +74,7 @@ impl FixedRepr for u64 {
 
 impl FixedRepr for u128 {
     const BYTE_SIZE: usize = 16;
+    const TYPE_TAG: [u8; 8] = *b"u128\0\0\0\0";
 
     fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
         writer.write_all(&self.to_le_bytes())?;
@@ -75,6 +88,125 @@ impl FixedRepr for u128 {
     }
 }
 
+// Reads of up to this many bytes use a buffer kept by each thread instead of allocating one
+const SMALL_READ_BYTES: usize = 1 << 16;
+
+thread_local! {
+    static READ_BUF: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Number of elements read by each task of [`FlatFileVec::load_all_par`]
+pub const LOAD_CHUNK_LEN: usize = 1 << 20;
+
+/// First bytes of a file with a header
+pub const HEADER_MAGIC: &[u8; 8] = b"FLATFVEC";
+/// Version of the header, bumped when its layout changes
+pub const HEADER_VERSION: u32 = 1;
+/// Size of the header in bytes. Elements start here, aligned for any [`PlainRepr`] type
+pub const HEADER_SIZE: usize = 32;
+
+// Header layout, little-endian:
+// * 8 bytes: HEADER_MAGIC
+// * u32: HEADER_VERSION
+// * u32: element size in bytes
+// * 8 bytes: element type tag
+// * u64: number of elements
+pub(crate) const HEADER_LEN_OFFSET: u64 = 24;
+
+pub(crate) fn header<T: FixedRepr>(magic: &[u8; 8], len: usize) -> [u8; HEADER_SIZE] {
+    let mut header = [0u8; HEADER_SIZE];
+    header[..8].copy_from_slice(magic);
+    header[8..12].copy_from_slice(&HEADER_VERSION.to_le_bytes());
+    header[12..16].copy_from_slice(&(T::BYTE_SIZE as u32).to_le_bytes());
+    header[16..24].copy_from_slice(&T::TYPE_TAG);
+    header[24..].copy_from_slice(&(len as u64).to_le_bytes());
+    header
+}
+
+fn tag_name(tag: &[u8]) -> String {
+    String::from_utf8_lossy(tag).trim_end_matches('\0').to_string()
+}
+
+/// Offset of the first element of a file of `file_size` bytes starting with `head`, which
+/// should hold at least [`HEADER_SIZE`] bytes if the file is that long.
+///
+/// A file with a header must hold elements of type `T` and exactly as many as the header
+/// says. Files without a header, written before headers were added, are accepted if
+/// their size is a multiple of the element size.
+pub fn data_offset<T: FixedRepr>(head: &[u8], file_size: u64) -> Result<u64> {
+    ensure!(
+        !head.starts_with(CHECKED_MAGIC),
+        "File has checksums, open it with CheckedFileVec"
+    );
+    ensure!(
+        !head.starts_with(COMPRESSED_MAGIC),
+        "File is compressed, open it with CompressedFileVec"
+    );
+    if !head.starts_with(HEADER_MAGIC) {
+        ensure!(
+            file_size % T::BYTE_SIZE as u64 == 0,
+            "File size is not a multiple of element size"
+        );
+        return Ok(0);
+    }
+    let len = parse_header::<T>(head)?;
+    let data_size = file_size - HEADER_SIZE as u64;
+    ensure!(
+        data_size == len * T::BYTE_SIZE as u64,
+        "Header says {} elements, but the file holds {} bytes of elements",
+        len,
+        data_size
+    );
+    Ok(HEADER_SIZE as u64)
+}
+
+// Check the version and element type of a header starting with a known magic, returning the length
+pub(crate) fn parse_header<T: FixedRepr>(head: &[u8]) -> Result<u64> {
+    ensure!(head.len() >= HEADER_SIZE, "File header is truncated");
+    let u32_at = |i: usize| u32::from_le_bytes(head[i..i + 4].try_into().unwrap());
+    let version = u32_at(8);
+    ensure!(
+        version == HEADER_VERSION,
+        "Unsupported file version {} (expected {})",
+        version,
+        HEADER_VERSION
+    );
+    let (byte_size, tag) = (u32_at(12) as usize, &head[16..24]);
+    ensure!(
+        byte_size == T::BYTE_SIZE && tag == T::TYPE_TAG,
+        "File holds {} ({} bytes), not {} ({} bytes)",
+        tag_name(tag),
+        byte_size,
+        tag_name(&T::TYPE_TAG),
+        T::BYTE_SIZE
+    );
+    Ok(u64::from_le_bytes(head[24..32].try_into().unwrap()))
+}
+
+/// How [`FlatFileVec::save_all_with`] treats an existing file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveMode {
+    /// Write to `<path>.tmp` and rename it to `path` when done, so that the file at `path` is
+    /// either the old one or complete, even if the writer crashes
+    Overwrite,
+    /// Append to the file at `path`, creating it if needed. A crash while appending leaves
+    /// a file whose header does not match its size, which `open` rejects
+    Append,
+}
+
+/// Types whose [`FixedRepr`] is their little-endian memory representation, so that a file
+/// can be used in place as a slice of them on little-endian targets
+///
+/// # Safety
+/// `BYTE_SIZE` must be `size_of::<Self>()`, a multiple of its alignment, and every bit
+/// pattern must be a valid value.
+pub unsafe trait PlainRepr: FixedRepr {}
+
+unsafe impl PlainRepr for u16 {}
+unsafe impl PlainRepr for u32 {}
+unsafe impl PlainRepr for u64 {}
+unsafe impl PlainRepr for u128 {}
+
 /// A flat file vector that stores elements in a file. It's just like a Vec, but the elements are stored in a file.
 /// 
 /// This structure provides methods to create, open, and manipulate a vector of elements stored in a file.
@@ -82,10 +214,18 @@ impl FixedRepr for u128 {
 /// 
 /// The file is opened in read-write mode by default, but can be opened in read-only mode if needed.
 /// The file is automatically created if it doesn't exist.
+///
+/// Files created by `create` start with a header of the element type and length, checked by
+/// `open`, so that a file is not read as another type. Files without a header are read as before.
+///
+/// `get` and `get_range` read at a position (`pread`) without moving the file offset, so they take
+/// `&self` and a read-only vector can be shared behind an `Arc` and read from many threads at once.
 #[derive(Debug)]
 pub struct FlatFileVec<T: FixedRepr> {
     file: File,
     len: usize,
+    /// Position of the first element, after the header if any
+    offset: u64,
     _phantom: PhantomData<T>,
 }
 
@@ -95,10 +235,12 @@ impl<T: FixedRepr> FlatFileVec<T> {
         if let Some(parent) = path.as_ref().parent() {
             create_dir_all(parent)?;
         }
-        let file = File::create(path)?;
+        let mut file = File::create(path)?;
+        file.write_all(&header::<T>(HEADER_MAGIC, 0))?;
         Ok(Self {
             file,
             len: 0,
+            offset: HEADER_SIZE as u64,
             _phantom: PhantomData,
         })
     }
@@ -106,55 +248,58 @@ impl<T: FixedRepr> FlatFileVec<T> {
     /// Open an existing flat file vector
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
         let file = OpenOptions::new().read(true).write(true).open(path)?;
-        let file_size = file.metadata()?.len() as usize;
-
-        if file_size % T::BYTE_SIZE != 0 {
-            return Err(anyhow::Error::msg(
-                "File size is not a multiple of element size",
-            ));
-        }
-
-        let len = file_size / T::BYTE_SIZE;
-        Ok(Self {
-            file,
-            len,
-            _phantom: PhantomData,
-        })
+        Self::from_file(file)
     }
 
     /// Open an existing flat file vector in read-only mode
     pub fn open_readonly<P: AsRef<Path>>(path: P) -> Result<Self> {
         let file = File::open(path)?;
-        let file_size = file.metadata()?.len() as usize;
+        Self::from_file(file)
+    }
 
-        if file_size % T::BYTE_SIZE != 0 {
-            return Err(anyhow::Error::msg(
-                "File size is not a multiple of element size",
-            ));
-        }
+    /// Open an existing file as a read-only memory map, see [`FlatFileMmap`]
+    pub fn open_mmap<P: AsRef<Path>>(path: P) -> Result<FlatFileMmap<T>> {
+        let file = File::open(path)?;
+        // SAFETY: the file must not be modified while mapped, as documented on FlatFileMmap
+        let mmap = unsafe { Mmap::map(&file)? };
+        let offset = data_offset::<T>(&mmap[..HEADER_SIZE.min(mmap.len())], mmap.len() as u64)?;
 
-        let len = file_size / T::BYTE_SIZE;
-        Ok(Self {
-            file,
+        let len = (mmap.len() - offset as usize) / T::BYTE_SIZE;
+        Ok(FlatFileMmap {
+            mmap,
             len,
+            offset: offset as usize,
             _phantom: PhantomData,
         })
     }
 
     /// Create a flat file vector from an existing File object
     pub fn from_file(file: File) -> Result<Self> {
-        let file_size = file.metadata()?.len() as usize;
+        let file_size = file.metadata()?.len();
+        let mut head = vec![0u8; HEADER_SIZE.min(file_size as usize)];
+        read_exact_at(&file, &mut head, 0)?;
+        let offset = data_offset::<T>(&head, file_size)?;
 
-        if file_size % T::BYTE_SIZE != 0 {
-            return Err(anyhow::Error::msg(
-                "File size is not a multiple of element size",
-            ));
-        }
-
-        let len = file_size / T::BYTE_SIZE;
+        let len = ((file_size - offset) / T::BYTE_SIZE as u64) as usize;
         Ok(Self {
             file,
             len,
+            offset,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Create another handle to the same file with a duplicated file descriptor.
+    ///
+    /// Positioned reads (`get`, `get_range`) of the handles are independent, so each rayon
+    /// worker or thread can own one. The handles share the file position like any duplicated
+    /// descriptor, so only one of them at a time should use `iter`, `set` or `push`.
+    /// The length is copied, so elements appended through one handle are not seen by the others.
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(Self {
+            file: self.file.try_clone()?,
+            len: self.len,
+            offset: self.offset,
             _phantom: PhantomData,
         })
     }
@@ -170,28 +315,109 @@ impl<T: FixedRepr> FlatFileVec<T> {
 
     /// Load all elements from a file path
     pub fn load_all<P: AsRef<Path>>(path: P) -> Result<Vec<T>> {
-        let mut ffv = Self::open_readonly(path)?;
+        let ffv = Self::open_readonly(path)?;
         let result = ffv.get_range(0, ffv.len())?;
         Ok(result)
     }
 
+    /// Load all elements from a file path, reading chunks of [`LOAD_CHUNK_LEN`] elements in
+    /// parallel with rayon. Faster than `load_all` for large files on disks that serve
+    /// several reads at once, such as NVMe drives.
+    pub fn load_all_par<P: AsRef<Path>>(path: P) -> Result<Vec<T>>
+    where
+        T: Send + Sync,
+    {
+        let ffv = Self::open_readonly(path)?;
+        let mut result = vec![T::default(); ffv.len()];
+        result
+            .par_chunks_mut(LOAD_CHUNK_LEN)
+            .enumerate()
+            .try_for_each(|(chunk_id, chunk)| -> Result<()> {
+                let mut buf = vec![0; chunk.len() * T::BYTE_SIZE];
+                read_exact_at(&ffv.file, &mut buf, ffv.byte_pos(chunk_id * LOAD_CHUNK_LEN))?;
+                let mut bytes = &buf[..];
+                for item in chunk.iter_mut() {
+                    *item = T::deserialize(&mut bytes)?;
+                }
+                Ok(())
+            })?;
+        Ok(result)
+    }
+
     /// Load all elements from a file
     pub fn load_all_from_file(file: File) -> Result<Vec<T>> {
-        let mut ffv = Self::from_file(file)?;
+        let ffv = Self::from_file(file)?;
         let result = ffv.get_range(0, ffv.len())?;
         Ok(result)
     }
 
-    /// Save all elements to a file path
+    /// Save all elements to a file path, replacing any existing file, see [`SaveMode::Overwrite`]
     pub fn save_all<P: AsRef<Path>, I>(items: I, path: P) -> Result<()>
     where
         I: IntoIterator<Item = T>,
     {
-        let mut ffv = Self::open_or_create(path)?;
-        ffv.extend(items)?;
+        Self::save_all_with(items, path, SaveMode::Overwrite)
+    }
+
+    /// Save all elements to a file path, replacing or appending to an existing file
+    pub fn save_all_with<P: AsRef<Path>, I>(items: I, path: P, mode: SaveMode) -> Result<()>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        match mode {
+            SaveMode::Overwrite => {
+                let path = path.as_ref();
+                let tempname = temp_path(path);
+                let mut ffv = Self::create(&tempname)?;
+                ffv.extend(items)?;
+                ffv.sync_all()?;
+                drop(ffv);
+                rename(tempname, path)?;
+            }
+            SaveMode::Append => {
+                let mut ffv = Self::open_or_create(path)?;
+                ffv.extend(items)?;
+            }
+        }
         Ok(())
     }
 
+    /// Concatenate the files at `inputs` into a new file at `output` and return the number of
+    /// elements. Inputs are read [`LOAD_CHUNK_LEN`] elements at a time, and like `save_all`,
+    /// the output is written to `<output>.tmp` and renamed when its length is checked.
+    pub fn concat<P: AsRef<Path>, Q: AsRef<Path>>(inputs: &[P], output: Q) -> Result<usize> {
+        let inputs = inputs
+            .iter()
+            .map(|path| {
+                let path = path.as_ref();
+                Self::open_readonly(path).with_context(|| format!("Failed to open {}", path.display()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let total = inputs.iter().map(Self::len).sum();
+
+        let output = output.as_ref();
+        let tempname = temp_path(output);
+        let mut ffv = Self::create(&tempname)?;
+        let mut buf = Vec::new();
+        for input in &inputs {
+            for start in (0..input.len()).step_by(LOAD_CHUNK_LEN) {
+                input.get_range_into(start, input.len().min(start + LOAD_CHUNK_LEN), &mut buf)?;
+                ffv.extend(buf.drain(..))?;
+            }
+        }
+        ffv.sync_all()?;
+        drop(ffv);
+        let written = Self::open_readonly(&tempname)?.len();
+        ensure!(
+            written == total,
+            "Wrote {} elements, but the inputs hold {}",
+            written,
+            total
+        );
+        rename(tempname, output)?;
+        Ok(total)
+    }
+
     /// Save all elements to a file
     pub fn save_all_to_file<I>(items: I, file: File) -> Result<()>
     where
@@ -204,8 +430,8 @@ impl<T: FixedRepr> FlatFileVec<T> {
 
     pub fn set_len(&mut self, len: usize) -> Result<()> {
         self.len = len;
-        self.file.set_len(len as u64 * T::BYTE_SIZE as u64)?;
-        Ok(())
+        self.file.set_len(self.offset + len as u64 * T::BYTE_SIZE as u64)?;
+        self.write_header_len()
     }
 
     /// Get the number of elements in the vector
@@ -219,35 +445,46 @@ impl<T: FixedRepr> FlatFileVec<T> {
     }
 
     /// Get a single element at the specified index
-    pub fn get(&mut self, index: usize) -> Result<T> {
+    pub fn get(&self, index: usize) -> Result<T> {
         if index >= self.len {
             return Err(anyhow::Error::msg("Index out of bounds"));
         }
 
-        self.file
-            .seek(SeekFrom::Start((index * T::BYTE_SIZE) as u64))?;
-        T::deserialize(&mut self.file)
+        let mut buf = vec![0; T::BYTE_SIZE];
+        read_exact_at(&self.file, &mut buf, self.byte_pos(index))?;
+        T::deserialize(&mut &buf[..])
     }
 
     /// Get a range of elements [start, end)
-    pub fn get_range(&mut self, start: usize, end: usize) -> Result<Vec<T>> {
+    pub fn get_range(&self, start: usize, end: usize) -> Result<Vec<T>> {
+        let mut result = Vec::new();
+        self.get_range_into(start, end, &mut result)?;
+        Ok(result)
+    }
+
+    /// Like `get_range`, replacing the contents of `buf` so that its allocation can be reused
+    pub fn get_range_into(&self, start: usize, end: usize, buf: &mut Vec<T>) -> Result<()> {
         if start > end || end > self.len {
             return Err(anyhow::Error::msg("Invalid range"));
         }
 
-        let count = end - start;
-        let mut result = Vec::with_capacity(count);
-
-        self.file
-            .seek(SeekFrom::Start((start * T::BYTE_SIZE) as u64))?;
-        let mut reader = BufReader::new(&mut self.file);
-
-        for _ in 0..count {
-            let element = T::deserialize(&mut reader)?;
-            result.push(element);
+        buf.clear();
+        buf.reserve(end - start);
+        let size = (end - start) * T::BYTE_SIZE;
+        let mut read = |bytes: &mut Vec<u8>| -> Result<()> {
+            bytes.resize(size, 0);
+            read_exact_at(&self.file, bytes, self.byte_pos(start))?;
+            let mut bytes = &bytes[..];
+            for _ in start..end {
+                buf.push(T::deserialize(&mut bytes)?);
+            }
+            Ok(())
+        };
+        if size <= SMALL_READ_BYTES {
+            READ_BUF.with(|bytes| read(&mut bytes.borrow_mut()))
+        } else {
+            read(&mut Vec::new())
         }
-
-        Ok(result)
     }
 
     /// Append a single element to the end of the vector
@@ -256,8 +493,9 @@ impl<T: FixedRepr> FlatFileVec<T> {
         let mut writer = BufWriter::new(&mut self.file);
         item.serialize(&mut writer)?;
         writer.flush()?;
+        drop(writer);
         self.len += 1;
-        Ok(())
+        self.write_header_len()
     }
 
     /// Append multiple elements to the end of the vector
@@ -276,17 +514,18 @@ impl<T: FixedRepr> FlatFileVec<T> {
         }
         
         writer.flush()?;
+        drop(writer);
         self.len += count;
-        Ok(())
+        self.write_header_len()
     }
 
     /// Clear all elements from the vector
     pub fn clear(&mut self) -> Result<()> {
-        // Truncate file to 0 bytes
-        self.file.set_len(0)?;
-        self.file.seek(SeekFrom::Start(0))?;
+        // Truncate file to the header
+        self.file.set_len(self.offset)?;
+        self.file.seek(SeekFrom::Start(self.offset))?;
         self.len = 0;
-        Ok(())
+        self.write_header_len()
     }
 
     /// Get the current file position (useful for debugging)
@@ -307,7 +546,7 @@ impl<T: FixedRepr> FlatFileVec<T> {
         }
 
         self.file
-            .seek(SeekFrom::Start((index * T::BYTE_SIZE) as u64))?;
+            .seek(SeekFrom::Start(self.byte_pos(index)))?;
         let mut writer = BufWriter::new(&mut self.file);
         value.serialize(&mut writer)?;
         writer.flush()?;
@@ -325,7 +564,7 @@ impl<T: FixedRepr> FlatFileVec<T> {
         }
 
         self.file
-            .seek(SeekFrom::Start((start * T::BYTE_SIZE) as u64))?;
+            .seek(SeekFrom::Start(self.byte_pos(start)))?;
         let mut writer = BufWriter::new(&mut self.file);
 
         for value in values {
@@ -337,7 +576,7 @@ impl<T: FixedRepr> FlatFileVec<T> {
 
     /// Create an iterator over all elements in the vector
     pub fn iter(&mut self) -> Result<FlatFileVecIterator<T>> {
-        self.file.seek(SeekFrom::Start(0))?;
+        self.file.seek(SeekFrom::Start(self.offset))?;
         Ok(FlatFileVecIterator::new(self))
     }
 
@@ -346,9 +585,122 @@ impl<T: FixedRepr> FlatFileVec<T> {
         if start > end || end > self.len {
             return Err(anyhow::Error::msg("Invalid range"));
         }
-        self.file.seek(SeekFrom::Start((start * T::BYTE_SIZE) as u64))?;
+        self.file.seek(SeekFrom::Start(self.byte_pos(start)))?;
         Ok(FlatFileVecIterator::new_with_range(self, start, end))
     }
+
+    fn byte_pos(&self, index: usize) -> u64 {
+        self.offset + (index * T::BYTE_SIZE) as u64
+    }
+
+    // Keep the length in the header in sync with the file, if it has a header
+    fn write_header_len(&mut self) -> Result<()> {
+        if self.offset > 0 {
+            self.file.seek(SeekFrom::Start(HEADER_LEN_OFFSET))?;
+            self.file.write_all(&(self.len as u64).to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+fn temp_path(path: &Path) -> OsString {
+    let mut tempname = path.as_os_str().to_owned();
+    tempname.push(".tmp");
+    tempname
+}
+
+#[cfg(unix)]
+pub(crate) fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+// Windows' `seek_read` also moves the file offset, which the other methods seek before using
+#[cfg(windows)]
+pub(crate) fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                let rest = buf;
+                buf = &mut rest[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// A read-only flat file vector backed by a memory map, created by [`FlatFileVec::open_mmap`].
+///
+/// `get` and `get_range` work like those of [`FlatFileVec`], copying from the page cache instead
+/// of reading the file. For [`PlainRepr`] types, `as_slice` uses the file in place.
+/// The file must not be modified or truncated while it is mapped.
+#[derive(Debug)]
+pub struct FlatFileMmap<T: FixedRepr> {
+    mmap: Mmap,
+    len: usize,
+    offset: usize,
+    _phantom: PhantomData<T>,
+}
+
+impl<T: FixedRepr> FlatFileMmap<T> {
+    /// Get the number of elements in the vector
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if the vector is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get a single element at the specified index
+    pub fn get(&self, index: usize) -> Result<T> {
+        if index >= self.len {
+            return Err(anyhow::Error::msg("Index out of bounds"));
+        }
+        T::deserialize(&mut &self.mmap[self.offset + index * T::BYTE_SIZE..])
+    }
+
+    /// Get a range of elements [start, end)
+    pub fn get_range(&self, start: usize, end: usize) -> Result<Vec<T>> {
+        let mut result = Vec::new();
+        self.get_range_into(start, end, &mut result)?;
+        Ok(result)
+    }
+
+    /// Like `get_range`, replacing the contents of `buf` so that its allocation can be reused
+    pub fn get_range_into(&self, start: usize, end: usize, buf: &mut Vec<T>) -> Result<()> {
+        if start > end || end > self.len {
+            return Err(anyhow::Error::msg("Invalid range"));
+        }
+        let bytes = &self.mmap[self.offset..];
+        let mut bytes = &bytes[start * T::BYTE_SIZE..end * T::BYTE_SIZE];
+        buf.clear();
+        buf.reserve(end - start);
+        for _ in start..end {
+            buf.push(T::deserialize(&mut bytes)?);
+        }
+        Ok(())
+    }
+}
+
+impl<T: PlainRepr> FlatFileMmap<T> {
+    /// All elements, read in place from the memory map
+    #[cfg(target_endian = "little")]
+    pub fn as_slice(&self) -> &[T] {
+        if self.len == 0 {
+            return &[];
+        }
+        assert_eq!(T::BYTE_SIZE, size_of::<T>());
+        let data = self.mmap[self.offset..].as_ptr();
+        assert_eq!(data as usize % align_of::<T>(), 0);
+        // SAFETY: the data is aligned, holds `len` elements and `T` is plain data in little-endian
+        unsafe { std::slice::from_raw_parts(data as *const T, self.len) }
+    }
 }
 
 /// Iterator for FlatFileVec that uses BufReader for efficient reading
@@ -439,8 +791,8 @@ pub struct FlatFileVecIntoIterator<T: FixedRepr> {
 
 impl<T: FixedRepr> FlatFileVecIntoIterator<T> {
     fn new(mut ffv: FlatFileVec<T>) -> Self {
-        // Seek to the beginning of the file
-        let _ = ffv.file.seek(SeekFrom::Start(0));
+        // Seek to the first element
+        let _ = ffv.file.seek(SeekFrom::Start(ffv.offset));
         let reader = BufReader::new(ffv.file);
         
         Self {
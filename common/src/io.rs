@@ -1,10 +1,10 @@
 use std::{
     fs::{create_dir_all, rename, File},
-    io::{BufReader, BufWriter},
+    io::{BufReader, BufWriter, Read, Write},
     path::Path,
 };
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 
 use serde;
 
@@ -20,3 +20,105 @@ pub fn load_object<T: serde::de::DeserializeOwned, U: AsRef<Path>>(filename: U)
     bincode::deserialize_from(BufReader::new(File::open(filename)?))
         .map_err(|e| anyhow::Error::new(e))
 }
+
+/// Like [`save_object`], with a header of `magic`, `version` (u32) and the
+/// [`content_hash`] (u64) of the serialized content, both little-endian.
+pub fn save_object_with_header<T: serde::Serialize, U: AsRef<Path>>(
+    filename: U,
+    magic: &[u8; 8],
+    version: u32,
+    content: &T,
+) -> Result<()> {
+    let bytes = bincode::serialize(content)?;
+    let tempname = filename.as_ref().to_str().unwrap().to_string() + ".temp";
+    create_dir_all(Path::new(&tempname).parent().unwrap())?;
+    let mut writer = BufWriter::new(File::create(&tempname)?);
+    writer.write_all(magic)?;
+    writer.write_all(&version.to_le_bytes())?;
+    writer.write_all(&content_hash(&bytes).to_le_bytes())?;
+    writer.write_all(&bytes)?;
+    writer.flush()?;
+    drop(writer);
+    rename(tempname, filename)?;
+    Ok(())
+}
+
+/// Load an object saved by [`save_object_with_header`], checking its version and hash.
+///
+/// Returns `None` if the file does not start with `magic`, e.g. when it was saved by [`save_object`].
+pub fn load_object_with_header<T: serde::de::DeserializeOwned, U: AsRef<Path>>(
+    filename: U,
+    magic: &[u8; 8],
+    version: u32,
+) -> Result<Option<T>> {
+    let mut reader = BufReader::new(File::open(filename)?);
+    let mut head = Vec::with_capacity(magic.len());
+    (&mut reader)
+        .take(magic.len() as u64)
+        .read_to_end(&mut head)?;
+    if head != magic {
+        return Ok(None);
+    }
+
+    let mut buf = [0u8; 12];
+    reader.read_exact(&mut buf)?;
+    let file_version = u32::from_le_bytes(buf[..4].try_into().unwrap());
+    let hash = u64::from_le_bytes(buf[4..].try_into().unwrap());
+    if file_version != version {
+        bail!(
+            "Unsupported file version {} (expected {})",
+            file_version,
+            version
+        );
+    }
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+    if content_hash(&bytes) != hash {
+        bail!("File is corrupted: content hash mismatch");
+    }
+    Ok(Some(bincode::deserialize(&bytes)?))
+}
+
+/// The content hash in the header of a file saved by [`save_object_with_header`] with `magic`,
+/// without reading the content. Returns `None` if the file does not start with `magic`.
+pub fn read_header_hash<U: AsRef<Path>>(filename: U, magic: &[u8; 8]) -> Result<Option<u64>> {
+    let mut head = Vec::with_capacity(magic.len() + 12);
+    File::open(filename)?
+        .take(magic.len() as u64 + 12)
+        .read_to_end(&mut head)?;
+    if !head.starts_with(magic) {
+        return Ok(None);
+    }
+    if head.len() < magic.len() + 12 {
+        bail!("File header is truncated");
+    }
+    let hash = &head[magic.len() + 4..];
+    Ok(Some(u64::from_le_bytes(hash.try_into().unwrap())))
+}
+
+/// 64-bit FNV-1a hash, stable across builds and platforms
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    fnv1a(FNV_OFFSET_BASIS, bytes)
+}
+
+/// [`content_hash`] of the whole content of a file, read in chunks
+pub fn file_content_hash<U: AsRef<Path>>(filename: U) -> Result<u64> {
+    let mut reader = BufReader::new(File::open(filename)?);
+    let mut buf = vec![0u8; 1 << 20];
+    let mut hash = FNV_OFFSET_BASIS;
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            return Ok(hash);
+        }
+        hash = fnv1a(hash, &buf[..n]);
+    }
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |h, &b| {
+        (h ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
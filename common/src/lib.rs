@@ -1,3 +1,6 @@
 pub mod io;
 pub mod mahjong;
-pub mod flat_file_vec;
\ No newline at end of file
+pub mod flat_file_vec;
+pub mod cached_file_vec;
+pub mod checked_file_vec;
+pub mod compressed_file_vec;
\ No newline at end of file
@@ -0,0 +1,281 @@
+use itertools::Itertools;
+
+use crate::mahjong::{Dimension, Hand, Tile};
+
+// Honors only keep the number of kinds per count in `Hand`, so honor sets in a
+// decomposition are written with the count instead of the tile, the same way
+// the canonical metrics do: `Kotsu(Jihai(3))` is a honor triplet and
+// `Toitsu(Jihai(2))` is a honor pair.
+const JIHAI_KOTSU: Dimension = Dimension::Kotsu(Tile::Jihai(3));
+const JIHAI_TOITSU: Dimension = Dimension::Toitsu(Tile::Jihai(2));
+
+/// The three kinds of winning shape
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AgariFamily {
+    /// Four sets and a pair
+    Standard,
+    Chiitoitsu,
+    Kokushi,
+}
+
+impl AgariFamily {
+    pub const ALL: [AgariFamily; 3] = [
+        AgariFamily::Standard,
+        AgariFamily::Chiitoitsu,
+        AgariFamily::Kokushi,
+    ];
+
+    /// The family of a decomposition from [`agari_decompositions`] or [`for_each_agari`]
+    pub fn of(sets: &[Dimension]) -> AgariFamily {
+        match sets {
+            [Dimension::Kokushi] => AgariFamily::Kokushi,
+            _ if sets.len() == 7 => AgariFamily::Chiitoitsu,
+            _ => AgariFamily::Standard,
+        }
+    }
+
+    /// Lowercase name used in file names and APIs
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AgariFamily::Standard => "standard",
+            AgariFamily::Chiitoitsu => "chiitoitsu",
+            AgariFamily::Kokushi => "kokushi",
+        }
+    }
+}
+
+/// Whether the hand is a winning shape: sets and a pair, seven pairs, or
+/// thirteen orphans.
+///
+/// The hand may have fewer than 14 tiles when the rest are called sets, e.g.
+/// 11 tiles for a hand with one meld.
+pub fn is_agari(hand: &Hand) -> bool {
+    !agari_decompositions(hand).is_empty()
+}
+
+/// All ways to read the hand as a winning shape.
+///
+/// Each decomposition lists the sets with the pair last, seven `Toitsu` for
+/// seven pairs, or only `Kokushi`. A hand such as `111222333m` has several
+/// decompositions. Returns an empty list if the hand is not a winning shape.
+pub fn agari_decompositions(hand: &Hand) -> Vec<Vec<Dimension>> {
+    let num_tiles = hand.num_tiles();
+    let mut res = Vec::new();
+    if num_tiles % 3 != 2 {
+        return res;
+    }
+
+    // Sets and a pair
+    if hand.jihai[1] == 0 && hand.jihai[4] == 0 && hand.jihai[2] <= 1 {
+        let mut sets = vec![JIHAI_KOTSU; hand.jihai[3] as usize];
+        let mut supai = hand.supai;
+        let pair = (hand.jihai[2] == 1).then_some(JIHAI_TOITSU);
+        search_sets(&mut supai, 0, pair, &mut sets, &mut res);
+    }
+
+    if num_tiles == 14 {
+        // Seven pairs; four of a kind is not two pairs
+        let supai_pairs = hand.supai.iter().flatten().all(|&c| c == 0 || c == 2);
+        if supai_pairs && hand.jihai[1] == 0 && hand.jihai[3] == 0 && hand.jihai[4] == 0 {
+            let mut pairs = Vec::with_capacity(7);
+            for (suit, counts) in hand.supai.iter().enumerate() {
+                for (num, &c) in counts.iter().enumerate() {
+                    if c == 2 {
+                        pairs.push(Dimension::Toitsu(Tile::Supai(suit as u8, num as u8)));
+                    }
+                }
+            }
+            pairs.extend(std::iter::repeat_n(JIHAI_TOITSU, hand.jihai[2] as usize));
+            res.push(pairs);
+        }
+
+        // Thirteen orphans: one of each terminal and honor, and one of them paired
+        let terminals = hand
+            .supai
+            .iter()
+            .all(|counts| counts[1..8].iter().all(|&c| c == 0) && counts[0] > 0 && counts[8] > 0);
+        if terminals && hand.jihai[0] == 0 && hand.jihai[3] == 0 && hand.jihai[4] == 0 {
+            res.push(vec![Dimension::Kokushi]);
+        }
+    }
+    res
+}
+
+/// Like [`agari_decompositions`], but with the actual honor tiles in place of
+/// the honor sets written by count.
+pub fn decompose_agari(tiles: &[Tile]) -> Vec<Vec<Dimension>> {
+    let (hand, jihai_cnt) = Hand::from_tiles_with_jihai_cnt(tiles);
+    let honors = |cnt: usize| {
+        (0..7u8)
+            .filter(move |&ji| jihai_cnt[ji as usize] == cnt)
+            .map(Tile::Jihai)
+    };
+    agari_decompositions(&hand)
+        .into_iter()
+        .map(|mut decomposition| {
+            let mut kotsu = honors(3);
+            let mut toitsu = honors(2);
+            for dim in decomposition.iter_mut() {
+                match *dim {
+                    JIHAI_KOTSU => *dim = Dimension::Kotsu(kotsu.next().unwrap()),
+                    JIHAI_TOITSU => *dim = Dimension::Toitsu(toitsu.next().unwrap()),
+                    _ => {}
+                }
+            }
+            decomposition
+        })
+        .collect()
+}
+
+/// Read the numbered tiles from `supai[suit][num]` on as sets.
+///
+/// All copies of the lowest remaining tile are used up at once, as at most one
+/// triplet, at most one pair and sequences starting there, so that each
+/// decomposition is found once.
+fn search_sets(
+    supai: &mut [[u8; 9]; 3],
+    mut pos: usize,
+    pair: Option<Dimension>,
+    sets: &mut Vec<Dimension>,
+    res: &mut Vec<Vec<Dimension>>,
+) {
+    while pos < 27 && supai[pos / 9][pos % 9] == 0 {
+        pos += 1;
+    }
+    if pos == 27 {
+        if let Some(pair) = pair {
+            let mut decomposition = sets.clone();
+            decomposition.push(pair);
+            res.push(decomposition);
+        }
+        return;
+    }
+    let (suit, num) = (pos / 9, pos % 9);
+    let tile = Tile::Supai(suit as u8, num as u8);
+    let cnt = supai[suit][num];
+
+    for kotsu in 0..=(cnt / 3) {
+        for toitsu in 0..=u8::from(pair.is_none()) {
+            let Some(shuntsu) = cnt.checked_sub(3 * kotsu + 2 * toitsu) else {
+                continue;
+            };
+            let counts = &mut supai[suit];
+            if shuntsu > 0 && (num >= 7 || counts[num + 1] < shuntsu || counts[num + 2] < shuntsu) {
+                continue;
+            }
+            counts[num] = 0;
+            if shuntsu > 0 {
+                counts[num + 1] -= shuntsu;
+                counts[num + 2] -= shuntsu;
+            }
+            let len = sets.len();
+            sets.extend(std::iter::repeat_n(Dimension::Kotsu(tile), kotsu as usize));
+            sets.extend(std::iter::repeat_n(
+                Dimension::Shuntsu(tile),
+                shuntsu as usize,
+            ));
+            let pair = if toitsu == 1 {
+                Some(Dimension::Toitsu(tile))
+            } else {
+                pair
+            };
+            search_sets(supai, pos + 1, pair, sets, res);
+            sets.truncate(len);
+            let counts = &mut supai[suit];
+            counts[num] = cnt;
+            if shuntsu > 0 {
+                counts[num + 1] += shuntsu;
+                counts[num + 2] += shuntsu;
+            }
+        }
+    }
+}
+
+/// Enumerate every 14-tile winning shape together with its decomposition.
+///
+/// The same hand is visited once per decomposition. Honor sets are written by
+/// count as in [`agari_decompositions`]; hands that only differ in which
+/// honors they hold are visited once.
+pub fn for_each_agari<F: FnMut(&Hand, &[Dimension])>(mut op: F) {
+    // Sets and a pair. Locations 0..21 are sequences, 21..48 numbered
+    // triplets and 48 a honor triplet.
+    for mentsu_locations in (0usize..(21 + 28)).combinations_with_replacement(4) {
+        let mut hand = Hand::new();
+        let mut sets = [Dimension::Kokushi; 5];
+        for (set, i) in sets.iter_mut().zip(mentsu_locations) {
+            *set = if i < 21 + 27 {
+                Dimension::from_id(i)
+            } else {
+                JIHAI_KOTSU
+            };
+            match *set {
+                Dimension::Shuntsu(Tile::Supai(suit, num)) => {
+                    let (suit, num) = (suit as usize, num as usize);
+                    hand.supai[suit][num] += 1;
+                    hand.supai[suit][num + 1] += 1;
+                    hand.supai[suit][num + 2] += 1;
+                }
+                Dimension::Kotsu(Tile::Supai(suit, num)) => {
+                    hand.supai[suit as usize][num as usize] += 3;
+                }
+                _ => {
+                    hand.jihai[3] += 1;
+                    hand.jihai[0] -= 1;
+                }
+            }
+        }
+        if !hand.supai.iter().all(|l| l.iter().all(|v| *v <= 4)) {
+            continue;
+        }
+        for suit in 0..3 {
+            for num in 0..9 {
+                hand.supai[suit][num] += 2;
+                if hand.supai[suit][num] <= 4 {
+                    sets[4] = Dimension::Toitsu(Tile::Supai(suit as u8, num as u8));
+                    op(&hand, &sets);
+                }
+                hand.supai[suit][num] -= 2;
+            }
+        }
+        hand.jihai[0] -= 1;
+        hand.jihai[2] += 1;
+        sets[4] = JIHAI_TOITSU;
+        op(&hand, &sets);
+    }
+
+    // Seven pairs. Honors are anonymous, so only the first k honors are taken.
+    for p in (0..34).combinations(7) {
+        let jihai = p.iter().filter(|&&v| v >= 27).count();
+        if p[7 - jihai..].iter().zip(27..).any(|(&v, j)| v != j) {
+            continue;
+        }
+        let mut hand = Hand::new();
+        let mut pairs = [JIHAI_TOITSU; 7];
+        for (pair, v) in pairs.iter_mut().zip(p) {
+            if v < 27 {
+                hand.supai[v / 9][v % 9] += 2;
+                *pair = Dimension::Toitsu(Tile::Supai((v / 9) as u8, (v % 9) as u8));
+            } else {
+                hand.jihai[0] -= 1;
+                hand.jihai[2] += 1;
+            }
+        }
+        op(&hand, &pairs);
+    }
+
+    // Thirteen orphans, paired on a numbered tile or on a honor
+    let mut kokushi = Hand {
+        supai: [
+            [1, 0, 0, 0, 0, 0, 0, 0, 1],
+            [1, 0, 0, 0, 0, 0, 0, 0, 1],
+            [1, 0, 0, 0, 0, 0, 0, 0, 1],
+        ],
+        jihai: [0, 7, 0, 0, 0],
+    };
+    kokushi.supai[0][0] += 1;
+    op(&kokushi, &[Dimension::Kokushi]);
+    kokushi.supai[0][0] -= 1;
+    kokushi.jihai[1] -= 1;
+    kokushi.jihai[2] += 1;
+    op(&kokushi, &[Dimension::Kokushi]);
+}
@@ -0,0 +1,66 @@
+use anyhow::Result;
+
+use crate::mahjong::{parse_hand_str, Dimension, Tile, TileMultiset};
+
+/// At most 5 indicators are revealed: the first one plus one per kan
+pub const MAX_DORA_INDICATORS: usize = 5;
+
+/// The dora indicated by `indicator`: the next tile of the same suit.
+///
+/// Numbers wrap from 9 to 1, winds go east, south, west, north and back to
+/// east, and dragons go white, green, red and back to white.
+pub fn dora_from_indicator(indicator: Tile) -> Tile {
+    match indicator {
+        Tile::Supai(suit, num) => Tile::Supai(suit, (num + 1) % 9),
+        Tile::Jihai(num) if num < 4 => Tile::Jihai((num + 1) % 4),
+        Tile::Jihai(num) => Tile::Jihai(4 + (num - 3) % 3),
+    }
+}
+
+/// Parse dora indicators written as a hand string, e.g. `3m7z`.
+///
+/// Red fives count as normal fives. Fails on more than
+/// [`MAX_DORA_INDICATORS`] indicators or more than 4 copies of a tile.
+pub fn parse_dora_indicators(s: &str) -> Result<Vec<Tile>> {
+    let mut indicators = parse_hand_str(s)?;
+    if indicators.len() > MAX_DORA_INDICATORS {
+        return Err(anyhow::anyhow!(
+            "At most {} dora indicators, got {}",
+            MAX_DORA_INDICATORS,
+            indicators.len()
+        ));
+    }
+    TileMultiset::from_tiles(&indicators)?;
+    // parse_hand_str reads the string backwards
+    indicators.reverse();
+    Ok(indicators)
+}
+
+/// Han from dora for one copy of `tile`: the number of indicators pointing at it
+pub fn dora_count(tile: Tile, indicators: &[Tile]) -> usize {
+    indicators
+        .iter()
+        .filter(|&&indicator| dora_from_indicator(indicator) == tile)
+        .count()
+}
+
+/// Han from dora for the tiles of a set. A kokushi hand holds each terminal
+/// and honor once plus one of them twice; the pair is counted as 1/13 of a
+/// copy of each.
+pub fn dimension_dora_count(dim: Dimension, indicators: &[Tile]) -> f64 {
+    match dim {
+        Dimension::Shuntsu(Tile::Supai(suit, num)) => (num..num + 3)
+            .map(|n| dora_count(Tile::Supai(suit, n), indicators))
+            .sum::<usize>() as f64,
+        Dimension::Shuntsu(_) => 0.0,
+        Dimension::Kotsu(tile) => 3.0 * dora_count(tile, indicators) as f64,
+        Dimension::Toitsu(tile) => 2.0 * dora_count(tile, indicators) as f64,
+        Dimension::Kokushi => {
+            let yaochuu = indicators
+                .iter()
+                .filter(|&&indicator| dora_from_indicator(indicator).is_yaochuu())
+                .count();
+            yaochuu as f64 * 14.0 / 13.0
+        }
+    }
+}
@@ -1,14 +1,25 @@
-use std::path::Path;
+use std::{fmt, fs::File, io::Read, ops::Range, path::Path};
 
 use itertools::{Itertools, MultiProduct};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use crate::{io, mahjong::Tile};
+use crate::{
+    io,
+    mahjong::{
+        raw_file::{has_magic, Column, RawReader, RawWriter},
+        Tile, TileMultiset,
+    },
+};
 
-use anyhow::Result;
+use anyhow::{ensure, Result};
 
 pub const NUM_HAND13: usize = 322565293;
 pub const NUM_HAND14: usize = 923597122;
+pub const NUM_SANMA_HAND13: usize = 61039965;
+pub const NUM_SANMA_HAND14: usize = 144398937;
+pub const NUM_MINI_HAND13: usize = 212558;
+pub const NUM_MINI_HAND14: usize = 309501;
 
 fn product_repeat<I>(it: I, repeat: usize) -> MultiProduct<I>
 where
@@ -27,6 +38,19 @@ fn to_octal<T: Iterator<Item = u32>>(iterator: T) -> u32 {
     res
 }
 
+/// Bits of the counts of 2 to 8 in a supai encoding
+const MIDDLE_SUPAI_MASK: u32 = 0o077777770;
+
+/// Reverse the order of the 9 counts of a supai encoding
+fn reverse_octal(mut v: u32) -> u32 {
+    let mut res = 0;
+    for _ in 0..9 {
+        res = (res << 3) | (v & 7);
+        v >>= 3;
+    }
+    res
+}
+
 fn from_octal(mut v: u32, dst: &mut [u8]) {
     for t in dst.iter_mut().rev() {
         *t = (v & 7) as u8;
@@ -34,6 +58,21 @@ fn from_octal(mut v: u32, dst: &mut [u8]) {
     }
 }
 
+/// Check that two tables are equal, naming the first entry that differs
+fn check_same_table<T: PartialEq + fmt::Debug>(name: &str, a: &[T], b: &[T]) -> Result<()> {
+    if let Some(i) = a.iter().zip(b).position(|(x, y)| x != y) {
+        anyhow::bail!("{}[{}] differs: {:?} != {:?}", name, i, a[i], b[i]);
+    }
+    ensure!(
+        a.len() == b.len(),
+        "{} has {} entries, not {}",
+        name,
+        a.len(),
+        b.len()
+    );
+    Ok(())
+}
+
 // 手牌を表す構造体
 // supai[0][k] は萬子のk番目の牌の枚数
 // supai[1][k] は筒子のk番目の牌の枚数
@@ -73,6 +112,66 @@ impl Hand {
         (hand, jihai_cnt)
     }
 
+    /// Add one copy of `tile`, keeping `jihai` a valid count distribution.
+    ///
+    /// Honors are identified as in [`Hand::tiles`]: `Jihai(i)` is the i-th
+    /// honor in descending order of count, and an index past the held honors
+    /// is a honor not in the hand. Fails without changing the hand if the tile
+    /// is out of range or already held 4 times.
+    pub fn add_tile(&mut self, tile: Tile) -> Result<()> {
+        let cnt = self.tile_count(tile)?;
+        if cnt >= 4 {
+            return Err(anyhow::anyhow!("Too many copies of tile: {:?}", tile));
+        }
+        match tile {
+            Tile::Supai(suit, num) => self.supai[suit as usize][num as usize] += 1,
+            Tile::Jihai(_) => {
+                self.jihai[cnt] -= 1;
+                self.jihai[cnt + 1] += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove one copy of `tile`, with honors identified as in [`Hand::add_tile`].
+    /// Fails without changing the hand if the tile is not held.
+    pub fn remove_tile(&mut self, tile: Tile) -> Result<()> {
+        let cnt = self.tile_count(tile)?;
+        if cnt == 0 {
+            return Err(anyhow::anyhow!("No copy of tile to remove: {:?}", tile));
+        }
+        match tile {
+            Tile::Supai(suit, num) => self.supai[suit as usize][num as usize] -= 1,
+            Tile::Jihai(_) => {
+                self.jihai[cnt] -= 1;
+                self.jihai[cnt - 1] += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of copies of `tile` held, with honors identified as in [`Hand::add_tile`]
+    fn tile_count(&self, tile: Tile) -> Result<usize> {
+        match tile {
+            Tile::Supai(suit, num) if suit < 3 && num < 9 => {
+                Ok(self.supai[suit as usize][num as usize] as usize)
+            }
+            Tile::Jihai(ji) if ji < 7 => {
+                // Skip the kinds held more often than the ji-th honor
+                let mut rest = ji as usize;
+                for cnt in (1..5).rev() {
+                    let kinds = self.jihai[cnt] as usize;
+                    if rest < kinds {
+                        return Ok(cnt);
+                    }
+                    rest -= kinds;
+                }
+                Ok(0)
+            }
+            _ => Err(anyhow::anyhow!("Invalid tile: {:?}", tile)),
+        }
+    }
+
     pub fn for_each_discard_hand<F: FnMut(&Hand, u8)>(&mut self, mut op: F) {
         for suit in 0..3 {
             for num in 0..9 {
@@ -131,6 +230,94 @@ impl Hand {
                 .map(|(i, &v)| v as usize * i)
                 .sum::<usize>()
     }
+
+    /// All tiles of the hand with counts expanded, sorted in m/p/s/z order.
+    ///
+    /// Jihai only keep the number of kinds per count, so they are numbered
+    /// from `1z` in descending order of count. Use [`Hand::tiles_with_jihai_cnt`]
+    /// to get the actual honors back.
+    pub fn tiles(&self) -> impl Iterator<Item = Tile> + '_ {
+        let jihai = (1..5)
+            .rev()
+            .flat_map(|cnt| std::iter::repeat_n(cnt, self.jihai[cnt] as usize))
+            .zip(0u8..)
+            .flat_map(|(cnt, ji)| std::iter::repeat_n(Tile::Jihai(ji), cnt));
+        self.supai_tiles().chain(jihai)
+    }
+
+    /// Like [`Hand::tiles`], but with the honors taken from the per-tile counts
+    /// returned by [`Hand::from_tiles_with_jihai_cnt`] instead of `jihai`.
+    pub fn tiles_with_jihai_cnt<'a>(
+        &'a self,
+        jihai_cnt: &'a [usize; 7],
+    ) -> impl Iterator<Item = Tile> + 'a {
+        let jihai = jihai_cnt
+            .iter()
+            .zip(0u8..)
+            .flat_map(|(&cnt, ji)| std::iter::repeat_n(Tile::Jihai(ji), cnt));
+        self.supai_tiles().chain(jihai)
+    }
+
+    fn supai_tiles(&self) -> impl Iterator<Item = Tile> + '_ {
+        self.supai.iter().zip(0u8..).flat_map(|(counts, suit)| {
+            counts.iter().zip(0u8..).flat_map(move |(&cnt, num)| {
+                std::iter::repeat_n(Tile::Supai(suit, num), cnt as usize)
+            })
+        })
+    }
+
+    /// Format the hand in sorted mpsz notation, e.g. `123m456p789s11z`.
+    ///
+    /// Jihai are numbered as in [`Hand::tiles`].
+    pub fn to_mpsz_string(&self) -> String {
+        const SUIT_LOOKUP: [char; 4] = ['m', 'p', 's', 'z'];
+
+        let mut s = String::new();
+        let mut last_suit = None;
+        for tile in self.tiles() {
+            let (suit, num) = match tile {
+                Tile::Supai(suit, num) => (suit as usize, num),
+                Tile::Jihai(num) => (3, num),
+            };
+            if let Some(last) = last_suit.filter(|&last| last != suit) {
+                s.push(SUIT_LOOKUP[last]);
+            }
+            last_suit = Some(suit);
+            s.push((b'1' + num) as char);
+        }
+        if let Some(last) = last_suit {
+            s.push(SUIT_LOOKUP[last]);
+        }
+        s
+    }
+}
+
+impl fmt::Display for Hand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_mpsz_string())
+    }
+}
+
+// A hand is (de)serialized in mpsz notation. Jihai are renumbered as in
+// `to_mpsz_string`, which does not change the hand.
+impl Serialize for Hand {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_mpsz_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Hand {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let tiles = parse_hand_str(&s).map_err(serde::de::Error::custom)?;
+        Ok(Hand::from_tiles(&tiles))
+    }
 }
 
 /// # Supai Encoding
@@ -139,35 +326,364 @@ impl Hand {
 /// # Jihai Encoding
 /// Bit-pack of `(# of solo jihai, # of toitsu jihai, # of kotsu jihai, # of kantsu jihai)`.
 ///
-/// # Hand Encoding
-/// * 00-17 bits: smallest supai encoding
-/// * 18-35 bits: mid supai encoding
-/// * 36-53 bits: largest supai encoding
-/// * 54-62 bits: jihai encoding
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+/// # Hand Index
+/// A hand is identified by the indices of its three supai encodings in `su_lookup`,
+/// sorted as `a <= b <= c`, and the index `j` of its jihai encoding in `ji_lookup`.
+/// Hands with the same number of tiles are numbered in lexicographic order of
+/// `(j, c, b, a)`. The index is computed by counting the smaller hands with
+/// prefix sums over `su_lookup` (a combinatorial number system), so no table of
+/// all hands is kept.
+///
+/// For [`TileSet::Sanma`], manzu is not interchangeable with the other suits: `c` is
+/// the index of manzu, which stays suit 0, and `a <= b` are those of pinzu and souzu.
+/// [`TileSet::Mini`] is numbered the same way with `a = b = 0`, and its `ji_lookup`
+/// holds only the encodings of at most two kinds of jihai.
+///
+/// A supai encoding is found in `su_lookup` by a binary search within the bucket
+/// of its first [`SU_BUCKET_DIGITS`] tiles, whose range is kept in `su_buckets`.
+///
+/// # Files
+/// [`HandConverter::save_as_file`] writes only the lookups with bincode, and the
+/// other tables are rebuilt on load. [`HandConverter::save_as_raw_file`] writes all
+/// tables as little-endian flat sections, which [`HandConverter::map_raw_file`]
+/// uses in place, so loading takes no time and processes share the page cache.
+///
+/// # Memory
+/// The tables take about 60 MB for [`TileSet::Yonma`], mostly the prefix sums for
+/// ranking. No sorted list of the keys of all hands is kept, so the resident size
+/// does not grow with the number of hands and there is no such list to compress.
+#[derive(PartialEq, Debug)]
 pub struct HandConverter {
+    su_lookup: Column<u32>,
+    ji_lookup: Column<u32>,
+    /// `su_buckets[k]`: index of the first entry of `su_lookup` in bucket `k` or later
+    su_buckets: Column<u32>,
+    ranking: Ranking,
+    space13: HandSpace,
+    space14: HandSpace,
+}
+
+/// Tile set whose hands a [`HandConverter`] numbers
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum TileSet {
+    /// Four-player mahjong with all 136 tiles
+    #[default]
+    Yonma,
+    /// Three-player mahjong without 2m–8m, 108 tiles
+    Sanma,
+    /// Manzu and two kinds of jihai, 44 tiles. A reduced set for development and tests,
+    /// whose tables are small enough to compute on a laptop
+    Mini,
+}
+
+impl TileSet {
+    /// Number of 13-tile hand indices, `NUM_HAND13` or `NUM_SANMA_HAND13`
+    pub fn num_hand13(self) -> usize {
+        match self {
+            TileSet::Yonma => NUM_HAND13,
+            TileSet::Sanma => NUM_SANMA_HAND13,
+            TileSet::Mini => NUM_MINI_HAND13,
+        }
+    }
+
+    /// Number of 14-tile hand indices, `NUM_HAND14` or `NUM_SANMA_HAND14`
+    pub fn num_hand14(self) -> usize {
+        match self {
+            TileSet::Yonma => NUM_HAND14,
+            TileSet::Sanma => NUM_SANMA_HAND14,
+            TileSet::Mini => NUM_MINI_HAND14,
+        }
+    }
+
+    /// Number of tiles of the whole set
+    pub fn num_tiles(self) -> usize {
+        match self {
+            TileSet::Yonma => 136,
+            TileSet::Sanma => 108,
+            TileSet::Mini => 44,
+        }
+    }
+
+    /// Whether the set has the supai `num` (0-indexed) of `suit`
+    pub fn has_supai(self, suit: usize, num: usize) -> bool {
+        match self {
+            TileSet::Yonma => true,
+            TileSet::Sanma => suit != 0 || num == 0 || num == 8,
+            TileSet::Mini => suit == 0,
+        }
+    }
+
+    /// Number of kinds of jihai. Jihai are encoded by counts only, so any kinds of
+    /// a hand stand for those of the set
+    pub fn num_jihai_kinds(self) -> usize {
+        match self {
+            TileSet::Yonma | TileSet::Sanma => 7,
+            TileSet::Mini => 2,
+        }
+    }
+}
+
+/// Number of leading supai counts that select a bucket of `su_lookup`
+const SU_BUCKET_DIGITS: usize = 4;
+const SU_BUCKET_SHIFT: usize = 3 * (9 - SU_BUCKET_DIGITS);
+
+/// Number of hands encoded together by one task of [`HandConverter::encode_batch_in`]
+const BATCH_CHUNK: usize = 4096;
+
+/// First bytes of a converter file saved by [`HandConverter::save_as_file`]
+pub const CONVERTER_MAGIC: &[u8; 8] = b"HCONVBIN";
+/// Version of the converter file, bumped when its contents change incompatibly
+pub const CONVERTER_VERSION: u32 = 2;
+
+/// First bytes of a raw converter file
+const RAW_CONVERTER_MAGIC: &[u8; 8] = b"HCONVRAW";
+/// Number of sections of a raw converter file, one per table
+const RAW_CONVERTER_SECTIONS: usize = 10;
+
+/// Prefix sums for ranking hands, indexed by the total number of tiles
+///
+/// The outer supai index `c` ranges over all of `su_lookup` for [`TileSet::Yonma`]
+/// and [`TileSet::Mini`], and over `manzu` for [`TileSet::Sanma`].
+#[derive(PartialEq, Debug)]
+struct Ranking {
+    tile_set: TileSet,
+    /// Number of tiles of each entry of `su_lookup`
+    su_cnt: Column<u8>,
+    /// Number of tiles of each entry of `ji_lookup`
+    ji_cnt: Column<u8>,
+    /// `singles[i][t]`: number of `a < i` with `t` tiles
+    singles: Column<[u32; 15]>,
+    /// `pairs[i][t]`: number of `a <= b < i` with `t` tiles in total
+    pairs: Column<[u64; 15]>,
+    /// `outer[k][t]`: number of `(a, b, c)` with `t` tiles in total whose `c` is before
+    /// the k-th outer index. For Yonma, the number of `a <= b <= c < k`. For Mini,
+    /// the number of `c < k` with `a = b = 0`, the empty pinzu and souzu
+    outer: Column<[u64; 15]>,
+    /// Indices of the entries of `su_lookup` without 2m–8m, empty for Yonma and Mini
+    manzu: Column<u32>,
+}
+
+impl Ranking {
+    fn new(su_lookup: &[u32], ji_lookup: &[u32], tile_set: TileSet) -> Ranking {
+        let mut supai = [0u8; 9];
+        let su_cnt: Vec<u8> = su_lookup
+            .iter()
+            .map(|&code| {
+                from_octal(code, &mut supai);
+                supai.iter().sum()
+            })
+            .collect();
+        let mut jihai = [0u8; 5];
+        let ji_cnt: Vec<u8> = ji_lookup
+            .iter()
+            .map(|&code| {
+                from_octal(code, &mut jihai);
+                jihai.iter().enumerate().map(|(k, &v)| k as u8 * v).sum()
+            })
+            .collect();
+
+        let mut singles = vec![[0u32; 15]];
+        let mut pairs = vec![[0u64; 15]];
+        let mut triples = vec![[0u64; 15]];
+        for (i, &cnt) in su_cnt.iter().enumerate() {
+            let cnt = cnt as usize;
+            let mut single = singles[i];
+            single[cnt] += 1;
+            // Pairs and triples whose largest index is i
+            let mut pair = pairs[i];
+            for t in cnt..15 {
+                pair[t] += single[t - cnt] as u64;
+            }
+            singles.push(single);
+            pairs.push(pair);
+            if tile_set == TileSet::Yonma {
+                let mut triple = triples[i];
+                for t in cnt..15 {
+                    triple[t] += pair[t - cnt];
+                }
+                triples.push(triple);
+            }
+        }
+
+        // For Sanma, any pinzu and souzu go with each manzu
+        let mut manzu = vec![];
+        if tile_set == TileSet::Sanma {
+            let all_pairs = *pairs.last().unwrap();
+            for (i, &code) in su_lookup.iter().enumerate() {
+                if code & MIDDLE_SUPAI_MASK != 0 {
+                    continue;
+                }
+                let cnt = su_cnt[i] as usize;
+                let mut triple = *triples.last().unwrap();
+                for t in cnt..15 {
+                    triple[t] += all_pairs[t - cnt];
+                }
+                triples.push(triple);
+                manzu.push(i as u32);
+            }
+        }
+
+        // For Mini, pinzu and souzu are always the empty entry 0
+        if tile_set == TileSet::Mini {
+            let empty_pair = pairs[1];
+            for &cnt in &su_cnt {
+                let cnt = cnt as usize;
+                let mut triple = *triples.last().unwrap();
+                for t in cnt..15 {
+                    triple[t] += empty_pair[t - cnt];
+                }
+                triples.push(triple);
+            }
+        }
+        Ranking {
+            tile_set,
+            su_cnt: su_cnt.into(),
+            ji_cnt: ji_cnt.into(),
+            singles: singles.into(),
+            pairs: pairs.into(),
+            outer: triples.into(),
+            manzu: manzu.into(),
+        }
+    }
+
+    /// Position of the outer index `c` in `outer`
+    fn outer_pos(&self, c: usize) -> usize {
+        match self.tile_set {
+            TileSet::Yonma | TileSet::Mini => c,
+            TileSet::Sanma => self.manzu.binary_search(&(c as u32)).unwrap(),
+        }
+    }
+
+    /// Outer index at position `k` of `outer`
+    fn outer_index(&self, k: usize) -> usize {
+        match self.tile_set {
+            TileSet::Yonma | TileSet::Mini => k,
+            TileSet::Sanma => self.manzu[k] as usize,
+        }
+    }
+
+    fn space(&self, num_tiles: usize) -> HandSpace {
+        let all = self.outer.last().unwrap();
+        let mut offsets = Vec::with_capacity(self.ji_cnt.len() + 1);
+        let mut offset = 0;
+        offsets.push(offset);
+        for &cnt in self.ji_cnt.iter() {
+            if let Some(rest) = num_tiles.checked_sub(cnt as usize) {
+                offset += all[rest];
+            }
+            offsets.push(offset);
+        }
+        HandSpace { num_tiles, offsets }
+    }
+
+    fn rank(&self, space: &HandSpace, [a, b, c]: [usize; 3], j: usize) -> u32 {
+        let t = space.num_tiles - self.ji_cnt[j] as usize;
+        let t2 = t - self.su_cnt[c] as usize;
+        let t3 = t2 - self.su_cnt[b] as usize;
+        assert_eq!(
+            t3, self.su_cnt[a] as usize,
+            "Hand must contain {} tiles",
+            space.num_tiles
+        );
+        let rank = space.offsets[j]
+            + self.outer[self.outer_pos(c)][t]
+            + self.pairs[b][t2]
+            + self.singles[a][t3] as u64;
+        rank as u32
+    }
+
+    fn unrank(&self, space: &HandSpace, encoded: u32) -> ([usize; 3], usize) {
+        assert!(
+            (encoded as usize) < space.len(),
+            "Invalid hand index: {}",
+            encoded
+        );
+        let mut rest = encoded as u64;
+        // The last entry at most `rest` skips sizes with no hand
+        let j = space.offsets.partition_point(|&v| v <= rest) - 1;
+        rest -= space.offsets[j];
+        let t = space.num_tiles - self.ji_cnt[j] as usize;
+        let k = self.outer.partition_point(|v| v[t] <= rest) - 1;
+        rest -= self.outer[k][t];
+        let c = self.outer_index(k);
+        let t2 = t - self.su_cnt[c] as usize;
+        let b = self.pairs.partition_point(|v| v[t2] <= rest) - 1;
+        rest -= self.pairs[b][t2];
+        let t3 = t2 - self.su_cnt[b] as usize;
+        let a = self.singles.partition_point(|v| v[t3] as u64 <= rest) - 1;
+        ([a, b, c], j)
+    }
+}
+
+// The converter file holds only the lookups, and the ranking is rebuilt on load.
+#[derive(Serialize)]
+struct ConverterFileRef<'a> {
+    su_lookup: &'a [u32],
+    ji_lookup: &'a [u32],
+    tile_set: TileSet,
+}
+
+#[derive(Deserialize)]
+struct ConverterFile {
+    su_lookup: Vec<u32>,
+    ji_lookup: Vec<u32>,
+    tile_set: TileSet,
+}
+
+// Files saved without a header are of Yonma. When the converter also held tables
+// of all 13- and 14-tile hands, they followed the lookups and are not read.
+#[derive(Deserialize)]
+struct LegacyConverterFile {
     su_lookup: Vec<u32>,
     ji_lookup: Vec<u32>,
-    hand13_lookup: Vec<u64>,
-    hand14_lookup: Vec<u64>,
+}
+
+impl Serialize for HandConverter {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        ConverterFileRef {
+            su_lookup: &self.su_lookup,
+            ji_lookup: &self.ji_lookup,
+            tile_set: self.tile_set(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for HandConverter {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let file = ConverterFile::deserialize(deserializer)?;
+        Ok(HandConverter::from_lookups(
+            file.su_lookup,
+            file.ji_lookup,
+            file.tile_set,
+        ))
+    }
 }
 
 impl HandConverter {
     pub fn empty() -> HandConverter {
-        HandConverter {
-            su_lookup: vec![],
-            ji_lookup: vec![],
-            hand13_lookup: vec![],
-            hand14_lookup: vec![],
-        }
+        Self::from_lookups(vec![], vec![], TileSet::Yonma)
     }
+
+    /// Build the lookups and the ranking tables.
+    ///
+    /// No list of all 13- or 14-tile hands is built, so this takes well under a second
+    /// and a few tens of MB of memory.
     pub fn new() -> HandConverter {
+        Self::with_tile_set(TileSet::Yonma)
+    }
+
+    /// Like [`HandConverter::new`], numbering the hands of `tile_set`
+    pub fn with_tile_set(tile_set: TileSet) -> HandConverter {
         let mut su_lookup = Vec::with_capacity(203122);
         let mut ji_lookup = Vec::with_capacity(177);
 
-        let mut su_memo: [Vec<u32>; 15] = Default::default();
-        let mut ji_memo: [Vec<u32>; 15] = Default::default();
-
         for p in product_repeat(0..5, 9) {
             let cnt = p.iter().copied().sum::<u32>();
             if cnt > 14 {
@@ -177,15 +693,9 @@ impl HandConverter {
             let y = to_octal(p.iter().copied().rev());
             if x <= y {
                 su_lookup.push(x);
-                su_memo[cnt as usize].push(x);
             }
         }
         su_lookup.sort_unstable();
-        for l in su_memo.iter_mut() {
-            for v in l.iter_mut() {
-                *v = su_lookup.binary_search(v).unwrap() as u32;
-            }
-        }
 
         for p in (0..8).combinations_with_replacement(4) {
             let jihai = [p[0], p[1] - p[0], p[2] - p[1], p[3] - p[2], 7 - p[3]];
@@ -195,162 +705,279 @@ impl HandConverter {
                 .enumerate()
                 .map(|(i, v)| (i as u32) * v)
                 .sum::<u32>();
-            if cnt <= 14 {
-                let code = to_octal(jihai.iter().copied());
-                ji_lookup.push(code);
-                ji_memo[cnt as usize].push(code);
+            if cnt <= 14 && jihai[0] as usize + tile_set.num_jihai_kinds() >= 7 {
+                ji_lookup.push(to_octal(jihai.iter().copied()));
             }
         }
         ji_lookup.sort_unstable();
-        for l in ji_memo.iter_mut() {
-            for v in l.iter_mut() {
-                *v = ji_lookup.binary_search(v).unwrap() as u32;
-            }
-        }
-        let generate_all_hands = |tiles: usize, expected_len: usize| {
-            let mut hands = Vec::with_capacity(expected_len);
-            let mut helper = |a: &u32, b: &u32, c: &u32, r: usize| {
-                let mut su_codes = [*a as u64, *b as u64, *c as u64];
-                su_codes.sort_unstable();
-                let x = su_codes[0] | (su_codes[1] << 18) | (su_codes[2] << 36);
-                for d in ji_memo[r].iter() {
-                    hands.push(x | ((*d as u64) << 54));
-                }
-            };
 
-            for p in (0..(tiles + 1)).combinations_with_replacement(3) {
-                let q = [p[0], p[1] - p[0], p[2] - p[1]];
-                if (q[0] > q[1]) || q[1] > q[2] {
-                    continue;
-                }
-                let r = tiles - p[2];
-
-                if q[0] == q[2] {
-                    for abc in su_memo
-                        .get(q[0])
-                        .unwrap()
-                        .iter()
-                        .combinations_with_replacement(3)
-                    {
-                        helper(abc[0], abc[1], abc[2], r);
-                    }
-                } else if q[0] == q[1] {
-                    for ab in su_memo
-                        .get(q[0])
-                        .unwrap()
-                        .iter()
-                        .combinations_with_replacement(2)
-                    {
-                        for c in su_memo.get(q[2]).unwrap() {
-                            helper(ab[0], ab[1], c, r);
-                        }
-                    }
-                } else if q[1] == q[2] {
-                    for bc in su_memo
-                        .get(q[1])
-                        .unwrap()
-                        .iter()
-                        .combinations_with_replacement(2)
-                    {
-                        for a in su_memo.get(q[0]).unwrap() {
-                            helper(a, bc[0], bc[1], r);
-                        }
-                    }
-                } else {
-                    for abc in q
-                        .map(|t| su_memo.get(t).unwrap())
-                        .iter()
-                        .copied()
-                        .multi_cartesian_product()
-                    {
-                        helper(abc[0], abc[1], abc[2], r);
-                    }
-                }
-            }
-            hands.sort_unstable();
-            hands
-        };
+        let conv = Self::from_lookups(su_lookup, ji_lookup, tile_set);
+        assert_eq!(conv.num_hand13(), tile_set.num_hand13());
+        assert_eq!(conv.num_hand14(), tile_set.num_hand14());
+        conv
+    }
 
-        println!("Generating hand13_lookup...");
-        let hand13_lookup = generate_all_hands(13, NUM_HAND13);
-        println!("Generating hand14_lookup...");
-        let hand14_lookup = generate_all_hands(14, NUM_HAND14);
-        println!("Done!");
-        assert_eq!(hand13_lookup.len(), NUM_HAND13);
-        assert_eq!(hand14_lookup.len(), NUM_HAND14);
+    fn from_lookups(su_lookup: Vec<u32>, ji_lookup: Vec<u32>, tile_set: TileSet) -> HandConverter {
+        let ranking = Ranking::new(&su_lookup, &ji_lookup, tile_set);
+        let su_buckets: Vec<u32> = (0..=(1u32 << (3 * SU_BUCKET_DIGITS)))
+            .map(|k| su_lookup.partition_point(|&code| (code >> SU_BUCKET_SHIFT) < k) as u32)
+            .collect();
+        Self::from_tables(
+            su_lookup.into(),
+            ji_lookup.into(),
+            su_buckets.into(),
+            ranking,
+        )
+    }
+
+    fn from_tables(
+        su_lookup: Column<u32>,
+        ji_lookup: Column<u32>,
+        su_buckets: Column<u32>,
+        ranking: Ranking,
+    ) -> HandConverter {
+        let space13 = ranking.space(13);
+        let space14 = ranking.space(14);
         HandConverter {
             su_lookup,
             ji_lookup,
-            hand13_lookup,
-            hand14_lookup,
+            su_buckets,
+            ranking,
+            space13,
+            space14,
         }
     }
 
+    /// Save the lookups with a header of [`CONVERTER_MAGIC`], [`CONVERTER_VERSION`] and a content hash
     pub fn save_as_file<P: AsRef<Path>>(&self, filename: P) -> Result<()> {
-        io::save_object(filename, self)
+        io::save_object_with_header(filename, CONVERTER_MAGIC, CONVERTER_VERSION, self)
     }
 
+    /// Load a file saved by [`HandConverter::save_as_file`], checking its header.
+    ///
+    /// Files saved before the header was added are read as Yonma converters. The
+    /// loaded converter must number as many hands as the current one.
     pub fn load_from_file<P: AsRef<Path>>(filename: P) -> Result<Self> {
-        io::load_object(filename)
+        let conv: Self =
+            match io::load_object_with_header(&filename, CONVERTER_MAGIC, CONVERTER_VERSION)? {
+                Some(conv) => conv,
+                None => {
+                    let file: LegacyConverterFile = io::load_object(filename)?;
+                    Self::from_lookups(file.su_lookup, file.ji_lookup, TileSet::Yonma)
+                }
+            };
+        conv.validate()?;
+        Ok(conv)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let tile_set = self.tile_set();
+        ensure!(
+            self.num_hand13() == tile_set.num_hand13()
+                && self.num_hand14() == tile_set.num_hand14(),
+            "{:?} converter numbers {} 13-tile and {} 14-tile hands (expected {} and {})",
+            tile_set,
+            self.num_hand13(),
+            self.num_hand14(),
+            tile_set.num_hand13(),
+            tile_set.num_hand14()
+        );
+        Ok(())
+    }
+
+    pub fn tile_set(&self) -> TileSet {
+        self.ranking.tile_set
+    }
+
+    /// Save all tables in the raw format read by [`HandConverter::map_raw_file`]
+    pub fn save_as_raw_file<P: AsRef<Path>>(&self, filename: P) -> Result<()> {
+        let r = &self.ranking;
+        let lens = [
+            self.su_lookup.len(),
+            self.ji_lookup.len(),
+            self.su_buckets.len(),
+            r.su_cnt.len(),
+            r.ji_cnt.len(),
+            r.singles.len(),
+            r.pairs.len(),
+            r.outer.len(),
+            r.manzu.len(),
+            1,
+        ];
+        let mut writer = RawWriter::create(filename, RAW_CONVERTER_MAGIC, &lens)?;
+        writer.write(&self.su_lookup)?;
+        writer.write(&self.ji_lookup)?;
+        writer.write(&self.su_buckets)?;
+        writer.write(&r.su_cnt)?;
+        writer.write(&r.ji_cnt)?;
+        writer.write(&r.singles)?;
+        writer.write(&r.pairs)?;
+        writer.write(&r.outer)?;
+        writer.write(&r.manzu)?;
+        writer.write(&[r.tile_set as u8])?;
+        writer.finish()
+    }
+
+    /// Map a file saved by [`HandConverter::save_as_raw_file`] and use its tables in place.
+    ///
+    /// The file must not be modified while the converter is alive.
+    pub fn map_raw_file<P: AsRef<Path>>(filename: P) -> Result<Self> {
+        let mut reader = RawReader::open(filename, RAW_CONVERTER_MAGIC, RAW_CONVERTER_SECTIONS)?;
+        let su_lookup = reader.next()?;
+        let ji_lookup = reader.next()?;
+        let su_buckets = reader.next()?;
+        let su_cnt = reader.next()?;
+        let ji_cnt = reader.next()?;
+        let singles = reader.next()?;
+        let pairs = reader.next()?;
+        let outer = reader.next()?;
+        let manzu = reader.next()?;
+        let tile_set = match *reader.next::<u8>()? {
+            [0] => TileSet::Yonma,
+            [1] => TileSet::Sanma,
+            [2] => TileSet::Mini,
+            _ => anyhow::bail!("Raw converter file has an invalid tile set"),
+        };
+        let ranking = Ranking {
+            tile_set,
+            su_cnt,
+            ji_cnt,
+            singles,
+            pairs,
+            outer,
+            manzu,
+        };
+        let n = su_lookup.len();
+        let r = &ranking;
+        let num_outer = match tile_set {
+            TileSet::Yonma | TileSet::Mini => n,
+            TileSet::Sanma => r.manzu.len(),
+        };
+        ensure!(
+            r.su_cnt.len() == n
+                && r.ji_cnt.len() == ji_lookup.len()
+                && su_buckets.len() == (1 << (3 * SU_BUCKET_DIGITS)) + 1
+                && r.singles.len() == n + 1
+                && r.pairs.len() == n + 1
+                && r.outer.len() == num_outer + 1,
+            "Raw converter file has inconsistent table sizes"
+        );
+        let conv = Self::from_tables(su_lookup, ji_lookup, su_buckets, ranking);
+        conv.validate()?;
+        Ok(conv)
+    }
+
+    /// Load a converter in either format, mapping a raw file and deserializing a bincode file
+    pub fn open<P: AsRef<Path>>(filename: P) -> Result<Self> {
+        let mut head = Vec::with_capacity(8);
+        File::open(&filename)?.take(8).read_to_end(&mut head)?;
+        if has_magic(&head, RAW_CONVERTER_MAGIC) {
+            Self::map_raw_file(filename)
+        } else {
+            Self::load_from_file(filename)
+        }
+    }
+
+    /// Check that `other` has the same tables, returning an error naming the first
+    /// entry that differs. Converters loaded from either format compare equal.
+    pub fn check_same_tables(&self, other: &HandConverter) -> Result<()> {
+        ensure!(
+            self.tile_set() == other.tile_set(),
+            "Tile sets differ: {:?} != {:?}",
+            self.tile_set(),
+            other.tile_set()
+        );
+        let (r, s) = (&self.ranking, &other.ranking);
+        check_same_table("su_lookup", &self.su_lookup, &other.su_lookup)?;
+        check_same_table("ji_lookup", &self.ji_lookup, &other.ji_lookup)?;
+        check_same_table("su_buckets", &self.su_buckets, &other.su_buckets)?;
+        check_same_table("su_cnt", &r.su_cnt, &s.su_cnt)?;
+        check_same_table("ji_cnt", &r.ji_cnt, &s.ji_cnt)?;
+        check_same_table("singles", &r.singles, &s.singles)?;
+        check_same_table("pairs", &r.pairs, &s.pairs)?;
+        check_same_table("outer", &r.outer, &s.outer)?;
+        check_same_table("manzu", &r.manzu, &s.manzu)?;
+        Ok(())
+    }
+
+    /// Supai indices of a canonical hand in suit order, from the key of [`Ranking::rank`]
+    fn suits_of(&self, [a, b, c]: [usize; 3]) -> [usize; 3] {
+        match self.tile_set() {
+            TileSet::Yonma => [a, b, c],
+            TileSet::Sanma | TileSet::Mini => [c, a, b],
+        }
+    }
+
+    /// The key of [`Ranking::rank`] from the supai indices of a canonical hand in suit order
+    fn key_of(&self, su: [usize; 3]) -> [usize; 3] {
+        match self.tile_set() {
+            TileSet::Yonma => su,
+            TileSet::Sanma | TileSet::Mini => [su[1], su[2], su[0]],
+        }
     }
 
-    fn encode_into_key(&self, hand: &Hand) -> (u64, [i8; 3]) {
-        let mut memo = [(0u64, 0i8); 3];
+    /// Reorder the interchangeable suits so that their indices are sorted
+    fn canonical_order<T: Ord>(&self, su: &mut [T; 3]) {
+        match self.tile_set() {
+            TileSet::Yonma => su.sort_unstable(),
+            TileSet::Sanma | TileSet::Mini => su[1..].sort_unstable(),
+        }
+    }
+
+    /// Key of [`Ranking::rank`], jihai index and the translation of a hand
+    fn encode_into_indices(&self, hand: &Hand) -> ([usize; 3], usize, [i8; 3]) {
+        let mut memo = [(0usize, 0i8); 3];
         for i in 0..3usize {
             let p = to_octal(hand.supai[i].iter().map(|&v| v as u32));
             let q = to_octal(hand.supai[i].iter().rev().map(|&v| v as u32));
             if p <= q {
-                memo[i] = (self.su_lookup.binary_search(&p).unwrap() as u64, i as i8);
+                memo[i] = (self.su_index(p), i as i8);
             } else {
-                memo[i] = (self.su_lookup.binary_search(&q).unwrap() as u64, !(i as i8));
+                memo[i] = (self.su_index(q), !(i as i8));
             }
         }
-        memo.sort_unstable();
-        let trans = core::array::from_fn(|i| memo[i].1);
-        let mut key = self
-            .ji_lookup
-            .binary_search(&to_octal(hand.jihai.iter().map(|&v| v as u32)))
-            .unwrap() as u64;
-        for (v, _) in memo.iter().rev() {
-            key <<= 18;
-            key |= v;
-        }
-        (key, trans)
+        self.canonical_order(&mut memo);
+        let su = memo.map(|(v, _)| v);
+        let trans = memo.map(|(_, t)| t);
+        (self.key_of(su), self.ji_index(hand), trans)
     }
 
-    fn encode_into_key_fast(&self, hand: &Hand) -> u64 {
-        let mut memo = [0u64; 3];
-        for i in 0..3usize {
+    fn encode_into_indices_fast(&self, hand: &Hand) -> ([usize; 3], usize) {
+        let mut su = [0usize; 3];
+        for (i, s) in su.iter_mut().enumerate() {
             let p = to_octal(hand.supai[i].iter().map(|&v| v as u32));
             let q = to_octal(hand.supai[i].iter().rev().map(|&v| v as u32));
-            memo[i] = self.su_lookup.binary_search(&(p.min(q))).unwrap() as u64;
+            *s = self.su_index(p.min(q));
         }
-        memo.sort_unstable();
-        let mut key = self
-            .ji_lookup
+        self.canonical_order(&mut su);
+        (self.key_of(su), self.ji_index(hand))
+    }
+
+    fn su_index(&self, code: u32) -> usize {
+        self.find_su_index(code).unwrap()
+    }
+
+    fn find_su_index(&self, code: u32) -> Option<usize> {
+        let bucket = (code >> SU_BUCKET_SHIFT) as usize;
+        let start = self.su_buckets[bucket] as usize;
+        let end = self.su_buckets[bucket + 1] as usize;
+        let i = self.su_lookup[start..end].binary_search(&code).ok()?;
+        Some(start + i)
+    }
+
+    fn ji_index(&self, hand: &Hand) -> usize {
+        self.ji_lookup
             .binary_search(&to_octal(hand.jihai.iter().map(|&v| v as u32)))
-            .unwrap() as u64;
-        for v in memo.iter().rev() {
-            key <<= 18;
-            key |= v;
-        }
-        key
+            .unwrap()
     }
 
-    fn decode_from_key(&self, mut key: u64) -> Hand {
+    fn decode_from_indices(&self, su: [usize; 3], j: usize) -> Hand {
         let mut supai = [[0u8; 9]; 3];
         let mut jihai = [0u8; 5];
-        let x = self.su_lookup[(key & 0x3ffff) as usize];
-        key >>= 18;
-        from_octal(x, supai.get_mut(0).unwrap());
-        let x = self.su_lookup[(key & 0x3ffff) as usize];
-        key >>= 18;
-        from_octal(x, supai.get_mut(1).unwrap());
-        let x = self.su_lookup[(key & 0x3ffff) as usize];
-        key >>= 18;
-        from_octal(x, supai.get_mut(2).unwrap());
-        let x = self.ji_lookup[key as usize];
-        from_octal(x, &mut jihai);
+        for (dst, &i) in supai.iter_mut().zip(&su) {
+            from_octal(self.su_lookup[i], dst);
+        }
+        from_octal(self.ji_lookup[j], &mut jihai);
         Hand { supai, jihai }
     }
 
@@ -366,21 +993,28 @@ impl HandConverter {
     ///
     /// # Translation list
     /// Negative value means that the number is reversed. You should bit-negate it to find out the original suit.
+    /// Wrap it in [`Translation`](crate::mahjong::Translation) to map tiles and sets back to the original hand.
     ///
     /// For example, if it is `[!1, 2, 0]`, then:
     /// * suit 0 of the encoded is suit 1 of the original, but number is reversed (1 -> 9, 9 -> 1)
     /// * suit 1 of the encoded is suit 2 of the original
     /// * suit 2 of the encoded is suit 0 of the original
     pub fn encode_hand14(&self, hand: &Hand) -> (u32, [i8; 3]) {
-        let (key, trans) = self.encode_into_key(hand);
-        (
-            self.hand14_lookup.binary_search(&key).unwrap() as u32,
-            trans,
-        )
+        self.encode_in(&self.space14, hand)
     }
     pub fn encode_hand14_fast(&self, hand: &Hand) -> u32 {
-        let key = self.encode_into_key_fast(hand);
-        self.hand14_lookup.binary_search(&key).unwrap() as u32
+        self.encode_in_fast(&self.space14, hand)
+    }
+
+    /// Like [`HandConverter::encode_hand14_fast`] for many hands, see [`HandConverter::encode_batch_in`]
+    pub fn encode_hand14_batch(&self, hands: &[Hand]) -> Vec<u32> {
+        self.encode_batch_in(&self.space14, hands)
+    }
+
+    /// Like [`HandConverter::encode_hand14`], returning an error instead of panicking
+    /// for a hand that is not a valid 14-tile hand
+    pub fn try_encode_hand14(&self, hand: &Hand) -> Result<(u32, [i8; 3])> {
+        self.try_encode_in(&self.space14, hand)
     }
 
     /// Encode a hand with 13 tiles into a u32. This also returns a translation done on supai.
@@ -395,39 +1029,366 @@ impl HandConverter {
     ///
     /// # Translation list
     /// Negative value means that the number is reversed. You should bit-not it to find out the original suit.
+    /// Wrap it in [`Translation`](crate::mahjong::Translation) to map tiles and sets back to the original hand.
     ///
     /// For example, if it is `[!1, 2, 0]`, then:
     /// * suit 0 of the encoded is suit 1 of the original, but number is reversed (1 -> 9, 9 -> 1)
     /// * suit 1 of the encoded is suit 2 of the original
     /// * suit 2 of the encoded is suit 0 of the original
     pub fn encode_hand13(&self, hand: &Hand) -> (u32, [i8; 3]) {
-        let (key, trans) = self.encode_into_key(hand);
-        (
-            self.hand13_lookup.binary_search(&key).unwrap() as u32,
-            trans,
-        )
+        self.encode_in(&self.space13, hand)
     }
     pub fn encode_hand13_fast(&self, hand: &Hand) -> u32 {
-        let key = self.encode_into_key_fast(hand);
-        self.hand13_lookup.binary_search(&key).unwrap() as u32
+        self.encode_in_fast(&self.space13, hand)
+    }
+
+    /// Like [`HandConverter::encode_hand13_fast`] for many hands, see [`HandConverter::encode_batch_in`]
+    pub fn encode_hand13_batch(&self, hands: &[Hand]) -> Vec<u32> {
+        self.encode_batch_in(&self.space13, hands)
+    }
+
+    /// Like [`HandConverter::encode_hand13`], returning an error instead of panicking
+    /// for a hand that is not a valid 13-tile hand
+    pub fn try_encode_hand13(&self, hand: &Hand) -> Result<(u32, [i8; 3])> {
+        self.try_encode_in(&self.space13, hand)
+    }
+
+    /// Number of 13-tile hand indices this converter can encode. Equals `NUM_HAND13` for a valid converter.
+    pub fn num_hand13(&self) -> usize {
+        self.space13.len()
+    }
+
+    /// Number of 14-tile hand indices this converter can encode. Equals `NUM_HAND14` for a valid converter.
+    pub fn num_hand14(&self) -> usize {
+        self.space14.len()
     }
 
     pub fn decode_hand14(&self, encoded: u32) -> Hand {
-        self.decode_from_key(self.hand14_lookup[encoded as usize])
+        self.decode_in(&self.space14, encoded)
     }
 
     pub fn decode_hand13(&self, encoded: u32) -> Hand {
-        self.decode_from_key(self.hand13_lookup[encoded as usize])
+        self.decode_in(&self.space13, encoded)
+    }
+
+    /// The canonical hands with `num_tiles` tiles (at most 14).
+    ///
+    /// Used for the concealed tiles of open hands, e.g. 10 or 11 tiles with one meld.
+    pub fn hand_space(&self, num_tiles: usize) -> HandSpace {
+        assert!(num_tiles <= 14, "Too many tiles: {}", num_tiles);
+        self.ranking.space(num_tiles)
+    }
+
+    /// Like [`HandConverter::encode_hand13`], for a hand with `space.num_tiles()` tiles
+    pub fn encode_in(&self, space: &HandSpace, hand: &Hand) -> (u32, [i8; 3]) {
+        let (su, j, trans) = self.encode_into_indices(hand);
+        (self.ranking.rank(space, su, j), trans)
+    }
+
+    /// Like [`HandConverter::encode_in`] without the translation.
+    ///
+    /// This reads `su_lookup` only within buckets of at most 2878 entries, `ji_lookup` and
+    /// three rows of the prefix sums, all of which stay in cache. A nearby hand as a hint
+    /// would not help, since finding the keys of a hand index takes longer than this.
+    pub fn encode_in_fast(&self, space: &HandSpace, hand: &Hand) -> u32 {
+        let (su, j) = self.encode_into_indices_fast(hand);
+        self.ranking.rank(space, su, j)
+    }
+
+    /// Like [`HandConverter::encode_in_fast`] for many hands, returning the indices in the order of `hands`.
+    ///
+    /// Chunks of hands are encoded in parallel. Within a chunk, hands are ranked in
+    /// the order of their indices, so the prefix sums are read mostly forward.
+    pub fn encode_batch_in(&self, space: &HandSpace, hands: &[Hand]) -> Vec<u32> {
+        let mut encoded = vec![0u32; hands.len()];
+        encoded
+            .par_chunks_mut(BATCH_CHUNK)
+            .zip(hands.par_chunks(BATCH_CHUNK))
+            .for_each(|(encoded, hands)| {
+                let mut keys: Vec<_> = hands
+                    .iter()
+                    .enumerate()
+                    .map(|(i, hand)| {
+                        let (su, j) = self.encode_into_indices_fast(hand);
+                        (i, su, j)
+                    })
+                    .collect();
+                keys.sort_unstable_by_key(|&(_, [a, b, c], j)| (j, c, b, a));
+                for (i, su, j) in keys {
+                    encoded[i] = self.ranking.rank(space, su, j);
+                }
+            });
+        encoded
+    }
+
+    /// Call `op(hand13_id, cnt, trans)` for each hand made by discarding a tile of the
+    /// 14-tile hand `hand14_id`, like [`Hand::for_each_discard_hand`] on the decoded hand
+    /// followed by [`HandConverter::encode_hand13`], see [`HandConverter::for_each_neighbor_in`]
+    pub fn for_each_discard_encoded<F: FnMut(u32, u8, [i8; 3])>(&self, hand14_id: u32, op: F) {
+        self.for_each_neighbor_in(&self.space14, &self.space13, hand14_id, op)
+    }
+
+    /// Call `op(hand14_id, cnt, trans)` for each hand made by drawing a tile to the
+    /// 13-tile hand `hand13_id`, like [`Hand::for_each_draw_hand`] on the decoded hand
+    /// followed by [`HandConverter::encode_hand14`], see [`HandConverter::for_each_neighbor_in`]
+    pub fn for_each_draw_encoded<F: FnMut(u32, u8, [i8; 3])>(&self, hand13_id: u32, op: F) {
+        self.for_each_neighbor_in(&self.space13, &self.space14, hand13_id, op)
+    }
+
+    /// Call `op(to_id, cnt, trans)` for each hand of `to` made by discarding a tile from
+    /// (if `to` has one tile less) or drawing a tile to (if one tile more) the hand
+    /// `from_id` of `from`, in the order of [`Hand::for_each_discard_hand`] and
+    /// [`Hand::for_each_draw_hand`].
+    ///
+    /// `trans` is the translation from the decoded hand of `from_id`. Only the changed
+    /// suit is looked up again, without decoding and encoding the whole hand.
+    pub fn for_each_neighbor_in<F: FnMut(u32, u8, [i8; 3])>(
+        &self,
+        from: &HandSpace,
+        to: &HandSpace,
+        from_id: u32,
+        mut op: F,
+    ) {
+        let discard = to.num_tiles + 1 == from.num_tiles;
+        assert!(
+            discard || to.num_tiles == from.num_tiles + 1,
+            "Hands of {} tiles are not neighbors of hands of {} tiles",
+            to.num_tiles,
+            from.num_tiles
+        );
+        // The decoded hand has the supai of index su[i] as suit i, all in canonical order
+        let (key, j) = self.ranking.unrank(from, from_id);
+        let su = self.suits_of(key);
+        let tile_set = self.tile_set();
+        for (suit, &index) in su.iter().enumerate() {
+            let code = self.su_lookup[index];
+            for num in (0..9).filter(|&num| tile_set.has_supai(suit, num)) {
+                let shift = 3 * (8 - num);
+                let cnt = ((code >> shift) & 7) as u8;
+                let (cnt, next) = match discard {
+                    true if cnt > 0 => (cnt, code - (1 << shift)),
+                    false if cnt < 4 => (4 - cnt, code + (1 << shift)),
+                    _ => continue,
+                };
+                let rev = reverse_octal(next);
+                let mut memo: [(usize, i8); 3] = core::array::from_fn(|i| (su[i], i as i8));
+                memo[suit] = if next <= rev {
+                    (self.su_index(next), suit as i8)
+                } else {
+                    (self.su_index(rev), !(suit as i8))
+                };
+                self.canonical_order(&mut memo);
+                let to_id = self.ranking.rank(to, self.key_of(memo.map(|(v, _)| v)), j);
+                op(to_id, cnt, memo.map(|(_, t)| t));
+            }
+        }
+
+        // Supai are unchanged and already sorted, so the translation is the identity
+        let mut jihai = [0u8; 5];
+        from_octal(self.ji_lookup[j], &mut jihai);
+        let counts = if discard { 1..5 } else { 0..4 };
+        // Kinds not in the tile set are counted in jihai[0] but cannot be drawn
+        let missing = (7 - tile_set.num_jihai_kinds()) as u8;
+        for i in counts {
+            let kinds = if i == 0 { jihai[0] - missing } else { jihai[i] };
+            if kinds == 0 {
+                continue;
+            }
+            let (next, cnt) = if discard {
+                (i - 1, kinds * i as u8)
+            } else {
+                (i + 1, kinds * (4 - i) as u8)
+            };
+            jihai[i] -= 1;
+            jihai[next] += 1;
+            let to_j = self
+                .ji_lookup
+                .binary_search(&to_octal(jihai.iter().map(|&v| v as u32)))
+                .unwrap();
+            jihai[next] -= 1;
+            jihai[i] += 1;
+            op(self.ranking.rank(to, key, to_j), cnt, [0, 1, 2]);
+        }
+    }
+
+    /// Like [`HandConverter::encode_in`], returning an error instead of panicking when
+    /// the hand has more than 4 copies of a tile, not `space.num_tiles()` tiles or
+    /// a tile not in the tile set
+    pub fn try_encode_in(&self, space: &HandSpace, hand: &Hand) -> Result<(u32, [i8; 3])> {
+        ensure!(
+            hand.supai.iter().flatten().all(|&v| v <= 4),
+            "More than 4 copies of a supai"
+        );
+        let tile_set = self.tile_set();
+        ensure!(
+            (0..3).all(|suit| {
+                (0..9).all(|num| tile_set.has_supai(suit, num) || hand.supai[suit][num] == 0)
+            }),
+            "{:?} hands have no {}",
+            tile_set,
+            match tile_set {
+                TileSet::Mini => "pinzu or souzu",
+                _ => "2m-8m",
+            }
+        );
+        ensure!(
+            hand.jihai.iter().map(|&v| v as usize).sum::<usize>() == 7,
+            "Jihai counts must cover 7 kinds"
+        );
+        ensure!(
+            hand.jihai[0] as usize + tile_set.num_jihai_kinds() >= 7,
+            "{:?} hands have at most {} kinds of jihai",
+            tile_set,
+            tile_set.num_jihai_kinds()
+        );
+        ensure!(
+            hand.num_tiles() == space.num_tiles,
+            "Hand must contain {} tiles, not {}",
+            space.num_tiles,
+            hand.num_tiles()
+        );
+        Ok(self.encode_in(space, hand))
+    }
+
+    pub fn decode_in(&self, space: &HandSpace, encoded: u32) -> Hand {
+        let (key, j) = self.ranking.unrank(space, encoded);
+        self.decode_from_indices(self.suits_of(key), j)
+    }
+
+    /// Indices of the hands of `space` with the jihai counts `jihai` (`jihai[k]` kinds
+    /// with k tiles, as in [`Hand::jihai`]). Hands are numbered by their jihai first,
+    /// so they form one range.
+    pub fn jihai_range_in(&self, space: &HandSpace, jihai: &[u8; 5]) -> Result<Range<u32>> {
+        let j = self.find_ji_index(jihai)?;
+        Ok(space.offsets[j] as u32..space.offsets[j + 1] as u32)
+    }
+
+    /// Ranges of the indices of the hands of `space` whose jihai counts satisfy `pred`,
+    /// sorted and with adjacent ranges merged.
+    ///
+    /// For example, `|jihai| jihai[2] > 0` selects the hands with a toitsu of jihai.
+    pub fn jihai_ranges_in<F: Fn(&[u8; 5]) -> bool>(
+        &self,
+        space: &HandSpace,
+        pred: F,
+    ) -> Vec<Range<u32>> {
+        let mut ranges: Vec<Range<u32>> = vec![];
+        let mut jihai = [0u8; 5];
+        for (j, &code) in self.ji_lookup.iter().enumerate() {
+            from_octal(code, &mut jihai);
+            let (start, end) = (space.offsets[j] as u32, space.offsets[j + 1] as u32);
+            if start == end || !pred(&jihai) {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some(last) if last.end == start => last.end = end,
+                _ => ranges.push(start..end),
+            }
+        }
+        ranges
+    }
+
+    /// Like [`HandConverter::jihai_range_in`], also fixing the counts of the outer suit
+    /// to `supai` or its reverse. The outer suit is the one whose encoding comes last in
+    /// `su_lookup` for [`TileSet::Yonma`], and manzu for the other tile sets.
+    ///
+    /// Within the hands of the same jihai, hands are numbered by their outer suit first,
+    /// so they form one range.
+    pub fn outer_range_in(
+        &self,
+        space: &HandSpace,
+        jihai: &[u8; 5],
+        supai: &[u8; 9],
+    ) -> Result<Range<u32>> {
+        let j = self.find_ji_index(jihai)?;
+        ensure!(
+            supai.iter().all(|&v| v <= 4),
+            "More than 4 copies of a supai"
+        );
+        let p = to_octal(supai.iter().map(|&v| v as u32));
+        let q = to_octal(supai.iter().rev().map(|&v| v as u32));
+        let c = self
+            .find_su_index(p.min(q))
+            .ok_or_else(|| anyhow::anyhow!("Too many supai: {:?}", supai))?;
+        let r = &self.ranking;
+        let k = match self.tile_set() {
+            TileSet::Yonma | TileSet::Mini => c,
+            TileSet::Sanma => r
+                .manzu
+                .binary_search(&(c as u32))
+                .map_err(|_| anyhow::anyhow!("Sanma hands have no 2m-8m"))?,
+        };
+        let offset = space.offsets[j];
+        // Empty if the supai do not fit in the hand
+        let range = match space.num_tiles.checked_sub(r.ji_cnt[j] as usize) {
+            Some(t) => offset + r.outer[k][t]..offset + r.outer[k + 1][t],
+            None => offset..offset,
+        };
+        Ok(range.start as u32..range.end as u32)
+    }
+
+    fn find_ji_index(&self, jihai: &[u8; 5]) -> Result<usize> {
+        self.ji_lookup
+            .binary_search(&to_octal(jihai.iter().map(|&v| v as u32)))
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "Invalid jihai counts for {:?}: {:?}",
+                    self.tile_set(),
+                    jihai
+                )
+            })
+    }
+}
+
+/// Canonical hands of one size, indexed like the 13- and 14-tile hands of
+/// [`HandConverter`]. Created by [`HandConverter::hand_space`].
+#[derive(Clone, PartialEq, Debug)]
+pub struct HandSpace {
+    num_tiles: usize,
+    /// `offsets[j]`: number of hands whose jihai index is less than `j`
+    offsets: Vec<u64>,
+}
+
+impl HandSpace {
+    pub fn num_tiles(&self) -> usize {
+        self.num_tiles
+    }
+
+    /// Number of hand indices
+    pub fn len(&self) -> usize {
+        *self.offsets.last().unwrap() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 }
 
 
 pub fn parse_hand_str(s: &str) -> Result<Vec<Tile>> {
+    parse_hand_str_with_aka(s).map(|(tiles, _)| tiles)
+}
+
+/// Parse a hand string that may contain red fives (`0m`, `0p`, `0s`).
+///
+/// Red fives are mapped to the normal five, since the tables do not
+/// distinguish them. Returns the tiles together with the number of red fives.
+pub fn parse_hand_str_with_aka(s: &str) -> Result<(Vec<Tile>, usize)> {
     let mut tiles = Vec::new();
+    let mut aka_count = 0;
     let mut mode = 'z';
     for c in s.chars().rev() {
         match c {
             'm' | 'p' | 's' | 'z' => {mode = c},
+            '0' => {
+                match mode {
+                    'm' => tiles.push(Tile::Supai(0, 4)),
+                    'p' => tiles.push(Tile::Supai(1, 4)),
+                    's' => tiles.push(Tile::Supai(2, 4)),
+                    _ => return Err(anyhow::anyhow!("Red five must be 0m, 0p or 0s")),
+                }
+                aka_count += 1;
+            }
             '1'..='9' => {
                 match mode {
                     'm' => tiles.push(Tile::Supai(0, (c as u8 - '1' as u8) as u8)),
@@ -442,5 +1403,58 @@ pub fn parse_hand_str(s: &str) -> Result<Vec<Tile>> {
             }
         }
     }
-    Ok(tiles)
-}
\ No newline at end of file
+    Ok((tiles, aka_count))
+}
+
+/// Convert a hand in Tenhou's 136-tile numbering to mpsz notation.
+///
+/// Tile ids (`0`-`135`) are separated by commas. `id / 4` is the kind, in the
+/// order 1m-9m, 1p-9p, 1s-9s, 1z-7z, and the copy with `id % 4 == 0` of each
+/// five (ids 16, 52 and 88) is the red five, written as `0`.
+pub fn tenhou_to_mpsz(s: &str) -> Result<String> {
+    let mut ids = Vec::new();
+    for id in s.split(',').map(str::trim).filter(|id| !id.is_empty()) {
+        let id: u8 = id
+            .parse()
+            .ok()
+            .filter(|&id| id < 136)
+            .ok_or_else(|| anyhow::anyhow!("Invalid Tenhou tile id: {}", id))?;
+        if ids.contains(&id) {
+            return Err(anyhow::anyhow!("Duplicate Tenhou tile id: {}", id));
+        }
+        ids.push(id);
+    }
+    ids.sort_unstable();
+
+    let mut res = String::new();
+    for (suit, mark) in ['m', 'p', 's', 'z'].into_iter().enumerate() {
+        let digits: String = ids
+            .iter()
+            .filter(|&&id| (id / 36) as usize == suit)
+            .map(|&id| match (id / 4 % 9, id % 4) {
+                (4, 0) if suit < 3 => '0',
+                (num, _) => (b'1' + num) as char,
+            })
+            .collect();
+        if !digits.is_empty() {
+            res.push_str(&digits);
+            res.push(mark);
+        }
+    }
+    Ok(res)
+}
+
+/// Validate tiles parsed by [`parse_hand_str`] before encoding them.
+///
+/// Checks that every tile is in range, no tile appears more than 4 times,
+/// and the hand consists of 13 or 14 tiles.
+pub fn validate_hand_tiles(tiles: &[Tile]) -> Result<()> {
+    TileMultiset::from_tiles(tiles)?;
+    if tiles.len() != 13 && tiles.len() != 14 {
+        return Err(anyhow::anyhow!(
+            "Hand must contain 13 or 14 tiles, got {}",
+            tiles.len()
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use anyhow::Result;
+
+use crate::mahjong::{parse_hand_str, Tile, TileMultiset};
+
+/// A called set (furo).
+///
+/// Written in a hand string as a bracketed group after the concealed tiles,
+/// e.g. `234m567p33z[c789s][p111z]`. The prefix is `c` for chi, `p` for pon,
+/// `k` for an open kan and `a` for a closed kan (ankan).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Meld {
+    /// Sequence; the tile is the lowest one
+    Chi(Tile),
+    Pon(Tile),
+    Kan(Tile),
+    Ankan(Tile),
+}
+
+impl Meld {
+    /// Tiles taken out of play by this meld
+    pub fn tiles(&self) -> Vec<Tile> {
+        match *self {
+            Meld::Chi(Tile::Supai(suit, num)) => {
+                (num..num + 3).map(|n| Tile::Supai(suit, n)).collect()
+            }
+            Meld::Pon(tile) => vec![tile; 3],
+            Meld::Kan(tile) | Meld::Ankan(tile) => vec![tile; 4],
+            Meld::Chi(_) => panic!("Invalid meld: {:?}", self),
+        }
+    }
+
+    fn parse(s: &str) -> Result<Self> {
+        let mut chars = s.chars();
+        let kind = chars.next().ok_or_else(|| anyhow::anyhow!("Empty meld"))?;
+        let mut tiles = parse_hand_str(chars.as_str())?;
+        // The called tile may be written in any position, e.g. [c879s]
+        tiles.sort_by_key(|tile| match *tile {
+            Tile::Supai(suit, num) => (suit, num),
+            Tile::Jihai(num) => (3, num),
+        });
+        let meld = match (kind, &tiles[..]) {
+            ('c', &[Tile::Supai(suit, num), b, c])
+                if b == Tile::Supai(suit, num + 1) && c == Tile::Supai(suit, num + 2) =>
+            {
+                Meld::Chi(Tile::Supai(suit, num))
+            }
+            ('p', &[a, b, c]) if a == b && b == c => Meld::Pon(a),
+            ('k', &[a, b, c, d]) if a == b && b == c && c == d => Meld::Kan(a),
+            ('a', &[a, b, c, d]) if a == b && b == c && c == d => Meld::Ankan(a),
+            _ => return Err(anyhow::anyhow!("Invalid meld: [{}]", s)),
+        };
+        Ok(meld)
+    }
+}
+
+/// Parse a hand string with called sets, e.g. `234m567p33z[c789s][p111z]`.
+///
+/// Returns the concealed tiles and the melds in the order they were written.
+pub fn parse_hand_str_with_melds(s: &str) -> Result<(Vec<Tile>, Vec<Meld>)> {
+    let (concealed, mut rest) = match s.find('[') {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+    let tiles = parse_hand_str(concealed)?;
+    let mut melds = Vec::new();
+    while !rest.is_empty() {
+        let end = rest
+            .find(']')
+            .filter(|_| rest.starts_with('['))
+            .ok_or_else(|| {
+                anyhow::anyhow!("Melds must be written as [...] after the concealed tiles")
+            })?;
+        melds.push(Meld::parse(&rest[1..end])?);
+        rest = &rest[end + 1..];
+    }
+    Ok((tiles, melds))
+}
+
+/// Replace each closed kan with a triplet so the hand can be looked up in the
+/// closed-hand tables.
+///
+/// Returns `None` if any other meld is present. The result is an
+/// approximation: the tables assume the fourth tile can still be drawn and
+/// ignore the replacement draw.
+pub fn approximate_ankan(concealed: &[Tile], melds: &[Meld]) -> Option<Vec<Tile>> {
+    let mut tiles = concealed.to_vec();
+    for meld in melds {
+        match *meld {
+            Meld::Ankan(tile) => tiles.extend([tile; 3]),
+            _ => return None,
+        }
+    }
+    Some(tiles)
+}
+
+/// Validate the concealed tiles of an open hand before analysing them.
+///
+/// Each meld stands in for three concealed tiles (a kan is followed by a
+/// replacement draw), so the concealed part must have 13 or 14 tiles minus
+/// three per meld. No tile may appear more than 4 times across the concealed
+/// tiles and the melds.
+pub fn validate_open_hand(concealed: &[Tile], melds: &[Meld]) -> Result<()> {
+    let mut tiles = TileMultiset::from_tiles(concealed)?;
+    for tile in melds.iter().flat_map(Meld::tiles) {
+        tiles.insert(tile)?;
+    }
+    if melds.len() > 4 {
+        return Err(anyhow::anyhow!(
+            "A hand can have at most 4 melds, got {}",
+            melds.len()
+        ));
+    }
+    let expected = 13 - 3 * melds.len();
+    if concealed.len() != expected && concealed.len() != expected + 1 {
+        return Err(anyhow::anyhow!(
+            "Hand with {} melds must contain {} or {} concealed tiles, got {}",
+            melds.len(),
+            expected,
+            expected + 1,
+            concealed.len()
+        ));
+    }
+    Ok(())
+}
@@ -1,9 +1,40 @@
 // Mahjong types and metrics
 pub mod types;
 pub mod hand;
+pub mod shanten;
+pub mod meld;
+pub mod agari;
+pub mod score;
+pub mod multiset;
+pub mod random;
+pub mod translation;
+pub mod tile_counts;
+pub mod dora;
+mod raw_file;
 
 // Re-export commonly used types from types module
-pub use types::{Tile, Dimension, Metrics, NUM_ROUNDS};
+pub use types::{Tile, Dimension, Metrics, NUM_ROUNDS, TURNS_SCALE};
 
 // Re-export everything from hand module for backward compatibility
-pub use hand::*; 
\ No newline at end of file
+pub use hand::*;
+
+pub use shanten::{shanten, ukeire, ukeire_count, Shanten};
+
+pub use meld::{approximate_ankan, parse_hand_str_with_melds, validate_open_hand, Meld};
+
+pub use agari::{agari_decompositions, decompose_agari, for_each_agari, is_agari, AgariFamily};
+
+pub use score::{base_points, count_fu, payment, Payment, WinContext};
+
+pub use multiset::TileMultiset;
+
+pub use random::{random_hand, RandomHandGenerator};
+
+pub use translation::Translation;
+
+pub use tile_counts::TileCounts;
+
+pub use dora::{
+    dimension_dora_count, dora_count, dora_from_indicator, parse_dora_indicators,
+    MAX_DORA_INDICATORS,
+};
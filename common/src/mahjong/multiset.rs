@@ -0,0 +1,172 @@
+use anyhow::Result;
+
+use crate::mahjong::{Hand, Tile};
+
+const NUM_KINDS: usize = 34;
+
+/// A multiset of concrete tiles with at most 4 copies of each tile.
+///
+/// Unlike [`Hand`], honors are kept per tile. Converting to a `Hand` forgets
+/// which honors are held, and converting from a `Hand` numbers them from `1z`
+/// in descending order of count as [`Hand::to_mpsz_string`] does.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TileMultiset {
+    counts: [u8; NUM_KINDS],
+    len: usize,
+}
+
+impl Default for TileMultiset {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TileMultiset {
+    pub fn new() -> Self {
+        Self {
+            counts: [0; NUM_KINDS],
+            len: 0,
+        }
+    }
+
+    /// Collect tiles, rejecting out-of-range tiles and a fifth copy of a tile.
+    pub fn from_tiles(tiles: &[Tile]) -> Result<Self> {
+        let mut multiset = Self::new();
+        for &tile in tiles {
+            multiset.insert(tile)?;
+        }
+        Ok(multiset)
+    }
+
+    /// Add one copy of `tile`; the multiset is unchanged on error.
+    pub fn insert(&mut self, tile: Tile) -> Result<()> {
+        let cnt = &mut self.counts[index(tile)?];
+        if *cnt >= 4 {
+            return Err(anyhow::anyhow!("Too many copies of tile: {:?}", tile));
+        }
+        *cnt += 1;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Remove one copy of `tile`; the multiset is unchanged on error.
+    pub fn remove(&mut self, tile: Tile) -> Result<()> {
+        let cnt = &mut self.counts[index(tile)?];
+        if *cnt == 0 {
+            return Err(anyhow::anyhow!("No copy of tile to remove: {:?}", tile));
+        }
+        *cnt -= 1;
+        self.len -= 1;
+        Ok(())
+    }
+
+    /// Number of copies of `tile`, 0 for an out-of-range tile
+    pub fn count(&self, tile: Tile) -> u8 {
+        index(tile).map_or(0, |i| self.counts[i])
+    }
+
+    /// Total number of tiles
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Each tile held with its count, in m/p/s/z order
+    pub fn iter(&self) -> impl Iterator<Item = (Tile, u8)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &cnt)| cnt > 0)
+            .map(|(i, &cnt)| (tile(i), cnt))
+    }
+
+    /// All tiles sorted in m/p/s/z order
+    pub fn to_tiles(&self) -> Vec<Tile> {
+        self.iter()
+            .flat_map(|(tile, cnt)| std::iter::repeat_n(tile, cnt as usize))
+            .collect()
+    }
+
+    pub fn to_hand(&self) -> Hand {
+        let mut hand = Hand::new();
+        for (i, &cnt) in self.counts.iter().enumerate() {
+            match tile(i) {
+                Tile::Supai(suit, num) => hand.supai[suit as usize][num as usize] = cnt,
+                Tile::Jihai(_) => {
+                    hand.jihai[0] -= 1;
+                    hand.jihai[cnt as usize] += 1;
+                }
+            }
+        }
+        hand
+    }
+
+    /// Convert a `Hand`, checking the counts and that its honor counts cover
+    /// exactly the 7 honors.
+    pub fn from_hand(hand: &Hand) -> Result<Self> {
+        if hand.jihai.iter().map(|&v| v as usize).sum::<usize>() != 7 {
+            return Err(anyhow::anyhow!("Invalid jihai counts: {:?}", hand.jihai));
+        }
+        let mut multiset = Self::new();
+        for tile in hand.tiles() {
+            multiset.insert(tile)?;
+        }
+        Ok(multiset)
+    }
+}
+
+impl TryFrom<&[Tile]> for TileMultiset {
+    type Error = anyhow::Error;
+
+    fn try_from(tiles: &[Tile]) -> Result<Self> {
+        Self::from_tiles(tiles)
+    }
+}
+
+impl TryFrom<Vec<Tile>> for TileMultiset {
+    type Error = anyhow::Error;
+
+    fn try_from(tiles: Vec<Tile>) -> Result<Self> {
+        Self::from_tiles(&tiles)
+    }
+}
+
+impl TryFrom<&Hand> for TileMultiset {
+    type Error = anyhow::Error;
+
+    fn try_from(hand: &Hand) -> Result<Self> {
+        Self::from_hand(hand)
+    }
+}
+
+impl From<TileMultiset> for Vec<Tile> {
+    fn from(multiset: TileMultiset) -> Self {
+        multiset.to_tiles()
+    }
+}
+
+impl From<&TileMultiset> for Hand {
+    fn from(multiset: &TileMultiset) -> Self {
+        multiset.to_hand()
+    }
+}
+
+pub(crate) fn index(tile: Tile) -> Result<usize> {
+    match tile {
+        Tile::Supai(suit, num) if suit < 3 && num < 9 => Ok(suit as usize * 9 + num as usize),
+        Tile::Jihai(num) if num < 7 => Ok(27 + num as usize),
+        _ => Err(anyhow::anyhow!("Invalid tile: {:?}", tile)),
+    }
+}
+
+/// The tile at `index` in m/p/s/z order
+pub(crate) fn tile(index: usize) -> Tile {
+    if index < 27 {
+        Tile::Supai((index / 9) as u8, (index % 9) as u8)
+    } else {
+        Tile::Jihai((index - 27) as u8)
+    }
+}
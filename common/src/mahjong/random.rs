@@ -0,0 +1,113 @@
+use anyhow::Result;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::mahjong::{multiset, shanten, Hand, TileMultiset};
+
+/// Number of hands drawn before giving up on a shanten constraint
+pub const MAX_ATTEMPTS: usize = 200_000;
+
+const NUM_KINDS: usize = 34;
+
+/// Draws hands uniformly from all legal hands of a given size.
+///
+/// Every multiset of tiles with at most 4 copies of each tile is equally
+/// likely, so the distribution differs from dealing tiles off a shuffled wall,
+/// which favors hands with many distinct tiles.
+pub struct RandomHandGenerator<R: Rng> {
+    rng: R,
+    num_tiles: usize,
+    /// `ways[k][r]`: number of ways to hold `r` tiles using only tiles `k..34`
+    ways: Vec<[u64; 15]>,
+}
+
+impl RandomHandGenerator<StdRng> {
+    /// A generator whose sequence of hands is fixed by `seed`
+    pub fn from_seed(seed: u64, num_tiles: usize) -> Result<Self> {
+        Self::new(StdRng::seed_from_u64(seed), num_tiles)
+    }
+}
+
+impl<R: Rng> RandomHandGenerator<R> {
+    pub fn new(rng: R, num_tiles: usize) -> Result<Self> {
+        if num_tiles != 13 && num_tiles != 14 {
+            return Err(anyhow::anyhow!(
+                "Hand must contain 13 or 14 tiles, got {}",
+                num_tiles
+            ));
+        }
+        let mut ways = vec![[0u64; 15]; NUM_KINDS + 1];
+        ways[NUM_KINDS][0] = 1;
+        for k in (0..NUM_KINDS).rev() {
+            for r in 0..=num_tiles {
+                ways[k][r] = (0..=r.min(4)).map(|c| ways[k + 1][r - c]).sum();
+            }
+        }
+        Ok(Self {
+            rng,
+            num_tiles,
+            ways,
+        })
+    }
+
+    /// Draw one hand
+    pub fn generate(&mut self) -> TileMultiset {
+        let mut hand = TileMultiset::new();
+        let mut rest = self.num_tiles;
+        for k in 0..NUM_KINDS {
+            // Pick the count of tile k in proportion to the ways to fill the rest
+            let mut x = self.rng.gen_range(0..self.ways[k][rest]);
+            for c in 0..=rest.min(4) {
+                let w = self.ways[k + 1][rest - c];
+                if x < w {
+                    let tile = multiset::tile(k);
+                    for _ in 0..c {
+                        hand.insert(tile).expect("at most 4 copies");
+                    }
+                    rest -= c;
+                    break;
+                }
+                x -= w;
+            }
+        }
+        hand
+    }
+
+    /// Draw hands until one has the given minimum shanten (-1 for a winning
+    /// 14-tile hand), which keeps the draw uniform over those hands.
+    ///
+    /// Fails after [`MAX_ATTEMPTS`] draws; low shanten, especially winning
+    /// hands, are rare among uniform hands.
+    pub fn generate_with_shanten(&mut self, target: i8) -> Result<TileMultiset> {
+        let lowest = if self.num_tiles == 14 { -1 } else { 0 };
+        if !(lowest..=6).contains(&target) {
+            return Err(anyhow::anyhow!(
+                "Shanten of a {}-tile hand must be between {} and 6, got {}",
+                self.num_tiles,
+                lowest,
+                target
+            ));
+        }
+        for _ in 0..MAX_ATTEMPTS {
+            let hand = self.generate();
+            if shanten(&Hand::from(&hand)).min() == target {
+                return Ok(hand);
+            }
+        }
+        Err(anyhow::anyhow!(
+            "No {}-tile hand with shanten {} found in {} attempts",
+            self.num_tiles,
+            target,
+            MAX_ATTEMPTS
+        ))
+    }
+}
+
+/// Draw a legal hand of 13 or 14 tiles determined by `seed`, optionally with
+/// the given minimum shanten. See [`RandomHandGenerator`].
+pub fn random_hand(seed: u64, num_tiles: usize, shanten: Option<i8>) -> Result<TileMultiset> {
+    let mut generator = RandomHandGenerator::from_seed(seed, num_tiles)?;
+    match shanten {
+        Some(target) => generator.generate_with_shanten(target),
+        None => Ok(generator.generate()),
+    }
+}
@@ -0,0 +1,224 @@
+use std::{
+    fs::{create_dir_all, rename, File},
+    io::{BufWriter, Write},
+    mem::{align_of, size_of, size_of_val},
+    ops::Deref,
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Result};
+use memmap2::Mmap;
+
+// Raw files are little-endian flat files read in place through mmap.
+//
+// Layout:
+// * 8 bytes: magic
+// * u64: number of sections `n`
+// * `n` x u64: number of elements of each section
+// * the sections in order, each starting at a multiple of 8 bytes
+
+const SECTION_ALIGN: usize = 8;
+
+/// Plain data stored as is in a raw file
+///
+/// # Safety
+/// The type must have no padding, every bit pattern must be a valid value and
+/// its alignment must be at most `SECTION_ALIGN`, so that a little-endian
+/// section can be used in place as a slice of it.
+pub(crate) unsafe trait RawElement: Copy {
+    fn write_le<W: Write>(&self, writer: &mut W) -> Result<()>;
+    fn read_le(bytes: &[u8]) -> Self;
+}
+
+macro_rules! impl_raw_element {
+    ($($t:ty),*) => {
+        $(
+            unsafe impl RawElement for $t {
+                fn write_le<W: Write>(&self, writer: &mut W) -> Result<()> {
+                    writer.write_all(&self.to_le_bytes())?;
+                    Ok(())
+                }
+
+                fn read_le(bytes: &[u8]) -> Self {
+                    Self::from_le_bytes(bytes[..size_of::<Self>()].try_into().unwrap())
+                }
+            }
+        )*
+    };
+}
+
+impl_raw_element!(u8, u32, u64);
+
+unsafe impl<T: RawElement, const N: usize> RawElement for [T; N] {
+    fn write_le<W: Write>(&self, writer: &mut W) -> Result<()> {
+        for v in self {
+            v.write_le(writer)?;
+        }
+        Ok(())
+    }
+
+    fn read_le(bytes: &[u8]) -> Self {
+        core::array::from_fn(|i| T::read_le(&bytes[i * size_of::<T>()..]))
+    }
+}
+
+/// A table owned in memory or used in place in a memory-mapped raw file
+pub(crate) enum Column<T> {
+    Owned(Vec<T>),
+    Mapped {
+        mmap: Arc<Mmap>,
+        offset: usize,
+        len: usize,
+    },
+}
+
+impl<T> From<Vec<T>> for Column<T> {
+    fn from(v: Vec<T>) -> Self {
+        Column::Owned(v)
+    }
+}
+
+impl<T: RawElement> Deref for Column<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self {
+            Column::Owned(v) => v,
+            // SAFETY: `RawReader::next` only maps aligned sections within the file
+            // on little-endian targets, and `T` is plain data
+            Column::Mapped { mmap, offset, len } => unsafe {
+                std::slice::from_raw_parts(mmap.as_ptr().add(*offset) as *const T, *len)
+            },
+        }
+    }
+}
+
+impl<T: RawElement + PartialEq> PartialEq for Column<T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: RawElement + std::fmt::Debug> std::fmt::Debug for Column<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+fn padding(offset: usize) -> usize {
+    (SECTION_ALIGN - offset % SECTION_ALIGN) % SECTION_ALIGN
+}
+
+/// Writes a raw file section by section
+pub(crate) struct RawWriter {
+    writer: BufWriter<File>,
+    tempname: String,
+    filename: String,
+    offset: usize,
+}
+
+impl RawWriter {
+    /// Write the header of a raw file whose sections have `lens` elements each
+    pub fn create<P: AsRef<Path>>(filename: P, magic: &[u8; 8], lens: &[usize]) -> Result<Self> {
+        let filename = filename.as_ref().to_str().unwrap().to_string();
+        let tempname = filename.clone() + ".temp";
+        create_dir_all(Path::new(&tempname).parent().unwrap())?;
+        let mut writer = BufWriter::new(File::create(&tempname)?);
+        writer.write_all(magic)?;
+        (lens.len() as u64).write_le(&mut writer)?;
+        for &len in lens {
+            (len as u64).write_le(&mut writer)?;
+        }
+        Ok(RawWriter {
+            writer,
+            tempname,
+            filename,
+            offset: 8 * (lens.len() + 2),
+        })
+    }
+
+    /// Write the next section
+    pub fn write<T: RawElement>(&mut self, section: &[T]) -> Result<()> {
+        for v in section {
+            v.write_le(&mut self.writer)?;
+        }
+        self.offset += size_of_val(section);
+        let pad = padding(self.offset);
+        self.writer.write_all(&[0u8; SECTION_ALIGN][..pad])?;
+        self.offset += pad;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        self.writer.into_inner()?.sync_all()?;
+        rename(self.tempname, self.filename)?;
+        Ok(())
+    }
+}
+
+/// Reads the sections of a memory-mapped raw file in order
+pub(crate) struct RawReader {
+    mmap: Arc<Mmap>,
+    lens: Vec<usize>,
+    section: usize,
+    offset: usize,
+}
+
+impl RawReader {
+    /// Map a raw file, checking its magic and that it has `num_sections` sections
+    pub fn open<P: AsRef<Path>>(filename: P, magic: &[u8; 8], num_sections: usize) -> Result<Self> {
+        let file = File::open(filename)?;
+        // SAFETY: the file must not be modified while mapped, like the DP tables
+        let mmap = unsafe { Mmap::map(&file)? };
+        if !has_magic(&mmap, magic) {
+            bail!("Not a raw file: wrong magic");
+        }
+        let header_len = 8 * (num_sections + 2);
+        if mmap.len() < header_len || u64::read_le(&mmap[8..]) != num_sections as u64 {
+            bail!("Raw file must have {} sections", num_sections);
+        }
+        let lens = (0..num_sections)
+            .map(|i| u64::read_le(&mmap[8 * (i + 2)..]) as usize)
+            .collect();
+        Ok(RawReader {
+            mmap: Arc::new(mmap),
+            lens,
+            section: 0,
+            offset: header_len,
+        })
+    }
+
+    /// The next section, used in place if the target is little-endian
+    pub fn next<T: RawElement>(&mut self) -> Result<Column<T>> {
+        let len = *self
+            .lens
+            .get(self.section)
+            .ok_or_else(|| anyhow!("No more sections in raw file"))?;
+        let size = len
+            .checked_mul(size_of::<T>())
+            .filter(|&size| self.offset + size <= self.mmap.len())
+            .ok_or_else(|| anyhow!("Raw file is truncated at section {}", self.section))?;
+        let offset = self.offset;
+        self.section += 1;
+        self.offset += size + padding(size);
+
+        if cfg!(target_endian = "little") && align_of::<T>() <= SECTION_ALIGN {
+            Ok(Column::Mapped {
+                mmap: self.mmap.clone(),
+                offset,
+                len,
+            })
+        } else {
+            let bytes = &self.mmap[offset..offset + size];
+            Ok(Column::Owned(
+                bytes.chunks_exact(size_of::<T>()).map(T::read_le).collect(),
+            ))
+        }
+    }
+}
+
+/// Whether `bytes` starts with `magic`
+pub(crate) fn has_magic(bytes: &[u8], magic: &[u8; 8]) -> bool {
+    bytes.get(..8) == Some(magic.as_slice())
+}
@@ -0,0 +1,171 @@
+use crate::mahjong::{Dimension, Meld, Tile};
+
+/// How and where a hand was won
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WinContext {
+    /// The tile that completed the hand; it must be one of the concealed tiles
+    pub winning_tile: Tile,
+    /// Self-drawn (tsumo) rather than ron
+    pub tsumo: bool,
+    /// Seat wind; east is the dealer
+    pub seat: Tile,
+    /// Round wind
+    pub round: Tile,
+    /// Repeat counter (honba), 300 points per counter
+    pub honba: u32,
+}
+
+impl WinContext {
+    pub fn is_dealer(&self) -> bool {
+        self.seat == Tile::east()
+    }
+}
+
+/// Points paid for a win, including honba
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Payment {
+    /// Paid in full by the player who discarded the winning tile
+    Ron(u32),
+    /// Dealer tsumo; each of the three other players pays this much
+    DealerTsumo(u32),
+    /// Non-dealer tsumo
+    Tsumo { dealer: u32, non_dealer: u32 },
+}
+
+impl Payment {
+    /// Points received by the winner
+    pub fn total(&self) -> u32 {
+        match *self {
+            Payment::Ron(points) => points,
+            Payment::DealerTsumo(points) => 3 * points,
+            Payment::Tsumo { dealer, non_dealer } => dealer + 2 * non_dealer,
+        }
+    }
+}
+
+/// Fu of a winning hand, rounded up to a multiple of 10.
+///
+/// `sets` is one decomposition of the concealed tiles from
+/// [`decompose_agari`](crate::mahjong::decompose_agari). When the winning tile
+/// fits more than one set the reading with the most fu is used. Seven pairs
+/// are always 25 fu; thirteen orphans are a yakuman and have no fu, so 0 is
+/// returned for them.
+pub fn count_fu(sets: &[Dimension], melds: &[Meld], ctx: &WinContext) -> u32 {
+    if sets.contains(&Dimension::Kokushi) {
+        return 0;
+    }
+    if sets.len() == 7 {
+        return 25;
+    }
+    let closed = melds.iter().all(|meld| matches!(meld, Meld::Ankan(_)));
+
+    let meld_fu: u32 = melds
+        .iter()
+        .map(|meld| match *meld {
+            Meld::Chi(_) => 0,
+            Meld::Pon(tile) => triplet_fu(tile, true),
+            Meld::Kan(tile) => 4 * triplet_fu(tile, true),
+            Meld::Ankan(tile) => 4 * triplet_fu(tile, false),
+        })
+        .sum();
+
+    let fu = sets
+        .iter()
+        .enumerate()
+        .filter(|(_, set)| contains(set, ctx.winning_tile))
+        .map(|(winning_set, _)| {
+            let mut fu = meld_fu;
+            for (i, set) in sets.iter().enumerate() {
+                fu += match *set {
+                    // A triplet completed by ron counts as open
+                    Dimension::Kotsu(tile) => triplet_fu(tile, i == winning_set && !ctx.tsumo),
+                    Dimension::Toitsu(tile) => pair_fu(tile, ctx),
+                    _ => 0,
+                };
+            }
+            fu + wait_fu(sets[winning_set], ctx.winning_tile)
+        })
+        .max()
+        .unwrap_or(0);
+
+    let fu = match (closed, ctx.tsumo) {
+        // Pinfu tsumo gets no tsumo fu
+        (true, true) if fu == 0 => 20,
+        (true, true) => 20 + fu + 2,
+        (true, false) => 30 + fu,
+        (false, true) => 20 + fu + 2,
+        // An open hand without fu is rounded to 30
+        (false, false) => (20 + fu).max(30),
+    };
+    fu.div_ceil(10) * 10
+}
+
+/// Base points (fu × 2^(han + 2)) with the mangan and higher limits.
+///
+/// 13 han or more is counted as a (kazoe) yakuman.
+pub fn base_points(han: u32, fu: u32) -> u32 {
+    match han {
+        0..=4 => (fu << (han + 2)).min(2000),
+        5 => 2000,
+        6 | 7 => 3000,
+        8..=10 => 4000,
+        11 | 12 => 6000,
+        _ => 8000,
+    }
+}
+
+/// Points paid for a hand with `han` and `fu`, each payment rounded up to 100.
+pub fn payment(han: u32, fu: u32, ctx: &WinContext) -> Payment {
+    let base = base_points(han, fu);
+    let pay = |multiplier: u32| (base * multiplier).div_ceil(100) * 100;
+    match (ctx.tsumo, ctx.is_dealer()) {
+        (false, true) => Payment::Ron(pay(6) + 300 * ctx.honba),
+        (false, false) => Payment::Ron(pay(4) + 300 * ctx.honba),
+        (true, true) => Payment::DealerTsumo(pay(2) + 100 * ctx.honba),
+        (true, false) => Payment::Tsumo {
+            dealer: pay(2) + 100 * ctx.honba,
+            non_dealer: pay(1) + 100 * ctx.honba,
+        },
+    }
+}
+
+fn contains(set: &Dimension, tile: Tile) -> bool {
+    match (*set, tile) {
+        (Dimension::Shuntsu(Tile::Supai(suit, num)), Tile::Supai(s, n)) => {
+            suit == s && (num..num + 3).contains(&n)
+        }
+        (Dimension::Kotsu(t) | Dimension::Toitsu(t), tile) => t == tile,
+        _ => false,
+    }
+}
+
+/// Fu of a triplet; kans are four times this
+fn triplet_fu(tile: Tile, open: bool) -> u32 {
+    match (tile.is_yaochuu(), open) {
+        (false, true) => 2,
+        (false, false) | (true, true) => 4,
+        (true, false) => 8,
+    }
+}
+
+/// 2 fu for each of dragon, seat wind and round wind, so a double wind pair is 4
+fn pair_fu(tile: Tile, ctx: &WinContext) -> u32 {
+    2 * (tile.is_dragon() as u32 + (tile == ctx.seat) as u32 + (tile == ctx.round) as u32)
+}
+
+/// 2 fu for a closed (kanchan), edge (penchan) or pair (tanki) wait
+fn wait_fu(winning_set: Dimension, winning_tile: Tile) -> u32 {
+    match (winning_set, winning_tile) {
+        (Dimension::Shuntsu(Tile::Supai(_, num)), Tile::Supai(_, n)) => {
+            let kanchan = n == num + 1;
+            let penchan = (num == 0 && n == 2) || (num == 6 && n == 6);
+            if kanchan || penchan {
+                2
+            } else {
+                0
+            }
+        }
+        (Dimension::Toitsu(_), _) => 2,
+        _ => 0,
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::mahjong::{Hand, Tile};
+
+/// 向聴数。和了形は-1、聴牌は0
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shanten {
+    /// 4面子1雀頭
+    pub standard: i8,
+    /// 七対子
+    pub chiitoitsu: i8,
+    /// 国士無双
+    pub kokushi: i8,
+}
+
+impl Shanten {
+    /// 3種類のうち最小の向聴数
+    pub fn min(&self) -> i8 {
+        self.standard.min(self.chiitoitsu).min(self.kokushi)
+    }
+}
+
+/// 手牌の向聴数を計算する
+///
+/// 字牌は種類ごとの枚数分布しか持たないが、向聴数は字牌の種類に依存しないためこれで十分である。
+pub fn shanten(hand: &Hand) -> Shanten {
+    Shanten {
+        standard: standard_shanten(hand),
+        chiitoitsu: chiitoitsu_shanten(hand),
+        kokushi: kokushi_shanten(hand),
+    }
+}
+
+/// 有効牌の枚数（ツモると最小の向聴数が下がる牌の、手牌にない残り枚数の合計）
+///
+/// 14枚の手牌なら、向聴数を保つ打牌のうち有効牌が最も多いものの枚数を返す。和了形なら0。
+pub fn ukeire_count(hand: &Hand) -> u32 {
+    if hand.num_tiles() % 3 != 2 {
+        return draw_ukeire(hand);
+    }
+    let current = shanten(hand).min();
+    let mut best = 0;
+    hand.clone().for_each_discard_hand(|discarded, _| {
+        if shanten(discarded).min() == current {
+            best = best.max(draw_ukeire(discarded));
+        }
+    });
+    best
+}
+
+/// 有効牌（ツモると最小の向聴数が下がる牌）と、手牌にない残り枚数の一覧
+///
+/// 13枚（3n+1枚）の手牌を想定する。萬子・筒子・索子・字牌の順に並べて返す。
+pub fn ukeire(tiles: &[Tile]) -> Vec<(Tile, u8)> {
+    let current = shanten(&Hand::from_tiles(tiles)).min();
+    let all_tiles = (0..3)
+        .flat_map(|suit| (0..9).map(move |num| Tile::Supai(suit, num)))
+        .chain((0..7).map(Tile::Jihai));
+
+    let mut drawn = tiles.to_vec();
+    let mut res = Vec::new();
+    for tile in all_tiles {
+        let cnt = tiles.iter().filter(|&&t| t == tile).count() as u8;
+        if cnt >= 4 {
+            continue;
+        }
+        drawn.push(tile);
+        if shanten(&Hand::from_tiles(&drawn)).min() < current {
+            res.push((tile, 4 - cnt));
+        }
+        drawn.pop();
+    }
+    res
+}
+
+fn draw_ukeire(hand: &Hand) -> u32 {
+    let current = shanten(hand).min();
+    let mut count = 0;
+    hand.clone().for_each_draw_hand(|drawn, remaining| {
+        if shanten(drawn).min() < current {
+            count += remaining as u32;
+        }
+    });
+    count
+}
+
+/// (面子数, 塔子数, 雀頭の有無)
+type Block = (u8, u8, bool);
+
+fn standard_shanten(hand: &Hand) -> i8 {
+    let max_blocks = (hand.num_tiles() / 3) as u8;
+
+    // 字牌は順子を作れないので、刻子・対子の数だけで決まる
+    let ji_mentsu = hand.jihai[3] + hand.jihai[4];
+    let ji_pairs = hand.jihai[2];
+    let mut candidates: Vec<Block> = vec![(ji_mentsu, ji_pairs, false)];
+    if ji_pairs > 0 {
+        candidates.push((ji_mentsu, ji_pairs - 1, true));
+    }
+
+    for counts in hand.supai.iter() {
+        let suit_blocks = suit_blocks(counts);
+
+        let mut next = Vec::with_capacity(candidates.len() * suit_blocks.len());
+        for &(m1, t1, h1) in candidates.iter() {
+            for &(m2, t2, h2) in suit_blocks.iter() {
+                if h1 && h2 {
+                    continue;
+                }
+                next.push((m1 + m2, t1 + t2, h1 || h2));
+            }
+        }
+        next.sort_unstable();
+        next.dedup();
+        candidates = next;
+    }
+
+    candidates
+        .into_iter()
+        .map(|(m, t, h)| {
+            let m = m.min(max_blocks);
+            let t = t.min(max_blocks - m);
+            8 - 2 * m as i8 - t as i8 - h as i8
+        })
+        .min()
+        .unwrap_or(8)
+}
+
+/// 1種類の数牌について、取りうる(面子数, 塔子数, 雀頭の有無)を列挙する
+fn suit_blocks(counts: &[u8; 9]) -> Vec<Block> {
+    let mut memo = HashMap::new();
+    let mut counts = *counts;
+    let mut blocks = search_suit(&mut counts, 0, &mut memo);
+    blocks.sort_unstable();
+    blocks.dedup();
+    blocks
+}
+
+fn search_suit(
+    counts: &mut [u8; 9],
+    mut i: usize,
+    memo: &mut HashMap<([u8; 9], usize), Vec<Block>>,
+) -> Vec<Block> {
+    while i < 9 && counts[i] == 0 {
+        i += 1;
+    }
+    if i >= 9 {
+        return vec![(0, 0, false)];
+    }
+    if let Some(blocks) = memo.get(&(*counts, i)) {
+        return blocks.clone();
+    }
+
+    let mut blocks = Vec::new();
+    let mut recurse = |counts: &mut [u8; 9], add: Block, blocks: &mut Vec<Block>| {
+        for (m, t, h) in search_suit(counts, i, memo) {
+            if h && add.2 {
+                continue;
+            }
+            blocks.push((m + add.0, t + add.1, h || add.2));
+        }
+    };
+
+    // 刻子
+    if counts[i] >= 3 {
+        counts[i] -= 3;
+        recurse(counts, (1, 0, false), &mut blocks);
+        counts[i] += 3;
+    }
+    // 順子
+    if i < 7 && counts[i + 1] > 0 && counts[i + 2] > 0 {
+        counts[i] -= 1;
+        counts[i + 1] -= 1;
+        counts[i + 2] -= 1;
+        recurse(counts, (1, 0, false), &mut blocks);
+        counts[i] += 1;
+        counts[i + 1] += 1;
+        counts[i + 2] += 1;
+    }
+    // 対子（雀頭または塔子）
+    if counts[i] >= 2 {
+        counts[i] -= 2;
+        recurse(counts, (0, 0, true), &mut blocks);
+        recurse(counts, (0, 1, false), &mut blocks);
+        counts[i] += 2;
+    }
+    // 両面・辺張、嵌張
+    for d in 1..=2 {
+        if i + d < 9 && counts[i + d] > 0 {
+            counts[i] -= 1;
+            counts[i + d] -= 1;
+            recurse(counts, (0, 1, false), &mut blocks);
+            counts[i] += 1;
+            counts[i + d] += 1;
+        }
+    }
+    // 孤立牌として使わない
+    counts[i] -= 1;
+    recurse(counts, (0, 0, false), &mut blocks);
+    counts[i] += 1;
+
+    blocks.sort_unstable();
+    blocks.dedup();
+    memo.insert((*counts, i), blocks.clone());
+    blocks
+}
+
+fn chiitoitsu_shanten(hand: &Hand) -> i8 {
+    let mut kinds = 7 - hand.jihai[0] as i8;
+    let mut pairs = hand.jihai[2..].iter().map(|&v| v as i8).sum::<i8>();
+    for counts in hand.supai.iter() {
+        for &cnt in counts.iter() {
+            if cnt > 0 {
+                kinds += 1;
+            }
+            if cnt >= 2 {
+                pairs += 1;
+            }
+        }
+    }
+    6 - pairs + (7 - kinds).max(0)
+}
+
+fn kokushi_shanten(hand: &Hand) -> i8 {
+    let mut kinds = 7 - hand.jihai[0] as i8;
+    let mut has_pair = hand.jihai[2..].iter().any(|&v| v > 0);
+    for counts in hand.supai.iter() {
+        for num in [0, 8] {
+            if counts[num] > 0 {
+                kinds += 1;
+            }
+            if counts[num] >= 2 {
+                has_pair = true;
+            }
+        }
+    }
+    13 - kinds - has_pair as i8
+}
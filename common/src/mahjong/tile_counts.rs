@@ -0,0 +1,150 @@
+use anyhow::Result;
+
+use crate::mahjong::{multiset, parse_hand_str_with_melds, Meld, Tile, TileMultiset};
+
+const NUM_KINDS: usize = 34;
+
+/// Remaining copies of each of the 34 tiles, e.g. the tiles not yet seen in
+/// the wall or in the other players' hands.
+///
+/// Every count is between 0 and 4. Red fives are counted as normal fives.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TileCounts {
+    counts: [u8; NUM_KINDS],
+}
+
+impl Default for TileCounts {
+    fn default() -> Self {
+        Self::full()
+    }
+}
+
+impl TileCounts {
+    /// All 136 tiles, 4 copies of each
+    pub fn full() -> Self {
+        Self {
+            counts: [4; NUM_KINDS],
+        }
+    }
+
+    /// No tiles at all
+    pub fn empty() -> Self {
+        Self {
+            counts: [0; NUM_KINDS],
+        }
+    }
+
+    /// Counts in m/p/s/z order, rejecting a count above 4.
+    pub fn from_counts(counts: [u8; NUM_KINDS]) -> Result<Self> {
+        let tile_counts = Self { counts };
+        tile_counts.validate()?;
+        Ok(tile_counts)
+    }
+
+    /// The tiles remaining after `visible` tiles have been seen.
+    pub fn from_visible(visible: &[Tile]) -> Result<Self> {
+        let mut tile_counts = Self::full();
+        tile_counts.remove_all(visible)?;
+        Ok(tile_counts)
+    }
+
+    /// The tiles remaining after the tiles of a hand string have been seen,
+    /// e.g. `1m9p55z[p777s]` for discards plus a called pon. Red fives
+    /// (`0m`, `0p`, `0s`) count as normal fives.
+    pub fn from_visible_str(visible: &str) -> Result<Self> {
+        let (mut tiles, melds) = parse_hand_str_with_melds(visible)?;
+        tiles.extend(melds.iter().flat_map(Meld::tiles));
+        Self::from_visible(&tiles)
+    }
+
+    /// Check that every count is at most 4
+    pub fn validate(&self) -> Result<()> {
+        match self.counts.iter().position(|&cnt| cnt > 4) {
+            Some(i) => Err(anyhow::anyhow!(
+                "Too many copies of tile: {:?} ({})",
+                multiset::tile(i),
+                self.counts[i]
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Remaining copies of `tile`, 0 for an out-of-range tile
+    pub fn get(&self, tile: Tile) -> u8 {
+        multiset::index(tile).map_or(0, |i| self.counts[i])
+    }
+
+    /// Total number of remaining tiles
+    pub fn total(&self) -> usize {
+        self.counts.iter().map(|&cnt| cnt as usize).sum()
+    }
+
+    /// Take one copy of `tile`; unchanged on error.
+    pub fn remove(&mut self, tile: Tile) -> Result<()> {
+        let cnt = &mut self.counts[multiset::index(tile)?];
+        if *cnt == 0 {
+            return Err(anyhow::anyhow!("No copy of tile left: {:?}", tile));
+        }
+        *cnt -= 1;
+        Ok(())
+    }
+
+    /// Put back one copy of `tile`; unchanged on error.
+    pub fn insert(&mut self, tile: Tile) -> Result<()> {
+        let cnt = &mut self.counts[multiset::index(tile)?];
+        if *cnt >= 4 {
+            return Err(anyhow::anyhow!("Too many copies of tile: {:?}", tile));
+        }
+        *cnt += 1;
+        Ok(())
+    }
+
+    /// Take one copy of each of `tiles`; unchanged on error.
+    pub fn remove_all(&mut self, tiles: &[Tile]) -> Result<()> {
+        let mut counts = *self;
+        for &tile in tiles {
+            counts.remove(tile)?;
+        }
+        *self = counts;
+        Ok(())
+    }
+
+    /// Sum of the counts, failing if a count exceeds 4.
+    pub fn checked_add(&self, other: &TileCounts) -> Result<TileCounts> {
+        let mut counts = [0; NUM_KINDS];
+        for (i, cnt) in counts.iter_mut().enumerate() {
+            *cnt = self.counts[i] + other.counts[i];
+        }
+        Self::from_counts(counts)
+    }
+
+    /// Difference of the counts, failing if `other` has more copies of a tile.
+    pub fn checked_sub(&self, other: &TileCounts) -> Result<TileCounts> {
+        let mut counts = [0; NUM_KINDS];
+        for (i, cnt) in counts.iter_mut().enumerate() {
+            *cnt = self.counts[i]
+                .checked_sub(other.counts[i])
+                .ok_or_else(|| anyhow::anyhow!("No copy of tile left: {:?}", multiset::tile(i)))?;
+        }
+        Ok(Self { counts })
+    }
+
+    /// Each tile with its remaining count, including tiles with none left,
+    /// in m/p/s/z order
+    pub fn iter(&self) -> impl Iterator<Item = (Tile, u8)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .map(|(i, &cnt)| (multiset::tile(i), cnt))
+    }
+}
+
+impl From<&TileMultiset> for TileCounts {
+    fn from(tiles: &TileMultiset) -> Self {
+        let mut counts = [0; NUM_KINDS];
+        for (tile, cnt) in tiles.iter() {
+            counts[multiset::index(tile).expect("valid tile")] = cnt;
+        }
+        Self { counts }
+    }
+}
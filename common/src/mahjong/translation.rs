@@ -0,0 +1,76 @@
+use crate::mahjong::{Dimension, Tile};
+
+/// The suit reordering done by [`HandConverter::encode_hand13`] and
+/// [`HandConverter::encode_hand14`], mapping tiles of the encoded (canonical)
+/// hand back to the original hand.
+///
+/// Entry `i` is the original suit of encoded suit `i`, bit-notted if the
+/// numbers of the suit were reversed (1 <-> 9). Honors are never translated.
+///
+/// [`HandConverter::encode_hand13`]: crate::mahjong::HandConverter::encode_hand13
+/// [`HandConverter::encode_hand14`]: crate::mahjong::HandConverter::encode_hand14
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Translation(pub [i8; 3]);
+
+impl Translation {
+    /// The translation of a hand that is already canonical
+    pub const IDENTITY: Translation = Translation([0, 1, 2]);
+
+    /// The original suit of encoded suit `suit`, and whether its numbers are reversed
+    pub fn source(&self, suit: usize) -> (usize, bool) {
+        let t = self.0[suit];
+        if t < 0 {
+            (!t as usize, true)
+        } else {
+            (t as usize, false)
+        }
+    }
+
+    /// Map a tile of the encoded hand to the original hand
+    pub fn apply_to_tile(&self, tile: Tile) -> Tile {
+        match tile {
+            Tile::Supai(suit, num) => match self.source(suit as usize) {
+                (suit, true) => Tile::Supai(suit as u8, 8 - num),
+                (suit, false) => Tile::Supai(suit as u8, num),
+            },
+            Tile::Jihai(_) => tile,
+        }
+    }
+
+    /// Map a set of the encoded hand to the original hand.
+    ///
+    /// A sequence is written by its lowest tile, so a reversed sequence
+    /// starting at `num` starts at `6 - num` in the original hand.
+    pub fn map_dimension(&self, dim: Dimension) -> Dimension {
+        match dim {
+            Dimension::Shuntsu(Tile::Supai(suit, num)) => match self.source(suit as usize) {
+                (suit, true) => Dimension::Shuntsu(Tile::Supai(suit as u8, 6 - num)),
+                (suit, false) => Dimension::Shuntsu(Tile::Supai(suit as u8, num)),
+            },
+            Dimension::Shuntsu(tile) => Dimension::Shuntsu(tile),
+            Dimension::Kotsu(tile) => Dimension::Kotsu(self.apply_to_tile(tile)),
+            Dimension::Toitsu(tile) => Dimension::Toitsu(self.apply_to_tile(tile)),
+            Dimension::Kokushi => Dimension::Kokushi,
+        }
+    }
+
+    /// The translation mapping tiles of the original hand to the encoded hand
+    pub fn invert(&self) -> Translation {
+        let mut inverse = [0; 3];
+        for encoded in 0..3 {
+            let (original, reversed) = self.source(encoded);
+            inverse[original] = if reversed {
+                !(encoded as i8)
+            } else {
+                encoded as i8
+            };
+        }
+        Translation(inverse)
+    }
+}
+
+impl From<[i8; 3]> for Translation {
+    fn from(trans: [i8; 3]) -> Self {
+        Translation(trans)
+    }
+}
@@ -1,15 +1,129 @@
+use std::{fmt, str::FromStr};
 use std::io::{Read, Write};
 use anyhow::Result;
 use crate::flat_file_vec::FixedRepr;
+use crate::mahjong::parse_hand_str;
 
 pub const NUM_ROUNDS: usize = 18;
 
+/// Fixed-point scale of the turns tables (`tsumo_13_turns.dat` etc.).
+///
+/// They hold the expected number of draws left at the time of winning, counted
+/// as 0 when the hand does not win. The values are at most 17, so they fit in a
+/// `u32` with this scale.
+pub const TURNS_SCALE: u32 = 1 << 27;
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Tile {
     Supai(u8, u8),
     Jihai(u8),
 }
 
+impl Tile {
+    pub const fn east() -> Tile {
+        Tile::Jihai(0)
+    }
+
+    pub const fn south() -> Tile {
+        Tile::Jihai(1)
+    }
+
+    pub const fn west() -> Tile {
+        Tile::Jihai(2)
+    }
+
+    pub const fn north() -> Tile {
+        Tile::Jihai(3)
+    }
+
+    /// White dragon (5z)
+    pub const fn haku() -> Tile {
+        Tile::Jihai(4)
+    }
+
+    /// Green dragon (6z)
+    pub const fn hatsu() -> Tile {
+        Tile::Jihai(5)
+    }
+
+    /// Red dragon (7z)
+    pub const fn chun() -> Tile {
+        Tile::Jihai(6)
+    }
+
+    /// 1 or 9 of a numbered suit
+    pub const fn is_terminal(&self) -> bool {
+        matches!(self, Tile::Supai(_, 0 | 8))
+    }
+
+    pub const fn is_honor(&self) -> bool {
+        matches!(self, Tile::Jihai(_))
+    }
+
+    pub const fn is_wind(&self) -> bool {
+        matches!(self, Tile::Jihai(0..=3))
+    }
+
+    pub const fn is_dragon(&self) -> bool {
+        matches!(self, Tile::Jihai(4..=6))
+    }
+
+    /// Terminal or honor tile
+    pub const fn is_yaochuu(&self) -> bool {
+        self.is_terminal() || self.is_honor()
+    }
+
+    /// 2-8 of a numbered suit
+    pub const fn is_simple(&self) -> bool {
+        !self.is_yaochuu()
+    }
+
+    /// Whether a triplet of this tile is a yakuhai: a dragon, the seat wind or
+    /// the round wind. A double wind (seat and round) counts once here.
+    pub fn is_yakuhai(&self, seat: Tile, round: Tile) -> bool {
+        self.is_dragon() || *self == seat || *self == round
+    }
+
+    /// The Unicode mahjong tile character (U+1F000 block) for this tile.
+    ///
+    /// Honors follow mpsz order: 1z-4z are the winds, 5z-7z are white, green
+    /// and red dragons.
+    pub fn to_unicode(&self) -> char {
+        let code = match *self {
+            Tile::Supai(0, num) => 0x1F007 + num as u32,
+            Tile::Supai(1, num) => 0x1F019 + num as u32,
+            Tile::Supai(_, num) => 0x1F010 + num as u32,
+            Tile::Jihai(num) if num < 4 => 0x1F000 + num as u32,
+            Tile::Jihai(num) => 0x1F006 - (num as u32 - 4),
+        };
+        char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+    }
+}
+
+const SUIT_LOOKUP: [char; 4] = ['m', 'p', 's', 'z'];
+
+impl fmt::Display for Tile {
+    /// mpsz notation, e.g. `5m` or `1z`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Tile::Supai(suit, num) => write!(f, "{}{}", num + 1, SUIT_LOOKUP[suit as usize]),
+            Tile::Jihai(num) => write!(f, "{}z", num + 1),
+        }
+    }
+}
+
+impl FromStr for Tile {
+    type Err = anyhow::Error;
+
+    /// Parse a single tile in mpsz notation. `0m`/`0p`/`0s` are read as fives.
+    fn from_str(s: &str) -> Result<Self> {
+        match parse_hand_str(s)?[..] {
+            [tile] => Ok(tile),
+            _ => Err(anyhow::anyhow!("Expected a single tile, got {:?}", s)),
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Dimension {
     Shuntsu(Tile), // tile denote the lowest one
@@ -66,9 +180,9 @@ impl Dimension {
         match self {
             Dimension::Shuntsu(Tile::Supai(x, y)) => x * 7 + y,
             Dimension::Kotsu(Tile::Supai(x, y)) => 21 + x * 9 + y,
-            Dimension::Kotsu(Tile::Jihai(x)) => 21 + 27 + x,
+            Dimension::Kotsu(Tile::Jihai(x)) if *x < 5 => 21 + 27 + x,
             Dimension::Toitsu(Tile::Supai(x, y)) => 21 + 27 + 5 + x * 9 + y,
-            Dimension::Toitsu(Tile::Jihai(x)) => 21 + 27 + 5 + 27 + x,
+            Dimension::Toitsu(Tile::Jihai(x)) if *x < 5 => 21 + 27 + 5 + 27 + x,
             Dimension::Kokushi => 85,
             _ => panic!("Invalid dimension: {:?}", self),
         }
@@ -82,6 +196,98 @@ impl Dimension {
     pub const fn all_dimensions() -> [Dimension; Self::len()] {
         ID_TO_DIMENSION
     }
+
+    /// The tiles of the set written in mpsz notation, e.g. `123m`, `555z`, or `kokushi`
+    pub fn label(&self) -> String {
+        let (tile, len) = match *self {
+            Dimension::Shuntsu(Tile::Supai(suit, num)) => {
+                return format!("{}{}{}{}", num + 1, num + 2, num + 3, SUIT_LOOKUP[suit as usize]);
+            }
+            Dimension::Shuntsu(_) => panic!("Invalid dimension: {:?}", self),
+            Dimension::Kotsu(tile) => (tile, 3),
+            Dimension::Toitsu(tile) => (tile, 2),
+            Dimension::Kokushi => return "kokushi".to_string(),
+        };
+        let (num, suit) = match tile {
+            Tile::Supai(suit, num) => (num, SUIT_LOOKUP[suit as usize]),
+            Tile::Jihai(num) => (num, 'z'),
+        };
+        let mut label: String = std::iter::repeat_n((b'1' + num) as char, len).collect();
+        label.push(suit);
+        label
+    }
+
+    /// Inverse of [`Dimension::label`].
+    ///
+    /// Any jihai `1z`-`7z` is accepted, but only `1z`-`5z` sets have an ID
+    /// (in the canonical form jihai are numbered by count).
+    pub fn from_label(label: &str) -> Result<Self> {
+        if label.eq_ignore_ascii_case("kokushi") {
+            return Ok(Dimension::Kokushi);
+        }
+        let mut tiles = parse_hand_str(label)?;
+        tiles.reverse();
+        let dim = match tiles[..] {
+            [Tile::Supai(suit, num), b, c]
+                if num < 7
+                    && b == Tile::Supai(suit, num + 1)
+                    && c == Tile::Supai(suit, num + 2) =>
+            {
+                Some(Dimension::Shuntsu(tiles[0]))
+            }
+            [a, b, c] if a == b && b == c => Some(Dimension::Kotsu(a)),
+            [a, b] if a == b => Some(Dimension::Toitsu(a)),
+            _ => None,
+        };
+        dim.ok_or_else(|| anyhow::anyhow!("Invalid dimension: {}", label))
+    }
+}
+
+// Serde uses the mpsz notation so that API types can embed tiles directly.
+// The traits are not imported because `FixedRepr` has methods of the same names.
+
+impl serde::Serialize for Tile {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Tile {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl serde::Serialize for Dimension {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.label())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Dimension {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+        let dim = Dimension::from_label(&s).map_err(serde::de::Error::custom)?;
+        match dim {
+            Dimension::Kotsu(Tile::Jihai(num)) | Dimension::Toitsu(Tile::Jihai(num)) if num >= 5 => {
+                Err(serde::de::Error::custom(format!("Dimension has no ID: {}", s)))
+            }
+            dim => Ok(dim),
+        }
+    }
 }
 
 
@@ -162,6 +368,7 @@ impl AsMut<[u32]> for Metrics {
 
 impl FixedRepr for Metrics {
     const BYTE_SIZE: usize = Dimension::len() * 4;
+    const TYPE_TAG: [u8; 8] = *b"metrics\0";
     fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
         for v in self.values.iter() {
             writer.write_all(&v.to_le_bytes())?;
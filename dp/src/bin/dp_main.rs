@@ -5,8 +5,11 @@ use std::{
 
 use anyhow::Result;
 use common::{
-    flat_file_vec::FlatFileVec,
-    mahjong::{Dimension, Hand, HandConverter, Metrics, Tile, NUM_HAND13, NUM_HAND14, NUM_ROUNDS},
+    flat_file_vec::{FlatFileMmap, FlatFileVec},
+    mahjong::{
+        AgariFamily, Dimension, Hand, HandConverter, Metrics, Tile, NUM_HAND13, NUM_HAND14,
+        NUM_ROUNDS, TURNS_SCALE,
+    },
 };
 use dp::metrics;
 use itertools::{iproduct, izip};
@@ -24,9 +27,19 @@ fn log(msg: impl std::fmt::Display) {
     );
 }
 
+// ツモ率のDPで残り０巡に1とする手
+#[derive(Clone, Copy)]
+enum Seed {
+    // 和了形。familyを指定すると、その和了形のみを和了とみなす（tsumo_13_{family}.datなど）
+    Agari(Option<AgariFamily>),
+    // 聴牌。流局時の聴牌率を計算する（tenpai_13.datなど）
+    Tenpai,
+}
+
 struct DpMain {
     conv: HandConverter,
     dir: PathBuf,
+    seed: Seed,
 }
 
 impl DpMain {
@@ -35,6 +48,26 @@ impl DpMain {
         Self {
             conv,
             dir: dir.as_ref().to_path_buf(),
+            seed: Seed::Agari(None),
+        }
+    }
+
+    fn with_family(mut self, family: AgariFamily) -> Self {
+        self.seed = Seed::Agari(Some(family));
+        self
+    }
+
+    fn with_tenpai(mut self) -> Self {
+        self.seed = Seed::Tenpai;
+        self
+    }
+
+    // ツモ率のファイル名。partは`temp`、`13`、`14`で、`tsumo_13`、`tsumo_13_standard`、`tenpai_13`などになる
+    fn tsumo_name(&self, part: &str) -> String {
+        match self.seed {
+            Seed::Agari(None) => format!("tsumo_{}", part),
+            Seed::Agari(Some(family)) => format!("tsumo_{}_{}", part, family.as_str()),
+            Seed::Tenpai => format!("tenpai_{}", part),
         }
     }
 
@@ -46,11 +79,21 @@ impl DpMain {
     }
 
     fn get_tsumo_temp_path(&self, round: usize) -> PathBuf {
-        self.dir.join(format!("tsumo_temp/{:02}.dat", round))
+        self.dir
+            .join(format!("{}/{:02}.dat", self.tsumo_name("temp"), round))
+    }
+
+    fn get_turns_temp_path(&self, round: usize) -> PathBuf {
+        self.dir.join(format!(
+            "{}_turns/{:02}.dat",
+            self.tsumo_name("temp"),
+            round
+        ))
     }
 
     fn fill_tsumo_temp(&self) -> Result<()> {
-        let paths = glob::glob(self.dir.join("tsumo_temp/??.dat").to_str().unwrap()).unwrap();
+        let pattern = self.dir.join(format!("{}/??.dat", self.tsumo_name("temp")));
+        let paths = glob::glob(pattern.to_str().unwrap()).unwrap();
         let last_path = paths
             .map(|r| {
                 r.as_deref()
@@ -80,10 +123,14 @@ impl DpMain {
         }
 
         // init agari hands and prev_memo
+        // 聴牌はツモった牌を捨てれば保てるため、聴牌の手も和了形と同様に以降の巡でも1とできる
         let mut cur_memo: Vec<u128>;
         let mut agari_hands: Vec<u32> = Vec::new();
         if round == 0 {
-            cur_memo = dp::tsumo::dp14_r0(&self.conv);
+            cur_memo = match self.seed {
+                Seed::Agari(family) => dp::tsumo::dp14_r0_family(&self.conv, family),
+                Seed::Tenpai => dp::tsumo::dp14_r0_tenpai(&self.conv),
+            };
             for (hi, &v) in cur_memo.iter().enumerate() {
                 if v > 0 {
                     agari_hands.push(hi as u32);
@@ -98,7 +145,7 @@ impl DpMain {
                     agari_hands.push(hi as u32);
                 }
             }
-            cur_memo = FlatFileVec::<u128>::load_all(self.get_tsumo_temp_path(round - 1))?;
+            cur_memo = FlatFileVec::<u128>::load_all_par(self.get_tsumo_temp_path(round - 1))?;
         }
         agari_hands.shrink_to_fit();
 
@@ -129,6 +176,85 @@ impl DpMain {
         Ok(())
     }
 
+    // 和了したときの残り巡数の期待値（和了しなければ0）を、ツモ率を最大にする打牌に沿って計算する。
+    // 和了までのツモ回数の期待値は、残り巡数からこの値をツモ率で割ったものを引けば求まる。
+    // 1次元の値を伝播するだけなので、国士無双のメトリクスと同じ処理を使う。fill_tsumo_tempの後に行う
+    fn fill_turns_temp(&self) -> Result<()> {
+        let dp0 = FlatFileVec::<u128>::open_readonly(self.get_tsumo_temp_path(0))?;
+        let mut agari_hands: Vec<u32> = Vec::new();
+        for (hi, v) in dp0.into_iter().enumerate() {
+            if v.unwrap() > 0 {
+                agari_hands.push(hi as u32);
+            }
+        }
+
+        let mut turns_14 = vec![0u32; NUM_HAND14];
+        let mut turns_13 = vec![0u32; NUM_HAND13];
+        // 残り０巡で和了しても残り巡数は0
+        FlatFileVec::save_all(turns_14.iter().copied(), self.get_turns_temp_path(0))?;
+        for round in 1..(NUM_ROUNDS * 2) {
+            log(format!("turns: round={:02}", round));
+            if round % 2 == 0 {
+                let tsumo_13 =
+                    FlatFileVec::<u128>::load_all_par(self.get_tsumo_temp_path(round - 1))?;
+                let agari: Vec<(u32, u32)> = agari_hands
+                    .iter()
+                    .map(|&hi| (hi, (round / 2) as u32 * TURNS_SCALE))
+                    .collect();
+                metrics::process_13_to_14_kokushi(
+                    &self.conv,
+                    &turns_13,
+                    &mut turns_14,
+                    &tsumo_13,
+                    &agari,
+                );
+                FlatFileVec::save_all(turns_14.iter().copied(), self.get_turns_temp_path(round))?;
+            } else {
+                metrics::process_14_to_13_kokushi(&self.conv, &turns_14, &mut turns_13);
+                FlatFileVec::save_all(turns_13.iter().copied(), self.get_turns_temp_path(round))?;
+            }
+        }
+        Ok(())
+    }
+
+    // 残り巡数ごとの値を手牌ごとにまとめ、ツモ率と同じ並びの`tsumo_13_turns.dat`などに書き出す
+    fn collect_turns_temps(&self, num_tiles: usize) -> Result<()> {
+        let (num_hands, first_round) = match num_tiles {
+            13 => (NUM_HAND13, 1),
+            _ => (NUM_HAND14, 0),
+        };
+        let temp_files: Vec<FlatFileMmap<u32>> = (0..NUM_ROUNDS)
+            .map(|round| {
+                FlatFileVec::<u32>::open_mmap(self.get_turns_temp_path(round * 2 + first_round))
+                    .unwrap()
+            })
+            .collect();
+
+        let name = self.tsumo_name(&num_tiles.to_string());
+        let path = self.dir.join(format!("{}_turns.dat", name));
+        let mut turns_store = FlatFileVec::<u32>::open_or_create(path)?;
+
+        const SHARD_SIZE: usize = 1 << 28;
+        let mut hi_start = 0;
+        while hi_start < num_hands {
+            log(format!(
+                "turns {}: hi_start={:10}/{:10}",
+                num_tiles, hi_start, num_hands
+            ));
+            let size = SHARD_SIZE.min(num_hands - hi_start);
+            let hi_end = hi_start + size;
+            let mut temp = vec![0u32; size * NUM_ROUNDS];
+            for (r, ffv) in temp_files.iter().enumerate() {
+                for (i, &v) in ffv.as_slice()[hi_start..hi_end].iter().enumerate() {
+                    temp[i * NUM_ROUNDS + r] = v;
+                }
+            }
+            turns_store.extend(temp)?;
+            hi_start = hi_end;
+        }
+        Ok(())
+    }
+
     fn write_metrics_temp<I>(&self, metrics: I, round: usize, dim_id: usize) -> Result<()>
     where
         I: IntoIterator<Item = u32>,
@@ -277,7 +403,7 @@ impl DpMain {
         for round in 1..(NUM_ROUNDS * 2) {
             log(format!("round={:02}", round));
             if round % 2 == 0 {
-                let tsumo_13 = FlatFileVec::<u128>::load_all(
+                let tsumo_13 = FlatFileVec::<u128>::load_all_par(
                     self.dir.join(format!("tsumo_temp/{:02}.dat", round - 1)),
                 )?;
                 metrics::process_13_to_14_supai(
@@ -363,7 +489,7 @@ impl DpMain {
         for round in 1..(NUM_ROUNDS * 2) {
             log(format!("round={:02}", round));
             if round % 2 == 0 {
-                let tsumo_13 = FlatFileVec::<u128>::load_all(
+                let tsumo_13 = FlatFileVec::<u128>::load_all_par(
                     self.dir.join(format!("tsumo_temp/{:02}.dat", round - 1)),
                 )?;
                 metrics::process_13_to_14_jihai(
@@ -416,7 +542,7 @@ impl DpMain {
         for round in 1..(NUM_ROUNDS * 2) {
             log(format!("round={:02}", round));
             if round % 2 == 0 {
-                let tsumo_13 = FlatFileVec::<u128>::load_all(
+                let tsumo_13 = FlatFileVec::<u128>::load_all_par(
                     self.dir.join(format!("tsumo_temp/{:02}.dat", round - 1)),
                 )?;
                 metrics::process_13_to_14_kokushi(
@@ -536,14 +662,14 @@ impl DpMain {
     }
 
     fn collect_tsumo_13_temps(&self) -> Result<()> {
-        let mut temp_files: Vec<FlatFileVec<u128>> = (0..NUM_ROUNDS)
+        let temp_files: Vec<FlatFileMmap<u128>> = (0..NUM_ROUNDS)
             .map(|round| {
-                FlatFileVec::<u128>::open_readonly(self.get_tsumo_temp_path(round * 2 + 1)).unwrap()
+                FlatFileVec::<u128>::open_mmap(self.get_tsumo_temp_path(round * 2 + 1)).unwrap()
             })
             .collect();
 
-        let mut tsumo_13_store =
-            FlatFileVec::<u32>::open_or_create(self.dir.join("tsumo_13.dat"))?;
+        let path = self.dir.join(format!("{}.dat", self.tsumo_name("13")));
+        let mut tsumo_13_store = FlatFileVec::<u32>::open_or_create(path)?;
 
         const SHARD_SIZE: usize = 1 << 28;
         let mut hi_start = 0;
@@ -552,9 +678,9 @@ impl DpMain {
             let size = SHARD_SIZE.min(NUM_HAND13 - hi_start);
             let hi_end = hi_start + size;
             let mut temp = vec![0u32; size * NUM_ROUNDS];
-            for (r, ffv) in temp_files.iter_mut().enumerate() {
+            for (r, ffv) in temp_files.iter().enumerate() {
                 let div = (136u128 - 13).pow(1 + r as u32);
-                for (i, v) in ffv.get_range(hi_start, hi_end).unwrap().iter().enumerate() {
+                for (i, v) in ffv.as_slice()[hi_start..hi_end].iter().enumerate() {
                     let k = v.leading_zeros().min(32);
                     temp[i * NUM_ROUNDS + r] =
                         u32::try_from((v << k) / (div >> (32 - k))).unwrap_or(u32::MAX);
@@ -567,14 +693,14 @@ impl DpMain {
     }
 
     fn collect_tsumo_14_temps(&self) -> Result<()> {
-        let mut temp_files: Vec<FlatFileVec<u128>> = (0..NUM_ROUNDS)
+        let temp_files: Vec<FlatFileMmap<u128>> = (0..NUM_ROUNDS)
             .map(|round| {
-                FlatFileVec::<u128>::open_readonly(self.get_tsumo_temp_path(round * 2)).unwrap()
+                FlatFileVec::<u128>::open_mmap(self.get_tsumo_temp_path(round * 2)).unwrap()
             })
             .collect();
 
-        let mut tsumo_14_store =
-            FlatFileVec::<u32>::open_or_create(self.dir.join("tsumo_14.dat"))?;
+        let path = self.dir.join(format!("{}.dat", self.tsumo_name("14")));
+        let mut tsumo_14_store = FlatFileVec::<u32>::open_or_create(path)?;
 
         const SHARD_SIZE: usize = 1 << 28;
         let mut hi_start = 0;
@@ -583,9 +709,9 @@ impl DpMain {
             let size = SHARD_SIZE.min(NUM_HAND14 - hi_start);
             let hi_end = hi_start + size;
             let mut temp = vec![0u32; size * NUM_ROUNDS];
-            for (r, ffv) in temp_files.iter_mut().enumerate() {
+            for (r, ffv) in temp_files.iter().enumerate() {
                 let div = (136u128 - 13).pow(r as u32);
-                for (i, v) in ffv.get_range(hi_start, hi_end).unwrap().iter().enumerate() {
+                for (i, v) in ffv.as_slice()[hi_start..hi_end].iter().enumerate() {
                     let k = v.leading_zeros().min(32);
                     temp[i * NUM_ROUNDS + r] =
                         u32::try_from((v << k) / (div >> (32 - k))).unwrap_or(u32::MAX);
@@ -596,6 +722,26 @@ impl DpMain {
         }
         Ok(())
     }
+
+    // 副露がnum_melds個の手牌のツモ率を計算し、門前の牌の枚数nについて`tsumo_{n}.dat`と`tsumo_{n+1}.dat`に書き出す。
+    // 手牌の数が少ないため、一時ファイルを使わずにまとめて計算する
+    fn fill_open_tsumo(&self, num_melds: usize) -> Result<()> {
+        let num_tiles = 13 - 3 * num_melds;
+        log(format!("open tsumo: num_tiles={}", num_tiles));
+        let space_13 = self.conv.hand_space(num_tiles);
+        let space_14 = self.conv.hand_space(num_tiles + 1);
+        let (tsumo_13, tsumo_14) = dp::tsumo::open_tsumo(&self.conv, &space_13, &space_14);
+        FlatFileVec::save_all(
+            tsumo_13.iter().copied(),
+            self.dir.join(format!("tsumo_{}.dat", num_tiles)),
+        )?;
+        FlatFileVec::save_all(
+            tsumo_14.iter().copied(),
+            self.dir.join(format!("tsumo_{}.dat", num_tiles + 1)),
+        )?;
+        log(format!("open tsumo: num_tiles={} done", num_tiles));
+        Ok(())
+    }
 }
 
 fn debug(mut hand: Hand, dims: &[Dimension], converter: &HandConverter, dir: &Path) {
@@ -608,7 +754,7 @@ fn debug(mut hand: Hand, dims: &[Dimension], converter: &HandConverter, dir: &Pa
             for round in 0..18 {
                 let round = round * 2 + 1;
                 println!("round={}", round);
-                let mut tsumo_13 = FlatFileVec::<u128>::open_readonly(
+                let tsumo_13 = FlatFileVec::<u128>::open_readonly(
                     dir.join(format!("tsumo_temp/{:02}.dat", round))
                 )
                 .unwrap();
@@ -623,7 +769,7 @@ fn debug(mut hand: Hand, dims: &[Dimension], converter: &HandConverter, dir: &Pa
 
                 let mut total = 0;
                 for dim_id in 0..Dimension::len() {
-                    let mut metrics_13 = FlatFileVec::<u32>::open_readonly(
+                    let metrics_13 = FlatFileVec::<u32>::open_readonly(
                         dir.join(format!("metrics_temp/{:02}/{:02}/{:03}.dat",
                         dim_id, round, shard_id))
                     )
@@ -664,7 +810,7 @@ fn debug(mut hand: Hand, dims: &[Dimension], converter: &HandConverter, dir: &Pa
             for round in 0..18 {
                 let round = round * 2;
                 println!("round={}", round);
-                let mut tsumo_14 = FlatFileVec::<u128>::open_readonly(
+                let tsumo_14 = FlatFileVec::<u128>::open_readonly(
                     dir.join(format!("tsumo_temp/{:02}.dat", round))
                 )
                 .unwrap();
@@ -679,7 +825,7 @@ fn debug(mut hand: Hand, dims: &[Dimension], converter: &HandConverter, dir: &Pa
 
                 let mut total = 0;
                 for dim_id in 0..Dimension::len() {
-                    let mut metrics_14 = FlatFileVec::<u32>::open_readonly(
+                    let metrics_14 = FlatFileVec::<u32>::open_readonly(
                         dir.join(format!("metrics_temp/{:02}/{:02}/{:03}.dat",
                         dim_id, round, shard_id))
                     )
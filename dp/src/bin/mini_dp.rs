@@ -0,0 +1,46 @@
+use std::{env, path::Path};
+
+use anyhow::{ensure, Result};
+use common::{
+    flat_file_vec::FlatFileVec,
+    mahjong::{HandConverter, Metrics, TileSet, NUM_ROUNDS},
+};
+
+// 小さなconverter（`generate_hand_converter --mini`）のデータファイルをまとめて計算する。
+// バックエンドがそのまま読み込める`tsumo_13.dat`・`tsumo_14.dat`・`metrics_13.dat`・`metrics_14.dat`と、
+// 副露した手牌の`tsumo_{n}.dat`を書き出す。数秒で終わるため、CIや手元で一通りの流れを試すのに使う。
+// メンツのメトリクスは計算せず、すべて0とする
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("Usage: {} <converter_path> <output_dir>", args[0]);
+        std::process::exit(1);
+    }
+    let conv = HandConverter::open(&args[1])?;
+    ensure!(
+        conv.tile_set() == TileSet::Mini,
+        "{:?} converter is too large, use dp_main",
+        conv.tile_set()
+    );
+    let dir = Path::new(&args[2]);
+
+    let (tsumo_13, tsumo_14) = dp::tsumo::mini_tsumo(&conv);
+    FlatFileVec::save_all(tsumo_13, dir.join("tsumo_13.dat"))?;
+    FlatFileVec::save_all(tsumo_14, dir.join("tsumo_14.dat"))?;
+    // 0で埋めたファイルは長さだけを設定し、ディスクを使わない疎なファイルにする
+    FlatFileVec::<Metrics>::create(dir.join("metrics_13.dat"))?
+        .set_len(conv.num_hand13() * NUM_ROUNDS)?;
+    FlatFileVec::<Metrics>::create(dir.join("metrics_14.dat"))?
+        .set_len(conv.num_hand14() * NUM_ROUNDS)?;
+
+    for num_melds in 1..=4 {
+        let num_tiles = 13 - 3 * num_melds;
+        let space_13 = conv.hand_space(num_tiles);
+        let space_14 = conv.hand_space(num_tiles + 1);
+        let (tsumo_13, tsumo_14) = dp::tsumo::open_tsumo(&conv, &space_13, &space_14);
+        FlatFileVec::save_all(tsumo_13, dir.join(format!("tsumo_{}.dat", num_tiles)))?;
+        FlatFileVec::save_all(tsumo_14, dir.join(format!("tsumo_{}.dat", num_tiles + 1)))?;
+    }
+    println!("Mini tables saved to: {}", dir.display());
+    Ok(())
+}
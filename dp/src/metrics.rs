@@ -1,129 +1,34 @@
 use std::collections;
 
-use itertools::Itertools;
 use rayon::prelude::*;
 
-use common::mahjong::{Dimension, Hand, HandConverter, Metrics, Tile, NUM_HAND13, NUM_HAND14};
+use common::mahjong::{
+    for_each_agari, Dimension, HandConverter, Metrics, Tile, Translation, NUM_HAND13, NUM_HAND14,
+};
 
 pub fn construct_agari_metrics(conv: &HandConverter) -> Vec<(u32, Metrics)> {
     let mut entries = Vec::new();
-
-    // 4面子+1雀頭
-    for mentsu_locations in (0..(21 + 28)).combinations_with_replacement(4) {
-        let mut hand = Hand::new();
-        for i in mentsu_locations.iter().copied() {
-            if i < 21 {
-                let suit = i / 7;
-                let num = i % 7;
-                hand.supai[suit][num] += 1;
-                hand.supai[suit][num + 1] += 1;
-                hand.supai[suit][num + 2] += 1;
-            } else if i < 21 + 27 {
-                let suit = (i - 21) / 9;
-                let num = (i - 21) % 9;
-                hand.supai[suit][num] += 3;
-            } else {
-                hand.jihai[3] += 1;
-                hand.jihai[0] -= 1;
-            }
-        }
-        if !hand.supai.iter().all(|l| l.iter().all(|v| *v <= 4)) || hand.jihai[0] > 7 {
-            continue;
-        }
+    for_each_agari(|hand, sets| {
+        // 国士無双以外は、同じ手牌を数えすぎないように正規形のものだけを使う
+        let hi = match sets {
+            [Dimension::Kokushi] => conv.encode_hand14_fast(hand),
+            _ => match conv.encode_hand14(hand) {
+                (hi, [0, 1, 2]) => hi,
+                _ => return,
+            },
+        };
         let mut memo = [0u8; Dimension::len()];
-        for i in mentsu_locations {
-            if i < 21 + 27 {
-                memo[i] += 1;
-            } else {
-                memo[Dimension::Kotsu(Tile::Jihai(3)).to_id() as usize] = 1;
-            }
-        }
-        for suit in 0..3 {
-            for num in 0..9 {
-                hand.supai[suit][num] += 2;
-                if hand.supai[suit][num] <= 4 {
-                    let (hi, trans) = conv.encode_hand14(&hand);
-                    if trans == [0, 1, 2] {
-                        let t =
-                            Dimension::Toitsu(Tile::Supai(suit as u8, num as u8)).to_id() as usize;
-                        memo[t] += 1;
-                        entries.push((hi, memo.clone()));
-                        memo[t] -= 1;
-                    }
-                }
-                hand.supai[suit][num] -= 2;
-            }
-        }
-        if hand.jihai[0] > 0 {
-            hand.jihai[0] -= 1;
-            hand.jihai[2] += 1;
-            let (hi, trans) = conv.encode_hand14(&hand);
-            if trans == [0, 1, 2] {
-                let t = Dimension::Toitsu(Tile::Jihai(2)).to_id() as usize;
-                memo[t] = 1;
-                entries.push((hi, memo.clone()));
-                memo[t] = 0;
-            }
-            hand.jihai[0] += 1;
-            hand.jihai[2] -= 1;
-        }
-    }
-
-    // 七対子
-    for p in (0..34).combinations(7) {
-        let jihai_temp = p.iter().copied().filter(|v| *v >= 27).collect_vec();
-        if !jihai_temp.is_empty()
-            && !(jihai_temp[0] == 27
-                && jihai_temp
-                    .iter()
-                    .copied()
-                    .zip(jihai_temp[1..].iter().copied())
-                    .all(|(x, y)| x + 1 == y))
-        {
-            continue;
-        }
-        let mut hand = Hand::new();
-        for v in p.iter().copied() {
-            if v < 27 {
-                hand.supai[v / 9][v % 9] += 2;
-            } else {
-                hand.jihai[2] += 1;
-                hand.jihai[0] -= 1;
-            }
-        }
-
-        let (hi, trans) = conv.encode_hand14(&hand);
-        if trans == [0, 1, 2] {
-            let mut memo = [0u8; Dimension::len()];
-            for i in p {
-                if i < 27 {
-                    memo[Dimension::Toitsu(Tile::Supai((i / 9) as u8, (i % 9) as u8)).to_id()
-                        as usize] += 1;
-                } else {
-                    memo[Dimension::Toitsu(Tile::Jihai(2)).to_id() as usize] = 1;
+        for &dim in sets {
+            match dim {
+                // 字牌は枚数で区別するので、同じ枚数の字牌のメンツは1つとして数える
+                Dimension::Kotsu(Tile::Jihai(_)) | Dimension::Toitsu(Tile::Jihai(_)) => {
+                    memo[dim.to_id() as usize] = 1
                 }
+                _ => memo[dim.to_id() as usize] += 1,
             }
-            entries.push((hi, memo));
         }
-    }
-    {
-        let mut hand = Hand {
-            supai: [
-                [1, 0, 0, 0, 0, 0, 0, 0, 1],
-                [1, 0, 0, 0, 0, 0, 0, 0, 1],
-                [1, 0, 0, 0, 0, 0, 0, 0, 1],
-            ],
-            jihai: [0, 7, 0, 0, 0],
-        };
-        hand.supai[0][0] += 1;
-        let mut kokushi = [0u8; Dimension::len()];
-        kokushi[Dimension::Kokushi.to_id() as usize] = 1;
-        entries.push((conv.encode_hand14_fast(&hand), kokushi.clone()));
-        hand.supai[0][0] -= 1;
-        hand.jihai[1] -= 1;
-        hand.jihai[2] += 1;
-        entries.push((conv.encode_hand14_fast(&hand), kokushi));
-    }
+        entries.push((hi, memo));
+    });
 
     let mut agari_shapes: collections::HashMap<u32, (u32, [u32; Dimension::len()])> =
         collections::HashMap::new();
@@ -166,29 +71,24 @@ pub fn process_13_to_14_supai(
             .for_each_discard_hand(|hand, cnt| {
                 let cnt = cnt as u64;
                 let (hi, trans) = conv.encode_hand13(hand);
+                let trans = Translation::from(trans);
                 let hi = hi as usize;
                 let p = tsumo_13[hi];
                 if p > best {
                     best = p;
-                    for (i, v) in trans.into_iter().enumerate() {
-                        let (s, d) = if v >= 0 {
-                            (v as usize, 0)
-                        } else {
-                            (!v as usize, 1)
-                        };
-                        sum[s][0] = cnt * metrics_13[hi][i][d] as u64;
-                        sum[s][1] = cnt * metrics_13[hi][i][d ^ 1] as u64;
+                    for (i, m) in metrics_13[hi].iter().enumerate() {
+                        let (s, reversed) = trans.source(i);
+                        let d = reversed as usize;
+                        sum[s][0] = cnt * m[d] as u64;
+                        sum[s][1] = cnt * m[d ^ 1] as u64;
                     }
                     total = cnt;
                 } else if p == best {
-                    for (i, v) in trans.into_iter().enumerate() {
-                        let (s, d) = if v >= 0 {
-                            (v as usize, 0)
-                        } else {
-                            (!v as usize, 1)
-                        };
-                        sum[s][0] += cnt * metrics_13[hi][i][d] as u64;
-                        sum[s][1] += cnt * metrics_13[hi][i][d ^ 1] as u64;
+                    for (i, m) in metrics_13[hi].iter().enumerate() {
+                        let (s, reversed) = trans.source(i);
+                        let d = reversed as usize;
+                        sum[s][0] += cnt * m[d] as u64;
+                        sum[s][1] += cnt * m[d ^ 1] as u64;
                     }
                     total += cnt;
                 }
@@ -325,15 +225,13 @@ pub fn process_14_to_13_supai(
             .for_each_draw_hand(|hand, cnt| {
                 let cnt = cnt as u64;
                 let (hi, trans) = conv.encode_hand14(hand);
+                let trans = Translation::from(trans);
                 let hi = hi as usize;
-                for (i, v) in trans.into_iter().enumerate() {
-                    let (s, d) = if v >= 0 {
-                        (v as usize, 0)
-                    } else {
-                        (!v as usize, 1)
-                    };
-                    sum[s][0] += cnt * metrics_14[hi][i][d] as u64;
-                    sum[s][1] += cnt * metrics_14[hi][i][d ^ 1] as u64;
+                for (i, m) in metrics_14[hi].iter().enumerate() {
+                    let (s, reversed) = trans.source(i);
+                    let d = reversed as usize;
+                    sum[s][0] += cnt * m[d] as u64;
+                    sum[s][1] += cnt * m[d ^ 1] as u64;
                 }
             });
         let mut out = [[0u32; 2]; 3];
@@ -1,88 +1,40 @@
-use itertools::Itertools;
 use rayon::prelude::*;
 
-use common::mahjong::{Hand, HandConverter, NUM_HAND13, NUM_HAND14};
+use common::mahjong::{
+    for_each_agari, is_agari, shanten, AgariFamily, Hand, HandConverter, HandSpace, TileSet,
+    NUM_HAND13, NUM_HAND14, NUM_ROUNDS,
+};
 
 // 残り０巡のdp14を計算する。残り０巡のため、すでに和了形になっている手のみを考えればよい。
 pub fn dp14_r0(conv: &HandConverter) -> Vec<u128> {
-    let mut res = vec![0; NUM_HAND14];
+    dp14_r0_family(conv, None)
+}
 
-    // 4面子+1雀頭
-    for mentsu_locations in (0..(21 + 28)).combinations_with_replacement(4) {
-        let mut hand = Hand::new();
-        for i in mentsu_locations {
-            if i < 21 {
-                let suit = i / 7;
-                let num = i % 7;
-                hand.supai[suit][num] += 1;
-                hand.supai[suit][num + 1] += 1;
-                hand.supai[suit][num + 2] += 1;
-            } else if i < 21 + 27 {
-                let suit = (i - 21) / 9;
-                let num = (i - 21) % 9;
-                hand.supai[suit][num] += 3;
-            } else {
-                hand.jihai[3] += 1;
-                hand.jihai[0] -= 1;
-            }
-        }
-        if !hand.supai.iter().all(|l| l.iter().all(|v| *v <= 4)) {
-            continue
-        }
-        for suit in 0..3 {
-            for num in 0..9 {
-                hand.supai[suit][num] += 2;
-                if hand.supai[suit][num] <= 4 {
-                    res[conv.encode_hand14_fast(&hand) as usize] = 1;
-                }
-                hand.supai[suit][num] -= 2;
-            }
-        }
-        hand.jihai[2] += 1;
-        hand.jihai[0] -= 1;
-        res[conv.encode_hand14_fast(&hand) as usize] = 1;
-        hand.jihai[0] += 1;
-        hand.jihai[2] -= 1;
-    }
+// familyを指定すると、その和了形のみを和了とみなす（和了形ごとのツモ率の計算用）。
+pub fn dp14_r0_family(conv: &HandConverter, family: Option<AgariFamily>) -> Vec<u128> {
+    let mut res = vec![0; NUM_HAND14];
 
-    // 七対子
-    for p in (0..34).combinations(7) {
-        let mut hand = Hand::new();
-        for v in p {
-            if v < 27 {
-                hand.supai[v / 9][v % 9] += 2;
-            } else {
-                hand.jihai[0] -= 1;
-                hand.jihai[2] += 1;
-            }
+    for_each_agari(|hand, sets| {
+        if family.is_none_or(|f| f == AgariFamily::of(sets)) {
+            res[conv.encode_hand14_fast(hand) as usize] = 1;
         }
-        res[conv.encode_hand14_fast(&hand) as usize] = 1;
-    }
-
-    // 国士無双
-    let mut kokushi = Hand {
-        supai: [
-            [1, 0, 0, 0, 0, 0, 0, 0, 1],
-            [1, 0, 0, 0, 0, 0, 0, 0, 1],
-            [1, 0, 0, 0, 0, 0, 0, 0, 1],
-        ],
-        jihai: [0, 7, 0, 0, 0],
-    };
-    kokushi.supai[0][0] += 1;
-    res[conv.encode_hand14_fast(&kokushi) as usize] = 1;
-    kokushi.supai[0][0] -= 1;
-    kokushi.jihai[1] -= 1;
-    kokushi.jihai[2] += 1;
-    res[conv.encode_hand14_fast(&kokushi) as usize] = 1;
+    });
     res
 }
 
+// 残り０巡で聴牌しているかを計算する（流局時の聴牌率の計算用）。
+// 14牌から１牌を捨てて聴牌にできる手、すなわち向聴数が0以下の手を1とする。
+pub fn dp14_r0_tenpai(conv: &HandConverter) -> Vec<u128> {
+    let derive = |hand_id: usize| (shanten(&conv.decode_hand14(hand_id as u32)).min() <= 0) as u128;
+    (0..NUM_HAND14).into_par_iter().map(derive).collect()
+}
+
 // dp14からdp13を計算する。13牌にランダムに１牌を積もって14牌のDPを計算する。
 pub fn dp14_to_dp13(conv: &HandConverter, dp14: &[u128]) -> Vec<u128> {
     let derive = |hand_id: usize| {
         let mut total = 0;
-        conv.decode_hand13(hand_id as u32).for_each_draw_hand(
-            |hand, cnt| total += dp14[conv.encode_hand14_fast(hand) as usize]*(cnt as u128));
+        conv.for_each_draw_encoded(hand_id as u32,
+            |hi, cnt, _| total += dp14[hi as usize]*(cnt as u128));
         total
     };
     (0..NUM_HAND13).into_par_iter().map(derive).collect()
@@ -92,8 +44,8 @@ pub fn dp14_to_dp13(conv: &HandConverter, dp14: &[u128]) -> Vec<u128> {
 pub fn dp13_to_dp14(conv: &HandConverter, dp13: &[u128], agari_hands: &[u32], one: u128) -> Vec<u128> {
     let derive = |hand_id: usize| {
         let mut best = 0;
-        conv.decode_hand14(hand_id as u32).for_each_discard_hand(
-            |hand, _| best = best.max(dp13[conv.encode_hand13_fast(hand) as usize]));
+        conv.for_each_discard_encoded(hand_id as u32,
+            |hi, _, _| best = best.max(dp13[hi as usize]));
         best
     };
     let mut out: Vec<u128> = (0..NUM_HAND14).into_par_iter().map(derive).collect();
@@ -116,3 +68,77 @@ pub fn check(conv: &HandConverter, table: &Vec<u128>) -> u128 {
     };
     table[conv.encode_hand13_fast(&hand) as usize]
 }
+
+// 副露した手牌のツモ率を計算する。門前の牌がspace_13（3n+1枚）とspace_14（3n+2枚）の手牌で、
+// 13枚・14枚と同じくツモと打牌を繰り返す。和了形は一般形のみで、副露の牌も山に残っているものとみなす。
+// 結果は`tsumo_13.dat`・`tsumo_14.dat`と同じく、手牌ごとにNUM_ROUNDS巡分の確率を2^32倍して並べたもの。
+// 手牌の数が少ないため、一時ファイルを使わずにメモリ上で計算する
+pub fn open_tsumo(
+    conv: &HandConverter,
+    space_13: &HandSpace,
+    space_14: &HandSpace,
+) -> (Vec<u32>, Vec<u32>) {
+    assert_eq!(space_13.num_tiles() + 1, space_14.num_tiles());
+    let unseen = (conv.tile_set().num_tiles() - space_13.num_tiles()) as u128;
+    let mut out_13 = vec![0u32; space_13.len() * NUM_ROUNDS];
+    let mut out_14 = vec![0u32; space_14.len() * NUM_ROUNDS];
+    let store = |out: &mut [u32], round: usize, dp: &[u128], div: u128| {
+        for (hi, &v) in dp.iter().enumerate() {
+            out[hi * NUM_ROUNDS + round] = to_fixed_point(v, div);
+        }
+    };
+
+    let mut dp14: Vec<u128> = (0..space_14.len())
+        .into_par_iter()
+        .map(|hi| is_agari(&conv.decode_in(space_14, hi as u32)) as u128)
+        .collect();
+    let agari_hands: Vec<u32> = (0..dp14.len() as u32)
+        .filter(|&hi| dp14[hi as usize] > 0)
+        .collect();
+    store(&mut out_14, 0, &dp14, 1);
+    for round in 0..NUM_ROUNDS {
+        let dp13: Vec<u128> = (0..space_13.len())
+            .into_par_iter()
+            .map(|hi| {
+                let mut total = 0;
+                conv.for_each_neighbor_in(space_13, space_14, hi as u32, |hi, cnt, _| {
+                    total += dp14[hi as usize] * (cnt as u128)
+                });
+                total
+            })
+            .collect();
+        store(&mut out_13, round, &dp13, unseen.pow(round as u32 + 1));
+        if round + 1 == NUM_ROUNDS {
+            break;
+        }
+        dp14 = (0..space_14.len())
+            .into_par_iter()
+            .map(|hi| {
+                let mut best = 0;
+                conv.for_each_neighbor_in(space_14, space_13, hi as u32, |hi, _, _| {
+                    best = best.max(dp13[hi as usize])
+                });
+                best
+            })
+            .collect();
+        let one = unseen.pow(round as u32 + 1);
+        for &hi in &agari_hands {
+            dp14[hi as usize] = one;
+        }
+        store(&mut out_14, round + 1, &dp14, one);
+    }
+    (out_13, out_14)
+}
+
+// 小さなconverter（`TileSet::Mini`）の13枚・14枚の手牌のツモ率を計算する。結果は`tsumo_13.dat`・`tsumo_14.dat`と同じ並び。
+// 手牌の数が少ないため、副露した手牌と同じくメモリ上で計算する。is_agariは七対子も和了とみなし、Miniに国士無双はない
+pub fn mini_tsumo(conv: &HandConverter) -> (Vec<u32>, Vec<u32>) {
+    assert_eq!(conv.tile_set(), TileSet::Mini, "Converter must be of TileSet::Mini");
+    open_tsumo(conv, &conv.hand_space(13), &conv.hand_space(14))
+}
+
+// 残りk巡の値をunseen^kで割った確率を2^32倍の固定小数点にする（1はu32::MAXに丸める）
+fn to_fixed_point(v: u128, div: u128) -> u32 {
+    let k = v.leading_zeros().min(32);
+    u32::try_from((v << k) / (div >> (32 - k))).unwrap_or(u32::MAX)
+}